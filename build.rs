@@ -2,7 +2,7 @@ use std::process::Command;
 
 fn main() {
     let git_hash = Command::new("git")
-        .args(&["rev-parse", "--short", "HEAD"])
+        .args(["rev-parse", "--short", "HEAD"])
         .output()
         .ok()
         .and_then(|output| String::from_utf8(output.stdout).ok())
@@ -10,7 +10,7 @@ fn main() {
         .unwrap_or_else(|| "unknown".to_string());
 
     let build_time = Command::new("date")
-        .args(&["-u", "+%Y-%m-%dT%H:%M:%SZ"])
+        .args(["-u", "+%Y-%m-%dT%H:%M:%SZ"])
         .output()
         .ok()
         .and_then(|output| String::from_utf8(output.stdout).ok())
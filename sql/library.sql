@@ -1,9 +1,12 @@
 -- name: get_entity_counts->
--- Returns the number of person and office entities present
+-- Returns the number of person and office entities present, leaving out
+-- abolished offices unless asked to include them
+-- param: include_abolished: bool
 SELECT 
     COUNT(CASE WHEN type = 'person' THEN 1 END) AS persons,
-    COUNT(CASE WHEN type = 'office' THEN 1 END) AS offices
-FROM entity;
+    COUNT(CASE WHEN type = 'office' AND (:include_abolished OR l.abolition IS NULL) THEN 1 END) AS offices
+FROM entity
+LEFT JOIN office_lifecycle AS l ON type = 'office' AND l.office_id = id;
 /
 -- name: exists_entity->
 -- param: typ: &dto::EntityType
@@ -19,16 +22,96 @@ SELECT id
 FROM entity 
 WHERE type = :typ
 /
--- name: search_entity->
--- Search for a best matching entity for the query optionally restricting to the given entity type.
+-- name: get_entities?
+-- Get the type, ID and name of all entities
+SELECT type, id, name
+FROM entity
+ORDER BY type, id
+/
+-- name: search_entity?
+-- Search for entities matching the query, best first, optionally restricting to the given entity type
+-- and to offices in the given jurisdiction. Persons are named with their honorific, if any.
 -- param: typ: Option<&dto::EntityType>
+-- param: jurisdiction: Option<&str>
 -- param: query: &str
-SELECT e.type, e.id, e.name
+-- param: limit: u32
+-- param: offset: u32
+SELECT e.type, e.id, COALESCE(h.honorific || ' ' || e.name, e.name)
 FROM entity_idx(:query) AS fts
 JOIN entity AS e ON fts.rowid = e.rowid
-WHERE :typ IS NULL OR e.type == :typ
+LEFT JOIN person_honorific AS h ON e.type = 'person' AND h.person_id = e.id
+WHERE (:typ IS NULL OR e.type == :typ)
+AND (
+    :jurisdiction IS NULL
+    OR EXISTS (
+        SELECT 1 FROM office_jurisdiction AS j
+        WHERE e.type = 'office' AND j.office_id = e.id AND j.jurisdiction = :jurisdiction
+    )
+)
 ORDER BY rank
-LIMIT 1
+LIMIT :limit OFFSET :offset
+/
+-- name: list_offices?
+-- Lists offices by ID, optionally only those in the given jurisdiction and those with a supervisor in
+-- the given relation, starting after the given ID.
+-- param: jurisdiction: Option<&str>
+-- param: relation: Option<&crate::data::SupervisingRelation>
+-- param: after: Option<&str>
+-- param: limit: u32
+SELECT e.id, e.name
+FROM entity AS e
+WHERE e.type = 'office'
+AND (
+    :jurisdiction IS NULL
+    OR EXISTS (
+        SELECT 1 FROM office_jurisdiction AS j
+        WHERE j.office_id = e.id AND j.jurisdiction = :jurisdiction
+    )
+)
+AND (
+    :relation IS NULL
+    OR EXISTS (
+        SELECT 1 FROM office_supervisor AS s
+        WHERE s.office_id = e.id AND s.relation = :relation
+    )
+)
+AND (:after IS NULL OR e.id > :after)
+ORDER BY e.id
+LIMIT :limit
+/
+-- name: get_office_jurisdiction_counts?
+-- Returns the number of offices in each jurisdiction, counting only offices with a supervisor in the
+-- given relation when one is given
+-- param: relation: Option<&crate::data::SupervisingRelation>
+SELECT j.jurisdiction, COUNT(*)
+FROM office_jurisdiction AS j
+JOIN entity AS e ON e.type = 'office' AND e.id = j.office_id
+WHERE (
+    :relation IS NULL
+    OR EXISTS (
+        SELECT 1 FROM office_supervisor AS s
+        WHERE s.office_id = j.office_id AND s.relation = :relation
+    )
+)
+GROUP BY j.jurisdiction
+ORDER BY j.jurisdiction
+/
+-- name: get_office_relation_counts?
+-- Returns the number of offices with a supervisor in each relation, counting only offices in the
+-- given jurisdiction when one is given
+-- param: jurisdiction: Option<&str>
+SELECT s.relation, COUNT(DISTINCT s.office_id)
+FROM office_supervisor AS s
+JOIN entity AS e ON e.type = 'office' AND e.id = s.office_id
+WHERE (
+    :jurisdiction IS NULL
+    OR EXISTS (
+        SELECT 1 FROM office_jurisdiction AS j
+        WHERE j.office_id = s.office_id AND j.jurisdiction = :jurisdiction
+    )
+)
+GROUP BY s.relation
+ORDER BY s.relation
 /
 -- name: get_entity_name->
 -- Returns the name of the entity of the given type with the given id
@@ -41,37 +124,22 @@ WHERE type = :typ
     AND id = :id
 LIMIT 1
 /
--- name: exists_entity_photo->
--- Returns if an entity has a photo
--- # Parameters
--- param: typ: &dto::EntityType - entity type
--- param: id: &str - entity ID
-SELECT EXISTS(
-    SELECT 1
-    FROM entity_photo
-    WHERE entity_type = :typ AND entity_id = :id
-)
-/
 -- name: get_entity_photo->
 -- Returns the photo of the entity of the given type with the given id
 -- # Parameters
 -- param: typ: &dto::EntityType - entity type
 -- param: id: &str - entity ID
-SELECT url, attribution
+SELECT url, attribution, license, source_url
 FROM entity_photo
 WHERE entity_type = :typ
     AND entity_id = :id
 LIMIT 1
 /
--- name: exists_entity_contact->
--- # Parameters
--- param: entity_type: &dto::EntityType
--- param: entity_id: &str
--- param: typ: &data::ContactType
-SELECT EXISTS(
-    SELECT 1 FROM entity_contact
-    WHERE entity_type = :entity_type AND entity_id = :entity_id AND type = :typ
-)
+-- name: get_entity_photo_urls?
+-- Returns the photo url of every entity that has one
+SELECT entity_type, entity_id, url
+FROM entity_photo
+ORDER BY entity_type, entity_id
 /
 -- name: get_entity_contacts?
 -- Returns the contacts of the entity with the given id
@@ -124,18 +192,26 @@ FROM person_office_tenure
 WHERE person_id = :id
 /
 -- name: get_past_tenures?
--- Returns the past tenures of the person with the given id
+-- Returns the past tenures of the person with the given id, latest first, keeping those that
+-- ended on or after `after` and started on or before `before`
 -- # Parameters
 -- param: id: &str - person ID
+-- param: after: Option<&chrono::NaiveDate>
+-- param: before: Option<&chrono::NaiveDate>
+-- param: limit: Option<u32> - at most this many, or all of them for NULL
 SELECT
     q.office_id,
     o.name,
     q.start,
-    q.end
+    q.end,
+    q.acting
 FROM person_office_quondam AS q
 INNER JOIN office AS o ON o.id = q.office_id
 WHERE q.person_id = :id
+AND (:after IS NULL OR q.end >= :after)
+AND (:before IS NULL OR q.start IS NULL OR q.start <= :before)
 ORDER BY q.end DESC
+LIMIT COALESCE(:limit, -1)
 /
 -- name: exists_office_supervisor->
 -- Returns if an office has a supervisor with the given relation
@@ -150,15 +226,42 @@ SELECT EXISTS(
 -- Returns the quondams for a given office
 -- # Parameters
 -- param: office_id: &str
-SELECT q.person_id, p.name, q.start, q.end FROM person_office_quondam AS q
+SELECT q.person_id, p.name, q.start, q.end, q.acting FROM person_office_quondam AS q
 JOIN person AS p ON q.person_id = p.id
 WHERE q.office_id = :office_id ORDER BY q.end DESC
 /
+-- name: get_office_succession?
+-- Returns every tenure of the given office, earliest first. Tenures are
+-- placed by their start, or their end if the start is not known, and those
+-- with neither come last.
+-- # Parameters
+-- param: office_id: &str
+SELECT t.person_id, p.name, t.start, t.end, t.acting
+FROM person_office_tenure AS t
+JOIN person AS p ON t.person_id = p.id
+WHERE t.office_id = :office_id
+ORDER BY COALESCE(t.start, t.end) IS NULL, COALESCE(t.start, t.end), t.end IS NULL, t.end
+/
+-- name: get_office_lifecycle->
+-- Returns the establishment and abolition dates of an office
+-- param: office_id: &str
+SELECT establishment, abolition FROM office_lifecycle WHERE office_id = :office_id
+/
+-- name: get_person_honorific->
+-- Returns the honorific shown before a person's name
+-- param: person_id: &str
+SELECT honorific FROM person_honorific WHERE person_id = :person_id
+/
+-- name: get_office_jurisdiction->
+-- Returns the region an office has authority over
+-- param: office_id: &str
+SELECT jurisdiction FROM office_jurisdiction WHERE office_id = :office_id
+/
 -- name: get_office_incumbent->
 -- Returns the incumbent for a given office
 -- # Parameters
 -- param: office_id: &str
-SELECT p.id, p.name, i.start FROM person_office_incumbent AS i
+SELECT p.id, p.name, i.start, i.acting FROM person_office_incumbent AS i
 JOIN person AS p ON i.person_id = p.id
 WHERE i.office_id = :office_id 
 LIMIT 1
@@ -189,7 +292,8 @@ FROM office_supervisor AS s
 INNER JOIN office AS o ON o.id = s.supervisor_office_id
 LEFT JOIN person_office_incumbent AS i ON i.office_id = s.supervisor_office_id
 LEFT JOIN person as p on p.id = i.person_id
-WHERE s.office_id = :office_id;
+WHERE s.office_id = :office_id
+ORDER BY s.supervisor_office_id;
 /
 -- name: get_office_supervising_offices?
 -- Returns supervising offices for an office.
@@ -197,12 +301,38 @@ WHERE s.office_id = :office_id;
 -- param: office_id: &str
 SELECT relation, supervisor_office_id
 FROM office_supervisor
-WHERE office_id = :office_id;
+WHERE office_id = :office_id
+ORDER BY supervisor_office_id;
+/
+-- name: get_office_supervisor_edges?
+-- Returns every supervisor relation between offices.
+SELECT office_id, relation, supervisor_office_id
+FROM office_supervisor
+ORDER BY office_id, supervisor_office_id;
+/
+-- name: get_related_entities?
+-- Returns the entities whose pages show the given one: the offices a person
+-- held, the persons who held an office, and the offices supervising or
+-- supervised by an office.
+-- # Parameters
+-- param: typ: &dto::EntityType
+-- param: id: &str
+SELECT 'office', office_id FROM person_office_tenure
+WHERE :typ = 'person' AND person_id = :id
+UNION
+SELECT 'person', person_id FROM person_office_tenure
+WHERE :typ = 'office' AND office_id = :id
+UNION
+SELECT 'office', supervisor_office_id FROM office_supervisor
+WHERE :typ = 'office' AND office_id = :id
+UNION
+SELECT 'office', office_id FROM office_supervisor
+WHERE :typ = 'office' AND supervisor_office_id = :id
 /
 -- name: get_person_incumbent_office_details?
 -- # Parameter
 -- param: person_id: &str
-SELECT i.office_id, e.name, p.url, p.attribution, i.start
+SELECT i.office_id, e.name, p.url, p.attribution, p.license, p.source_url, i.start, i.acting
 FROM person_office_incumbent AS i
 JOIN entity AS e ON i.office_id = e.id AND e.type = 'office'
 LEFT JOIN entity_photo AS p ON i.office_id = p.entity_id AND p.entity_type = 'office'
@@ -0,0 +1,149 @@
+-- name: ensure_office_lifecycle &
+-- Create the table for the establishment and abolition of offices, and its
+-- triggers, in databases created before offices had them.
+CREATE TABLE IF NOT EXISTS office_lifecycle (
+  office_id TEXT NOT NULL PRIMARY KEY,
+  establishment TEXT,
+  abolition TEXT
+);
+CREATE TRIGGER IF NOT EXISTS office_lifecycle_ai_commit
+AFTER
+INSERT ON office_lifecycle
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
+CREATE TRIGGER IF NOT EXISTS office_lifecycle_au_commit
+AFTER
+UPDATE ON office_lifecycle
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
+/
+-- name: rekey_office_supervisor &
+-- Recreate the table of office supervisors, and its triggers, with the key
+-- that allows several supervisors per relation. Its rows are left for the
+-- index rebuild to fill in again.
+DROP TABLE office_supervisor;
+CREATE TABLE office_supervisor (
+  office_id TEXT NOT NULL,
+  relation TEXT NOT NULL,
+  supervisor_office_id TEXT NOT NULL,
+  PRIMARY KEY(office_id, relation, supervisor_office_id)
+);
+CREATE TRIGGER office_supervisor_ai_commit
+AFTER
+INSERT ON office_supervisor
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
+CREATE TRIGGER office_supervisor_au_commit
+AFTER
+UPDATE ON office_supervisor
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
+CREATE TRIGGER office_supervisor_ad_commit
+AFTER DELETE ON office_supervisor
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = old.office_id;
+END;
+/
+-- name: add_tenure_acting &
+-- Add the acting flag to tenures, and to the views of incumbents and
+-- quondams over them.
+ALTER TABLE person_office_tenure ADD COLUMN acting INTEGER NOT NULL DEFAULT 0;
+DROP VIEW IF EXISTS person_office_incumbent;
+CREATE VIEW person_office_incumbent (person_id, office_id, start, acting) AS
+SELECT person_id,
+  office_id,
+  start,
+  acting
+FROM person_office_tenure
+WHERE
+end IS NULL;
+DROP VIEW IF EXISTS person_office_quondam;
+CREATE VIEW person_office_quondam (person_id, office_id, start,end, acting
+) AS
+SELECT person_id,
+  office_id,
+  start,
+  end,
+  acting
+FROM person_office_tenure
+WHERE
+end IS NOT NULL;
+/
+-- name: add_photo_credit &
+-- Add the license and source page of photos.
+ALTER TABLE entity_photo ADD COLUMN license TEXT;
+ALTER TABLE entity_photo ADD COLUMN source_url TEXT;
+/
+-- name: ensure_office_jurisdiction &
+-- Create the table for the jurisdiction of offices in databases created
+-- before offices had one.
+CREATE TABLE IF NOT EXISTS office_jurisdiction (
+  office_id TEXT NOT NULL PRIMARY KEY,
+  jurisdiction TEXT NOT NULL
+);
+/
+-- name: ensure_person_honorific &
+-- Create the table for the honorifics of persons in databases created
+-- before persons had one.
+CREATE TABLE IF NOT EXISTS person_honorific (
+  person_id TEXT NOT NULL PRIMARY KEY,
+  honorific TEXT NOT NULL
+);
+/
+-- name: add_entity_search_name &
+-- Add the name entities are searched for by, and point the triggers that keep
+-- the full-text index current at it. The index itself has to be created
+-- again over the new column.
+ALTER TABLE entity ADD COLUMN search_name TEXT NOT NULL DEFAULT '';
+DROP TRIGGER IF EXISTS entity_ai_fts;
+DROP TRIGGER IF EXISTS entity_ad_fts;
+DROP TRIGGER IF EXISTS entity_au_fts;
+CREATE TRIGGER entity_ai_fts
+AFTER
+INSERT ON entity BEGIN
+INSERT INTO entity_idx(rowid, id, search_name)
+VALUES (new.rowid, new.id, new.search_name);
+END;
+CREATE TRIGGER entity_ad_fts
+AFTER DELETE ON entity BEGIN
+INSERT INTO entity_idx(entity_idx, rowid, id, search_name)
+VALUES('delete', old.rowid, old.id, old.search_name);
+END;
+CREATE TRIGGER entity_au_fts
+AFTER
+UPDATE ON entity BEGIN
+INSERT INTO entity_idx(entity_idx, rowid, id, search_name)
+VALUES('delete', old.rowid, old.id, old.search_name);
+INSERT INTO entity_idx(rowid, id, search_name)
+VALUES (new.rowid, new.id, new.search_name);
+END;
+/
@@ -6,29 +6,31 @@ CREATE TABLE entity (
   type TEXT NOT NULL,
   id TEXT NOT NULL,
   name TEXT NOT NULL,
+  -- the name as it is searched for
+  search_name TEXT NOT NULL,
   PRIMARY KEY(type, id),
   UNIQUE(type, id)
 );
 -- FTS
-CREATE VIRTUAL TABLE entity_idx USING fts5(id, name, content = 'entity');
+CREATE VIRTUAL TABLE entity_idx USING fts5(id, search_name, content = 'entity');
 CREATE TRIGGER entity_ai_fts
 AFTER
 INSERT ON entity BEGIN
-INSERT INTO entity_idx(rowid, id, name)
-VALUES (new.rowid, new.id, new.name);
+INSERT INTO entity_idx(rowid, id, search_name)
+VALUES (new.rowid, new.id, new.search_name);
 END;
 CREATE TRIGGER entity_ad_fts
 AFTER DELETE ON entity BEGIN
-INSERT INTO entity_idx(entity_idx, rowid, id, name)
-VALUES('delete', old.rowid, old.id, old.name);
+INSERT INTO entity_idx(entity_idx, rowid, id, search_name)
+VALUES('delete', old.rowid, old.id, old.search_name);
 END;
 CREATE TRIGGER entity_au_fts
 AFTER
 UPDATE ON entity BEGIN
-INSERT INTO entity_idx(entity_idx, rowid, id, name)
-VALUES('delete', old.rowid, old.id, old.name);
-INSERT INTO entity_idx(rowid, id, name)
-VALUES (new.rowid, new.id, new.name);
+INSERT INTO entity_idx(entity_idx, rowid, id, search_name)
+VALUES('delete', old.rowid, old.id, old.search_name);
+INSERT INTO entity_idx(rowid, id, search_name)
+VALUES (new.rowid, new.id, new.search_name);
 END;
 --
 COMMIT;
@@ -42,6 +44,8 @@ CREATE TABLE entity_photo (
   entity_id TEXT NOT NULL,
   url TEXT NOT NULL,
   attribution TEXT,
+  license TEXT,
+  source_url TEXT,
   PRIMARY KEY(entity_type, entity_id) FOREIGN KEY(entity_type, entity_id) REFERENCES entity(type, id)
 );
 -- [entity_contact]
@@ -69,30 +73,49 @@ CREATE TABLE office_supervisor (
   office_id TEXT NOT NULL,
   relation TEXT NOT NULL,
   supervisor_office_id TEXT NOT NULL,
-  PRIMARY KEY(office_id, relation)
+  PRIMARY KEY(office_id, relation, supervisor_office_id)
+);
+-- [office_lifecycle]
+CREATE TABLE office_lifecycle (
+  office_id TEXT NOT NULL PRIMARY KEY,
+  establishment TEXT,
+  abolition TEXT
+);
+-- [office_jurisdiction]
+CREATE TABLE office_jurisdiction (
+  office_id TEXT NOT NULL PRIMARY KEY,
+  jurisdiction TEXT NOT NULL
+);
+-- [person_honorific]
+CREATE TABLE person_honorific (
+  person_id TEXT NOT NULL PRIMARY KEY,
+  honorific TEXT NOT NULL
 );
 -- [person_office_tenure]
 CREATE TABLE person_office_tenure (
   person_id TEXT NOT NULL,
   office_id TEXT NOT NULL,
   start TEXT,
-end TEXT
+end TEXT,
+  acting INTEGER NOT NULL DEFAULT 0
 );
 -- [person_office_incumbent]
-CREATE VIEW person_office_incumbent (person_id, office_id, start) AS
+CREATE VIEW person_office_incumbent (person_id, office_id, start, acting) AS
 SELECT person_id,
   office_id,
-  start
+  start,
+  acting
 FROM person_office_tenure
 WHERE
 end IS NULL;
 --[person_office_quondam]
-CREATE VIEW person_office_quondam (person_id, office_id, start,end
+CREATE VIEW person_office_quondam (person_id, office_id, start,end, acting
 ) AS
 SELECT person_id,
   office_id,
   start,
-  end
+  end,
+  acting
 FROM person_office_tenure
 WHERE
 end IS NOT NULL;
@@ -229,6 +252,29 @@ DELETE FROM entity_commit
 WHERE entity_type = 'office'
   AND entity_id = old.office_id;
 END;
+--- for office_lifecycle
+CREATE TRIGGER office_lifecycle_ai_commit
+AFTER
+INSERT ON office_lifecycle
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
+CREATE TRIGGER office_lifecycle_au_commit
+AFTER
+UPDATE ON office_lifecycle
+  WHEN (
+    SELECT enabled
+    FROM commit_tracking
+  ) > 0 BEGIN
+DELETE FROM entity_commit
+WHERE entity_type = 'office'
+  AND entity_id = new.office_id;
+END;
 --- for person_office_tenure
 CREATE TRIGGER person_office_tenure_ai_commit
 AFTER
@@ -279,5 +325,15 @@ CREATE TABLE secrets (
   name TEXT NOT NULL PRIMARY KEY,
   value BLOB NOT NULL
 );
+-- [commits]
+CREATE TABLE commits (
+  hash BLOB NOT NULL PRIMARY KEY,
+  blob BLOB NOT NULL
+);
+-- [index_snapshots]
+CREATE TABLE index_snapshots (
+  hash BLOB NOT NULL PRIMARY KEY,
+  blob BLOB NOT NULL
+);
 COMMIT;
 /
@@ -4,9 +4,10 @@
 -- param: typ: &crate::dto::EntityType - entity type
 -- param: id: &str - entity ID
 -- param: name: &str - name
-INSERT INTO entity (type, id, name)
-VALUES (:typ, :id, :name)
-ON CONFLICT (type, id) DO UPDATE SET name = :name;
+-- param: search_name: &str - name as it is searched for
+INSERT INTO entity (type, id, name, search_name)
+VALUES (:typ, :id, :name, :search_name)
+ON CONFLICT (type, id) DO UPDATE SET name = :name, search_name = :search_name;
 /
 -- name: save_entity_photo!
 -- Save the photo for the given type with the given id
@@ -15,12 +16,16 @@ ON CONFLICT (type, id) DO UPDATE SET name = :name;
 -- param: id: &str - entity ID
 -- param: url: &str - url
 -- param: attribution: Option<&str> - attribution
-INSERT INTO entity_photo (entity_type, entity_id, url, attribution)
-VALUES (:typ, :id, :url, :attribution)
+-- param: license: Option<&str> - license
+-- param: source_url: Option<&str> - source page url
+INSERT INTO entity_photo (entity_type, entity_id, url, attribution, license, source_url)
+VALUES (:typ, :id, :url, :attribution, :license, :source_url)
 ON CONFLICT (entity_type, entity_id) DO UPDATE
 SET
     url = :url,
-    attribution = :attribution
+    attribution = :attribution,
+    license = :license,
+    source_url = :source_url
 WHERE
     entity_type = :typ AND entity_id = :id
 /
@@ -32,15 +37,17 @@ WHERE
 -- param: value: &str
 INSERT INTO entity_contact (entity_type, entity_id, type, value)
 VALUES (:entity_type, :entity_id, :typ, :value)
+ON CONFLICT (entity_type, entity_id, type) DO UPDATE SET value = :value
 /
 -- name: save_office_supervisor!
--- Save tenure of person in an office
+-- Save an office supervising another office under the given relation
 -- # Parameters
 -- param: office_id: &str
 -- param: relation: &crate::data::SupervisingRelation
 -- param: supervisor_office_id: &str
 INSERT INTO office_supervisor (office_id, relation, supervisor_office_id)
 VALUES (:office_id, :relation, :supervisor_office_id)
+ON CONFLICT (office_id, relation, supervisor_office_id) DO UPDATE SET supervisor_office_id = :supervisor_office_id
 /
 -- name: save_tenure!
 -- Save tenure of person in an office
@@ -49,8 +56,45 @@ VALUES (:office_id, :relation, :supervisor_office_id)
 -- param: office_id: &str
 -- param: start: Option<&chrono::NaiveDate>
 -- param: end: Option<&chrono::NaiveDate>
-INSERT INTO person_office_tenure (person_id, office_id, start, end)
-VALUES (:person_id, :office_id, :start, :end)
+-- param: acting: bool
+INSERT INTO person_office_tenure (person_id, office_id, start, end, acting)
+VALUES (:person_id, :office_id, :start, :end, :acting)
+/
+-- name: save_office_establishment!
+-- Save the date an office was established
+-- # Parameters
+-- param: office_id: &str
+-- param: date: &chrono::NaiveDate
+INSERT INTO office_lifecycle (office_id, establishment)
+VALUES (:office_id, :date)
+ON CONFLICT (office_id) DO UPDATE SET establishment = :date
+/
+-- name: save_office_abolition!
+-- Save the date an office was abolished
+-- # Parameters
+-- param: office_id: &str
+-- param: date: &chrono::NaiveDate
+INSERT INTO office_lifecycle (office_id, abolition)
+VALUES (:office_id, :date)
+ON CONFLICT (office_id) DO UPDATE SET abolition = :date
+/
+-- name: save_office_jurisdiction!
+-- Save the region an office has authority over
+-- # Parameters
+-- param: office_id: &str
+-- param: jurisdiction: &str
+INSERT INTO office_jurisdiction (office_id, jurisdiction)
+VALUES (:office_id, :jurisdiction)
+ON CONFLICT (office_id) DO UPDATE SET jurisdiction = :jurisdiction
+/
+-- name: save_person_honorific!
+-- Save the honorific shown before a person's name
+-- # Parameters
+-- param: person_id: &str
+-- param: honorific: &str
+INSERT INTO person_honorific (person_id, honorific)
+VALUES (:person_id, :honorific)
+ON CONFLICT (person_id) DO UPDATE SET honorific = :honorific
 /
 -- name: delete_entity!
 -- Delete the entity of the given type with the given id
@@ -74,10 +118,13 @@ DELETE FROM entity_photo WHERE entity_type = :typ AND entity_id = :id
 DELETE FROM entity_contact WHERE entity_type = :entity_type AND entity_id = :entity_id AND type = :typ
 /
 -- name: delete_office_supervisor!
+-- Delete one supervisor of an office under the given relation
 -- # Parameters
 -- param: office_id: &str
 -- param: relation: &crate::data::SupervisingRelation
-DELETE FROM office_supervisor WHERE office_id = :office_id AND relation = :relation
+-- param: supervisor_office_id: &str
+DELETE FROM office_supervisor
+WHERE office_id = :office_id AND relation = :relation AND supervisor_office_id = :supervisor_office_id
 /
 -- name: delete_tenure!
 -- # Parameters
@@ -86,4 +133,35 @@ DELETE FROM office_supervisor WHERE office_id = :office_id AND relation = :relat
 -- param: start: Option<&chrono::NaiveDate>
 DELETE FROM person_office_tenure
 WHERE person_id = :person_id AND office_id = :office_id AND start IS :start
-/
\ No newline at end of file
+/
+-- name: delete_office_establishment!
+-- # Parameters
+-- param: office_id: &str
+UPDATE office_lifecycle SET establishment = NULL WHERE office_id = :office_id
+/
+-- name: delete_office_abolition!
+-- # Parameters
+-- param: office_id: &str
+UPDATE office_lifecycle SET abolition = NULL WHERE office_id = :office_id
+/
+-- name: delete_office_jurisdiction!
+-- # Parameters
+-- param: office_id: &str
+DELETE FROM office_jurisdiction WHERE office_id = :office_id
+/
+-- name: delete_person_honorific!
+-- # Parameters
+-- param: person_id: &str
+DELETE FROM person_honorific WHERE person_id = :person_id
+/
+-- name: clear_index &
+-- Empty the tables the records are indexed into, for rebuilding them
+DELETE FROM entity_photo;
+DELETE FROM entity_contact;
+DELETE FROM office_supervisor;
+DELETE FROM office_lifecycle;
+DELETE FROM office_jurisdiction;
+DELETE FROM person_honorific;
+DELETE FROM person_office_tenure;
+DELETE FROM entity;
+/
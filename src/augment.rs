@@ -21,6 +21,12 @@ pub async fn run(db_path: &Path, source: Source, fields: Vec<Field>) -> Result<(
         Source::Old => unimplemented!("old augmentor not yet implemented"),
     };
 
+    // Fill the Wikidata IDs in first, so the fields looked up from them can
+    // reach the entities just given one.
+    let mut fields = fields;
+    fields.sort();
+    fields.dedup();
+
     for field in &fields {
         match field {
             Field::Wikidata => {
@@ -91,12 +97,14 @@ impl Augmentor for WikidataAugmentor {
             && let Some(value) = datavalue.get("value")
             && let Some(file_name) = value.as_str()
         {
-            let attribution = self.fetch_file_attribution(file_name).await?;
-            let url = self.fetch_file_url(file_name).await?;
+            let (attribution, license) = self.fetch_file_credit(file_name).await?;
+            let (url, source_url) = self.fetch_file_url(file_name).await?;
 
             return Ok(Some(data::Photo {
                 url,
                 attribution: Some(attribution),
+                license,
+                source_url,
             }));
         }
 
@@ -107,26 +115,27 @@ impl Augmentor for WikidataAugmentor {
         let params: HashMap<String, String> = [
             ("action".to_string(), "wbgetentities".to_string()),
             ("ids".to_string(), id.to_string()),
-            ("props".to_string(), "sitelinks/urls".to_string()),
+            ("props".to_string(), "sitelinks".to_string()),
+            ("sitefilter".to_string(), "enwiki".to_string()),
         ]
         .iter()
         .cloned()
         .collect();
         let res = self.api.get_query_api_json(&params).await?;
 
-        if let Some(entity) = res["entities"].as_object().and_then(|e| e.get(id))
-            && let Some(sitelinks) = entity.get("sitelinks")
-            && let Some(enwiki) = sitelinks.get("enwiki")
-            && let Some(url) = enwiki.get("url")
-            && let Some(url_str) = url.as_str()
-            && let Some(title) = url_str.split('/').next_back()
-            && !title.is_empty()
-        {
-            return Ok(Some(title.to_string()));
-        }
+        Ok(enwiki_title(&res, id))
+    }
+}
 
-        Ok(None)
+/// The English Wikipedia title the `enwiki` sitelink of entity `id` gives in
+/// a `wbgetentities` response, written as in a page URL.
+fn enwiki_title(res: &serde_json::Value, id: &str) -> Option<String> {
+    let title = res["entities"][id]["sitelinks"]["enwiki"]["title"].as_str()?;
+    if title.is_empty() {
+        return None;
     }
+
+    Some(title.replace(' ', "_"))
 }
 
 impl WikidataAugmentor {
@@ -137,7 +146,9 @@ impl WikidataAugmentor {
         WikidataAugmentor { api }
     }
 
-    async fn fetch_file_attribution(&self, file_name: &str) -> Result<String> {
+    /// The attribution and, if known, the license of a file on Wikimedia
+    /// Commons.
+    async fn fetch_file_credit(&self, file_name: &str) -> Result<(String, Option<String>)> {
         let params: HashMap<String, String> = [
             ("action".to_string(), "query".to_string()),
             ("titles".to_string(), format!("File:{}", file_name)),
@@ -177,24 +188,17 @@ impl WikidataAugmentor {
                 attribution_parts.push(artist_str.to_string());
             }
 
-            let license_str = match (license_short_name, license_url) {
-                (Some(name), Some(url)) => Some(format!("{} <{}>", name, url)),
-                (Some(name), None) => Some(name.to_string()),
-                (None, Some(url)) => Some(format!("<{}>", url)),
-                (None, None) => None,
-            };
-            if let Some(license_str) = license_str {
-                attribution_parts.push(license_str);
-            }
+            let license = license_short_name.or(license_url).map(str::to_string);
 
             attribution_parts.push("via Wikimedia Commons".to_string());
-            return Ok(attribution_parts.join(", "));
+            return Ok((attribution_parts.join(", "), license));
         }
 
         bail!("could not fetch attribution for file")
     }
 
-    async fn fetch_file_url(&self, file_name: &str) -> Result<String> {
+    /// The URL of a file on Wikimedia Commons and of the page describing it.
+    async fn fetch_file_url(&self, file_name: &str) -> Result<(String, Option<String>)> {
         let params: HashMap<String, String> = [
             ("action".to_string(), "query".to_string()),
             ("titles".to_string(), format!("File:{}", file_name)),
@@ -219,7 +223,11 @@ impl WikidataAugmentor {
             && let Some(url) = imageinfo.get("url")
             && let Some(url_str) = url.as_str()
         {
-            return Ok(url_str.to_string());
+            let description_url = imageinfo
+                .get("descriptionurl")
+                .and_then(|url| url.as_str())
+                .map(str::to_string);
+            return Ok((url_str.to_string(), description_url));
         }
 
         bail!("could not fetch url for file")
@@ -264,6 +272,7 @@ async fn augment_wikidata_id(conn: &mut Connection, source: &dyn Augmentor) -> R
                 id: row.get(0)?,
                 name: row.get(1)?,
                 start: None,
+                acting: false,
             });
 
             Ok(())
@@ -305,12 +314,12 @@ async fn augment_wikipedia(conn: &mut Connection, source: &dyn Augmentor) -> Res
 
     for (wikidata_id, person_id) in map {
         println!("augmenting wikipedia for {}:{}...", wikidata_id, person_id);
-        let wikipedia_url = source.query_wikipedia(&wikidata_id).await?;
-        if let Some(wikipedia_url) = wikipedia_url {
-            println!("- found {}", wikipedia_url);
+        let title = source.query_wikipedia(&wikidata_id).await?;
+        if let Some(title) = title {
+            println!("- found {}", title);
             repo.working()?.save(
                 Key::<PersonPath, ()>::new(&person_id).contact(data::ContactType::Wikipedia),
-                &wikipedia_url,
+                &title,
             )?;
         } else {
             println!("- no wikipedia page found");
@@ -318,3 +327,30 @@ async fn augment_wikipedia(conn: &mut Connection, source: &dyn Augmentor) -> Res
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enwiki_title() {
+        let res = serde_json::json!({
+            "entities": {
+                "Q1058": {
+                    "id": "Q1058",
+                    "sitelinks": {
+                        "enwiki": {"site": "enwiki", "title": "Narendra Modi", "badges": []}
+                    }
+                },
+                "Q2": {"id": "Q2", "sitelinks": {}}
+            }
+        });
+
+        assert_eq!(
+            enwiki_title(&res, "Q1058").as_deref(),
+            Some("Narendra_Modi")
+        );
+        assert_eq!(enwiki_title(&res, "Q2"), None);
+        assert_eq!(enwiki_title(&res, "Q3"), None);
+    }
+}
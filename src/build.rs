@@ -1,65 +1,189 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
 
-use crate::data::{Data, indexer::Indexer};
+use crate::data::remote::{Checkout, Source};
+use crate::data::{Data, DataDiff, DataError, DataItem, indexer::Indexer};
 
-pub async fn run(data_dir: &Path) -> Result<()> {
+/// How long to wait for more file events before re-indexing, so that an
+/// editor's burst of writes is handled at once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Build the search index of the data in `data_dir`, parsing the entity
+/// files on `jobs` threads, or one per CPU. When `strict`, entities with
+/// unknown fields fail the build. With a `source`, the data is read from a
+/// temporary checkout of it instead, and the index still written to
+/// `data_dir`.
+pub async fn run(
+    data_dir: &Path,
+    source: Option<&Source>,
+    watch: bool,
+    jobs: Option<usize>,
+    strict: bool,
+) -> Result<()> {
     let output_dir = data_dir.join("output");
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()?;
 
-    let data = Data::open(&data_dir)?;
+    let checkout = source.map(Checkout::clone).transpose()?;
+    let source_dir = checkout.as_ref().map_or(data_dir, Checkout::path);
+    let data = Data::open(source_dir)?.strict(strict);
     let data_commit_id = data.commit_id()?;
 
     let mut indexer = Indexer::open(&output_dir)?;
     let indexer_commit_id = indexer.commit_id()?;
 
     if let Some(old_id) = indexer_commit_id {
-        if old_id == data_commit_id {
-            return Ok(());
-        }
-
-        let diffs = data.diff(&old_id).await?;
-        if !diffs.is_empty() {
+        if old_id != data_commit_id {
+            let diffs = data.diff(&old_id).await?;
             for diff in diffs {
-                match diff {
-                    crate::data::DataDiff::Added(id, item)
-                    | crate::data::DataDiff::Modified(id, item) => match item {
-                        crate::data::DataItem::Person(p) => indexer.add_person(&id, p)?,
-                        crate::data::DataItem::Office(o) => indexer.add_office(&id, o)?,
-                    },
-                    crate::data::DataDiff::Deleted(id, _) => {
-                        indexer.delete(&id)?;
-                    }
-                }
+                apply(&mut indexer, diff)?;
             }
         }
     } else {
-        for result in data.offices() {
-            match result {
-                Ok((id, office)) => {
-                    indexer.add_office(&id, office)?;
-                }
-                Err(crate::data::DataError::OfficeValidation(e)) => {
-                    eprintln!("{:?}", miette::Report::new(e));
-                }
-                Err(e) => return Err(e.into()),
+        let rejected = add_all(&data, &mut indexer, &pool)?;
+        if rejected > 0 {
+            anyhow::bail!("{} entities have unknown fields", rejected);
+        }
+    }
+
+    indexer.commit(&data_commit_id)?;
+
+    if watch {
+        watch_changes(data_dir, &data, &mut indexer, &pool, &data_commit_id).await?;
+    }
+
+    Ok(())
+}
+
+fn apply(indexer: &mut Indexer, diff: DataDiff) -> Result<()> {
+    match diff {
+        DataDiff::Added(id, item) | DataDiff::Modified(id, item) => match item {
+            DataItem::Person(p) => indexer.add_person(&id, p)?,
+            DataItem::Office(o) => indexer.add_office(&id, o)?,
+        },
+        DataDiff::Deleted(id) => {
+            indexer.delete(&id)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Index every entity. Parsing and validation run on `pool`; the results
+/// come back ordered by id and are indexed on this thread. Returns how many
+/// entities were left out for having unknown fields.
+fn add_all(data: &Data, indexer: &mut Indexer, pool: &rayon::ThreadPool) -> Result<usize> {
+    let (offices, persons) = pool.install(|| rayon::join(|| data.offices(), || data.persons()));
+    let mut rejected = 0;
+
+    for result in offices {
+        match result {
+            Ok((id, office)) => {
+                indexer.add_office(&id, office)?;
+            }
+            Err(e) => {
+                rejected += matches!(e, DataError::UnknownField(_)) as usize;
+                report(e)?
             }
         }
+    }
 
-        for result in data.persons() {
-            match result {
-                Ok((id, person)) => {
-                    indexer.add_person(&id, person)?;
-                }
-                Err(crate::data::DataError::PersonValidation(e)) => {
-                    eprintln!("{:?}", miette::Report::new(e));
-                }
-                Err(e) => return Err(e.into()),
+    for result in persons {
+        match result {
+            Ok((id, person)) => {
+                indexer.add_person(&id, person)?;
+            }
+            Err(e) => {
+                rejected += matches!(e, DataError::UnknownField(_)) as usize;
+                report(e)?
             }
         }
     }
 
-    indexer.commit(&data_commit_id)?;
+    Ok(rejected)
+}
+
+/// Print validation errors and carry on; fail on anything else.
+fn report(e: DataError) -> Result<()> {
+    match e {
+        DataError::OfficeValidation(e) => eprintln!("{:?}", miette::Report::new(*e)),
+        DataError::PersonValidation(e) => eprintln!("{:?}", miette::Report::new(*e)),
+        DataError::UnknownField(e) => eprintln!("{:?}", miette::Report::new(*e)),
+        e => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+/// Re-index entity files as they change until interrupted. The index keeps
+/// the commit id it was built from, since the edits are not snapshotted.
+async fn watch_changes(
+    data_dir: &Path,
+    data: &Data,
+    indexer: &mut Indexer,
+    pool: &rayon::ThreadPool,
+    commit_id: &str,
+) -> Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = tx.send(event);
+    })?;
+
+    if data.is_single_file() {
+        watcher.watch(&data_dir.join("data.toml"), RecursiveMode::NonRecursive)?;
+    } else {
+        for dir in ["person", "office"] {
+            let path = data_dir.join(dir);
+            if path.is_dir() {
+                watcher.watch(&path, RecursiveMode::NonRecursive)?;
+            }
+        }
+    }
+    println!("Watching {} for changes.", data_dir.display());
+
+    while let Some(event) = rx.recv().await {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        let mut next = Some(event);
+        while let Some(event) = next {
+            paths.extend(event?.paths);
+            next = tokio::time::timeout(DEBOUNCE, rx.recv())
+                .await
+                .ok()
+                .flatten();
+        }
+        paths.sort();
+        paths.dedup();
+
+        let start = Instant::now();
+        let updated = if data.is_single_file() {
+            indexer.clear()?;
+            add_all(data, indexer, pool)?;
+            "data.toml".to_string()
+        } else {
+            let mut updated = Vec::new();
+            for path in &paths {
+                match data.reload(path) {
+                    Some(Ok(diff)) => {
+                        apply(indexer, diff)?;
+                        updated.push(path.display().to_string());
+                    }
+                    Some(Err(e)) => report(e)?,
+                    None => {}
+                }
+            }
+            if updated.is_empty() {
+                continue;
+            }
+            updated.join(", ")
+        };
+        indexer.commit(commit_id)?;
+
+        println!("Re-indexed {} in {:?}", updated, start.elapsed());
+    }
 
     Ok(())
 }
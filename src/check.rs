@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{LibrarySql, data};
+
+/// How `check` reports what it finds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CheckFormat {
+    /// A line per cycle listing its offices.
+    #[default]
+    Text,
+    /// A Graphviz digraph with a cluster per cycle, holding only its offices
+    /// and the relations between them.
+    Dot,
+}
+
+/// A supervisor relation: `office` is `relation` `supervisor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub office: String,
+    pub relation: data::SupervisingRelation,
+    pub supervisor: String,
+}
+
+/// Look for cycles in the supervisor relations between offices. Fails if any
+/// are found, after reporting them in `format`.
+pub fn run(db: &Path, format: CheckFormat) -> Result<()> {
+    let conn = rusqlite::Connection::open(db)?;
+    let mut edges = Vec::new();
+    conn.get_office_supervisor_edges(|row| {
+        edges.push(Edge {
+            office: row.get(0)?,
+            relation: row.get(1)?,
+            supervisor: row.get(2)?,
+        });
+        Ok(())
+    })?;
+
+    let cycles = find_cycles(&edges);
+    if cycles.is_empty() {
+        eprintln!("No supervisor cycles found.");
+        return Ok(());
+    }
+
+    match format {
+        CheckFormat::Text => {
+            for cycle in &cycles {
+                let members: Vec<&str> = cycle.iter().map(String::as_str).collect();
+                println!("cycle: {}", members.join(", "));
+            }
+        }
+        CheckFormat::Dot => print!("{}", to_dot(&cycles, &edges)),
+    }
+
+    anyhow::bail!("found {} supervisor cycle(s)", cycles.len())
+}
+
+/// The sets of offices that can reach each other through supervisor
+/// relations, i.e. the strongly connected components with more than one
+/// office, or one office supervising itself.
+pub fn find_cycles(edges: &[Edge]) -> Vec<BTreeSet<String>> {
+    let mut graph: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for edge in edges {
+        graph
+            .entry(&edge.office)
+            .or_default()
+            .push(&edge.supervisor);
+        graph.entry(&edge.supervisor).or_default();
+    }
+
+    let mut tarjan = Tarjan {
+        graph: &graph,
+        index: BTreeMap::new(),
+        lowlink: BTreeMap::new(),
+        stack: Vec::new(),
+        on_stack: BTreeSet::new(),
+        components: Vec::new(),
+    };
+    for node in graph.keys() {
+        if !tarjan.index.contains_key(node) {
+            tarjan.visit(node);
+        }
+    }
+
+    tarjan
+        .components
+        .into_iter()
+        .filter(|component| {
+            component.len() > 1
+                || edges
+                    .iter()
+                    .any(|edge| edge.office == edge.supervisor && component.contains(&edge.office))
+        })
+        .collect()
+}
+
+struct Tarjan<'a> {
+    graph: &'a BTreeMap<&'a str, Vec<&'a str>>,
+    index: BTreeMap<&'a str, usize>,
+    lowlink: BTreeMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: BTreeSet<&'a str>,
+    components: Vec<BTreeSet<String>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: &'a str) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.lowlink.insert(node, index);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+
+        for &next in &self.graph[node] {
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                let low = self.lowlink[node].min(self.lowlink[next]);
+                self.lowlink.insert(node, low);
+            } else if self.on_stack.contains(next) {
+                let low = self.lowlink[node].min(self.index[next]);
+                self.lowlink.insert(node, low);
+            }
+        }
+
+        if self.lowlink[node] == self.index[node] {
+            let mut component = BTreeSet::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(member);
+                component.insert(member.to_string());
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+/// Render each cycle as a cluster holding its offices and the relations
+/// between them, labelled with [`data::SupervisingRelation::forward_label`].
+pub fn to_dot(cycles: &[BTreeSet<String>], edges: &[Edge]) -> String {
+    let mut dot = String::from("digraph cycles {\n");
+    for (i, cycle) in cycles.iter().enumerate() {
+        writeln!(dot, "  subgraph cluster_{} {{", i).unwrap();
+        writeln!(dot, "    label=\"cycle {}\";", i + 1).unwrap();
+        for office in cycle {
+            writeln!(dot, "    {:?};", office).unwrap();
+        }
+        for edge in edges {
+            if cycle.contains(&edge.office) && cycle.contains(&edge.supervisor) {
+                writeln!(
+                    dot,
+                    "    {:?} -> {:?} [label={:?}];",
+                    edge.office,
+                    edge.supervisor,
+                    edge.relation.forward_label()
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("  }\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(office: &str, relation: data::SupervisingRelation, supervisor: &str) -> Edge {
+        Edge {
+            office: office.to_string(),
+            relation,
+            supervisor: supervisor.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        use data::SupervisingRelation::*;
+        let edges = vec![
+            edge("a", ResponsibleTo, "b"),
+            edge("b", MemberOf, "c"),
+            edge("c", Head, "a"),
+            edge("c", MemberOf, "d"),
+            edge("e", Adviser, "e"),
+            edge("f", Minister, "d"),
+        ];
+
+        let cycles = find_cycles(&edges);
+        let cycles: Vec<Vec<&str>> = cycles
+            .iter()
+            .map(|cycle| cycle.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(cycles.len(), 2);
+        assert!(cycles.contains(&vec!["a", "b", "c"]));
+        assert!(cycles.contains(&vec!["e"]));
+
+        assert!(find_cycles(&edges[3..4]).is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        use data::SupervisingRelation::*;
+        let edges = vec![
+            edge("a", ResponsibleTo, "b"),
+            edge("b", MemberOf, "a"),
+            edge("b", MemberOf, "c"),
+        ];
+
+        let dot = to_dot(&find_cycles(&edges), &edges);
+        assert!(dot.contains("\"a\" -> \"b\" [label=\"Responsible to\"];"));
+        assert!(dot.contains("\"b\" -> \"a\" [label=\"Member of\"];"));
+        assert!(!dot.contains("\"c\""));
+    }
+}
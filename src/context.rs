@@ -3,6 +3,7 @@ use std::collections::BTreeMap;
 use serde_derive::Serialize;
 
 use crate::data::{self};
+use crate::dto;
 use crate::repo::Hash;
 
 #[derive(Serialize, Debug)]
@@ -10,6 +11,7 @@ pub struct Quondam {
     pub person: Person,
     pub start: String,
     pub end: String,
+    pub acting: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -17,13 +19,27 @@ pub struct TenureDetails {
     pub office: Office,
     pub start: String,
     pub end: String,
+    pub acting: bool,
+    pub preceded_by: Option<Person>,
+    pub succeeded_by: Option<Person>,
 }
 
+/// A tenure in an office, linked to the tenures just before and after it.
+/// Either link is missing at the ends of the chain.
 #[derive(Serialize, Debug)]
+pub struct Succession {
+    pub person: Person,
+    pub end: Option<String>,
+    pub preceded_by: Option<Person>,
+    pub succeeded_by: Option<Person>,
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct Person {
     pub id: String,
     pub name: String,
     pub start: Option<String>,
+    pub acting: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -37,9 +53,31 @@ pub struct OfficeDetails {
     pub office: Office,
     pub photo: Option<data::Photo>,
     pub contacts: Option<BTreeMap<data::ContactType, String>>,
-    pub supervisors: Option<BTreeMap<data::SupervisingRelation, Officer>>,
+    pub supervisors: Option<BTreeMap<data::SupervisingRelation, Vec<Officer>>>,
     pub subordinates: Option<BTreeMap<data::SupervisingRelation, Vec<Officer>>>,
     pub start: Option<String>,
+    pub acting: bool,
+    pub preceded_by: Option<Person>,
+}
+
+/// A line naming the person's primary current office, the one held longest
+/// with substantive tenures preferred over acting ones, e.g. "Minister of
+/// Finance" or "Chief Secretary (acting)".
+pub fn current_role(offices: &[dto::Office]) -> Option<String> {
+    let office = offices.iter().min_by_key(|o| {
+        (
+            o.acting,
+            o.start.is_none(),
+            o.start.as_deref(),
+            o.name.as_str(),
+        )
+    })?;
+
+    Some(if office.acting {
+        format!("{} (acting)", office.name)
+    } else {
+        office.name.clone()
+    })
 }
 
 #[derive(Serialize, Debug)]
@@ -65,3 +103,37 @@ pub struct Metadata {
 pub struct Maintenance {
     pub incomplete: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn office(name: &str, start: Option<&str>, acting: bool) -> dto::Office {
+        dto::Office {
+            id: name.to_lowercase(),
+            name: name.to_string(),
+            photo: None,
+            contacts: None,
+            start: start.map(str::to_string),
+            acting,
+        }
+    }
+
+    #[test]
+    fn test_current_role() {
+        assert_eq!(current_role(&[]), None);
+
+        let offices = [
+            office("Adviser", None, false),
+            office("Minister", Some("2020-01-01"), false),
+            office("Secretary", Some("2018-01-01"), true),
+            office("Member", Some("2021-01-01"), false),
+        ];
+        assert_eq!(current_role(&offices).as_deref(), Some("Minister"));
+        assert_eq!(current_role(&offices[..1]).as_deref(), Some("Adviser"));
+        assert_eq!(
+            current_role(&offices[2..3]).as_deref(),
+            Some("Secretary (acting)")
+        );
+    }
+}
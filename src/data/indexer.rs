@@ -13,12 +13,35 @@ use thiserror::Error;
 use crate::data::{Office, Person};
 
 const COMMIT_ID_FILE: &str = "commit_id";
+const SCHEMA_VERSION_FILE: &str = "schema_version";
+
+/// The version of the fields [`Indexer`] writes. Bump it whenever they change
+/// so that an index written by another version is not mistaken for a current
+/// one. Indexes written before the version was recorded count as version 0.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The version of the index under `output_dir`, as recorded on its last
+/// commit.
+pub fn schema_version(output_dir: &Path) -> Result<u32, std::io::Error> {
+    let file = output_dir.join("index").join(SCHEMA_VERSION_FILE);
+    if !file.try_exists()? {
+        return Ok(0);
+    }
+
+    fs::read_to_string(file)?.trim().parse().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "index schema version is not a number",
+        )
+    })
+}
 
 pub struct Indexer {
     path: PathBuf,
     type_field: Field,
     id_field: Field,
     name_field: Field,
+    honorific_field: Field,
     writer: IndexWriter,
 }
 
@@ -41,16 +64,33 @@ impl Indexer {
     pub fn open(output_dir: &Path) -> Result<Self, IndexerError> {
         let mut schema_builder = Schema::builder();
         let id_field = schema_builder.add_text_field("id", STRING | STORED);
-        let name_field = schema_builder.add_text_field("name", TEXT);
+        // Stored so that searches can highlight the matched terms.
+        let name_field = schema_builder.add_text_field("name", TEXT | STORED);
         let type_field = schema_builder.add_text_field("type", STRING | STORED);
+        // Shown with the name, but not searched.
+        let honorific_field = schema_builder.add_text_field("honorific", STORED);
         let schema = schema_builder.build();
 
         let path = output_dir.join("index");
+        let version = schema_version(output_dir).unwrap_or_default();
+        if version != 0 && version != SCHEMA_VERSION {
+            // Written by another version. Starting over drops the commit id
+            // too, so everything is indexed again.
+            fs::remove_dir_all(&path)?;
+        }
         if !path.exists() {
             fs::create_dir_all(&path)?;
         }
         let directory = tantivy::directory::MmapDirectory::open(&path)?;
-        let index = Index::open_or_create(directory, schema)?;
+        let index = match Index::open_or_create(directory, schema.clone()) {
+            // Written with other fields before the version was recorded.
+            Err(tantivy::TantivyError::SchemaError(_)) => {
+                fs::remove_dir_all(&path)?;
+                fs::create_dir_all(&path)?;
+                Index::create_in_dir(&path, schema)?
+            }
+            result => result?,
+        };
         let writer: IndexWriter = index.writer(50_000_000)?; // 50MB heap
 
         Ok(Indexer {
@@ -58,17 +98,22 @@ impl Indexer {
             type_field,
             id_field,
             name_field,
+            honorific_field,
             writer,
         })
     }
 
     pub fn add_person(&mut self, id: &str, person: Person) -> Result<(), IndexerError> {
         self.delete(id)?;
-        self.writer.add_document(doc!(
+        let mut document = doc!(
             self.id_field => id,
             self.name_field => person.name,
             self.type_field => "person",
-        ))?;
+        );
+        if let Some(honorific) = person.honorific {
+            document.add_text(self.honorific_field, honorific);
+        }
+        self.writer.add_document(document)?;
 
         Ok(())
     }
@@ -91,8 +136,19 @@ impl Indexer {
         Ok(())
     }
 
+    /// Remove every document, for rebuilding the index from scratch.
+    pub fn clear(&mut self) -> Result<(), IndexerError> {
+        self.writer.delete_all_documents()?;
+
+        Ok(())
+    }
+
     pub fn commit(&mut self, id: &str) -> Result<(), IndexerError> {
         self.writer.commit()?;
+        fs::write(
+            self.path.join(SCHEMA_VERSION_FILE),
+            SCHEMA_VERSION.to_string(),
+        )?;
         fs::write(self.path.join(COMMIT_ID_FILE), id)?;
 
         Ok(())
@@ -109,3 +165,43 @@ impl Indexer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_replaces_index_with_other_schema() {
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        fs::create_dir_all(&path).unwrap();
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("id", STRING | STORED);
+        schema_builder.add_text_field("name", TEXT);
+        Index::create_in_dir(&path, schema_builder.build()).unwrap();
+        fs::write(path.join(COMMIT_ID_FILE), "old").unwrap();
+
+        let indexer = Indexer::open(tmp_dir.path()).unwrap();
+        assert_eq!(indexer.commit_id().unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_replaces_index_with_other_version() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        indexer.commit("old").unwrap();
+        drop(indexer);
+        assert_eq!(schema_version(tmp_dir.path()).unwrap(), SCHEMA_VERSION);
+
+        let indexer = Indexer::open(tmp_dir.path()).unwrap();
+        assert_eq!(indexer.commit_id().unwrap().as_deref(), Some("old"));
+        drop(indexer);
+
+        let path = tmp_dir.path().join("index").join(SCHEMA_VERSION_FILE);
+        fs::write(&path, (SCHEMA_VERSION + 1).to_string()).unwrap();
+        let indexer = Indexer::open(tmp_dir.path()).unwrap();
+        assert_eq!(indexer.commit_id().unwrap(), None);
+        assert_eq!(schema_version(tmp_dir.path()).unwrap(), 0);
+    }
+}
@@ -5,10 +5,12 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use chrono::NaiveDate;
 use garde::Validate;
 use jj_lib::object_id::ObjectId;
 use jj_lib::repo::Repo;
 use miette::{Diagnostic, LabeledSpan, NamedSource, SourceSpan};
+use rayon::prelude::*;
 use rusqlite::{ToSql, types::FromSql};
 use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
@@ -16,12 +18,16 @@ use strum_macros::{EnumString, VariantArray};
 use thiserror::Error;
 
 pub mod indexer;
+pub mod remote;
 pub mod searcher;
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Validate)]
 pub struct Person {
     #[garde(length(max = 64))]
     pub name: String,
+    /// Shown before the name, e.g. `Dr.` or `Hon'ble`.
+    #[garde(length(max = 32))]
+    pub honorific: Option<String>,
     #[garde(dive)]
     pub photo: Option<Photo>,
     #[garde(skip)]
@@ -30,13 +36,75 @@ pub struct Person {
     pub tenures: Option<Vec<Tenure>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq, Validate)]
+#[derive(Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq, Validate)]
 #[serde(deny_unknown_fields)]
 pub struct Photo {
     #[garde(url)]
     pub url: String,
     #[garde(length(max = 256))]
     pub attribution: Option<String>,
+    /// The license the image is published under, e.g. `CC BY-SA 4.0`.
+    #[garde(length(max = 64))]
+    pub license: Option<String>,
+    /// The page describing the image, as opposed to the image itself.
+    #[garde(url)]
+    pub source_url: Option<String>,
+}
+
+/// A photo without a license or source URL is written with only `url` and
+/// `attribution`, which is all a photo record held before the others were
+/// added. Stored records therefore keep their encoding and hashes. Formats
+/// like TOML and JSON leave out whichever of the two is not set.
+impl serde::Serialize for Photo {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let human_readable = serializer.is_human_readable();
+        let details = self.license.is_some() || self.source_url.is_some();
+        let mut state = serializer.serialize_struct("Photo", if details { 4 } else { 2 })?;
+        state.serialize_field("url", &self.url)?;
+        state.serialize_field("attribution", &self.attribution)?;
+        if human_readable {
+            for (name, value) in [("license", &self.license), ("source_url", &self.source_url)] {
+                match value {
+                    Some(value) => state.serialize_field(name, value)?,
+                    None => state.skip_field(name)?,
+                }
+            }
+        } else if details {
+            state.serialize_field("license", &self.license)?;
+            state.serialize_field("source_url", &self.source_url)?;
+        }
+        state.end()
+    }
+}
+
+/// The offices supervising an office under one relation, written as a single
+/// id when there is only one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum Supervisors {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Supervisors {
+    pub fn ids(&self) -> &[String] {
+        match self {
+            Supervisors::One(id) => std::slice::from_ref(id),
+            Supervisors::Many(ids) => ids,
+        }
+    }
+}
+
+impl From<Vec<String>> for Supervisors {
+    fn from(mut ids: Vec<String>) -> Self {
+        if ids.len() == 1 {
+            Supervisors::One(ids.remove(0))
+        } else {
+            Supervisors::Many(ids)
+        }
+    }
 }
 
 #[derive(
@@ -66,6 +134,10 @@ pub enum ContactType {
     Facebook,
     Instagram,
     Wikidata,
+    Telegram,
+    #[serde(rename = "linkedin")]
+    #[strum(serialize = "linkedin")]
+    LinkedIn,
 }
 
 impl ContactType {
@@ -81,22 +153,41 @@ impl ContactType {
             ContactType::Facebook => "facebook",
             ContactType::Instagram => "instagram",
             ContactType::Wikidata => "wikidata",
+            ContactType::Telegram => "telegram",
+            ContactType::LinkedIn => "linkedin",
         }
     }
 
     pub fn to_link(&self, s: &str) -> String {
-        match *self {
-            ContactType::Address => String::from(""),
-            ContactType::Phone => format!("tel:{}", s),
-            ContactType::Email => format!("mailto:{}", s),
-            ContactType::Website => s.to_string(),
-            ContactType::Wikipedia => format!("https://en.wikipedia.org/wiki/{}", s),
-            ContactType::X => format!("https://x.com/{}", s),
-            ContactType::Youtube => format!("https://www.youtube.com/{}", s),
-            ContactType::Facebook => format!("https://www.facebook.com/{}", s),
-            ContactType::Instagram => format!("https://www.instagram.com/{}", s),
-            ContactType::Wikidata => format!("https://www.wikidata.org/wiki/{}", s),
-        }
+        self.link(crate::theme::config(), s)
+    }
+
+    /// The link to the contact `s` of this type, from the templates in
+    /// `config`.
+    pub fn link(&self, config: &crate::config::Config, s: &str) -> String {
+        let links = &config.links;
+        let template = match self {
+            ContactType::Address => links.address,
+            ContactType::Phone => links.phone,
+            ContactType::Email => links.email,
+            ContactType::Website => links.website,
+            ContactType::Wikipedia => links.wikipedia,
+            ContactType::X => links.x,
+            ContactType::Youtube => links.youtube,
+            ContactType::Facebook => links.facebook,
+            ContactType::Instagram => links.instagram,
+            ContactType::Wikidata => links.wikidata,
+            ContactType::Telegram => links.telegram,
+            ContactType::LinkedIn => links.linkedin,
+        };
+        let value = match self {
+            ContactType::Address => urlencoding::encode(s),
+            _ => s.into(),
+        };
+        // The value last, so that one holding `{lang}` is kept as it is.
+        template
+            .replace("{lang}", config.lang)
+            .replace("{value}", &value)
     }
 
     pub fn is_independent(&self) -> bool {
@@ -111,8 +202,65 @@ impl ContactType {
             ContactType::Facebook => false,
             ContactType::Instagram => false,
             ContactType::Wikidata => true,
+            ContactType::Telegram => false,
+            ContactType::LinkedIn => false,
         }
     }
+
+    /// Why `value` is not a contact of this type, if it is not. Addresses
+    /// and the other free-form types need only not be blank.
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        if value.trim().is_empty() {
+            return Err("must not be blank".to_string());
+        }
+        let valid = match self {
+            ContactType::Email => value.split_once('@').is_some_and(|(user, domain)| {
+                !user.is_empty()
+                    && !domain.contains('@')
+                    && domain.split('.').count() > 1
+                    && domain.split('.').all(|part| !part.is_empty())
+                    && !value.contains(char::is_whitespace)
+            }),
+            ContactType::Website => ["http://", "https://"].iter().any(|scheme| {
+                value.strip_prefix(scheme).is_some_and(|rest| {
+                    !rest.starts_with(['/', '.'])
+                        && !rest.is_empty()
+                        && !rest.contains(char::is_whitespace)
+                })
+            }),
+            // An article title, as the end of its url.
+            ContactType::Wikipedia => {
+                !value.contains("://") && !value.contains(['#', '<', '>', '[', ']', '|', '{', '}'])
+            }
+            ContactType::Wikidata => value
+                .strip_prefix('Q')
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())),
+            ContactType::X | ContactType::Instagram => value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.'),
+            ContactType::Telegram => value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            ContactType::LinkedIn => value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-'),
+            _ => true,
+        };
+        if !valid {
+            let expected = match self {
+                ContactType::Email => "an address like name@example.org",
+                ContactType::Website => "an http:// or https:// url",
+                ContactType::Wikipedia => "the title of an article",
+                ContactType::Wikidata => "an item id like Q42",
+                ContactType::Telegram => "a username of letters, digits and `_`",
+                ContactType::LinkedIn => "a profile name of letters, digits and `-`",
+                _ => "a handle of letters, digits, `_` and `.`",
+            };
+            return Err(format!("`{}` is not {}", value, expected));
+        }
+
+        Ok(())
+    }
 }
 
 impl Display for ContactType {
@@ -129,6 +277,9 @@ impl ToSql for ContactType {
 
 impl FromSql for ContactType {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        // Types are only ever added, so databases written before any of them
+        // still read. A database holding a newer type is refused by older
+        // binaries, here and in the records, rather than read without it.
         match value {
             rusqlite::types::ValueRef::Text(s) => match s {
                 b"address" => Ok(ContactType::Address),
@@ -141,6 +292,8 @@ impl FromSql for ContactType {
                 b"facebook" => Ok(ContactType::Facebook),
                 b"instagram" => Ok(ContactType::Instagram),
                 b"wikidata" => Ok(ContactType::Wikidata),
+                b"telegram" => Ok(ContactType::Telegram),
+                b"linkedin" => Ok(ContactType::LinkedIn),
                 _ => Err(rusqlite::types::FromSqlError::Other(
                     format!("Unrecognized ContactType: {}", String::from_utf8_lossy(s)).into(),
                 )),
@@ -159,6 +312,10 @@ pub struct Tenure {
     pub start: Option<String>,
     #[garde(ascii, length(max = 10))]
     pub end: Option<String>,
+    /// Held in an acting capacity rather than by appointment.
+    #[garde(skip)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub acting: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Validate)]
@@ -170,7 +327,14 @@ pub struct Office {
     #[garde(skip)]
     pub contacts: Option<BTreeMap<ContactType, String>>,
     #[garde(skip)]
-    pub supervisors: Option<BTreeMap<SupervisingRelation, String>>,
+    pub supervisors: Option<BTreeMap<SupervisingRelation, Supervisors>>,
+    #[garde(skip)]
+    pub establishment: Option<NaiveDate>,
+    #[garde(skip)]
+    pub abolition: Option<NaiveDate>,
+    /// The region the office has authority over.
+    #[garde(length(max = 128))]
+    pub jurisdiction: Option<String>,
 }
 
 #[derive(
@@ -183,6 +347,7 @@ pub struct Office {
     Ord,
     Hash,
     Clone,
+    JsonSchema,
     VariantArray,
     EnumString,
 )]
@@ -323,6 +488,21 @@ impl Diagnostic for OfficeValidationError {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown field(s) {} in '{id}'", fields.join(", "))]
+#[diagnostic(
+    code(tudgoi::validation::unknown_field),
+    help("These fields are ignored when not building with --strict")
+)]
+pub struct UnknownFieldError {
+    pub id: String,
+    pub fields: Vec<String>,
+    #[source_code]
+    pub src: NamedSource<String>,
+    #[label(collection)]
+    pub labels: Vec<LabeledSpan>,
+}
+
 #[derive(Error, Debug, Diagnostic)]
 pub enum DataError {
     #[error("io error: {0}")]
@@ -333,11 +513,23 @@ pub enum DataError {
     #[diagnostic(code(tudgoi::toml))]
     Toml(#[from] toml::de::Error),
 
+    #[error("Error deserializing JSON: {0}")]
+    #[diagnostic(code(tudgoi::json))]
+    Json(#[from] serde_json::Error),
+
+    #[error("Error parsing YAML: {0}")]
+    #[diagnostic(code(tudgoi::yaml))]
+    Yaml(#[from] yaml_rust2::ScanError),
+
+    #[error("Both {0:?} and {1:?} hold the same entity")]
+    #[diagnostic(code(tudgoi::fs::duplicate))]
+    DuplicateFile(PathBuf, PathBuf),
+
     #[error("Unexpected directory: {0:?}")]
     #[diagnostic(code(tudgoi::fs::unexpected_dir))]
     UnexpectedDir(PathBuf),
 
-    #[error("Unexpected extension for: {0:?}. Should be `toml`")]
+    #[error("Unexpected extension for: {0:?}. Should be `toml`, `json` or `yaml`")]
     #[diagnostic(code(tudgoi::fs::extension))]
     FileExtension(PathBuf),
 
@@ -349,6 +541,10 @@ pub enum DataError {
     #[diagnostic(code(tudgoi::fs::os_str))]
     OsStr(PathBuf),
 
+    #[error("Entry {1} of `[[{0}]]` in data.toml has no string `id`")]
+    #[diagnostic(code(tudgoi::data::id))]
+    MissingId(&'static str, usize),
+
     #[error("Could not load Jujutsu config: {0}")]
     #[diagnostic(code(tudgoi::jj::config))]
     Config(#[from] jj_lib::config::ConfigGetError),
@@ -365,24 +561,36 @@ pub enum DataError {
     #[diagnostic(code(tudgoi::jj::commit_id))]
     CommitId(PathBuf),
 
+    #[error("Invalid source {0:?}. Should be of the format `<url>@<rev>`")]
+    #[diagnostic(code(tudgoi::data::source))]
+    Source(String),
+
+    #[error("`git {0}` failed: {1}")]
+    #[diagnostic(code(tudgoi::data::git))]
+    Git(String, String),
+
     #[error("Jujutsu error: {0}")]
     #[diagnostic(code(tudgoi::jj::other))]
     Jj(String),
 
     #[error(transparent)]
     #[diagnostic(transparent)]
-    PersonValidation(#[from] PersonValidationError),
+    PersonValidation(#[from] Box<PersonValidationError>),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    OfficeValidation(#[from] Box<OfficeValidationError>),
 
     #[error(transparent)]
     #[diagnostic(transparent)]
-    OfficeValidation(#[from] OfficeValidationError),
+    UnknownField(#[from] Box<UnknownFieldError>),
 }
 
 #[derive(Debug, Clone)]
 pub enum DataDiff {
     Added(String, DataItem),
     Modified(String, DataItem),
-    Deleted(String, DataItemType),
+    Deleted(String),
 }
 
 #[derive(Debug, Clone)]
@@ -397,17 +605,135 @@ pub enum DataItemType {
     Office,
 }
 
+/// Name of the file holding all entities of a small dataset, as `[[person]]`
+/// and `[[office]]` arrays of tables with an `id` each.
+const DATA_FILE: &str = "data.toml";
+
+/// Name of the file `export` writes its [`Manifest`] to.
+pub const MANIFEST_FILE: &str = "version.toml";
+
+/// Version of the layout `export` writes and `import` reads. Bump it when an
+/// older binary would lose data importing a newer export.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// What an export says about itself in `version.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// The [`FORMAT_VERSION`] of the binary that wrote the export.
+    pub format: u32,
+}
+
+/// Something stored in the data directory, one file per entity under
+/// `DIR` or one `[[DIR]]` table per entity in `data.toml`.
+trait Item: serde::de::DeserializeOwned + Validate<Context = ()> + Send {
+    const DIR: &'static str;
+
+    fn invalid(
+        id: String,
+        src: NamedSource<String>,
+        labels: Vec<LabeledSpan>,
+        source: garde::Report,
+    ) -> DataError;
+}
+
+impl Item for Person {
+    const DIR: &'static str = "person";
+
+    fn invalid(
+        id: String,
+        src: NamedSource<String>,
+        labels: Vec<LabeledSpan>,
+        source: garde::Report,
+    ) -> DataError {
+        DataError::PersonValidation(Box::new(PersonValidationError {
+            id,
+            src,
+            labels,
+            source,
+        }))
+    }
+}
+
+impl Item for Office {
+    const DIR: &'static str = "office";
+
+    fn invalid(
+        id: String,
+        src: NamedSource<String>,
+        labels: Vec<LabeledSpan>,
+        source: garde::Report,
+    ) -> DataError {
+        DataError::OfficeValidation(Box::new(OfficeValidationError {
+            id,
+            src,
+            labels,
+            source,
+        }))
+    }
+}
+
+fn jj_settings() -> Result<jj_lib::settings::UserSettings, DataError> {
+    let stacked = jj_lib::config::StackedConfig::with_defaults();
+    Ok(jj_lib::settings::UserSettings::from_config(stacked)?)
+}
+
 pub struct Data {
     dir: PathBuf,
+    single_file: bool,
+    strict: bool,
 }
 
 impl Data {
+    /// Open the data directory. If it contains `data.toml`, entities are read
+    /// from there; otherwise from the files in `person/` and `office/`.
     pub fn open(base_dir: &Path) -> Result<Self, DataError> {
         Ok(Self {
             dir: base_dir.to_path_buf(),
+            single_file: base_dir.join(DATA_FILE).is_file(),
+            strict: false,
         })
     }
 
+    /// The manifest of the export this directory holds, if it has one. Data
+    /// edited by hand does not.
+    pub fn manifest(&self) -> Result<Option<Manifest>, DataError> {
+        let path = self.dir.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    /// The directories besides `person/` and `office/`, which hold entity
+    /// types this binary does not know and so does not read. Hidden ones,
+    /// like `.jj`, are left out.
+    pub fn unknown_dirs(&self) -> Result<Vec<String>, DataError> {
+        if self.single_file {
+            return Ok(Vec::new());
+        }
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir()
+                && !name.starts_with('.')
+                && name != Person::DIR
+                && name != Office::DIR
+            {
+                dirs.push(name);
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+
+    /// Reject entities with fields that `Person` or `Office` do not have,
+    /// instead of ignoring them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     fn jj_repo(
         &self,
     ) -> Result<
@@ -421,8 +747,7 @@ impl Data {
         use jj_lib::local_working_copy::LocalWorkingCopyFactory;
         use jj_lib::workspace::WorkingCopyFactories;
 
-        let stacked = jj_lib::config::StackedConfig::with_defaults();
-        let settings = jj_lib::settings::UserSettings::from_config(stacked)?;
+        let settings = jj_settings()?;
 
         let store_factories = jj_lib::repo::StoreFactories::default();
         let mut wc_factories = WorkingCopyFactories::new();
@@ -484,11 +809,31 @@ impl Data {
         while let Some(entry) = diff_stream.next().await {
             let path = entry.path;
             let path_str = path.as_internal_file_string();
-            let (item_type, id) = if path_str.starts_with("person/") && path_str.ends_with(".toml") {
-                let id = &path_str[7..path_str.len() - 5];
+            if path_str == DATA_FILE {
+                let diff = entry
+                    .values
+                    .map_err(|e| DataError::Jj(format!("Diff error: {e}")))?;
+                let before = match diff.before.as_resolved().as_ref().and_then(|v| v.as_ref()) {
+                    Some(value) => read_value(&path, value, repo.as_ref()).await?,
+                    None => None,
+                };
+                let after = match diff.after.as_resolved().as_ref().and_then(|v| v.as_ref()) {
+                    Some(value) => read_value(&path, value, repo.as_ref()).await?,
+                    None => None,
+                };
+                diffs.extend(data_file_diff(
+                    before.as_deref().unwrap_or_default(),
+                    after.as_deref().unwrap_or_default(),
+                    self.strict,
+                )?);
+                continue;
+            }
+            let Some((stem, format)) = FileFormat::split(path_str) else {
+                continue;
+            };
+            let (item_type, id) = if let Some(id) = stem.strip_prefix("person/") {
                 (DataItemType::Person, id.to_string())
-            } else if path_str.starts_with("office/") && path_str.ends_with(".toml") {
-                let id = &path_str[7..path_str.len() - 5];
+            } else if let Some(id) = stem.strip_prefix("office/") {
                 (DataItemType::Office, id.to_string())
             } else {
                 continue;
@@ -501,21 +846,35 @@ impl Data {
             if let Some(to_value) = diff.after.as_resolved().as_ref().and_then(|v| v.as_ref()) {
                 if diff.before.is_absent() {
                     if let Some(item) = self
-                        .load_item_from_value(&path, to_value, item_type, &id, repo.as_ref())
+                        .load_item_from_value(
+                            &path,
+                            to_value,
+                            item_type,
+                            &id,
+                            format,
+                            repo.as_ref(),
+                        )
                         .await?
                     {
                         diffs.push(DataDiff::Added(id.to_string(), item));
                     }
                 } else {
                     if let Some(item) = self
-                        .load_item_from_value(&path, to_value, item_type, &id, repo.as_ref())
+                        .load_item_from_value(
+                            &path,
+                            to_value,
+                            item_type,
+                            &id,
+                            format,
+                            repo.as_ref(),
+                        )
                         .await?
                     {
                         diffs.push(DataDiff::Modified(id.to_string(), item));
                     }
                 }
             } else if diff.after.is_absent() && diff.before.is_present() {
-                diffs.push(DataDiff::Deleted(id.to_string(), item_type));
+                diffs.push(DataDiff::Deleted(id.to_string()));
             }
         }
 
@@ -528,96 +887,420 @@ impl Data {
         value: &jj_lib::backend::TreeValue,
         item_type: DataItemType,
         id: &str,
+        format: FileFormat,
         repo: &jj_lib::repo::ReadonlyRepo,
     ) -> Result<Option<DataItem>, DataError> {
-        use tokio::io::AsyncReadExt;
-
-        let file_id = match value {
-            jj_lib::backend::TreeValue::File { id, .. } => id,
-            _ => return Ok(None),
+        let Some(content) = read_value(path, value, repo).await? else {
+            return Ok(None);
         };
 
-        let mut reader = repo
-            .store()
-            .read_file(path, file_id)
-            .await
-            .map_err(|e| DataError::Jj(format!("Failed to read file: {e}")))?;
-        let mut content = String::new();
-        reader.read_to_string(&mut content).await?;
-
         match item_type {
             DataItemType::Person => {
-                let person: Person = toml::from_str(&content)?;
-                if let Err(e) = person.validate() {
-                    let labels = to_labels(&content, &e);
-                    return Err(DataError::PersonValidation(PersonValidationError {
-                        id: id.to_string(),
-                        src: NamedSource::new(format!("{}.toml", id), content),
-                        labels,
-                        source: e,
-                    }));
-                }
+                let (_, person) =
+                    parse_file::<Person>(id.to_string(), format, content, self.strict)?;
                 Ok(Some(DataItem::Person(person)))
             }
             DataItemType::Office => {
-                let office: Office = toml::from_str(&content)?;
-                if let Err(e) = office.validate() {
-                    let labels = to_labels(&content, &e);
-                    return Err(DataError::OfficeValidation(OfficeValidationError {
-                        id: id.to_string(),
-                        src: NamedSource::new(format!("{}.toml", id), content),
-                        labels,
-                        source: e,
-                    }));
-                }
+                let (_, office) =
+                    parse_file::<Office>(id.to_string(), format, content, self.strict)?;
                 Ok(Some(DataItem::Office(office)))
             }
         }
     }
 
+    /// Whether the entities are read from `data.toml`.
+    pub fn is_single_file(&self) -> bool {
+        self.single_file
+    }
+
+    /// Read the entity file at `path` again after it changed on disk. Returns
+    /// `None` if the path does not hold an entity, and `Deleted` if the file is
+    /// gone.
+    pub fn reload(&self, path: &Path) -> Option<Result<DataDiff, DataError>> {
+        let format = FileFormat::from_extension(path.extension()?.to_str()?)?;
+        let id = path.file_stem()?.to_str()?.to_string();
+        let dir = path.parent()?.file_name()?;
+
+        if dir != Person::DIR && dir != Office::DIR {
+            return None;
+        }
+        if !path.exists() {
+            return Some(Ok(DataDiff::Deleted(id)));
+        }
+
+        let diff = fs::read_to_string(path)
+            .map_err(DataError::from)
+            .and_then(|content| {
+                if dir == Person::DIR {
+                    let (id, person) = parse_file::<Person>(id, format, content, self.strict)?;
+                    Ok(DataDiff::Modified(id, DataItem::Person(person)))
+                } else {
+                    let (id, office) = parse_file::<Office>(id, format, content, self.strict)?;
+                    Ok(DataDiff::Modified(id, DataItem::Office(office)))
+                }
+            });
+        Some(diff)
+    }
+
     pub fn persons(&self) -> impl Iterator<Item = Result<(String, Person), DataError>> {
-        let person_dir = self.dir.join("person");
-        toml_content_in_dir(person_dir).map(|result| {
-            let (id, content) = result?;
-            let person: Person = toml::from_str(&content)?;
-            if let Err(e) = person.validate() {
-                let labels = to_labels(&content, &e);
-                return Err(DataError::PersonValidation(PersonValidationError {
-                    id: id.clone(),
-                    src: NamedSource::new(format!("{}.toml", id), content),
-                    labels,
-                    source: e,
-                }));
+        self.items::<Person>().into_iter()
+    }
+
+    pub fn offices(&self) -> impl Iterator<Item = Result<(String, Office), DataError>> {
+        self.items::<Office>().into_iter()
+    }
+
+    /// Parse and validate every entity of type `T` on the current rayon
+    /// pool. The results are ordered by id so that validation errors are
+    /// reported in the same order on every run.
+    fn items<T: Item>(&self) -> Vec<Result<(String, T), DataError>> {
+        if self.single_file {
+            let path = self.dir.join(DATA_FILE);
+            match fs::read_to_string(&path) {
+                Ok(content) => parse_data_file::<T>(&content, self.strict),
+                Err(e) => vec![Err(e.into())],
+            }
+        } else {
+            let mut files: Vec<_> = content_in_dir(self.dir.join(T::DIR)).collect();
+            files.sort_by_cached_key(|result| match result {
+                Ok(file) => file.id.clone(),
+                Err(e) => e.to_string(),
+            });
+            // An entity written in two formats would otherwise be read twice.
+            for i in 1..files.len() {
+                if let (Ok(first), Ok(second)) = (&files[i - 1], &files[i])
+                    && first.id == second.id
+                {
+                    files[i] = Err(DataError::DuplicateFile(
+                        first.path.clone(),
+                        second.path.clone(),
+                    ));
+                }
             }
-            Ok((id, person))
+            files
+                .into_par_iter()
+                .map(|result| {
+                    let file = result?;
+                    parse_file(file.id, file.format, file.content, self.strict)
+                })
+                .collect()
+        }
+    }
+}
+
+async fn read_value(
+    path: &jj_lib::repo_path::RepoPath,
+    value: &jj_lib::backend::TreeValue,
+    repo: &jj_lib::repo::ReadonlyRepo,
+) -> Result<Option<String>, DataError> {
+    use tokio::io::AsyncReadExt;
+
+    let file_id = match value {
+        jj_lib::backend::TreeValue::File { id, .. } => id,
+        _ => return Ok(None),
+    };
+
+    let mut reader = repo
+        .store()
+        .read_file(path, file_id)
+        .await
+        .map_err(|e| DataError::Jj(format!("Failed to read file: {e}")))?;
+    let mut content = String::new();
+    reader.read_to_string(&mut content).await?;
+
+    Ok(Some(content))
+}
+
+/// Deserialize `T`, collecting the paths of the fields it does not have.
+fn deserialize_item<'de, T, D>(deserializer: D) -> Result<(T, Vec<String>), D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    let mut unknown = Vec::new();
+    let item = serde_ignored::deserialize(deserializer, |path| unknown.push(path.to_string()))?;
+    Ok((item, unknown))
+}
+
+/// Label the unknown `fields` at their keys in `content`, which starts
+/// `offset` bytes into the source the labels will be shown against.
+fn unknown_field_labels(content: &str, offset: usize, fields: &[String]) -> Vec<LabeledSpan> {
+    fields
+        .iter()
+        .map(|field| {
+            let key = field.rsplit('.').next().unwrap_or(field);
+            let pos = content.find(key).unwrap_or(0);
+            LabeledSpan::new_with_span(
+                Some("unknown field".to_string()),
+                SourceSpan::new((offset + pos).into(), key.len()),
+            )
         })
+        .collect()
+}
+
+/// The formats an entity file may be written in. TOML is the one the tool
+/// writes; the others are read so that existing data need not be converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl FileFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "toml" => Some(FileFormat::Toml),
+            "json" => Some(FileFormat::Json),
+            "yaml" => Some(FileFormat::Yaml),
+            _ => None,
+        }
     }
 
-    pub fn offices(&self) -> impl Iterator<Item = Result<(String, Office), DataError>> {
-        let office_dir = self.dir.join("office");
-        toml_content_in_dir(office_dir).map(|result| {
-            let (id, content) = result?;
-            let office: Office = toml::from_str(&content)?;
-            if let Err(e) = office.validate() {
-                let labels = to_labels(&content, &e);
-                return Err(DataError::OfficeValidation(OfficeValidationError {
-                    id: id.clone(),
-                    src: NamedSource::new(format!("{}.toml", id), content),
+    /// Split `path` into the part before the extension and the format the
+    /// extension names.
+    fn split(path: &str) -> Option<(&str, Self)> {
+        let (stem, extension) = path.rsplit_once('.')?;
+        Some((stem, Self::from_extension(extension)?))
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Toml => "toml",
+            FileFormat::Json => "json",
+            FileFormat::Yaml => "yaml",
+        }
+    }
+}
+
+/// Convert a YAML document to JSON so that it can be deserialized with
+/// `serde_json`. Keys that are not strings are written out as YAML scalars.
+fn yaml_to_json(yaml: yaml_rust2::Yaml) -> serde_json::Value {
+    use serde_json::Value;
+    use yaml_rust2::Yaml;
+
+    match yaml {
+        Yaml::Real(s) => s
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::String(s), Value::Number),
+        Yaml::Integer(i) => Value::from(i),
+        Yaml::String(s) => Value::String(s),
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Array(items) => Value::Array(items.into_iter().map(yaml_to_json).collect()),
+        Yaml::Hash(hash) => Value::Object(
+            hash.into_iter()
+                .map(|(key, value)| {
+                    let key = match yaml_to_json(key) {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    (key, yaml_to_json(value))
+                })
+                .collect(),
+        ),
+        Yaml::Null | Yaml::BadValue | Yaml::Alias(_) => Value::Null,
+    }
+}
+
+/// Deserialize `T` from `content` written in `format`, collecting the paths
+/// of the fields it does not have.
+fn deserialize_file<T: Item>(
+    format: FileFormat,
+    content: &str,
+) -> Result<(T, Vec<String>), DataError> {
+    Ok(match format {
+        FileFormat::Toml => deserialize_item(toml::Deserializer::parse(content)?)?,
+        FileFormat::Json => deserialize_item(&mut serde_json::Deserializer::from_str(content))?,
+        FileFormat::Yaml => {
+            let document = yaml_rust2::YamlLoader::load_from_str(content)?
+                .into_iter()
+                .next()
+                .unwrap_or(yaml_rust2::Yaml::Null);
+            deserialize_item(yaml_to_json(document))?
+        }
+    })
+}
+
+/// Parse and validate the content of `<id>.<format>`. When `strict`, fields
+/// that `T` does not have are an error. Diagnostics point into the content
+/// for every format, but only TOML is sure to be pointed at exactly.
+fn parse_file<T: Item>(
+    id: String,
+    format: FileFormat,
+    content: String,
+    strict: bool,
+) -> Result<(String, T), DataError> {
+    let (item, unknown): (T, _) = deserialize_file(format, &content)?;
+    let name = format!("{}.{}", id, format.extension());
+    if strict && !unknown.is_empty() {
+        let labels = unknown_field_labels(&content, 0, &unknown);
+        return Err(DataError::UnknownField(Box::new(UnknownFieldError {
+            src: NamedSource::new(name, content),
+            id,
+            fields: unknown,
+            labels,
+        })));
+    }
+    if let Err(e) = item.validate() {
+        let labels = to_labels(&content, 0, &e);
+        return Err(T::invalid(
+            id.clone(),
+            NamedSource::new(name, content),
+            labels,
+            e,
+        ));
+    }
+    Ok((id, item))
+}
+
+#[derive(Deserialize, Default)]
+struct DataFile {
+    #[serde(default)]
+    person: Vec<toml::Spanned<toml::Table>>,
+    #[serde(default)]
+    office: Vec<toml::Spanned<toml::Table>>,
+}
+
+/// The raw `[[T::DIR]]` tables of `data.toml` with their ids, along with the
+/// byte range each one spans.
+fn data_file_tables<T: Item>(
+    content: &str,
+) -> Result<Vec<(String, std::ops::Range<usize>, toml::Table)>, DataError> {
+    let file: DataFile = toml::from_str(content)?;
+    // The spans only cover the `[[...]]` headers, so an entry runs from its
+    // header up to the next entry's header.
+    let mut starts: Vec<usize> = file
+        .person
+        .iter()
+        .chain(&file.office)
+        .map(|table| table.span().start)
+        .collect();
+    starts.sort_unstable();
+    let tables = if T::DIR == Person::DIR {
+        file.person
+    } else {
+        file.office
+    };
+
+    tables
+        .into_iter()
+        .enumerate()
+        .map(|(index, table)| {
+            let start = table.span().start;
+            let end = starts
+                .iter()
+                .find(|&&next| next > start)
+                .copied()
+                .unwrap_or(content.len());
+            let span = start..end;
+            let mut table = table.into_inner();
+            match table.remove("id") {
+                Some(toml::Value::String(id)) => Ok((id, span, table)),
+                _ => Err(DataError::MissingId(T::DIR, index)),
+            }
+        })
+        .collect()
+}
+
+/// Parse and validate the `[[T::DIR]]` entries of `data.toml`. Validation
+/// labels point into the whole file.
+fn parse_data_file<T: Item>(content: &str, strict: bool) -> Vec<Result<(String, T), DataError>> {
+    let tables = match data_file_tables::<T>(content) {
+        Ok(tables) => tables,
+        Err(e) => return vec![Err(e)],
+    };
+
+    tables
+        .into_par_iter()
+        .map(|(id, span, table)| {
+            let (item, unknown): (T, _) = deserialize_item(toml::Value::Table(table))?;
+            if strict && !unknown.is_empty() {
+                let labels = unknown_field_labels(&content[span.clone()], span.start, &unknown);
+                return Err(DataError::UnknownField(Box::new(UnknownFieldError {
+                    id,
+                    fields: unknown,
+                    src: NamedSource::new(DATA_FILE, content.to_string()),
                     labels,
-                    source: e,
-                }));
+                })));
             }
-            Ok((id, office))
+            if let Err(e) = item.validate() {
+                let labels = to_labels(&content[span.clone()], span.start, &e);
+                return Err(T::invalid(
+                    id,
+                    NamedSource::new(DATA_FILE, content.to_string()),
+                    labels,
+                    e,
+                ));
+            }
+            Ok((id, item))
         })
+        .collect()
+}
+
+/// Compare two versions of `data.toml` entity by entity.
+fn data_file_diff(before: &str, after: &str, strict: bool) -> Result<Vec<DataDiff>, DataError> {
+    let mut diffs = Vec::new();
+    diffs.extend(data_file_item_diff::<Person>(
+        before,
+        after,
+        strict,
+        DataItem::Person,
+    )?);
+    diffs.extend(data_file_item_diff::<Office>(
+        before,
+        after,
+        strict,
+        DataItem::Office,
+    )?);
+    Ok(diffs)
+}
+
+fn data_file_item_diff<T: Item>(
+    before: &str,
+    after: &str,
+    strict: bool,
+    to_item: fn(T) -> DataItem,
+) -> Result<Vec<DataDiff>, DataError> {
+    let old: BTreeMap<String, toml::Table> = data_file_tables::<T>(before)?
+        .into_iter()
+        .map(|(id, _, table)| (id, table))
+        .collect();
+    let new_tables: BTreeMap<String, toml::Table> = data_file_tables::<T>(after)?
+        .into_iter()
+        .map(|(id, _, table)| (id, table))
+        .collect();
+
+    let mut diffs = Vec::new();
+    for result in parse_data_file::<T>(after, strict) {
+        let (id, item) = result?;
+        match old.get(&id) {
+            None => diffs.push(DataDiff::Added(id, to_item(item))),
+            Some(table) if Some(table) != new_tables.get(&id) => {
+                diffs.push(DataDiff::Modified(id, to_item(item)))
+            }
+            Some(_) => {}
+        }
+    }
+    for id in old.keys() {
+        if !new_tables.contains_key(id) {
+            diffs.push(DataDiff::Deleted(id.clone()));
+        }
     }
+
+    Ok(diffs)
 }
 
-fn to_labels(content: &str, report: &garde::Report) -> Vec<miette::LabeledSpan> {
+/// Label the validation errors in `content`, which starts `offset` bytes into
+/// the source the labels will be shown against.
+fn to_labels(content: &str, offset: usize, report: &garde::Report) -> Vec<miette::LabeledSpan> {
     report
         .iter()
         .map(|(path, error)| {
             let path_str = path.to_string();
             let span = find_span(content, &path_str).unwrap_or(SourceSpan::new(0.into(), 0));
+            let span = SourceSpan::new((span.offset() + offset).into(), span.len());
             LabeledSpan::new_with_span(Some(error.to_string()), span)
         })
         .collect()
@@ -625,14 +1308,14 @@ fn to_labels(content: &str, report: &garde::Report) -> Vec<miette::LabeledSpan>
 
 fn find_span(content: &str, path: &str) -> Option<SourceSpan> {
     let key = path
-        .split(|c| c == '.' || c == '[' || c == ']')
-        .filter(|s| !s.is_empty())
-        .last()?;
+        .split(['.', '[', ']'])
+        .rfind(|s| !s.is_empty())?;
 
     if let Some(pos) = content.find(key) {
         // Try to find the value after the key
         let rest = &content[pos + key.len()..];
-        if let Some(eq_pos) = rest.find('=') {
+        // `=` in TOML, `:` in JSON and YAML.
+        if let Some(eq_pos) = rest.find(['=', ':']) {
             let after_eq = &rest[eq_pos + 1..];
             // Find the first non-whitespace character
             if let Some(val_start) = after_eq.find(|c: char| !c.is_whitespace()) {
@@ -645,14 +1328,20 @@ fn find_span(content: &str, path: &str) -> Option<SourceSpan> {
                 ));
             }
         }
-        return Some(SourceSpan::new(pos.into(), key.len().into()));
+        return Some(SourceSpan::new(pos.into(), key.len()));
     }
     None
 }
 
-fn toml_content_in_dir(
-    dir: PathBuf,
-) -> impl Iterator<Item = Result<(String, String), DataError>> {
+/// An entity file read from `person/` or `office/`.
+struct EntityFile {
+    id: String,
+    path: PathBuf,
+    format: FileFormat,
+    content: String,
+}
+
+fn content_in_dir(dir: PathBuf) -> impl Iterator<Item = Result<EntityFile, DataError>> {
     // TODO This doesn't return an error when dir doesn't exist. Why?
     fs::read_dir(dir).into_iter().flatten().map(|entry| {
         let entry = entry?;
@@ -665,15 +1354,379 @@ fn toml_content_in_dir(
                 .extension()
                 .and_then(|s| s.to_str())
                 .unwrap_or_default();
-            if extension != "toml" {
-                Err(DataError::FileExtension(path))
-            } else {
-                let path = entry.path();
-                let stem = path.file_stem().ok_or(DataError::FileStem(path.clone()))?;
-                let id = stem.to_str().ok_or(DataError::OsStr(path.clone()))?;
-                let content = fs::read_to_string(&path)?;
-                Ok((id.to_string(), content))
-            }
+            let format = FileFormat::from_extension(extension)
+                .ok_or_else(|| DataError::FileExtension(path.clone()))?;
+            let stem = path.file_stem().ok_or(DataError::FileStem(path.clone()))?;
+            let id = stem.to_str().ok_or(DataError::OsStr(path.clone()))?;
+            let content = fs::read_to_string(&path)?;
+            Ok(EntityFile {
+                id: id.to_string(),
+                path,
+                format,
+                content,
+            })
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_link_is_url_encoded() {
+        assert_eq!(
+            ContactType::Address.to_link("North Block, New Delhi & 110001"),
+            "https://www.openstreetmap.org/search?query=North%20Block%2C%20New%20Delhi%20%26%20110001"
+        );
+    }
+
+    #[test]
+    fn test_contact_type_round_trip() {
+        use std::str::FromStr;
+        use strum::VariantArray;
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        for typ in ContactType::VARIANTS {
+            let name = typ.to_string();
+            assert_eq!(ContactType::from_str(&name).unwrap(), *typ);
+            assert_eq!(serde_json::to_string(typ).unwrap(), format!("\"{}\"", name));
+            let read: ContactType = conn
+                .query_row("SELECT ?1", [typ], |row| row.get(0))
+                .unwrap();
+            assert_eq!(read, *typ);
+        }
+
+        assert_eq!(ContactType::Telegram.as_str(), "telegram");
+        assert_eq!(ContactType::LinkedIn.as_str(), "linkedin");
+        assert_eq!(
+            ContactType::Telegram.to_link("PIB_India"),
+            "https://t.me/PIB_India"
+        );
+        assert_eq!(
+            ContactType::LinkedIn.to_link("some-official"),
+            "https://www.linkedin.com/in/some-official"
+        );
+    }
+
+    #[test]
+    fn test_validate_contact() {
+        let valid = [
+            (ContactType::Email, "pmo@gov.in"),
+            (ContactType::Website, "https://www.pmindia.gov.in/"),
+            (ContactType::Wikipedia, "Prime_Minister_of_India"),
+            (ContactType::Wikidata, "Q1"),
+            (ContactType::X, "PMOIndia"),
+            (ContactType::Instagram, "narendra.modi_"),
+            (ContactType::Address, "South Block, New Delhi"),
+            (ContactType::Telegram, "PIB_India"),
+            (ContactType::LinkedIn, "some-official"),
+        ];
+        for (typ, value) in valid {
+            assert_eq!(typ.validate(value), Ok(()), "{} {}", typ, value);
+        }
+
+        let invalid = [
+            (ContactType::Email, "pmo.gov.in"),
+            (ContactType::Email, "pmo@gov"),
+            (ContactType::Email, "p mo@gov.in"),
+            (ContactType::Website, "www.pmindia.gov.in"),
+            (ContactType::Website, "https://"),
+            (ContactType::Wikipedia, "https://en.wikipedia.org/wiki/India"),
+            (ContactType::Wikidata, "42"),
+            (ContactType::Wikidata, "Q"),
+            (ContactType::X, "@PMOIndia"),
+            (ContactType::Instagram, "narendra modi"),
+            (ContactType::Phone, " "),
+            (ContactType::Telegram, "t.me/PIB_India"),
+            (ContactType::LinkedIn, "some_official"),
+        ];
+        for (typ, value) in invalid {
+            assert!(typ.validate(value).is_err(), "{} {}", typ, value);
+        }
+    }
+
+    #[test]
+    fn test_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(DATA_FILE),
+            r#"
+[[person]]
+id = "alice"
+name = "Alice"
+
+[[office]]
+id = "cm"
+name = "Chief Minister"
+supervisors = { adviser = "governor" }
+"#,
+        )
+        .unwrap();
+
+        let data = Data::open(dir.path()).unwrap();
+        let persons: Vec<(String, Person)> = data.persons().map(Result::unwrap).collect();
+        assert_eq!(persons.len(), 1);
+        assert_eq!(persons[0].0, "alice");
+        assert_eq!(persons[0].1.name, "Alice");
+
+        let offices: Vec<(String, Office)> = data.offices().map(Result::unwrap).collect();
+        assert_eq!(offices.len(), 1);
+        assert_eq!(offices[0].0, "cm");
+        assert_eq!(
+            offices[0].1.supervisors.as_ref().unwrap()[&SupervisingRelation::Adviser].ids(),
+            ["governor"]
+        );
+    }
+
+    #[test]
+    fn test_office_with_several_supervisors() {
+        let office: Office = toml::from_str(
+            r#"
+name = "Mayor"
+supervisors = { member_of = ["council", "committee"], responsible_to = "governor" }
+"#,
+        )
+        .unwrap();
+        let supervisors = office.supervisors.unwrap();
+        assert_eq!(
+            supervisors[&SupervisingRelation::MemberOf].ids(),
+            ["council", "committee"]
+        );
+        assert_eq!(
+            supervisors[&SupervisingRelation::ResponsibleTo],
+            Supervisors::One("governor".to_string())
+        );
+    }
+
+    #[test]
+    fn test_photo_credit() {
+        let photo: Photo = toml::from_str("url = \"http://example.com/a.jpg\"").unwrap();
+        assert_eq!(photo.license, None);
+        assert_eq!(photo.source_url, None);
+
+        let photo: Photo = toml::from_str(
+            r#"
+url = "http://example.com/a.jpg"
+license = "CC BY-SA 4.0"
+source_url = "http://example.com/a"
+"#,
+        )
+        .unwrap();
+        assert_eq!(photo.license.as_deref(), Some("CC BY-SA 4.0"));
+        assert!(toml::to_string(&photo).unwrap().contains("source_url = "));
+        assert!(toml::from_str::<Photo>("url = \"x\"\nlicence = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn test_acting_tenure() {
+        let person: Person = toml::from_str(
+            r#"
+name = "Alice"
+tenures = [
+  { office_id = "pmo", start = "2020-01-01" },
+  { office_id = "minister", acting = true },
+]
+"#,
+        )
+        .unwrap();
+        let tenures = person.tenures.unwrap();
+        assert!(!tenures[0].acting);
+        assert!(tenures[1].acting);
+
+        assert!(!toml::to_string(&tenures[0]).unwrap().contains("acting"));
+        let text = toml::to_string(&tenures[1]).unwrap();
+        assert!(text.contains("acting = true"));
+    }
+
+    #[test]
+    fn test_data_file_labels_point_into_entry() {
+        let content = r#"[[person]]
+id = "alice"
+name = "Alice"
+photo = { url = "https://example.com/a.jpg" }
+
+[[person]]
+id = "bob"
+name = "Bob"
+photo = { url = "not a url" }
+"#;
+
+        let results = parse_data_file::<Person>(content, false);
+        assert!(results[0].is_ok());
+        match &results[1] {
+            Err(DataError::PersonValidation(e)) => {
+                assert_eq!(e.id, "bob");
+                let span = e.labels[0].inner();
+                let labelled = &content[span.offset()..span.offset() + span.len()];
+                assert!(labelled.contains("not a url"), "{labelled}");
+            }
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_fields() {
+        let content = "name = \"Alice\"\nnmae = \"Typo\"\n".to_string();
+        let parse = |strict| {
+            parse_file::<Person>(
+                "alice".to_string(),
+                FileFormat::Toml,
+                content.clone(),
+                strict,
+            )
+        };
+        assert!(parse(false).is_ok());
+        match parse(true) {
+            Err(DataError::UnknownField(e)) => {
+                assert_eq!(e.fields, ["nmae"]);
+                let span = e.labels[0].inner();
+                assert_eq!(&content[span.offset()..span.offset() + span.len()], "nmae");
+            }
+            other => panic!("expected an unknown field error, got {other:?}"),
+        }
+
+        let content = r#"[[office]]
+id = "cm"
+name = "Chief Minister"
+
+[[office]]
+id = "gov"
+name = "Governor"
+photo = { url = "https://example.com/g.jpg" }
+abolished = true
+"#;
+        let results = parse_data_file::<Office>(content, true);
+        assert!(results[0].is_ok());
+        match &results[1] {
+            Err(DataError::UnknownField(e)) => {
+                assert_eq!(e.id, "gov");
+                let span = e.labels[0].inner();
+                assert_eq!(
+                    &content[span.offset()..span.offset() + span.len()],
+                    "abolished"
+                );
+            }
+            other => panic!("expected an unknown field error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("person")).unwrap();
+        let path = dir.path().join("person").join("alice.toml");
+        fs::write(&path, "name = \"Alice\"\n").unwrap();
+        let data = Data::open(dir.path()).unwrap();
+
+        match data.reload(&path) {
+            Some(Ok(DataDiff::Modified(id, DataItem::Person(person)))) => {
+                assert_eq!(id, "alice");
+                assert_eq!(person.name, "Alice");
+            }
+            other => panic!("expected a modified person, got {other:?}"),
+        }
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(data.reload(&path), Some(Ok(DataDiff::Deleted(id))) if id == "alice"));
+        assert!(data.reload(&dir.path().join("person").join("notes.txt")).is_none());
+    }
+
+    #[test]
+    fn test_json_and_yaml_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let person_dir = dir.path().join("person");
+        fs::create_dir(&person_dir).unwrap();
+        fs::write(person_dir.join("alice.toml"), "name = \"Alice\"\n").unwrap();
+        fs::write(
+            person_dir.join("bob.json"),
+            r#"{"name": "Bob", "contacts": {"email": "bob@example.org"}}"#,
+        )
+        .unwrap();
+        fs::write(
+            person_dir.join("carol.yaml"),
+            "name: Carol\ntenures:\n  - office_id: pm\n    start: 2020-01-01\n",
+        )
+        .unwrap();
+        fs::write(
+            person_dir.join("dave.yaml"),
+            "name: Dave\nphoto:\n  url: not a url\n",
+        )
+        .unwrap();
+        let data = Data::open(dir.path()).unwrap();
+
+        let results: Vec<_> = data.persons().collect();
+        let (id, bob) = results[1].as_ref().unwrap();
+        assert_eq!(id, "bob");
+        assert_eq!(
+            bob.contacts.as_ref().unwrap()[&ContactType::Email],
+            "bob@example.org"
+        );
+        let (_, carol) = results[2].as_ref().unwrap();
+        let tenure = &carol.tenures.as_ref().unwrap()[0];
+        assert_eq!(tenure.office_id, "pm");
+        assert_eq!(tenure.start.as_deref(), Some("2020-01-01"));
+        match &results[3] {
+            Err(DataError::PersonValidation(e)) => {
+                assert_eq!(e.id, "dave");
+                assert_eq!(e.src.name(), "dave.yaml");
+                let span = e.labels[0].inner();
+                let content = fs::read_to_string(person_dir.join("dave.yaml")).unwrap();
+                let labelled = &content[span.offset()..span.offset() + span.len()];
+                assert_eq!(labelled, "not a url");
+            }
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+
+        fs::write(person_dir.join("alice.json"), r#"{"name": "Alice"}"#).unwrap();
+        let results: Vec<_> = data.persons().collect();
+        assert!(matches!(results[1], Err(DataError::DuplicateFile(..))));
+    }
+
+    #[test]
+    fn test_items_are_ordered_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let person_dir = dir.path().join("person");
+        fs::create_dir(&person_dir).unwrap();
+        for id in ["carol", "alice", "dave", "bob"] {
+            let content = format!("name = \"{id}\"\n");
+            fs::write(person_dir.join(format!("{id}.toml")), content).unwrap();
+        }
+        fs::write(person_dir.join("eve.toml"), "name = 1\n").unwrap();
+        let data = Data::open(dir.path()).unwrap();
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let results: Vec<_> = pool.install(|| data.persons().collect());
+        let ids: Vec<_> = results
+            .iter()
+            .map(|result| match result {
+                Ok((id, _)) => id.as_str(),
+                Err(_) => "error",
+            })
+            .collect();
+        assert_eq!(ids, vec!["alice", "bob", "carol", "dave", "error"]);
+    }
+
+    #[test]
+    fn test_data_file_missing_id() {
+        let results = parse_data_file::<Office>("[[office]]\nname = \"Governor\"\n", false);
+        assert!(matches!(results[0], Err(DataError::MissingId("office", 0))));
+    }
+
+    #[test]
+    fn test_data_file_diff() {
+        let before = "[[person]]\nid = \"a\"\nname = \"A\"\n\n[[person]]\nid = \"b\"\nname = \"B\"\n";
+        let after = "[[person]]\nid = \"a\"\nname = \"A2\"\n\n[[office]]\nid = \"c\"\nname = \"C\"\n";
+
+        let diffs = data_file_diff(before, after, false).unwrap();
+        let summary: Vec<String> = diffs
+            .iter()
+            .map(|diff| match diff {
+                DataDiff::Added(id, _) => format!("+{id}"),
+                DataDiff::Modified(id, _) => format!("~{id}"),
+                DataDiff::Deleted(id) => format!("-{id}"),
+            })
+            .collect();
+        assert_eq!(summary, vec!["~a", "-b", "+c"]);
+    }
+}
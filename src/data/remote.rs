@@ -0,0 +1,196 @@
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use jj_lib::repo::Repo;
+
+use super::DataError;
+
+/// The revision checked out when a source gives none.
+const DEFAULT_REV: &str = "HEAD";
+
+/// A git or jj repository to read the data from, written `<url>@<rev>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub url: String,
+    pub rev: String,
+}
+
+impl FromStr for Source {
+    type Err = DataError;
+
+    /// Splits at the last `@`. What follows it is taken to be part of the URL
+    /// rather than a revision when it holds a `:`, as in `git@host:repo`, and
+    /// the revision is then `HEAD`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (url, rev) = match s.rsplit_once('@') {
+            Some((url, rev)) if !rev.contains(':') => (url, rev),
+            _ => (s, DEFAULT_REV),
+        };
+        if url.is_empty() || rev.is_empty() {
+            return Err(DataError::Source(s.to_string()));
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            rev: rev.to_string(),
+        })
+    }
+}
+
+/// A temporary checkout of a [`Source`], removed when dropped.
+pub struct Checkout {
+    dir: tempfile::TempDir,
+}
+
+impl Checkout {
+    /// Clone `source` with git, check out its revision and set a jj workspace
+    /// up over the clone, with the revision as its working-copy commit, so
+    /// that [`super::Data::commit_id`] names it.
+    pub fn clone(source: &Source) -> Result<Self, DataError> {
+        let dir = tempfile::tempdir()?;
+        git(
+            dir.path(),
+            &["clone", "--quiet", "--no-checkout", &source.url, "."],
+        )?;
+        git(
+            dir.path(),
+            &["checkout", "--quiet", "--detach", &source.rev],
+        )?;
+
+        let settings = super::jj_settings()?;
+        let (workspace, repo) = jj_lib::workspace::Workspace::init_external_git(
+            &settings,
+            dir.path(),
+            &dir.path().join(".git"),
+        )
+        .map_err(|e| DataError::Jj(format!("Could not init workspace: {e}")))?;
+
+        let mut tx = repo.start_transaction();
+        jj_lib::git::import_head(tx.repo_mut())
+            .map_err(|e| DataError::Jj(format!("Could not import HEAD: {e}")))?;
+        let head_id = tx
+            .repo()
+            .view()
+            .git_head()
+            .as_normal()
+            .cloned()
+            .ok_or_else(|| DataError::CommitId(dir.path().to_path_buf()))?;
+        let head = tx
+            .repo()
+            .store()
+            .get_commit(&head_id)
+            .map_err(|_| DataError::CommitId(dir.path().to_path_buf()))?;
+        tx.repo_mut()
+            .edit(workspace.workspace_name().to_owned(), &head)
+            .map_err(|e| DataError::Jj(format!("Could not edit {}: {e}", source.rev)))?;
+        // Editing abandons the empty working-copy commit the workspace started with.
+        tx.repo_mut()
+            .rebase_descendants()
+            .map_err(|e| DataError::Jj(format!("Could not rebase: {e}")))?;
+        tx.commit(format!("check out {}", source.rev))
+            .map_err(|e| DataError::Jj(format!("Could not commit transaction: {e}")))?;
+
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// Run git in `dir`, failing with its stderr if it does.
+fn git(dir: &Path, args: &[&str]) -> Result<(), DataError> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        return Err(DataError::Git(
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_from_str() {
+        let source: Source = "https://example.com/data.git@v1.2".parse().unwrap();
+        assert_eq!(source.url, "https://example.com/data.git");
+        assert_eq!(source.rev, "v1.2");
+
+        let source: Source = "git@example.com:data.git".parse().unwrap();
+        assert_eq!(source.url, "git@example.com:data.git");
+        assert_eq!(source.rev, "HEAD");
+
+        let source: Source = "git@example.com:data.git@main".parse().unwrap();
+        assert_eq!(source.url, "git@example.com:data.git");
+        assert_eq!(source.rev, "main");
+
+        assert!("https://example.com/data.git@".parse::<Source>().is_err());
+        assert!("@main".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn test_checkout() {
+        let origin = tempfile::tempdir().unwrap();
+        let run = |args: &[&str]| git(origin.path(), args).unwrap();
+        run(&["init", "--quiet"]);
+        std::fs::create_dir(origin.path().join("person")).unwrap();
+        std::fs::write(
+            origin.path().join("person/alice.toml"),
+            "name = \"Alice\"\n",
+        )
+        .unwrap();
+        run(&["add", "."]);
+        run(&[
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "commit",
+            "--quiet",
+            "-m",
+            "one",
+        ]);
+        run(&["tag", "one"]);
+        std::fs::write(origin.path().join("person/bob.toml"), "name = \"Bob\"\n").unwrap();
+        run(&["add", "."]);
+        run(&[
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "commit",
+            "--quiet",
+            "-m",
+            "two",
+        ]);
+
+        let source = Source {
+            url: origin.path().display().to_string(),
+            rev: "one".to_string(),
+        };
+        let checkout = Checkout::clone(&source).unwrap();
+        let path = checkout.path().to_path_buf();
+        assert!(path.join("person/alice.toml").is_file());
+        assert!(!path.join("person/bob.toml").exists());
+
+        let output = Command::new("git")
+            .args(["rev-parse", "one"])
+            .current_dir(origin.path())
+            .output()
+            .unwrap();
+        let data = super::super::Data::open(&path).unwrap();
+        assert_eq!(
+            data.commit_id().unwrap(),
+            String::from_utf8(output.stdout).unwrap().trim()
+        );
+
+        drop(checkout);
+        assert!(!path.exists());
+    }
+}
@@ -1,14 +1,30 @@
 use miette::Diagnostic;
 use std::path::Path;
-use tantivy::{Index, IndexReader, collector::TopDocs, query::QueryParser, schema::Value};
+use tantivy::{
+    Index, IndexReader, TantivyDocument, Term,
+    collector::TopDocs,
+    query::{BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, QueryParser, TermQuery},
+    schema::{Field, IndexRecordOption, Value},
+    snippet::SnippetGenerator,
+};
 use thiserror::Error;
 
+use crate::data::indexer::{SCHEMA_VERSION, schema_version};
+
 #[derive(Error, Debug, Diagnostic)]
 pub enum SearcherError {
+    #[error("io error: {0}")]
+    #[diagnostic(code(tudgoi::io))]
+    Io(#[from] std::io::Error),
+
     #[error("tantivy error: {0}")]
     #[diagnostic(code(tudgoi::tantivy))]
     Tantivy(#[from] tantivy::TantivyError),
 
+    #[error("index is version {found}, expected {expected}; rerun `build`")]
+    #[diagnostic(code(tudgoi::index::version))]
+    SchemaVersion { found: u32, expected: u32 },
+
     #[error("query parser error: {0}")]
     #[diagnostic(code(tudgoi::tantivy::query_parser))]
     QueryParser(#[from] tantivy::query::QueryParserError),
@@ -23,45 +39,196 @@ impl Searcher {
     pub fn open(output_dir: &Path) -> Result<Self, SearcherError> {
         let path = output_dir.join("index");
         let index = Index::open_in_dir(path)?;
+        let found = schema_version(output_dir)?;
+        if found != SCHEMA_VERSION {
+            return Err(SearcherError::SchemaVersion {
+                found,
+                expected: SCHEMA_VERSION,
+            });
+        }
         let reader = index.reader()?;
         Ok(Searcher { index, reader })
     }
 
-    pub fn search(&self, query_str: &str) -> Result<Vec<SearchResult>, SearcherError> {
+    fn field(&self, name: &str) -> Field {
+        self.index
+            .schema()
+            .get_field(name)
+            .unwrap_or_else(|_| panic!("{} field should exist", name))
+    }
+
+    fn parse_query(&self, query_str: &str) -> Result<Box<dyn Query>, SearcherError> {
+        let query_parser =
+            QueryParser::for_index(&self.index, vec![self.field("id"), self.field("name")]);
+        Ok(query_parser.parse_query(query_str)?)
+    }
+
+    /// Collects the `limit` best matches after the `offset` best, or `None`
+    /// if there are none. Tantivy asserts a limit of at least 1 and sizes its
+    /// buffers by `limit + offset`, so both are bounded by the number of
+    /// documents in the index.
+    fn page(searcher: &tantivy::Searcher, offset: usize, limit: usize) -> Option<TopDocs> {
+        let num_docs = usize::try_from(searcher.num_docs()).unwrap_or(usize::MAX);
+        let limit = limit.min(num_docs.checked_sub(offset)?);
+        (limit > 0).then(|| TopDocs::with_limit(limit).and_offset(offset))
+    }
+
+    fn to_result(&self, doc: &TantivyDocument, score: f32) -> SearchResult {
+        let text = |field| {
+            doc.get_first(self.field(field))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        SearchResult {
+            id: text("id").unwrap_or_default(),
+            type_str: text("type").unwrap_or_default(),
+            name: text("name").unwrap_or_default(),
+            honorific: text("honorific"),
+            score,
+        }
+    }
+
+    /// Return at most `limit` matches for the query, most relevant first,
+    /// skipping the `offset` most relevant of all.
+    pub fn search(
+        &self,
+        query_str: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, SearcherError> {
+        let query = self.parse_query(query_str)?;
+        self.collect(&*query, offset, limit)
+    }
+
+    /// Like [`Searcher::search`], but each word of the query also matches
+    /// names with a word at most `max_distance` edits away, so that
+    /// misspellings still find the entity. Tantivy supports distances of up
+    /// to 2. Names matching more of the words rank higher, and names matching
+    /// a word exactly rank above those that only come close.
+    pub fn search_fuzzy(
+        &self,
+        query_str: &str,
+        max_distance: u8,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, SearcherError> {
+        let name = self.field("name");
+        let mut words = Vec::new();
+        let mut tokenizer = self.index.tokenizer_for_field(name)?;
+        tokenizer
+            .token_stream(query_str)
+            .process(&mut |token| words.push(Term::from_field_text(name, &token.text)));
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for word in words {
+            let exact = Box::new(TermQuery::new(word.clone(), IndexRecordOption::WithFreqs));
+            clauses.push((Occur::Should, Box::new(BoostQuery::new(exact, 2.0))));
+            clauses.push((
+                Occur::Should,
+                Box::new(FuzzyTermQuery::new(word, max_distance, true)),
+            ));
+        }
+
+        self.collect(&BooleanQuery::new(clauses), offset, limit)
+    }
+
+    fn collect(
+        &self,
+        query: &dyn Query,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, SearcherError> {
         let searcher = self.reader.searcher();
-        let schema = self.index.schema();
-        let id_field = schema.get_field("id").expect("id field should exist");
-        let name_field = schema.get_field("name").expect("name field should exist");
-        let type_field = schema.get_field("type").expect("type field should exist");
+        let Some(page) = Self::page(&searcher, offset, limit) else {
+            return Ok(Vec::new());
+        };
+        let top_docs = searcher.search(query, &page)?;
 
-        let query_parser = QueryParser::for_index(&self.index, vec![id_field, name_field]);
-        let query = query_parser.parse_query(query_str)?;
+        let mut results = Vec::new();
+        for (score, doc_address) in top_docs {
+            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
+            results.push(self.to_result(&retrieved_doc, score));
+        }
 
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+        Ok(results)
+    }
+
+    /// Like [`Searcher::search`], but with the part of each name that matched
+    /// as HTML, the matched terms wrapped in `<mark>` and led by the
+    /// honorific of the person, if any.
+    pub fn search_with_snippets(
+        &self,
+        query_str: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<SnippetResult>, SearcherError> {
+        let searcher = self.reader.searcher();
+        let query = self.parse_query(query_str)?;
+        let generator = SnippetGenerator::create(&searcher, &*query, self.field("name"))?;
+
+        let Some(page) = Self::page(&searcher, offset, limit) else {
+            return Ok(Vec::new());
+        };
+        let top_docs = searcher.search(&query, &page)?;
 
         let mut results = Vec::new();
-        for (_score, doc_address) in top_docs {
-            let retrieved_doc: tantivy::TantivyDocument = searcher.doc(doc_address)?;
-            let id = retrieved_doc
-                .get_first(id_field)
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_string();
-            let type_str = retrieved_doc
-                .get_first(type_field)
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_string();
-            results.push(SearchResult { id, type_str });
+        for (score, doc_address) in top_docs {
+            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
+            let mut snippet = generator.snippet_from_doc(&retrieved_doc);
+            snippet.set_snippet_prefix_postfix("<mark>", "</mark>");
+            let result = self.to_result(&retrieved_doc, score);
+            let snippet = (!snippet.is_empty()).then(|| match &result.honorific {
+                Some(honorific) => format!("{} {}", escape_html(honorific), snippet.to_html()),
+                None => snippet.to_html(),
+            });
+            results.push(SnippetResult { result, snippet });
         }
 
         Ok(results)
     }
 }
 
+/// Escape `text` the way snippets escape the name they are made from.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 pub struct SearchResult {
     pub id: String,
     pub type_str: String,
+    /// The name as it was indexed.
+    pub name: String,
+    /// Shown before the name of a person, if they have one.
+    pub honorific: Option<String>,
+    /// BM25 relevance of the match; higher is better.
+    pub score: f32,
+}
+
+impl SearchResult {
+    /// The name led by the honorific, the way persons are shown.
+    pub fn full_name(&self) -> String {
+        match &self.honorific {
+            Some(honorific) => format!("{} {}", honorific, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+pub struct SnippetResult {
+    pub result: SearchResult,
+    /// HTML of the matched part of the name, or `None` if only the id matched.
+    pub snippet: Option<String>,
 }
 
 #[cfg(test)]
@@ -78,6 +245,7 @@ mod tests {
         
         indexer.add_person("p1", Person {
             name: "Person One".to_string(),
+            honorific: None,
             photo: None,
             contacts: None,
             tenures: None,
@@ -88,22 +256,236 @@ mod tests {
             photo: None,
             contacts: None,
             supervisors: None,
+            establishment: None,
+            abolition: None,
+            jurisdiction: None,
         }).unwrap();
         
         indexer.commit("test").unwrap();
         
         let searcher = Searcher::open(tmp_dir.path()).unwrap();
         
-        let results = searcher.search("p1").unwrap();
+        let results = searcher.search("p1", 0, 10).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].id, "p1");
         
-        let results = searcher.search("o1").unwrap();
+        let results = searcher.search("o1", 0, 10).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].id, "o1");
 
-        let results = searcher.search("Person").unwrap();
+        let results = searcher.search("Person", 0, 10).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].id, "p1");
+        assert_eq!(results[0].type_str, "person");
+        assert_eq!(results[0].name, "Person One");
+        assert!(results[0].score > 0.0);
+    }
+
+    #[test]
+    fn test_open_checks_schema_version() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        indexer.commit("test").unwrap();
+        assert!(Searcher::open(tmp_dir.path()).is_ok());
+
+        std::fs::remove_file(tmp_dir.path().join("index").join("schema_version")).unwrap();
+        let err = Searcher::open(tmp_dir.path()).err().unwrap();
+        assert!(matches!(err, SearcherError::SchemaVersion { found: 0, .. }));
+        assert!(err.to_string().contains("rerun `build`"));
+    }
+
+    #[test]
+    fn test_search_with_snippets() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        for (id, name) in [("o1", "Minister of Finance"), ("o2", "R&D Minister")] {
+            indexer.add_office(id, Office {
+                name: name.to_string(),
+                photo: None,
+                contacts: None,
+                supervisors: None,
+                establishment: None,
+                abolition: None,
+                jurisdiction: None,
+            }).unwrap();
+        }
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+
+        let results = searcher.search_with_snippets("finance", 0, 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].result.id, "o1");
+        assert_eq!(
+            results[0].snippet.as_deref(),
+            Some("Minister of <mark>Finance</mark>")
+        );
+
+        let results = searcher.search_with_snippets("minister", 0, 10).unwrap();
+        let snippet = results.iter().find(|r| r.result.id == "o2").unwrap();
+        assert_eq!(
+            snippet.snippet.as_deref(),
+            Some("R&amp;D <mark>Minister</mark>")
+        );
+
+        let results = searcher.search_with_snippets("o2", 0, 10).unwrap();
+        assert_eq!(results[0].snippet, None);
+    }
+
+    #[test]
+    fn test_search_with_honorific() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        for (id, name, honorific) in [("p1", "B. R. Ambedkar", Some("Dr.")), ("p2", "Ambedkar", None)] {
+            indexer.add_person(id, Person {
+                name: name.to_string(),
+                honorific: honorific.map(str::to_string),
+                photo: None,
+                contacts: None,
+                tenures: None,
+            }).unwrap();
+        }
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+
+        let results = searcher.search_with_snippets("ambedkar", 0, 10).unwrap();
+        let p1 = results.iter().find(|r| r.result.id == "p1").unwrap();
+        assert_eq!(p1.result.honorific.as_deref(), Some("Dr."));
+        assert_eq!(p1.result.full_name(), "Dr. B. R. Ambedkar");
+        assert_eq!(p1.snippet.as_deref(), Some("Dr. B. R. <mark>Ambedkar</mark>"));
+        let p2 = results.iter().find(|r| r.result.id == "p2").unwrap();
+        assert_eq!(p2.result.honorific, None);
+        assert_eq!(p2.snippet.as_deref(), Some("<mark>Ambedkar</mark>"));
+
+        // The honorific is shown, not searched.
+        assert!(searcher.search("dr", 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_fuzzy() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        for (id, name) in [("p1", "Narendra Modi"), ("p2", "Narendran Mody"), ("p3", "Amit Shah")] {
+            indexer.add_person(id, Person {
+                name: name.to_string(),
+                honorific: None,
+                photo: None,
+                contacts: None,
+                tenures: None,
+            }).unwrap();
+        }
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+        let ids = |results: Vec<SearchResult>| {
+            let mut ids: Vec<_> = results.into_iter().map(|r| r.id).collect();
+            ids.sort();
+            ids
+        };
+
+        assert!(searcher.search("Narendr", 0, 10).unwrap().is_empty());
+        assert_eq!(ids(searcher.search_fuzzy("Narendr", 1, 0, 10).unwrap()), ["p1"]);
+        assert_eq!(ids(searcher.search_fuzzy("Narendr", 2, 0, 10).unwrap()), ["p1", "p2"]);
+
+        // Exact words outrank near ones.
+        let results = searcher.search_fuzzy("narendra modi", 1, 0, 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, "p1");
+        assert!(results[0].score > results[1].score);
+
+        assert!(searcher.search_fuzzy("", 2, 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_limit_and_order() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+
+        for (id, name) in [
+            ("o1", "Minister of State for Finance and Planning"),
+            ("o2", "Finance Minister"),
+            ("o3", "Minister of Finance"),
+        ] {
+            indexer.add_office(id, Office {
+                name: name.to_string(),
+                photo: None,
+                contacts: None,
+                supervisors: None,
+                establishment: None,
+                abolition: None,
+                jurisdiction: None,
+            }).unwrap();
+        }
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+
+        let results = searcher.search("finance minister", 0, 10).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+        // The longest name dilutes the match the most.
+        assert_eq!(results[2].id, "o1");
+
+        let results = searcher.search("finance minister", 0, 2).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_search_empty_page() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        indexer.add_office("o1", Office {
+            name: "Minister of Finance".to_string(),
+            photo: None,
+            contacts: None,
+            supervisors: None,
+            establishment: None,
+            abolition: None,
+            jurisdiction: None,
+        }).unwrap();
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+
+        assert!(searcher.search("finance", 0, 0).unwrap().is_empty());
+        assert!(searcher.search_fuzzy("finance", 1, 0, 0).unwrap().is_empty());
+        assert!(searcher.search_with_snippets("finance", 0, 0).unwrap().is_empty());
+        assert!(searcher.search("finance", 1, 10).unwrap().is_empty());
+        assert!(searcher.search("finance", usize::MAX, usize::MAX).unwrap().is_empty());
+        assert_eq!(searcher.search("finance", 0, usize::MAX).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_paged() {
+        let tmp_dir = tempdir().unwrap();
+        let mut indexer = Indexer::open(tmp_dir.path()).unwrap();
+        // Each name is longer than the last, so each matches a little worse.
+        for i in 0..30 {
+            indexer.add_office(&format!("o{}", i), Office {
+                name: format!("Minister{}", " of State".repeat(i)),
+                photo: None,
+                contacts: None,
+                supervisors: None,
+                establishment: None,
+                abolition: None,
+                jurisdiction: None,
+            }).unwrap();
+        }
+        indexer.commit("test").unwrap();
+
+        let searcher = Searcher::open(tmp_dir.path()).unwrap();
+
+        let all = searcher.search("minister", 0, 30).unwrap();
+        assert_eq!(all.len(), 30);
+        let page = searcher.search("minister", 10, 5).unwrap();
+        let ids: Vec<_> = page.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["o10", "o11", "o12", "o13", "o14"]);
+        let expected: Vec<_> = all[10..15].iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, expected);
+        assert!(page.windows(2).all(|w| w[0].score >= w[1].score));
+
+        assert_eq!(searcher.search("minister", 28, 5).unwrap().len(), 2);
+        assert!(searcher.search("minister", 30, 5).unwrap().is_empty());
     }
 }
@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{LibrarySql, dto};
+
+/// Print clusters of entities whose names are at least `threshold` similar.
+pub fn run(db: &Path, threshold: f64) -> Result<()> {
+    let conn = rusqlite::Connection::open(db)?;
+    let mut entities = Vec::new();
+    conn.get_entities(|row| {
+        entities.push(dto::Entity {
+            typ: row.get(0)?,
+            id: row.get(1)?,
+            name: row.get(2)?,
+        });
+        Ok(())
+    })?;
+
+    for cluster in find_clusters(&entities, threshold) {
+        let members: Vec<String> = cluster
+            .iter()
+            .map(|entity| format!("{} ({})", entity.id, entity.name))
+            .collect();
+        println!("{}: {}", cluster[0].typ, members.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Group entities of the same type whose names have a trigram similarity of
+/// at least `threshold`. Similarity is transitive within a cluster, so `a`
+/// and `c` can end up together through `b`. Only clusters with more than one
+/// member are returned.
+pub fn find_clusters(entities: &[dto::Entity], threshold: f64) -> Vec<Vec<&dto::Entity>> {
+    let trigrams: Vec<HashSet<String>> = entities
+        .iter()
+        .map(|entity| trigrams(&normalize(&entity.name)))
+        .collect();
+
+    let mut parent: Vec<usize> = (0..entities.len()).collect();
+    for i in 0..entities.len() {
+        for j in (i + 1)..entities.len() {
+            if entities[i].typ != entities[j].typ {
+                continue;
+            }
+            // Jaccard similarity is bounded by the ratio of the set sizes.
+            let (small, large) = if trigrams[i].len() <= trigrams[j].len() {
+                (trigrams[i].len(), trigrams[j].len())
+            } else {
+                (trigrams[j].len(), trigrams[i].len())
+            };
+            if large == 0 || (small as f64 / large as f64) < threshold {
+                continue;
+            }
+            if similarity(&trigrams[i], &trigrams[j]) >= threshold {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                parent[b] = a;
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<&dto::Entity>> = BTreeMap::new();
+    for (i, entity) in entities.iter().enumerate() {
+        let root = find(&mut parent, i);
+        clusters.entry(root).or_default().push(entity);
+    }
+
+    clusters
+        .into_values()
+        .filter(|cluster| cluster.len() > 1)
+        .collect()
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+/// Lowercase the name and reduce it to alphanumeric words separated by
+/// single spaces, so punctuation and spacing do not count as differences.
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn trigrams(name: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {} ", name).chars().collect();
+    padded
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn office(id: &str, name: &str) -> dto::Entity {
+        dto::Entity {
+            typ: dto::EntityType::Office,
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  Chief-Minister,  Kerala "), "chief minister kerala");
+    }
+
+    #[test]
+    fn test_find_clusters() {
+        let entities = vec![
+            office("cm-kerala", "Chief Minister of Kerala"),
+            office("cm-kl", "Chief Minister, Kerala"),
+            office("gov-kerala", "Governor of Kerala"),
+            dto::Entity {
+                typ: dto::EntityType::Person,
+                id: "cmk".to_string(),
+                name: "Chief Minister of Kerala".to_string(),
+            },
+        ];
+
+        let clusters = find_clusters(&entities, 0.7);
+        assert_eq!(clusters.len(), 1);
+        let ids: Vec<&str> = clusters[0].iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["cm-kerala", "cm-kl"]);
+    }
+}
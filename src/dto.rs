@@ -12,10 +12,12 @@ pub struct Office {
     pub photo: Option<data::Photo>,
     pub contacts: Option<BTreeMap<data::ContactType, String>>,
     pub start: Option<String>,
+    pub acting: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Entity {
+    #[serde(rename = "type")]
     pub typ: EntityType,
     pub id: String,
     pub name: String,
@@ -69,6 +71,18 @@ impl FromStr for EntityType {
     }
 }
 
+/// Split an entity given as `{type}/{id}` into its type and id, refusing an
+/// unknown type, an empty id or a path that goes on past the id.
+pub fn parse_entity(entity: &str) -> Result<(EntityType, &str), String> {
+    let malformed = || format!("expected `{{type}}/{{id}}`, got `{}`", entity);
+    let (typ, id) = entity.split_once('/').ok_or_else(malformed)?;
+    if id.is_empty() || id.contains('/') {
+        return Err(malformed());
+    }
+
+    Ok((typ.parse()?, id))
+}
+
 impl From<graph::EntityType> for EntityType {
     fn from(value: graph::EntityType) -> Self {
         match value {
@@ -1,17 +1,37 @@
 use anyhow::{Context, Result};
+use chrono::NaiveDate;
 use std::{
     collections::BTreeMap,
     fs::{self, File},
-    io::Write,
+    io::{BufWriter, Write},
     path::Path,
 };
 
 use crate::{
     data::{self, ContactType, Office, Person, SupervisingRelation, Tenure},
+    dto,
     record::{Key, OfficePath, PersonPath, RecordKey, RecordRepo, RecordValue},
 };
 
-pub fn run(db: &Path, output: &Path) -> Result<()> {
+/// What `export` writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// A directory of TOML files per entity, as `import` reads.
+    #[default]
+    Toml,
+    /// A single file with a JSON object per record, as `list` prints.
+    Jsonl,
+}
+
+/// Write the committed records to `output` as TOML files, in the layout
+/// `import` reads. With `only`, given as `{type}/{id}`, just that entity is
+/// written.
+pub fn run(db: &Path, output: &Path, only: Option<&str>) -> Result<()> {
+    let only = only
+        .map(dto::parse_entity)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+
     // Create output directories
     let person_dir = output.join("person");
     fs::create_dir_all(&person_dir)
@@ -34,6 +54,14 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
     }
 
     let repo_ref = repo.committed()?;
+    if let Some((typ, id)) = only
+        && repo_ref.get(&format!("{}/{}/name", typ, id))?.is_none()
+    {
+        anyhow::bail!("{}/{} is not in the committed records", typ, id);
+    }
+    // Records of other entities sharing the prefix of the ID are skipped
+    // below.
+    let skip = |typ: dto::EntityType, id: &str| only.is_some_and(|only| only != (typ, id));
 
     // Export persons
     let commit_id = repo_ref.commit_id()?;
@@ -44,9 +72,18 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
         .write_all(commit_id.to_hex().as_bytes())
         .with_context(|| format!("could not write to {:?}", commit_id_path))?;
 
+    let manifest_path = output.join(data::MANIFEST_FILE);
+    let manifest = toml::to_string(&data::Manifest {
+        format: data::FORMAT_VERSION,
+    })
+    .context("could not serialize manifest to TOML")?;
+    fs::write(&manifest_path, manifest)
+        .with_context(|| format!("could not write to {:?}", manifest_path))?;
+
     // Export persons
     struct PersonBuilder {
         name: Option<String>,
+        honorific: Option<String>,
         photo: Option<data::Photo>,
         tenures: Vec<Tenure>,
         contacts: BTreeMap<ContactType, String>,
@@ -56,6 +93,7 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
         if let Some(name) = builder.name {
             let person_data = Person {
                 name,
+                honorific: builder.honorific,
                 photo: builder.photo,
                 contacts: if builder.contacts.is_empty() {
                     None
@@ -83,16 +121,25 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
     let mut current_id: Option<String> = None;
     let mut current_person: Option<PersonBuilder> = None;
 
-    for item in repo_ref.scan(Key::<PersonPath, ()>::all())? {
+    let person_key = match only {
+        None => Some(Key::<PersonPath, ()>::all()),
+        Some((dto::EntityType::Person, id)) => Some(Key::<PersonPath, ()>::new(id)),
+        Some(_) => None,
+    };
+    for item in person_key.map(|key| repo_ref.scan(key)).transpose()?.into_iter().flatten() {
         let (key, value) = item?;
 
         let id = match &key {
             RecordKey::Name(k) => &k.entity_id,
+            RecordKey::Honorific(k) => &k.entity_id,
             RecordKey::Photo(k) => &k.entity_id,
             RecordKey::Contact(k) => &k.entity_id,
             RecordKey::Tenure(k) => &k.entity_id,
             _ => continue,
         };
+        if skip(dto::EntityType::Person, id) {
+            continue;
+        }
 
         if current_id.as_deref() != Some(id) {
             if let (Some(cid), Some(builder)) = (current_id.take(), current_person.take()) {
@@ -101,6 +148,7 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
             current_id = Some(id.clone());
             current_person = Some(PersonBuilder {
                 name: None,
+                honorific: None,
                 photo: None,
                 tenures: Vec::new(),
                 contacts: BTreeMap::new(),
@@ -111,16 +159,13 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
 
         match (key, value) {
             (RecordKey::Name(_), RecordValue::Name(v)) => builder.name = Some(v),
+            (RecordKey::Honorific(_), RecordValue::Honorific(v)) => builder.honorific = Some(v),
             (RecordKey::Photo(_), RecordValue::Photo(v)) => builder.photo = Some(v),
             (RecordKey::Contact(k), RecordValue::Contact(v)) => {
                 builder.contacts.insert(k.state.typ, v);
             }
             (RecordKey::Tenure(k), RecordValue::Tenure(v)) => {
-                builder.tenures.push(Tenure {
-                    office_id: k.state.office_id,
-                    start: k.state.start.map(|d| d.to_string()),
-                    end: v.map(|d| d.to_string()),
-                });
+                builder.tenures.push(k.to_tenure(v));
             }
             _ => {}
         }
@@ -134,8 +179,11 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
     struct OfficeBuilder {
         name: Option<String>,
         photo: Option<data::Photo>,
-        supervisors: BTreeMap<SupervisingRelation, String>,
+        supervisors: BTreeMap<SupervisingRelation, Vec<String>>,
         contacts: BTreeMap<ContactType, String>,
+        establishment: Option<NaiveDate>,
+        abolition: Option<NaiveDate>,
+        jurisdiction: Option<String>,
     }
 
     let flush_office = |id: &str, builder: OfficeBuilder, dir: &Path| -> Result<()> {
@@ -151,8 +199,17 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
                 supervisors: if builder.supervisors.is_empty() {
                     None
                 } else {
-                    Some(builder.supervisors)
+                    Some(
+                        builder
+                            .supervisors
+                            .into_iter()
+                            .map(|(relation, ids)| (relation, ids.into()))
+                            .collect(),
+                    )
                 },
+                establishment: builder.establishment,
+                abolition: builder.abolition,
+                jurisdiction: builder.jurisdiction,
             };
 
             let toml_string = toml::to_string_pretty(&office_data)
@@ -170,7 +227,12 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
     let mut current_id: Option<String> = None;
     let mut current_office: Option<OfficeBuilder> = None;
 
-    for item in repo_ref.scan(Key::<OfficePath, ()>::all())? {
+    let office_key = match only {
+        None => Some(Key::<OfficePath, ()>::all()),
+        Some((dto::EntityType::Office, id)) => Some(Key::<OfficePath, ()>::new(id)),
+        Some(_) => None,
+    };
+    for item in office_key.map(|key| repo_ref.scan(key)).transpose()?.into_iter().flatten() {
         let (key, value) = item?;
 
         let id = match &key {
@@ -178,8 +240,14 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
             RecordKey::Photo(k) => &k.entity_id,
             RecordKey::Contact(k) => &k.entity_id,
             RecordKey::Supervisor(k) => &k.entity_id,
+            RecordKey::Establishment(k) => &k.entity_id,
+            RecordKey::Abolition(k) => &k.entity_id,
+            RecordKey::Jurisdiction(k) => &k.entity_id,
             _ => continue,
         };
+        if skip(dto::EntityType::Office, id) {
+            continue;
+        }
 
         if current_id.as_deref() != Some(id) {
             if let (Some(cid), Some(builder)) = (current_id.take(), current_office.take()) {
@@ -191,6 +259,9 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
                 photo: None,
                 supervisors: BTreeMap::new(),
                 contacts: BTreeMap::new(),
+                establishment: None,
+                abolition: None,
+                jurisdiction: None,
             });
         }
 
@@ -203,7 +274,14 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
                 builder.contacts.insert(k.state.typ, v);
             }
             (RecordKey::Supervisor(k), RecordValue::Supervisor(v)) => {
-                builder.supervisors.insert(k.state.relation, v);
+                builder.supervisors.entry(k.state.relation).or_default().push(v);
+            }
+            (RecordKey::Establishment(_), RecordValue::Establishment(v)) => {
+                builder.establishment = Some(v)
+            }
+            (RecordKey::Abolition(_), RecordValue::Abolition(v)) => builder.abolition = Some(v),
+            (RecordKey::Jurisdiction(_), RecordValue::Jurisdiction(v)) => {
+                builder.jurisdiction = Some(v)
             }
             _ => {}
         }
@@ -221,9 +299,117 @@ pub fn run(db: &Path, output: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Write the tenures and supervisors of the committed records to `output` as
+/// CSV, one edge per row:
+///
+/// ```text
+/// kind,from,to,relation,start,end
+/// tenure,{person_id},{office_id},{held|acting},{start},{end}
+/// supervisor,{office_id},{supervisor_office_id},{relation},,
+/// ```
+///
+/// Rows come in the order of the records, so the same commit always gives
+/// the same file.
+pub fn relations(db: &Path, output: &Path) -> Result<()> {
+    let conn = rusqlite::Connection::open(db)
+        .with_context(|| format!("could not open database at {:?}", db))?;
+    let repo = RecordRepo::new(&conn);
+    let repo_ref = repo.committed()?;
+
+    let mut csv = String::from("kind,from,to,relation,start,end\n");
+    for item in repo_ref.scan(Key::<PersonPath, ()>::all())? {
+        if let (RecordKey::Tenure(k), RecordValue::Tenure(v)) = item? {
+            let tenure = k.to_tenure(v);
+            write_row(
+                &mut csv,
+                [
+                    "tenure",
+                    &k.entity_id,
+                    &tenure.office_id,
+                    if tenure.acting { "acting" } else { "held" },
+                    tenure.start.as_deref().unwrap_or_default(),
+                    tenure.end.as_deref().unwrap_or_default(),
+                ],
+            );
+        }
+    }
+    for item in repo_ref.scan(Key::<OfficePath, ()>::all())? {
+        if let (RecordKey::Supervisor(k), RecordValue::Supervisor(v)) = item? {
+            write_row(
+                &mut csv,
+                [
+                    "supervisor",
+                    &k.entity_id,
+                    &v,
+                    k.state.relation.as_str(),
+                    "",
+                    "",
+                ],
+            );
+        }
+    }
+
+    fs::write(output, csv).with_context(|| format!("could not write to {:?}", output))?;
+
+    Ok(())
+}
+
+/// Write the committed records to the file `output`, one
+/// `{"path": ..., "value": ...}` object per line in the order of their paths.
+/// With `only`, given as `{type}/{id}`, just the records of that entity are
+/// written. Records are written as they are read, never all held at once.
+pub fn jsonl(db: &Path, output: &Path, only: Option<&str>) -> Result<()> {
+    let only = only
+        .map(dto::parse_entity)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let conn = rusqlite::Connection::open(db)
+        .with_context(|| format!("could not open database at {:?}", db))?;
+    let repo = RecordRepo::new(&conn);
+    let repo_ref = repo.committed()?;
+
+    // The trailing separator keeps `p1` from matching the records of `p10`.
+    let prefix = only.map_or(String::new(), |(typ, id)| format!("{}/{}/", typ, id));
+    let file = File::create(output).with_context(|| format!("could not create {:?}", output))?;
+    let mut writer = BufWriter::new(file);
+    for item in repo_ref.list_page(&prefix, None, usize::MAX)? {
+        let (path, value) = item?;
+        let line = serde_json::json!({
+            "path": path,
+            "value": value,
+        });
+        serde_json::to_writer(&mut writer, &line)?;
+        writeln!(writer)?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("could not write to {:?}", output))?;
+
+    Ok(())
+}
+
+/// Append a CSV row to `csv`, quoting the fields that need it.
+fn write_row<const N: usize>(csv: &mut String, fields: [&str; N]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::SchemaSql;
+    use crate::record::TenureValue;
     use crate::record::sqlitebe::SqliteBackend;
     use crate::repo::Repo;
     use rusqlite::Connection;
@@ -247,6 +433,7 @@ mod tests {
               type TEXT NOT NULL,
               id TEXT NOT NULL,
               name TEXT NOT NULL,
+              search_name TEXT NOT NULL,
               PRIMARY KEY(type, id)
             );
             CREATE TABLE entity_photo (
@@ -260,7 +447,8 @@ mod tests {
               person_id TEXT NOT NULL,
               office_id TEXT NOT NULL,
               start TEXT,
-              end TEXT
+              end TEXT,
+              acting INTEGER NOT NULL DEFAULT 0
             );
         "#,
         )
@@ -279,10 +467,10 @@ mod tests {
             let backend = SqliteBackend::new(&conn);
             let mut repo = Repo::new(backend);
             repo.init()?;
-            repo.commit()?;
+            repo.commit(None, None)?;
         }
 
-        run(&db_path, &output_dir)?;
+        run(&db_path, &output_dir, None)?;
 
         let commit_id_path = output_dir.join("commit_id.txt");
         assert!(commit_id_path.exists());
@@ -298,6 +486,157 @@ mod tests {
 
         assert_eq!(content, expected_hash);
 
+        let manifest: data::Manifest =
+            toml::from_str(&fs::read_to_string(output_dir.join(data::MANIFEST_FILE))?)?;
+        assert_eq!(manifest.format, data::FORMAT_VERSION);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_only() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("test.db");
+        let output_dir = temp_dir.path().join("output");
+
+        {
+            let conn = Connection::open(&db_path)?;
+            setup_db(&conn);
+            let mut repo = RecordRepo::new(&conn);
+            repo.init()?;
+            let mut working = repo.working()?;
+            for (id, name) in [("p1", "Person One"), ("p10", "Person Ten")] {
+                let person = Key::<PersonPath, ()>::new(id);
+                working.save(person.name(), &name.to_string())?;
+                working.save(person.tenure("o1", None), &TenureValue::default())?;
+            }
+            repo.commit(None, None)?;
+        }
+
+        run(&db_path, &output_dir, Some("person/p1"))?;
+        let exported: Person = toml::from_str(&fs::read_to_string(
+            output_dir.join("person").join("p1.toml"),
+        )?)?;
+        assert_eq!(exported.name, "Person One");
+        assert_eq!(exported.tenures.map(|t| t.len()), Some(1));
+        assert!(!output_dir.join("person").join("p10.toml").exists());
+
+        assert!(run(&db_path, &output_dir, Some("person/nobody")).is_err());
+        assert!(run(&db_path, &output_dir, Some("p1")).is_err());
+        assert!(run(&db_path, &output_dir, Some("person/p1/name")).is_err());
+
         Ok(())
     }
+
+    #[test]
+    fn test_jsonl() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("test.db");
+        let output = temp_dir.path().join("records.jsonl");
+
+        {
+            let conn = Connection::open(&db_path)?;
+            conn.create_entity_tables()?;
+            conn.create_property_tables()?;
+            let mut repo = RecordRepo::new(&conn);
+            repo.init()?;
+            let mut working = repo.working()?;
+            for id in ["p1", "p10"] {
+                let person = Key::<PersonPath, ()>::new(id);
+                working.save(person.name(), &format!("Person {}", id))?;
+            }
+            let person = Key::<PersonPath, ()>::new("p1");
+            working.save(
+                person.tenure("o1", NaiveDate::from_ymd_opt(2020, 1, 2)),
+                &TenureValue {
+                    end: None,
+                    acting: true,
+                },
+            )?;
+            repo.commit(None, None)?;
+
+            // Uncommitted changes are not exported.
+            repo.working()?
+                .save(person.contact(ContactType::Email), &"p1@gov.in".to_string())?;
+        }
+
+        jsonl(&db_path, &output, None)?;
+        assert_eq!(
+            fs::read_to_string(&output)?,
+            concat!(
+                r#"{"path":"person/p1/name","value":"Person p1"}"#,
+                "\n",
+                r#"{"path":"person/p1/tenure/o1/2020-01-02","value":{"acting":true,"end":null}}"#,
+                "\n",
+                r#"{"path":"person/p10/name","value":"Person p10"}"#,
+                "\n",
+            )
+        );
+
+        jsonl(&db_path, &output, Some("person/p1"))?;
+        assert_eq!(fs::read_to_string(&output)?.lines().count(), 2);
+        for only in ["p1", "person/", "person/p1/name", "party/p1"] {
+            assert!(jsonl(&db_path, &output, Some(only)).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relations() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("test.db");
+        let output = temp_dir.path().join("relations.csv");
+
+        {
+            let conn = Connection::open(&db_path)?;
+            conn.create_entity_tables()?;
+            conn.create_property_tables()?;
+            let mut repo = RecordRepo::new(&conn);
+            repo.init()?;
+            let mut working = repo.working()?;
+            let person = Key::<PersonPath, ()>::new("p1");
+            working.save(person.name(), &"Person One".to_string())?;
+            working.save(
+                person.tenure("o1", NaiveDate::from_ymd_opt(2020, 1, 2)),
+                &TenureValue::default(),
+            )?;
+            working.save(
+                person.tenure("o2", None),
+                &TenureValue {
+                    end: NaiveDate::from_ymd_opt(2021, 3, 4),
+                    acting: true,
+                },
+            )?;
+            let office = Key::<OfficePath, ()>::new("o1");
+            working.save(office.name(), &"Office, One".to_string())?;
+            working.save(
+                office.supervisor(SupervisingRelation::ResponsibleTo, "o2"),
+                &"o2".to_string(),
+            )?;
+            repo.commit(None, None)?;
+
+            // Uncommitted changes are not exported.
+            repo.working()?
+                .save(person.tenure("o3", None), &TenureValue::default())?;
+        }
+
+        relations(&db_path, &output)?;
+        assert_eq!(
+            fs::read_to_string(&output)?,
+            "kind,from,to,relation,start,end\n\
+             tenure,p1,o1,held,2020-01-02,\n\
+             tenure,p1,o2,acting,,2021-03-04\n\
+             supervisor,o1,o2,responsible_to,,\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_row() {
+        let mut csv = String::new();
+        write_row(&mut csv, ["a", "b,c", "say \"hi\"", ""]);
+        assert_eq!(csv, "a,\"b,c\",\"say \"\"hi\"\"\",\n");
+    }
 }
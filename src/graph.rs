@@ -1,17 +1,19 @@
 use std::collections::HashMap;
 
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::data;
 
-#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum EntityType {
     Person,
     Office,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum Property {
     Type(EntityType),
     Id(String),
@@ -44,6 +46,8 @@ pub enum Key {
     Facebook,
     Instagram,
     Wikidata,
+    Telegram,
+    LinkedIn,
     Head,
     Adviser,
     ResponsibleTo,
@@ -70,6 +74,8 @@ impl Property {
                 data::ContactType::Facebook => Key::Facebook,
                 data::ContactType::Instagram => Key::Instagram,
                 data::ContactType::Wikidata => Key::Wikidata,
+                data::ContactType::Telegram => Key::Telegram,
+                data::ContactType::LinkedIn => Key::LinkedIn,
             },
             Property::Supervisor(relation, _) => match relation {
                 data::SupervisingRelation::MemberOf => Key::MemberOf,
@@ -86,6 +92,12 @@ impl Property {
 #[derive(Debug)]
 pub struct Entity(pub HashMap<Key, Property>);
 
+/// JSON Schema for an entity as ingestors read it: the list of its
+/// properties.
+pub fn entity_schema() -> schemars::Schema {
+    schemars::schema_for!(Vec<Property>)
+}
+
 impl From<Vec<Property>> for Entity {
     fn from(value: Vec<Property>) -> Self {
         let map = value
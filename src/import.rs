@@ -1,76 +1,304 @@
-use anyhow::{Context, Result, ensure};
+use anyhow::{Context, Result};
 use chrono::NaiveDate;
-use rusqlite::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
 
 use crate::SchemaSql;
 use crate::data::Data;
-use crate::record::{Key, OfficePath, PersonPath, RecordRepo};
+use crate::record::{
+    Key, OfficePath, PersonPath, RecordRepo, RecordRepoError, RecordRepoRef, TenureValue,
+    ValueIndexer, default_author, index_order,
+};
+use crate::repo::RepoError;
 
 use super::data;
 
-pub fn init(output: &Path) -> Result<()> {
-    ensure!(!output.exists(), "output DB already exists at {:?}", output);
+/// Create the DB at `output` with an empty repository. A DB that already
+/// holds one is refused unless `force` is set, in which case its data is
+/// dropped and it gets a new iroh secret.
+pub fn init(output: &Path, force: bool) -> Result<()> {
+    let exists = output.exists();
 
     // setup sqlite DB
     let conn = rusqlite::Connection::open(output)
         .with_context(|| format!("could not create sqlite DB at {:?}", output))?;
 
-    conn.create_entity_tables()
-        .context("could not create entity schema")?;
+    if !exists {
+        conn.create_entity_tables()
+            .context("could not create entity schema")?;
+        crate::ingest::create_entity_index(&conn, crate::theme::config().search_tokenizer)
+            .context("could not create entity search index")?;
 
-    conn.create_property_tables()
-        .context("could not create property schema")?;
+        conn.create_property_tables()
+            .context("could not create property schema")?;
+    }
 
-    RecordRepo::new(&conn).init()?;
+    let repo = RecordRepo::new(&conn);
+    if force {
+        repo.reinit()?;
+    } else if let Err(e) = repo.init() {
+        if matches!(e, RecordRepoError::Repo(RepoError::AlreadyInitialized)) {
+            return Err(anyhow::Error::new(e).context(format!(
+                "DB at {:?} is already initialized, pass --force to start over",
+                output
+            )));
+        }
+        return Err(e.into());
+    }
 
     Ok(())
 }
 
-pub fn run(source: &Path, output: &Path) -> Result<()> {
-    let mut conn = rusqlite::Connection::open(output)
-        .with_context(|| format!("could not open sqlite DB at {:?}", output))?;
+/// What to do with a record the database already holds at a path being
+/// imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Leave the existing record as it is.
+    Keep,
+    /// Take the value from the source.
+    Overwrite,
+}
 
-    let mut tx = conn.transaction()?;
-    
-    let data = Data::open(source)?;
-    
-    for result in data.offices() {
-        let (id, office) = result?;
-        insert_office_data(&mut tx, &id, &office)?;
+/// How many records an import wrote, by whether they were already there.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub added: usize,
+    pub skipped: usize,
+    pub overwritten: usize,
+    /// Directories of the source holding entity types this binary does not
+    /// know, and so left out.
+    pub unknown_types: Vec<String>,
+    /// Lines of a JSONL source that were left out, by number from 1, and why.
+    pub malformed: Vec<(usize, String)>,
+}
+
+/// Writes records into the working tree, counting them into a [`Summary`].
+struct Writer<'a, 'b> {
+    working: RecordRepoRef<'a, 'b>,
+    on_conflict: OnConflict,
+    summary: Summary,
+}
+
+impl Writer<'_, '_> {
+    fn write<P, T: Serialize>(&mut self, key: Key<P, T>, value: &T) -> Result<()>
+    where
+        Key<P, T>: ValueIndexer<T>,
+    {
+        if !self.working.exists(&key.path)? {
+            self.summary.added += 1;
+        } else if self.on_conflict == OnConflict::Keep {
+            self.summary.skipped += 1;
+            return Ok(());
+        } else {
+            self.summary.overwritten += 1;
+        }
+
+        self.working.save(key, value)?;
+        Ok(())
     }
 
-    for result in data.persons() {
-        let (id, person) = result?;
-        insert_person_data(&mut tx, &id, &person)?;
+    /// Like [`write`](Self::write) for each record in turn, saving the ones
+    /// not skipped together.
+    fn write_many<P, T: Serialize>(&mut self, records: Vec<(Key<P, T>, T)>) -> Result<()>
+    where
+        Key<P, T>: ValueIndexer<T>,
+    {
+        let mut to_save = Vec::with_capacity(records.len());
+        for (key, value) in records {
+            if !self.working.exists(&key.path)? {
+                self.summary.added += 1;
+            } else if self.on_conflict == OnConflict::Keep {
+                self.summary.skipped += 1;
+                continue;
+            } else {
+                self.summary.overwritten += 1;
+            }
+            to_save.push((key, value));
+        }
+
+        self.working.save_many(to_save)?;
+        Ok(())
     }
 
-    RecordRepo::new(&tx).commit()?;
+    /// Like [`write`](Self::write), for a record given by its path and its
+    /// value as JSON. Nothing is counted if the record is refused.
+    fn write_json(&mut self, path: &str, json: &str) -> Result<()> {
+        let exists = self.working.exists(path)?;
+        if exists && self.on_conflict == OnConflict::Keep {
+            self.summary.skipped += 1;
+            return Ok(());
+        }
+
+        self.working.save_from_json(path, json)?;
+        if exists {
+            self.summary.overwritten += 1;
+        } else {
+            self.summary.added += 1;
+        }
+        Ok(())
+    }
+}
+
+/// A line of a JSONL source, in the shape `export --format jsonl` writes.
+#[derive(Deserialize)]
+struct JsonlRecord {
+    path: String,
+    value: serde_json::Value,
+}
+
+/// Import the data at `source` into the database at `output` and commit it.
+/// The database may already hold records, in which case `on_conflict`
+/// decides which value wins where both have one.
+///
+/// An export written by a binary with a newer [`data::FORMAT_VERSION`] is
+/// refused, as it may hold records this one would drop.
+///
+/// A `source` ending in `.jsonl` is read as a record per line instead. Lines
+/// that are not a record this binary can store are left out and listed in
+/// the summary, unless `strict` is set, in which case the first one fails
+/// the import.
+pub fn run(source: &Path, output: &Path, on_conflict: OnConflict, strict: bool) -> Result<Summary> {
+    if source.extension().is_some_and(|ext| ext == "jsonl") {
+        return import(source, output, on_conflict, Summary::default(), |writer| {
+            insert_jsonl(writer, source, strict)
+        });
+    }
+
+    let data = Data::open(source)?;
+    if let Some(manifest) = data.manifest()?
+        && manifest.format > data::FORMAT_VERSION
+    {
+        anyhow::bail!(
+            "{:?} was exported in format {}, but this binary only reads up to {}",
+            source,
+            manifest.format,
+            data::FORMAT_VERSION
+        );
+    }
+    let summary = Summary {
+        unknown_types: data.unknown_dirs()?,
+        ..Summary::default()
+    };
+
+    import(source, output, on_conflict, summary, |writer| {
+        for result in data.offices() {
+            let (id, office) = result?;
+            insert_office_data(writer, &id, &office)?;
+        }
+
+        for result in data.persons() {
+            let (id, person) = result?;
+            insert_person_data(writer, &id, &person)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Write the records `insert` gives into the database at `output` and
+/// commit them, all in one transaction, counting them into `summary`.
+fn import(
+    source: &Path,
+    output: &Path,
+    on_conflict: OnConflict,
+    summary: Summary,
+    insert: impl FnOnce(&mut Writer) -> Result<()>,
+) -> Result<Summary> {
+    let mut conn = rusqlite::Connection::open(output)
+        .with_context(|| format!("could not open sqlite DB at {:?}", output))?;
+
+    // Records must reach the index in an order it can take, so have it check.
+    conn.pragma_update(None, "foreign_keys", true)?;
+    let tx = conn.transaction()?;
+
+    let summary = {
+        let repo = RecordRepo::new(&tx);
+        let mut writer = Writer {
+            working: repo.working()?,
+            on_conflict,
+            summary,
+        };
+        insert(&mut writer)?;
+        writer.summary
+    };
+
+    let message = format!("Import from {}", source.display());
+    RecordRepo::new(&tx).commit(default_author().as_deref(), Some(&message))?;
 
     tx.commit()?;
 
+    Ok(summary)
+}
+
+fn insert_jsonl(writer: &mut Writer, source: &Path, strict: bool) -> Result<()> {
+    let file = File::open(source).with_context(|| format!("could not open {:?}", source))?;
+    let mut records = Vec::new();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("could not read {:?}", source))?;
+        if !line.trim().is_empty() {
+            records.push((idx, serde_json::from_str::<JsonlRecord>(&line)));
+        }
+    }
+    // An export lists the records of an entity by path, which puts some
+    // before its name.
+    records.sort_by_key(|(_, record)| {
+        record.as_ref().map_or(0, |record| {
+            index_order(record.path.ends_with("/name"), false)
+        })
+    });
+
+    for (idx, record) in records {
+        let written = record
+            .map_err(anyhow::Error::from)
+            .and_then(|record| writer.write_json(&record.path, &record.value.to_string()));
+        let Err(e) = written else {
+            continue;
+        };
+        // Only a line that could not be stored at all is left out; anything
+        // going wrong while storing it fails the import.
+        let malformed = e.is::<serde_json::Error>()
+            || matches!(
+                e.downcast_ref::<RecordRepoError>(),
+                Some(RecordRepoError::InvalidPath(_) | RecordRepoError::UnknownRecordType(_))
+            );
+        if strict || !malformed {
+            return Err(e.context(format!("line {} of {:?}", idx + 1, source)));
+        }
+        writer.summary.malformed.push((idx + 1, e.to_string()));
+    }
+
     Ok(())
 }
 
-pub fn insert_person_data(tx: &mut Transaction, id: &str, person: &data::Person) -> Result<()> {
-    let repo = RecordRepo::new(tx);
+fn insert_person_data(writer: &mut Writer, id: &str, person: &data::Person) -> Result<()> {
     let person_path = Key::<PersonPath, ()>::new(id);
 
-    repo.working()?.save(person_path.name(), &person.name)?;
+    writer.write(person_path.name(), &person.name)?;
+
+    if let Some(honorific) = &person.honorific {
+        writer.write(person_path.honorific(), honorific)?;
+    }
 
     if let Some(photo) = &person.photo {
-        repo.working()?.save(person_path.photo(), photo)?;
+        writer.write(person_path.photo(), photo)?;
     }
     // Insert contacts if they exist
     if let Some(contacts) = &person.contacts {
-        for (contact_type, value) in contacts {
-            repo.working()?
-                .save(person_path.contact(contact_type.clone()), value)?;
-        }
+        writer.write_many(
+            contacts
+                .iter()
+                .map(|(contact_type, value)| {
+                    (person_path.contact(contact_type.clone()), value.clone())
+                })
+                .collect(),
+        )?;
     }
 
     // Insert tenures if they exist
     if let Some(tenures) = &person.tenures {
+        let mut records = Vec::with_capacity(tenures.len());
         for tenure in tenures {
             let start = tenure
                 .start
@@ -82,41 +310,256 @@ pub fn insert_person_data(tx: &mut Transaction, id: &str, person: &data::Person)
                 .as_ref()
                 .map(|d| d.parse::<NaiveDate>())
                 .transpose()?;
-            repo.working()?
-                .save(person_path.tenure(&tenure.office_id, start), &end)?;
+            records.push((
+                person_path.tenure(&tenure.office_id, start),
+                TenureValue {
+                    end,
+                    acting: tenure.acting,
+                },
+            ));
         }
+        writer.write_many(records)?;
     }
 
     Ok(())
 }
 
-fn insert_office_data(tx: &mut Transaction, id: &str, office: &data::Office) -> Result<()> {
-    let repo = RecordRepo::new(tx);
+fn insert_office_data(writer: &mut Writer, id: &str, office: &data::Office) -> Result<()> {
     let office_path = Key::<OfficePath, ()>::new(id);
 
-    repo.working()?.save(office_path.name(), &office.name)?;
+    writer.write(office_path.name(), &office.name)?;
 
     if let Some(photo) = &office.photo {
-        repo.working()?.save(office_path.photo(), photo)?;
+        writer.write(office_path.photo(), photo)?;
     }
 
     // Insert supervisors if they exist
     if let Some(supervisors) = &office.supervisors {
-        for (relation, supervisor_office_id) in supervisors {
-            repo.working()?.save(
-                office_path.supervisor(relation.clone()),
-                supervisor_office_id,
-            )?;
+        let mut records = Vec::new();
+        for (relation, supervisor_office_ids) in supervisors {
+            for supervisor_office_id in supervisor_office_ids.ids() {
+                records.push((
+                    office_path.supervisor(relation.clone(), supervisor_office_id),
+                    supervisor_office_id.clone(),
+                ));
+            }
         }
+        writer.write_many(records)?;
     }
 
     // Insert contacts if they exist
     if let Some(contacts) = &office.contacts {
-        for (contact_type, value) in contacts {
-            repo.working()?
-                .save(office_path.contact(contact_type.clone()), value)?;
-        }
+        writer.write_many(
+            contacts
+                .iter()
+                .map(|(contact_type, value)| {
+                    (office_path.contact(contact_type.clone()), value.clone())
+                })
+                .collect(),
+        )?;
+    }
+
+    if let Some(establishment) = &office.establishment {
+        writer.write(office_path.establishment(), establishment)?;
+    }
+
+    if let Some(abolition) = &office.abolition {
+        writer.write(office_path.abolition(), abolition)?;
+    }
+
+    if let Some(jurisdiction) = &office.jurisdiction {
+        writer.write(office_path.jurisdiction(), jurisdiction)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::RecordValue;
+    use std::fs;
+
+    fn write_person(dir: &Path, id: &str, toml: &str) {
+        fs::create_dir_all(dir.join("person")).unwrap();
+        fs::write(dir.join("person").join(format!("{}.toml", id)), toml).unwrap();
+    }
+
+    #[test]
+    fn test_init() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db = temp_dir.path().join("test.db");
+        let source = temp_dir.path().join("source");
+        write_person(&source, "p1", "name = \"Alice\"");
+        init(&db, false)?;
+        run(&source, &db, OnConflict::Overwrite, false)?;
+
+        let err = init(&db, false).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        let conn = rusqlite::Connection::open(&db)?;
+        assert!(RecordRepo::new(&conn).working()?.exists("person/p1/name")?);
+        drop(conn);
+
+        init(&db, true)?;
+        let conn = rusqlite::Connection::open(&db)?;
+        assert!(!RecordRepo::new(&conn).working()?.exists("person/p1/name")?);
+        let count: usize = conn.query_row("SELECT COUNT(*) FROM entity", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db = temp_dir.path().join("test.db");
+        let first = temp_dir.path().join("first");
+        let second = temp_dir.path().join("second");
+        init(&db, false)?;
+
+        write_person(
+            &first,
+            "p1",
+            "name = \"Alice\"\ncontacts = { email = \"a@old.org\" }",
+        );
+        write_person(
+            &second,
+            "p1",
+            "name = \"Alice\"\ncontacts = { email = \"a@new.org\", phone = \"123\" }",
+        );
+        write_person(&second, "p2", "name = \"Bob\"");
+
+        run(&first, &db, OnConflict::Keep, false)?;
+        let summary = run(&second, &db, OnConflict::Keep, false)?;
+        assert_eq!(
+            summary,
+            Summary {
+                added: 2,
+                skipped: 2,
+                overwritten: 0,
+                unknown_types: Vec::new(),
+                malformed: Vec::new(),
+            }
+        );
+
+        let conn = rusqlite::Connection::open(&db)?;
+        let email = |conn: &rusqlite::Connection| -> Result<Option<RecordValue>> {
+            Ok(RecordRepo::new(conn)
+                .committed()?
+                .get("person/p1/contact/email")?)
+        };
+        assert_eq!(
+            email(&conn)?,
+            Some(RecordValue::Contact("a@old.org".to_string()))
+        );
+
+        let summary = run(&second, &db, OnConflict::Overwrite, false)?;
+        assert_eq!(summary.added, 0);
+        assert_eq!(summary.overwritten, 4);
+        assert_eq!(
+            email(&conn)?,
+            Some(RecordValue::Contact("a@new.org".to_string()))
+        );
+        let indexed: String = conn.query_row(
+            "SELECT value FROM entity_contact WHERE entity_id = 'p1' AND type = 'email'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(indexed, "a@new.org");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db = temp_dir.path().join("test.db");
+        let source = temp_dir.path().join("source");
+        write_person(&source, "p1", "name = \"Alice\"");
+        fs::create_dir_all(source.join("region"))?;
+        init(&db, false)?;
+
+        fs::write(
+            source.join(data::MANIFEST_FILE),
+            format!("format = {}", data::FORMAT_VERSION),
+        )?;
+        let summary = run(&source, &db, OnConflict::Overwrite, false)?;
+        assert_eq!(summary.added, 1);
+        assert_eq!(summary.unknown_types, vec!["region".to_string()]);
+
+        fs::write(
+            source.join(data::MANIFEST_FILE),
+            format!("format = {}", data::FORMAT_VERSION + 1),
+        )?;
+        let err = run(&source, &db, OnConflict::Overwrite, false).unwrap_err();
+        assert!(err.to_string().contains("only reads up to"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonl() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db = temp_dir.path().join("test.db");
+        let source = temp_dir.path().join("records.jsonl");
+        init(&db, false)?;
+
+        fs::write(
+            &source,
+            concat!(
+                r#"{"path":"person/p1/contact/email","value":"alice@example.com"}"#,
+                "\n",
+                r#"{"path":"person/p1/name","value":"Alice"}"#,
+                "\n",
+                r#"{"path":"person/p1/name""#,
+                "\n\n",
+                r#"{"path":"person/p1/tenure/o1/2020-01-02","value":{"acting":true,"end":null}}"#,
+                "\n",
+                r#"{"path":"person/p1/shoe_size","value":"9"}"#,
+                "\n",
+                r#"{"path":"person/p2/name","value":"Bob"}"#,
+                "\n",
+            ),
+        )?;
+
+        let err = run(&source, &db, OnConflict::Overwrite, true).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 3 of"));
+        let conn = rusqlite::Connection::open(&db)?;
+        assert!(!RecordRepo::new(&conn).working()?.exists("person/p1/name")?);
+
+        let summary = run(&source, &db, OnConflict::Overwrite, false)?;
+        assert_eq!(summary.added, 4);
+        let lines: Vec<_> = summary.malformed.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 6]);
+
+        let repo = RecordRepo::new(&conn);
+        let committed = repo.committed()?;
+        assert_eq!(
+            committed.get("person/p2/name")?,
+            Some(RecordValue::Name("Bob".to_string()))
+        );
+        assert_eq!(
+            committed.get("person/p1/contact/email")?,
+            Some(RecordValue::Contact("alice@example.com".to_string()))
+        );
+        assert_eq!(
+            committed.get("person/p1/tenure/o1/2020-01-02")?,
+            Some(RecordValue::Tenure(TenureValue {
+                end: None,
+                acting: true,
+            }))
+        );
+
+        // What `export` writes reads back as the same tree.
+        let exported = temp_dir.path().join("exported.jsonl");
+        crate::export::jsonl(&db, &exported, None)?;
+        let copy = temp_dir.path().join("copy.db");
+        init(&copy, false)?;
+        run(&exported, &copy, OnConflict::Overwrite, true)?;
+        let copy_conn = rusqlite::Connection::open(&copy)?;
+        let copy_repo = RecordRepo::new(&copy_conn);
+        assert_eq!(copy_repo.committed()?.commit_id()?, committed.commit_id()?);
+
+        Ok(())
+    }
+}
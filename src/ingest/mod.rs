@@ -7,9 +7,9 @@ use crate::{
     dto::{self, Entity},
     graph,
     ingest::{derive::derive_id, old::OldIngestor},
-    record::{Key, OfficePath, PersonPath, RecordRepo},
+    record::{Key, OfficePath, PersonPath, RecordRepo, RecordRepoRef, TenureValue},
 };
-use rusqlite::OptionalExtension;
+use unicode_normalization::UnicodeNormalization;
 
 mod derive;
 mod old;
@@ -50,7 +50,7 @@ impl Ingestor {
                         result.with_context(|| format!("could not query from {:?}", source))?;
                     for entity in entities {
                         let entity: graph::Entity = entity.into();
-                        if let Err(e) = ingest_entity(&mut self.conn, entity).await {
+                        if let Err(e) = ingest_entity(&self.conn, entity) {
                             println!("ingestion failed: {}", e)
                         }
                     }
@@ -61,11 +61,26 @@ impl Ingestor {
     }
 }
 
-async fn ingest_entity(conn: &mut Connection, entity: graph::Entity) -> Result<()> {
+/// Write the entity and everything it refers to in one transaction, so that
+/// an entity that fails part way leaves nothing behind.
+fn ingest_entity(conn: &Connection, entity: graph::Entity) -> Result<()> {
+    RecordRepo::new(conn).transaction(|working| ingest_entity_in(conn, working, entity))
+}
+
+fn ingest_entity_in(
+    conn: &Connection,
+    working: &mut RecordRepoRef,
+    entity: graph::Entity,
+) -> Result<()> {
     let entity_type = entity.get_type().context("entity should have a type")?;
     let entity_type: dto::EntityType = entity_type.clone().into();
-    let id =
-        ingest_entity_id_or_name(conn, &entity_type, entity.get_id(), entity.get_name()).await?;
+    let id = ingest_entity_id_or_name(
+        conn,
+        working,
+        &entity_type,
+        entity.get_id(),
+        entity.get_name(),
+    )?;
 
     for property in entity.0.values() {
         match property {
@@ -81,50 +96,50 @@ async fn ingest_entity(conn: &mut Connection, entity: graph::Entity) -> Result<(
                 let office: graph::Entity = items.to_vec().into();
                 let office_id = ingest_entity_id_or_name(
                     conn,
+                    working,
                     &dto::EntityType::Office,
                     office.get_id(),
                     office.get_name(),
-                )
-                .await?;
-                RecordRepo::new(conn).working()?.save(
+                )?;
+                working.save(
                     Key::<PersonPath, ()>::new(&id).tenure(&office_id, None),
-                    &None,
+                    &TenureValue::default(),
                 )?;
             }
             graph::Property::Photo { url, attribution } => {
-                if !conn.exists_entity_photo(&entity_type, &id, |row| row.get(0))? {
-                    let photo = crate::data::Photo {
-                        url: url.clone(),
-                        attribution: attribution.clone(),
-                    };
-                    let repo = RecordRepo::new(conn);
-                    match entity_type {
-                        dto::EntityType::Person => {
-                            repo.working()?
-                                .save(Key::<PersonPath, ()>::new(&id).photo(), &photo)?;
+                let photo = crate::data::Photo {
+                    url: url.clone(),
+                    attribution: attribution.clone(),
+                    license: None,
+                    source_url: None,
+                };
+                match entity_type {
+                    dto::EntityType::Person => {
+                        let key = Key::<PersonPath, ()>::new(&id).photo();
+                        if !working.exists(&key.path)? {
+                            working.save(key, &photo)?;
                         }
-                        dto::EntityType::Office => {
-                            repo.working()?
-                                .save(Key::<OfficePath, ()>::new(&id).photo(), &photo)?;
+                    }
+                    dto::EntityType::Office => {
+                        let key = Key::<OfficePath, ()>::new(&id).photo();
+                        if !working.exists(&key.path)? {
+                            working.save(key, &photo)?;
                         }
                     }
                 }
             }
             graph::Property::Contact(contact_type, value) => {
-                if !conn.exists_entity_contact(&entity_type, &id, contact_type, |row| row.get(0))? {
-                    let repo = RecordRepo::new(conn);
-                    match entity_type {
-                        dto::EntityType::Person => {
-                            repo.working()?.save(
-                                Key::<PersonPath, ()>::new(&id).contact(contact_type.clone()),
-                                value,
-                            )?;
+                match entity_type {
+                    dto::EntityType::Person => {
+                        let key = Key::<PersonPath, ()>::new(&id).contact(contact_type.clone());
+                        if !working.exists(&key.path)? {
+                            working.save(key, value)?;
                         }
-                        dto::EntityType::Office => {
-                            repo.working()?.save(
-                                Key::<OfficePath, ()>::new(&id).contact(contact_type.clone()),
-                                value,
-                            )?;
+                    }
+                    dto::EntityType::Office => {
+                        let key = Key::<OfficePath, ()>::new(&id).contact(contact_type.clone());
+                        if !working.exists(&key.path)? {
+                            working.save(key, value)?;
                         }
                     }
                 }
@@ -141,14 +156,15 @@ async fn ingest_entity(conn: &mut Connection, entity: graph::Entity) -> Result<(
                 if !conn.exists_office_supervisor(&id, relation, |row| row.get(0))? {
                     let supervising_office_id = ingest_entity_id_or_name(
                         conn,
+                        working,
                         &dto::EntityType::Office,
                         supervising_office.get_id(),
                         supervising_office.get_name(),
-                    )
-                    .await?;
+                    )?;
 
-                    RecordRepo::new(conn).working()?.save(
-                        Key::<OfficePath, ()>::new(&id).supervisor(relation.clone()),
+                    working.save(
+                        Key::<OfficePath, ()>::new(&id)
+                            .supervisor(relation.clone(), &supervising_office_id),
                         &supervising_office_id,
                     )?;
                 }
@@ -159,9 +175,31 @@ async fn ingest_entity(conn: &mut Connection, entity: graph::Entity) -> Result<(
     Ok(())
 }
 
-fn escape_for_fts(input: &str) -> String {
+/// The form entity names are indexed and searched in. NFKC folds
+/// compatibility characters such as full-width letters into their plain
+/// forms; case and diacritics are left to the tokenizer.
+pub fn normalize_for_search(input: &str) -> String {
+    input.nfkc().collect()
+}
+
+/// Recreate the full-text index over entity names with `tokenizer` and fill
+/// it from the entity table. The triggers that keep it current refer to it by
+/// name, so they carry on working.
+pub fn create_entity_index(conn: &Connection, tokenizer: &str) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS entity_idx;
+        CREATE VIRTUAL TABLE entity_idx USING fts5(id, search_name, content = 'entity', tokenize = \"{}\");
+        INSERT INTO entity_idx(entity_idx) VALUES ('rebuild');",
+        tokenizer.replace('"', "\"\"")
+    ))
+}
+
+/// Quote `input` as a single FTS5 phrase, normalized like the indexed names.
+/// With `prefix`, the last word of the phrase may be the start of a longer
+/// one.
+pub fn escape_for_fts(input: &str, prefix: bool) -> String {
     let mut s = String::from("\"");
-    for c in input.chars() {
+    for c in normalize_for_search(input).chars() {
         if c == '"' {
             s.push_str("\"\""); // escape quotes by doubling
         } else {
@@ -169,31 +207,36 @@ fn escape_for_fts(input: &str) -> String {
         }
     }
     s.push('"');
+    if prefix {
+        s.push('*');
+    }
     s
 }
 
-async fn ingest_entity_id_or_name(
-    conn: &mut Connection,
+fn ingest_entity_id_or_name(
+    conn: &Connection,
+    working: &mut RecordRepoRef,
     entity_type: &dto::EntityType,
     id: Option<&str>,
     name: Option<&str>,
 ) -> Result<String> {
     if let Some(id) = id {
         // id provided. insert if it doesn't already exist
-        if !conn.exists_entity(entity_type, id, |row| row.get(0))? {
+        let exists = match entity_type {
+            dto::EntityType::Person => working.exists(&Key::<PersonPath, ()>::new(id).name().path)?,
+            dto::EntityType::Office => working.exists(&Key::<OfficePath, ()>::new(id).name().path)?,
+        };
+        if !exists {
             // The entity doesn't exist. So we lets insert.
             let name = name
                 .with_context(|| format!("entity {:?}:{} doesn't have a name", entity_type, id))?;
 
-            let repo = RecordRepo::new(conn);
             match entity_type {
                 dto::EntityType::Person => {
-                    repo.working()?
-                        .save(Key::<PersonPath, ()>::new(id).name(), &name.to_string())?;
+                    working.save(Key::<PersonPath, ()>::new(id).name(), &name.to_string())?;
                 }
                 dto::EntityType::Office => {
-                    repo.working()?
-                        .save(Key::<OfficePath, ()>::new(id).name(), &name.to_string())?;
+                    working.save(Key::<OfficePath, ()>::new(id).name(), &name.to_string())?;
                 }
             }
         }
@@ -202,29 +245,26 @@ async fn ingest_entity_id_or_name(
     } else {
         // id not provided. FTS by name and use it or else insert new
         let name = name.context("entity should have a name if id is not provided")?;
-        let query = escape_for_fts(name);
-        let entity = conn
-            .search_entity(Some(entity_type), &query, |row| {
-                Ok(Entity {
-                    typ: row.get(0)?,
-                    id: row.get(1)?,
-                    name: row.get(2)?,
-                })
-            })
-            .optional()?;
+        let query = escape_for_fts(name, false);
+        let mut entity = None;
+        conn.search_entity(Some(entity_type), None, &query, 1, 0, |row| {
+            entity = Some(Entity {
+                typ: row.get(0)?,
+                id: row.get(1)?,
+                name: row.get(2)?,
+            });
+            Ok(())
+        })?;
         if let Some(entity) = entity {
             Ok(entity.id)
         } else {
             let id = derive_id(entity_type, name);
-            let repo = RecordRepo::new(conn);
             match entity_type {
                 dto::EntityType::Person => {
-                    repo.working()?
-                        .save(Key::<PersonPath, ()>::new(&id).name(), &name.to_string())?;
+                    working.save(Key::<PersonPath, ()>::new(&id).name(), &name.to_string())?;
                 }
                 dto::EntityType::Office => {
-                    repo.working()?
-                        .save(Key::<OfficePath, ()>::new(&id).name(), &name.to_string())?;
+                    working.save(Key::<OfficePath, ()>::new(&id).name(), &name.to_string())?;
                 }
             }
 
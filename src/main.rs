@@ -6,25 +6,32 @@ use std::path::PathBuf;
 
 use crate::record::RecordRepo;
 use crate::record::sqlitebe::SqliteBackend;
+use crate::repo::sync::allowlist::Allowlist;
 
 mod augment;
 mod build;
+mod check;
 mod context;
 mod data;
+mod dedupe;
 mod dto;
 mod export;
 mod graph;
 mod import;
 mod ingest;
+mod photos;
 mod record;
 mod render;
 mod repo;
 mod search;
 mod serve;
+mod theme;
+mod webhook;
 
 include_sql!("sql/schema.sql");
 include_sql!("sql/library.sql");
 include_sql!("sql/write.sql");
+include_sql!("sql/migrate.sql");
 
 static_toml! {
     pub static CONFIG = include_toml!("config.toml");
@@ -32,7 +39,7 @@ static_toml! {
 
 impl data::ContactType {
     pub fn icon(&self) -> &'static str {
-        let icons = &CONFIG.icons;
+        let icons = &theme::config().icons;
         match self {
             Self::Address => icons.address,
             Self::Phone => icons.phone,
@@ -44,6 +51,8 @@ impl data::ContactType {
             Self::Facebook => icons.facebook,
             Self::Instagram => icons.instagram,
             Self::Wikidata => icons.wikidata,
+            Self::Telegram => icons.telegram,
+            Self::LinkedIn => icons.linkedin,
         }
     }
 }
@@ -51,8 +60,10 @@ impl data::ContactType {
 impl Default for data::Photo {
     fn default() -> Self {
         Self {
-            url: CONFIG.defaults.photo.url.to_string(),
+            url: theme::config().defaults.photo.url.to_string(),
             attribution: None,
+            license: None,
+            source_url: None,
         }
     }
 }
@@ -71,6 +82,19 @@ enum Commands {
         /// Path to the data directory. Defaults to current directory.
         #[arg(short, long, default_value = ".")]
         data_dir: PathBuf,
+        /// Read the data from a checkout of this git or jj repository, given as
+        /// `<url>@<rev>`, instead of the data directory
+        #[arg(long, conflicts_with = "watch")]
+        source: Option<data::remote::Source>,
+        /// Keep running and re-index entity files as they change
+        #[arg(short, long)]
+        watch: bool,
+        /// Number of threads parsing the entity files. Defaults to the number of CPUs
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Fail on entity fields that are not known, rather than ignoring them
+        #[arg(long)]
+        strict: bool,
     },
 
     /// Search the Index
@@ -80,12 +104,35 @@ enum Commands {
         data_dir: PathBuf,
         /// The search query
         query: String,
+        /// Maximum number of results to show
+        #[arg(short, long, default_value_t = 10, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        limit: usize,
+        /// Number of the most relevant results to skip
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        /// Also show each matched name with the matched terms in `<mark>`
+        #[arg(long)]
+        snippets: bool,
+        /// Also match names with words up to this many edits (at most 2) away
+        /// from the query's
+        #[arg(
+            long,
+            num_args = 0..=1,
+            default_missing_value = "1",
+            conflicts_with = "snippets",
+            value_parser = clap::value_parser!(u8).range(0..=2)
+        )]
+        fuzzy: Option<u8>,
     },
 
     /// Initialize the database
     Init {
         /// Path to the database file
         db: PathBuf,
+        /// Start over in a database that is already initialized, dropping its
+        /// data and generating a new endpoint ID
+        #[arg(long)]
+        force: bool,
     },
 
     /// Import data from the source directory into the database
@@ -93,15 +140,40 @@ enum Commands {
         /// Path to the database file
         db: PathBuf,
         source: PathBuf,
+        /// Import into a database that already has data, keeping the records
+        /// it holds where the source has a value too
+        #[arg(long)]
+        merge: bool,
+        /// With --merge, take the source's value where both have one
+        #[arg(long, requires = "merge")]
+        overwrite: bool,
+        /// With a `.jsonl` source, fail on the first line that is not a
+        /// record instead of leaving it out
+        #[arg(long)]
+        strict: bool,
     },
 
-    /// Export the database into the given directory.
+    /// Export the database into the given directory, or with `--format
+    /// jsonl` the given file.
     ///
     /// The same can be imported into another database using the import command.
     Export {
         /// Path to the database file
         db: PathBuf,
         output: PathBuf,
+        /// Export only this entity, given as `{type}/{id}`
+        #[arg(long)]
+        only: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        format: export::ExportFormat,
+    },
+
+    /// Export the committed tenures and supervisors as a CSV edge list
+    ExportRelations {
+        /// Path to the database file
+        db: PathBuf,
+        /// Path to the CSV file to write
+        output: PathBuf,
     },
 
     /// Render the static website
@@ -109,6 +181,16 @@ enum Commands {
         /// Path to the database file
         db: PathBuf,
         output: PathBuf,
+        /// Format of the index used by the client-side search
+        #[arg(long, value_enum, default_value_t)]
+        search_index: serve::SearchIndex,
+        /// TOML file overriding the embedded config (title, URLs, icons, defaults)
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Only rewrite the pages affected by the commits since the last render
+        /// into `output`, rendering everything if there was none
+        #[arg(long)]
+        incremental: bool,
     },
 
     /// Serve the Web UI for viewing and mantaining the database
@@ -117,6 +199,35 @@ enum Commands {
         db: PathBuf,
         #[arg(short = 'p', long)]
         port: Option<String>,
+        /// Expose Prometheus metrics at /metrics
+        #[arg(long)]
+        metrics: bool,
+        /// Path prefix to serve under, e.g. `/officers` behind a reverse proxy
+        #[arg(long)]
+        base_path: Option<String>,
+        /// TOML file overriding the embedded config (title, URLs, icons, defaults)
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// POST a JSON summary of each commit to this URL
+        #[arg(long)]
+        webhook: Option<String>,
+        /// Serve reads from a separate pool of this many read-only
+        /// connections. Switches the database to WAL mode
+        #[arg(long)]
+        read_connections: Option<u32>,
+        /// Only sync with the peers listed in this file, an endpoint ID per
+        /// line. Manage it with `peers`
+        #[arg(long)]
+        peer_allowlist: Option<PathBuf>,
+    },
+
+    /// List the peers in a sync allowlist file
+    Peers {
+        /// Path to the allowlist file, created when peers are added
+        allowlist: PathBuf,
+        /// Allow these endpoint IDs before listing
+        #[arg(long)]
+        add: Vec<String>,
     },
 
     /// Pull the data from a remote and replace the working copy with it
@@ -125,18 +236,110 @@ enum Commands {
         db: PathBuf,
         #[arg(short = 'p', long)]
         peer: String,
+        /// Times to try again after failing to reach the peer
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
+        /// Seconds an attempt may take to connect to the peer
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// Send the committed data to a remote, to become its working copy
+    Push {
+        /// Path to the database file
+        db: PathBuf,
+        #[arg(short = 'p', long)]
+        peer: String,
+        /// Times to try again after failing to reach the peer
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
+        /// Seconds an attempt may take to connect to the peer
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// Export the nodes added since a commit into a file for seeding a peer
+    ExportDelta {
+        /// Path to the database file
+        db: PathBuf,
+        /// Path to the delta file to write
+        output: PathBuf,
+        /// Hex hash of a commit the peer already has. Exports everything if omitted.
+        #[arg(short = 'f', long)]
+        from: Option<String>,
+    },
+
+    /// Replace the working copy with the tree at a node, given by its hash
+    /// or the start of it
+    Checkout {
+        /// Path to the database file
+        db: PathBuf,
+        hash: String,
+    },
+
+    /// Show the records that differ between two trees
+    Diff {
+        /// Path to the database file
+        db: PathBuf,
+        /// `working`, `committed`, or the hash of a commit or tree node, or
+        /// the start of it
+        #[arg(default_value = "committed")]
+        from: String,
+        /// Like `from`
+        #[arg(default_value = "working")]
+        to: String,
+    },
+
+    /// Import a delta file and replace the working copy with its root
+    ImportDelta {
+        /// Path to the database file
+        db: PathBuf,
+        /// Path to the delta file to read
+        input: PathBuf,
+    },
+
+    /// Show the tree nodes visited while looking up a record
+    #[command(hide = true)]
+    Trace {
+        /// Path to the database file
+        db: PathBuf,
+        /// Path of the record, e.g. `person/alice/name`
+        path: String,
     },
 
     /// Show statistics for the database
     Stats {
         /// Path to the database file
         db: PathBuf,
+        /// Only count the keys under this path prefix, e.g. `person/`
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+
+    /// Rebuild the index tables from the records in the repository
+    Reindex {
+        /// Path to the database file
+        db: PathBuf,
+        /// Index the committed records instead of the working copy
+        #[arg(long)]
+        committed: bool,
+        /// Index only this entity of the working copy, given as `{type}/{id}`
+        #[arg(long, conflicts_with = "committed")]
+        entity: Option<String>,
     },
 
-    /// Compact the database by removing data that is no longer referenced
+    /// Compact the database by removing data that is no longer referenced.
+    /// Do not run it while `serve` or another writer is using the database
     Gc {
         /// Path to the database file
         db: PathBuf,
+        /// First rewrite the working tree without the empty nodes earlier
+        /// deletes may have left in it; committed trees keep theirs
+        #[arg(long)]
+        prune_empty: bool,
+        /// Only show what would be deleted, changing nothing
+        #[arg(long)]
+        dry_run: bool,
     },
 
     Augment {
@@ -165,6 +368,17 @@ enum Commands {
         db: PathBuf,
         /// The path to the value
         path: String,
+        /// Read the last commit rather than the working tree
+        #[arg(long)]
+        committed: bool,
+    },
+
+    /// List the commits that changed the value at the given path
+    History {
+        /// Path to the database file
+        db: PathBuf,
+        /// The path to the value
+        path: String,
     },
 
     /// List keys and values with the given path prefix in JSONL format
@@ -173,6 +387,54 @@ enum Commands {
         db: PathBuf,
         /// The path prefix to list
         prefix: String,
+        /// Start after this path, e.g. the last one of the previous page
+        #[arg(long)]
+        after: Option<String>,
+        /// List at most this many records
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Read the last commit rather than the working tree
+        #[arg(long)]
+        committed: bool,
+    },
+
+    /// List the paths with the given prefix, one per line, without their
+    /// values
+    Keys {
+        /// Path to the database file
+        db: PathBuf,
+        /// The path prefix to list
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Only go this many levels below the prefix, listing the deeper
+        /// paths cut after the last `/` kept
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+
+    /// Print the past tenures of a person from the index, latest first
+    Tenures {
+        /// Path to the database file
+        db: PathBuf,
+        /// ID of the person
+        person: String,
+        /// Only tenures that ended on or after this date
+        #[arg(long)]
+        after: Option<chrono::NaiveDate>,
+        /// Only tenures that started on or before this date
+        #[arg(long)]
+        before: Option<chrono::NaiveDate>,
+    },
+
+    /// Print records whose value matches a regex in JSONL format
+    Grep {
+        /// Path to the database file
+        db: PathBuf,
+        /// The regex to match against each value as displayed
+        pattern: String,
+        /// Only search records with this path prefix
+        #[arg(long, default_value = "")]
+        path_prefix: String,
     },
 
     /// Set a value in the database
@@ -189,14 +451,115 @@ enum Commands {
     Delete {
         /// Path to the database file
         db: PathBuf,
-        /// The path to the value
+        /// The path to the value, or `{type}/{id}` for a whole entity
         path: String,
     },
 
+    /// End a tenure of a person in the working tree, so that they are no
+    /// longer the incumbent of the office
+    CloseTenure {
+        /// Path to the database file
+        db: PathBuf,
+        /// ID of the person
+        person: String,
+        /// ID of the office
+        office: String,
+        /// The day the tenure ended
+        end: chrono::NaiveDate,
+        /// The day the tenure began, if it has one
+        #[arg(long)]
+        start: Option<chrono::NaiveDate>,
+    },
+
     /// Commit the working changes to the database
     Commit {
         /// Path to the database file
         db: PathBuf,
+        /// Describe the changes being committed
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Who is committing. Defaults to the TUDGOI_AUTHOR environment variable
+        #[arg(long)]
+        author: Option<String>,
+        /// POST a JSON summary of the committed changes to this URL
+        #[arg(long)]
+        webhook: Option<String>,
+        /// Also store a hash of the index with the commit, for `doctor` to
+        /// check against the committed records
+        #[arg(long)]
+        snapshot_index: bool,
+    },
+
+    /// Show the commit history, newest first
+    Log {
+        /// Path to the database file
+        db: PathBuf,
+    },
+
+    /// List clusters of entities with similar names as merge candidates
+    FindDuplicates {
+        /// Path to the database file
+        db: PathBuf,
+        /// Minimum trigram similarity, between 0 and 1, for names to match
+        #[arg(long, default_value_t = 0.8)]
+        threshold: f64,
+    },
+
+    /// Look for cycles in the supervisor relations between offices
+    Check {
+        /// Path to the database file
+        db: PathBuf,
+        /// How to report the cycles found
+        #[arg(long, value_enum, default_value_t)]
+        format: check::CheckFormat,
+    },
+
+    /// Request every photo url and report the ones that do not load
+    CheckPhotos {
+        /// Path to the database file
+        db: PathBuf,
+        /// How many photos to request at once
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+        /// Seconds to wait for each photo before giving up on it
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+
+    /// Merge an entity into another, repointing references to it
+    MergeEntities {
+        /// Path to the database file
+        db: PathBuf,
+        /// Type of the entities
+        #[arg(long = "type", default_value = "office")]
+        typ: dto::EntityType,
+        /// ID of the entity to keep
+        keep: String,
+        /// ID of the entity merged into `keep` and deleted
+        drop: String,
+    },
+
+    /// Give an entity a new id, repointing references to it
+    Rename {
+        /// Path to the database file
+        db: PathBuf,
+        /// Type of the entity
+        #[arg(long = "type", default_value = "office")]
+        typ: dto::EntityType,
+        /// Current ID of the entity
+        old_id: String,
+        /// ID to move it to; no entity may have it yet
+        new_id: String,
+    },
+
+    /// Undo the changes a commit made to one entity in the working copy
+    Revert {
+        /// Path to the database file
+        db: PathBuf,
+        /// Hash of the commit, or a unique prefix of it
+        commit: String,
+        /// The entity to revert, as `{type}/{id}`
+        entity: String,
     },
 
     /// Abandon the working changes and revert to the last commit
@@ -205,6 +568,21 @@ enum Commands {
         db: PathBuf,
     },
 
+    /// Check that the index holds exactly what the records imply, both now
+    /// and at the commits whose index was snapshotted
+    #[command(alias = "fsck")]
+    Doctor {
+        /// Path to the database file
+        db: PathBuf,
+    },
+
+    /// Check that every stored tree is intact: each node hashes to its key,
+    /// and its keys are in order and at the right level
+    Verify {
+        /// Path to the database file
+        db: PathBuf,
+    },
+
     /// Show information about the database
     Info {
         /// Path to the database file
@@ -213,9 +591,14 @@ enum Commands {
 
     /// Show the database schema documentation
     Schema,
+
+    /// Print the JSON Schema of the entities read by the JSON ingestor
+    GraphSchema,
 }
 
-#[derive(Clone, ValueEnum)]
+/// A field `augment` can fill in. Photo and Wikipedia are looked up from the
+/// Wikidata ID, so they come after it when augmented together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum Field {
     Wikidata,
     Photo,
@@ -235,21 +618,89 @@ async fn main() -> Result<()> {
     let args = Cli::parse();
 
     match args.command {
-        Commands::Build { data_dir } => build::run(&data_dir).await,
-        Commands::Search { data_dir, query } => search::run(&data_dir, &query).await,
-        Commands::Init { db } => import::init(db.as_path()).with_context(|| "could not run `init`"),
+        Commands::Build {
+            data_dir,
+            source,
+            watch,
+            jobs,
+            strict,
+        } => build::run(&data_dir, source.as_ref(), watch, jobs, strict).await,
+        Commands::Search {
+            data_dir,
+            query,
+            limit,
+            offset,
+            snippets,
+            fuzzy,
+        } => search::run(&data_dir, &query, offset, limit, snippets, fuzzy).await,
+        Commands::Init { db, force } => {
+            import::init(db.as_path(), force).with_context(|| "could not run `init`")
+        }
 
-        Commands::Import { db, source } => {
-            import::run(source.as_path(), db.as_path()).with_context(|| "could not run `import`")
+        Commands::Import {
+            db,
+            source,
+            merge,
+            overwrite,
+            strict,
+        } => {
+            let on_conflict = if merge && !overwrite {
+                import::OnConflict::Keep
+            } else {
+                import::OnConflict::Overwrite
+            };
+            let summary = import::run(source.as_path(), db.as_path(), on_conflict, strict)
+                .with_context(|| "could not run `import`")?;
+            if !summary.unknown_types.is_empty() {
+                eprintln!(
+                    "Skipped entity types this version does not know: {}",
+                    summary.unknown_types.join(", ")
+                );
+            }
+            for (line, e) in &summary.malformed {
+                eprintln!("Skipped line {}: {}", line, e);
+            }
+            if merge {
+                eprintln!(
+                    "{} added, {} skipped, {} overwritten",
+                    summary.added, summary.skipped, summary.overwritten
+                );
+            } else {
+                eprintln!("{} record(s) imported", summary.added + summary.overwritten);
+            }
+            Ok(())
         }
 
-        Commands::Export { db, output } => {
-            export::run(db.as_path(), output.as_path()).with_context(|| "could not run `export`")
+        Commands::Export {
+            db,
+            output,
+            only,
+            format,
+        } => match format {
+            export::ExportFormat::Toml => export::run(&db, &output, only.as_deref()),
+            export::ExportFormat::Jsonl => export::jsonl(&db, &output, only.as_deref()),
         }
+        .with_context(|| "could not run `export`"),
 
-        Commands::Render { db, output } => render::run(db.as_path(), output.as_path())
-            .await
-            .with_context(|| "could not run `render`"),
+        Commands::ExportRelations { db, output } => {
+            export::relations(db.as_path(), output.as_path())
+                .with_context(|| "could not run `export-relations`")
+        }
+
+        Commands::Render {
+            db,
+            output,
+            search_index,
+            config,
+            incremental,
+        } => {
+            if let Some(config) = config {
+                theme::load(&config)?;
+            }
+            render::run(db.as_path(), output.as_path(), search_index, incremental)
+                .await
+                .with_context(|| "could not run `render`")
+        }
         Commands::Augment {
             db,
             source: source_name,
@@ -264,10 +715,19 @@ async fn main() -> Result<()> {
         } => ingest::run(db.as_path(), source, directory.as_deref())
             .with_context(|| "could not run `ingest`"),
 
-        Commands::Get { db, path } => {
+        Commands::Get {
+            db,
+            path,
+            committed,
+        } => {
             let conn = rusqlite::Connection::open(db)?;
             let repo = RecordRepo::new(&conn);
-            let value = repo.working()?.get(&path)?;
+            let tree = if committed {
+                repo.committed()?
+            } else {
+                repo.working()?
+            };
+            let value = tree.get(&path)?;
 
             match value {
                 Some(v) => println!("{}", serde_json::to_string_pretty(&v)?),
@@ -276,13 +736,44 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::List { db, prefix } => {
+        Commands::History { db, path } => {
             let conn = rusqlite::Connection::open(db)?;
             let repo = RecordRepo::new(&conn);
-            let working = repo.working()?;
 
-            for item in working.list(&prefix)? {
-                let (path, value) = item?;
+            for (hash, commit) in repo.history(&path)? {
+                println!(
+                    "{} {}",
+                    hash.to_hex(),
+                    commit.message.as_deref().unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+
+        Commands::List {
+            db,
+            prefix,
+            after,
+            limit,
+            committed,
+        } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::new(&conn);
+            let tree = if committed {
+                repo.committed()?
+            } else {
+                repo.working()?
+            };
+
+            let limit = limit.unwrap_or(usize::MAX);
+            for item in tree.list_page(&prefix, after.as_deref(), limit)? {
+                let (path, value) = match item {
+                    Ok(item) => item,
+                    Err(e) => {
+                        eprintln!("skipping record: {}", e);
+                        continue;
+                    }
+                };
                 let output = serde_json::json!({
                     "path": path,
                     "value": value,
@@ -292,9 +783,81 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::Set { db, path, value } => {
+        Commands::Keys { db, prefix, depth } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::new(&conn);
+            let working = repo.working()?;
+
+            let keys: Box<dyn Iterator<Item = _>> = match depth {
+                Some(depth) => Box::new(working.list_keys_depth(&prefix, depth)?),
+                None => Box::new(working.list_keys(&prefix)?),
+            };
+            for path in keys {
+                match path {
+                    Ok(path) => println!("{}", path),
+                    Err(e) => eprintln!("skipping record: {}", e),
+                }
+            }
+            Ok(())
+        }
+
+        Commands::Tenures {
+            db,
+            person,
+            after,
+            before,
+        } => {
+            let conn = rusqlite::Connection::open(db)?;
+            conn.get_past_tenures(&person, after.as_ref(), before.as_ref(), None, |row| {
+                let office_id: String = row.get(0)?;
+                let start: Option<String> = row.get(2)?;
+                let end: String = row.get(3)?;
+                let acting: bool = row.get(4)?;
+                println!(
+                    "{}\t{}\t{}{}",
+                    office_id,
+                    start.unwrap_or_default(),
+                    end,
+                    if acting { "\tacting" } else { "" }
+                );
+                Ok(())
+            })?;
+            Ok(())
+        }
+
+        Commands::Grep {
+            db,
+            pattern,
+            path_prefix,
+        } => {
+            let pattern = regex::Regex::new(&pattern)
+                .with_context(|| format!("invalid pattern: {}", pattern))?;
             let conn = rusqlite::Connection::open(db)?;
             let repo = RecordRepo::new(&conn);
+            let working = repo.working()?;
+
+            for item in working.list_page(&path_prefix, None, usize::MAX)? {
+                let (path, value) = match item {
+                    Ok(item) => item,
+                    Err(e) => {
+                        eprintln!("skipping record: {}", e);
+                        continue;
+                    }
+                };
+                if pattern.is_match(&value.to_string()) {
+                    let output = serde_json::json!({
+                        "path": path,
+                        "value": value,
+                    });
+                    println!("{}", serde_json::to_string(&output)?);
+                }
+            }
+            Ok(())
+        }
+
+        Commands::Set { db, path, value } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::open(&conn)?;
 
             repo.working()?.save_from_json(&path, &value)?;
             Ok(())
@@ -302,18 +865,128 @@ async fn main() -> Result<()> {
 
         Commands::Delete { db, path } => {
             let conn = rusqlite::Connection::open(db)?;
-            let repo = RecordRepo::new(&conn);
+            let repo = RecordRepo::open(&conn)?;
 
             repo.working()?.delete_path(&path)?;
             Ok(())
         }
 
-        Commands::Commit { db } => {
+        Commands::CloseTenure {
+            db,
+            person,
+            office,
+            end,
+            start,
+        } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::open(&conn)?;
+
+            repo.working()?.close_tenure(&person, &office, start, end)?;
+            Ok(())
+        }
+
+        Commands::Commit {
+            db,
+            message,
+            author,
+            webhook,
+            snapshot_index,
+        } => {
             let conn = rusqlite::Connection::open(db)?;
-            let mut repo = RecordRepo::new(&conn);
+            let mut repo = RecordRepo::open(&conn)?;
 
-            repo.commit()?;
+            let author = author.or_else(record::default_author);
+            let previous = repo.committed()?.commit_id()?;
+            repo.commit(author.as_deref(), message.as_deref())?;
             println!("Changes committed.");
+            if snapshot_index && let Some(hash) = repo.snapshot_index()? {
+                println!("Index snapshot: {}", hash.to_hex());
+            }
+
+            // The commit stands whether or not the webhook can be reached.
+            if let Some(url) = webhook {
+                let notified = match webhook::summarize(&conn, &previous) {
+                    Ok(payload) => webhook::send(&url, &payload).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = notified {
+                    eprintln!("{:#}", e);
+                }
+            }
+            Ok(())
+        }
+
+        Commands::Log { db } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::new(&conn);
+
+            for (hash, commit) in repo.log()? {
+                println!("commit {}", hash.to_hex());
+                if let Some(author) = &commit.author {
+                    println!("Author: {}", author);
+                }
+                if let Some(date) = chrono::DateTime::from_timestamp(commit.timestamp, 0) {
+                    println!("Date:   {}", date.to_rfc2822());
+                }
+                println!("Root:   {}", commit.root.to_hex());
+                if let Some(message) = &commit.message {
+                    println!();
+                    println!("    {}", message);
+                }
+                println!();
+            }
+            Ok(())
+        }
+
+        Commands::FindDuplicates { db, threshold } => dedupe::run(&db, threshold),
+
+        Commands::Check { db, format } => check::run(&db, format),
+
+        Commands::CheckPhotos {
+            db,
+            concurrency,
+            timeout,
+        } => photos::run(&db, concurrency, std::time::Duration::from_secs(timeout)).await,
+
+        Commands::MergeEntities {
+            db,
+            typ,
+            keep,
+            drop,
+        } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::open(&conn)?;
+
+            for change in repo.merge_entity(typ, &keep, &drop)? {
+                println!("{}", change);
+            }
+            Ok(())
+        }
+
+        Commands::Rename {
+            db,
+            typ,
+            old_id,
+            new_id,
+        } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::open(&conn)?;
+
+            for change in repo.working()?.rename_entity(typ, &old_id, &new_id)? {
+                println!("{}", change);
+            }
+            Ok(())
+        }
+
+        Commands::Revert { db, commit, entity } => {
+            dto::parse_entity(&entity).map_err(anyhow::Error::msg)?;
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::open(&conn)?;
+
+            let hash = repo.find_commit(&commit)?;
+            for path in repo.revert_entity(&hash, &entity)? {
+                println!("reverted {}", path);
+            }
             Ok(())
         }
 
@@ -324,6 +997,53 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
+        Commands::Verify { db } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = repo::Repo::new(SqliteBackend::new(&conn));
+            let report = repo.verify()?;
+
+            for (hash, problem) in &report.problems {
+                println!("{}: {}", hash.to_hex(), problem);
+            }
+            println!(
+                "Checked {} node(s), {} problem(s) found.",
+                report.nodes,
+                report.problems.len()
+            );
+
+            if !report.problems.is_empty() {
+                anyhow::bail!("the repository is damaged");
+            }
+            Ok(())
+        }
+
+        Commands::Doctor { db } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let report = RecordRepo::new(&conn).verify_index()?;
+
+            if report.index_matches {
+                println!("Index matches tree {}.", report.indexed.to_hex());
+            } else {
+                println!("Index does not match tree {}.", report.indexed.to_hex());
+            }
+            for commit in &report.mismatched_snapshots {
+                println!(
+                    "Index snapshot of commit {} does not match its tree.",
+                    commit.to_hex()
+                );
+            }
+            println!(
+                "Checked {} index snapshot(s), {} mismatched.",
+                report.snapshots,
+                report.mismatched_snapshots.len()
+            );
+
+            if !report.index_matches || !report.mismatched_snapshots.is_empty() {
+                anyhow::bail!("index drift found; `reindex` rebuilds the index from the records");
+            }
+            Ok(())
+        }
+
         Commands::Info { db } => {
             use crate::repo::backend::Backend;
             let conn = rusqlite::Connection::open(db)?;
@@ -344,7 +1064,7 @@ async fn main() -> Result<()> {
             let endpoint_id = secret_key.public();
 
             let (persons, offices) = conn
-                .get_entity_counts(|row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)))?;
+                .get_entity_counts(true, |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)))?;
 
             println!("Working ref:   {}", working_ref.to_hex());
             println!("Committed ref: {}", committed_ref.to_hex());
@@ -360,35 +1080,187 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::Serve { db, port } => serve::run(db, port.as_deref())
-            .await
-            .with_context(|| "failed to run `serve`"),
+        Commands::GraphSchema => {
+            println!("{}", serde_json::to_string_pretty(&graph::entity_schema())?);
+            Ok(())
+        }
+
+        Commands::Serve {
+            db,
+            port,
+            metrics,
+            base_path,
+            config,
+            webhook,
+            read_connections,
+            peer_allowlist,
+        } => {
+            if let Some(config) = config {
+                theme::load(&config)?;
+            }
+            let peer_allowlist = peer_allowlist
+                .map(|path| {
+                    Allowlist::load(&path)
+                        .with_context(|| format!("could not read peer allowlist {:?}", path))
+                })
+                .transpose()?;
+            serve::run(
+                db,
+                port.as_deref(),
+                metrics,
+                base_path.as_deref(),
+                webhook,
+                read_connections,
+                peer_allowlist,
+            )
+                .await
+                .with_context(|| "failed to run `serve`")
+        }
+
+        Commands::Peers { allowlist, add } => {
+            let mut peers = Allowlist::load(&allowlist)
+                .with_context(|| format!("could not read peer allowlist {:?}", allowlist))?;
+            if !add.is_empty() {
+                for peer in &add {
+                    let peer = peer
+                        .parse::<iroh::EndpointId>()
+                        .map_err(|e| anyhow::anyhow!("failed to parse peer ID {}: {}", peer, e))?;
+                    peers.insert(peer);
+                }
+                peers.save(&allowlist)?;
+            }
+            for peer in peers.iter() {
+                println!("{}", peer);
+            }
+
+            Ok(())
+        }
+
+        Commands::Trace { db, path } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = repo::Repo::new(SqliteBackend::new(&conn));
+            let root = repo.get_ref(repo::RepoRefType::Working)?.commit_id()?;
 
-        Commands::Stats { db } => {
+            let trace = repo.trace_get(&root, path.as_bytes())?;
+            for (depth, (hash, idx)) in trace.iter().enumerate() {
+                println!("{:>3} {} {}", depth, hash, idx);
+            }
+            match repo.get_ref(repo::RepoRefType::Working)?.read(path.as_bytes())? {
+                Some(_) => println!("found at item {}", trace.last().map_or(0, |(_, i)| *i)),
+                None => println!("not found"),
+            }
+
+            Ok(())
+        }
+
+        Commands::Stats { db, prefix } => {
             let conn = rusqlite::Connection::open(db)?;
             let backend = SqliteBackend::new(&conn);
             let repo = repo::Repo::new(backend);
-            let stats = repo.stats()?;
+            let stats = repo.stats(prefix.as_bytes())?;
 
             println!("Repository Statistics:");
             println!("----------------------");
+            if !prefix.is_empty() {
+                println!("Under prefix: {}", prefix);
+            }
             println!("Total Key-Value pairs: {}", stats.key_value_count);
             println!("Total value size: {}", stats.total_value_size);
             println!("Value size distribution:");
             print_binned_distribution(stats.value_size_distribution);
-            println!("");
-            println!("Total nodes in DB: {}", stats.node_count);
-            println!("Total nodes size: {}", stats.total_node_size);
-            println!("Node size distribution:");
-            print_binned_distribution(stats.node_size_distribution);
+            for (key_type, stored) in stats.stored {
+                println!();
+                println!("Total {}s in DB: {}", key_type, stored.count);
+                println!("Total {}s size: {}", key_type, stored.total_size);
+                println!("Size distribution of {}s:", key_type);
+                print_binned_distribution(stored.size_distribution);
+            }
+
+            println!();
+            println!("Field coverage (committed):");
+            for (typ, stats) in RecordRepo::new(&conn).field_stats()? {
+                let typ = typ.as_str();
+                let plural = format!("{}{}s", typ[..1].to_uppercase(), &typ[1..]);
+                for (field, count) in &stats.fields {
+                    // `contact/email` reads as it is; plain kinds take a plural.
+                    let s = if field.contains('/') { "" } else { "s" };
+                    println!(
+                        "  {} with {}{}: {}/{}",
+                        plural, field, s, count, stats.entities
+                    );
+                }
+            }
 
             Ok(())
         }
 
-        Commands::Gc { db } => {
+        Commands::Reindex {
+            db,
+            committed: _,
+            entity: Some(entity),
+        } => {
+            let (typ, id) = dto::parse_entity(&entity).map_err(anyhow::Error::msg)?;
             let conn = rusqlite::Connection::open(db)?;
+            let indexed = RecordRepo::open(&conn)?
+                .working()?
+                .reindex_entity(typ, id)?;
+
+            println!("Indexed {} records.", indexed);
+
+            Ok(())
+        }
+
+        Commands::Reindex {
+            db,
+            committed,
+            entity: None,
+        } => {
+            let mut conn = rusqlite::Connection::open(db)?;
+            let tx = conn.transaction()?;
+            let indexed = {
+                let repo = RecordRepo::new(&tx);
+                let tree = if committed {
+                    repo.committed()?
+                } else {
+                    repo.working()?
+                };
+                repo.rebuild_index(&tree.commit_id()?)?
+            };
+            tx.commit()?;
+
+            println!("Indexed {} records.", indexed);
+
+            Ok(())
+        }
+
+        Commands::Gc {
+            db,
+            prune_empty,
+            dry_run,
+        } => {
+            let mut conn = rusqlite::Connection::open(db)?;
+            if dry_run {
+                // Pruning writes, so it is previewed in a transaction that is
+                // rolled back.
+                let tx = conn.transaction()?;
+                let repo = repo::Repo::new(SqliteBackend::new(&tx));
+                if prune_empty {
+                    let pruned = repo.prune_empty()?;
+                    println!("Would prune {} empty nodes.", pruned);
+                }
+                let garbage = repo.gc_preview()?;
+                println!(
+                    "Would delete {} nodes ({} bytes).",
+                    garbage.nodes, garbage.bytes
+                );
+                return Ok(());
+            }
             let backend = SqliteBackend::new(&conn);
             let repo = repo::Repo::new(backend);
+            if prune_empty {
+                let pruned = repo.prune_empty()?;
+                println!("Pruned {} empty nodes.", pruned);
+            }
             let deleted = repo.gc()?;
 
             println!("Garbage collection finished. Deleted {} nodes.", deleted);
@@ -396,21 +1268,123 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::Pull { db, peer } => {
+        Commands::ExportDelta { db, output, from } => {
+            let from = from
+                .as_deref()
+                .map(repo::Hash::from_hex)
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("failed to parse hash: {}", e))?;
+
+            let conn = rusqlite::Connection::open(db)?;
+            let record_repo = RecordRepo::new(&conn);
+            let working = record_repo.working()?.commit_id()?;
+            if working != record_repo.committed()?.commit_id()? {
+                anyhow::bail!(
+                    "There are uncommitted changes in the database. Please commit them first."
+                );
+            }
+
+            let backend = SqliteBackend::new(&conn);
+            let file = std::fs::File::create(&output)
+                .with_context(|| format!("could not create {:?}", output))?;
+            let count = repo::Repo::new(backend).export_delta(
+                from.as_ref(),
+                &working,
+                std::io::BufWriter::new(file),
+            )?;
+
+            println!("Exported {} nodes up to {}.", count, working.to_hex());
+            Ok(())
+        }
+
+        Commands::ImportDelta { db, input } => {
+            let mut conn = rusqlite::Connection::open(&db)?;
+            let old_hash = {
+                let record_repo = RecordRepo::open(&conn)?;
+                let old_hash = record_repo.working()?.commit_id()?;
+                if old_hash != record_repo.committed()?.commit_id()? {
+                    anyhow::bail!(
+                        "There are uncommitted changes in the database. Please commit them first."
+                    );
+                }
+
+                let file = std::fs::File::open(&input)
+                    .with_context(|| format!("could not open {:?}", input))?;
+                let repo = repo::Repo::new(SqliteBackend::new(&conn));
+                let root = repo.import_delta(std::io::BufReader::new(file))?;
+                repo.set_ref(repo::RepoRefType::Working, &root)?;
+                println!("Imported delta up to {}.", root.to_hex());
+
+                old_hash
+            };
+
+            update_indexes(&mut conn, &old_hash)?;
+
+            Ok(())
+        }
+
+        Commands::Diff { db, from, to } => {
+            let conn = rusqlite::Connection::open(db)?;
+            let repo = RecordRepo::new(&conn);
+            let from = repo.resolve(&from)?;
+            let to = repo.resolve(&to)?;
+
+            for diff in from.iterate_diff(&to)? {
+                println!("{}", diff?);
+            }
+            Ok(())
+        }
+
+        Commands::Checkout { db, hash } => {
+            let mut conn = rusqlite::Connection::open(&db)?;
+            let (old_hash, root) = {
+                let record_repo = RecordRepo::open(&conn)?;
+                let old_hash = record_repo.working()?.commit_id()?;
+                if old_hash != record_repo.committed()?.commit_id()? {
+                    anyhow::bail!(
+                        "There are uncommitted changes in the database. Please commit them first."
+                    );
+                }
+
+                let repo = repo::Repo::new(SqliteBackend::new(&conn));
+                let root = repo.find_node(&hash)?;
+                repo.set_ref(repo::RepoRefType::Working, &root)?;
+
+                (old_hash, root)
+            };
+
+            update_indexes(&mut conn, &old_hash)?;
+            println!("Checked out {}.", root.to_hex());
+
+            Ok(())
+        }
+
+        Commands::Pull {
+            db,
+            peer,
+            retries,
+            timeout,
+        } => {
             let peer_id = peer
                 .parse::<iroh::EndpointId>()
                 .map_err(|e| anyhow::anyhow!("failed to parse peer ID: {}", e))?;
 
             // 1. Capture old state
             let mut conn = rusqlite::Connection::open(&db)?;
-            let repo = RecordRepo::new(&conn);
+            let repo = RecordRepo::open(&conn)?;
             let old_hash = repo.working()?.commit_id()?;
 
             // 2. Pull
             let manager = r2d2_sqlite::SqliteConnectionManager::file(&db);
             let pool = r2d2::Pool::new(manager)?;
             let backend = crate::record::sqlitebe::SqlitePoolBackend::new(pool);
-            let client = repo::sync::client::RepoClient::new(backend);
+            let client = repo::sync::client::RepoClient::new(backend).with_retry(
+                repo::sync::client::RetryPolicy {
+                    retries,
+                    timeout: std::time::Duration::from_secs(timeout),
+                    ..Default::default()
+                },
+            );
 
             println!("Pulling from {}...", peer_id);
             client
@@ -419,61 +1393,63 @@ async fn main() -> Result<()> {
                 .map_err(|e| anyhow::anyhow!("pull failed: {}", e))?;
 
             // 3. Re-index
-            let diffs = {
-                let new_working = repo.working()?;
-                let new_hash = new_working.commit_id()?;
-
-                if old_hash != new_hash {
-                    println!("Updating indexes...");
-                    let old_working = repo
-                        .get_at(&old_hash)
-                        .map_err(|e| anyhow::anyhow!("failed to get old ref: {}", e))?;
-
-                    old_working
-                        .iterate_diff(&new_working)
-                        .map_err(|e| anyhow::anyhow!("diff failed: {}", e))?
-                        .collect::<Result<Vec<_>, _>>()
-                        .map_err(|e| anyhow::anyhow!("diff iteration failed: {}", e))?
-                } else {
-                    Vec::new()
-                }
-            };
+            update_indexes(&mut conn, &old_hash)?;
 
-            if !diffs.is_empty() {
-                let mut diffs = diffs;
-                diffs.sort_by_key(|diff| {
-                    use crate::record::{RecordDiff, RecordKey};
-                    match diff {
-                        RecordDiff::Added(RecordKey::Name(_), _)
-                        | RecordDiff::Changed(RecordKey::Name(_), _, _) => 0,
+            println!("Pull complete.");
 
-                        RecordDiff::Added(_, _) | RecordDiff::Changed(_, _, _) => 1,
+            Ok(())
+        }
 
-                        RecordDiff::Removed(RecordKey::Name(_), _) => 3,
+        Commands::Push {
+            db,
+            peer,
+            retries,
+            timeout,
+        } => {
+            let peer_id = peer
+                .parse::<iroh::EndpointId>()
+                .map_err(|e| anyhow::anyhow!("failed to parse peer ID: {}", e))?;
 
-                        RecordDiff::Removed(_, _) => 2,
-                    }
-                });
+            let manager = r2d2_sqlite::SqliteConnectionManager::file(&db);
+            let pool = r2d2::Pool::new(manager)?;
+            let backend = crate::record::sqlitebe::SqlitePoolBackend::new(pool);
+            let client = repo::sync::client::RepoClient::new(backend).with_retry(
+                repo::sync::client::RetryPolicy {
+                    retries,
+                    timeout: std::time::Duration::from_secs(timeout),
+                    ..Default::default()
+                },
+            );
 
-                let tx = conn.transaction()?;
-                for diff in diffs {
-                    match diff {
-                        crate::record::RecordDiff::Added(k, v) => k.update_index(&tx, &v)?,
-                        crate::record::RecordDiff::Changed(k, _, v) => k.update_index(&tx, &v)?,
-                        crate::record::RecordDiff::Removed(k, _) => k.delete_index(&tx)?,
-                    }
-                }
-                tx.commit()?;
-                println!("Indexes updated.");
+            println!("Pushing to {}...", peer_id);
+            match client
+                .push(peer_id)
+                .await
+                .map_err(|e| anyhow::anyhow!("push failed: {}", e))?
+            {
+                Some(count) => println!("Push complete. Sent {} node(s).", count),
+                None => println!("Already up to date."),
             }
 
-            println!("Pull complete.");
-
             Ok(())
         }
     }
 }
 
+/// Bring the index tables in line with the working ref after it was moved
+/// away from `old_hash`.
+fn update_indexes(conn: &mut rusqlite::Connection, old_hash: &repo::Hash) -> Result<()> {
+    let tx = conn.transaction()?;
+    let updated = RecordRepo::new(&tx).reindex_from(old_hash)?;
+    tx.commit()?;
+
+    if updated > 0 {
+        println!("Updated indexes for {} records.", updated);
+    }
+
+    Ok(())
+}
+
 fn print_binned_distribution(dist: std::collections::BTreeMap<usize, usize>) {
     if dist.is_empty() {
         return;
@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+
+use crate::{LibrarySql, dto};
+
+/// A photo whose url did not answer with success.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct DeadPhoto {
+    #[serde(rename = "type")]
+    pub typ: dto::EntityType,
+    pub id: String,
+    pub url: String,
+    pub status: PhotoStatus,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PhotoStatus {
+    /// The HTTP status the server answered with.
+    Code(u16),
+    /// Why no answer came: `timeout` or the error.
+    Failed(String),
+}
+
+/// Request every photo url in the index and print a JSON line for each that
+/// is not reachable. Fails if any are found.
+pub async fn run(db: &Path, concurrency: usize, timeout: Duration) -> Result<()> {
+    let photos = {
+        let conn = rusqlite::Connection::open(db)?;
+        let mut photos = Vec::new();
+        conn.get_entity_photo_urls(|row| {
+            photos.push((row.get(0)?, row.get(1)?, row.get(2)?));
+            Ok(())
+        })?;
+        photos
+    };
+    let total = photos.len();
+
+    let dead = check(photos, concurrency, timeout).await?;
+    for photo in &dead {
+        println!("{}", serde_json::to_string(photo)?);
+    }
+    eprintln!("Checked {} photo(s), {} unreachable.", total, dead.len());
+
+    if !dead.is_empty() {
+        anyhow::bail!("found {} unreachable photo(s)", dead.len());
+    }
+
+    Ok(())
+}
+
+/// The photos among `photos` whose url does not answer with success within
+/// `timeout`, requesting at most `concurrency` at a time. In the order given.
+pub async fn check(
+    photos: Vec<(dto::EntityType, String, String)>,
+    concurrency: usize,
+    timeout: Duration,
+) -> Result<Vec<DeadPhoto>> {
+    // Wikimedia turns away requests without a user agent.
+    let client = Client::builder()
+        .user_agent(concat!("tudgoi/", env!("CARGO_PKG_VERSION")))
+        .timeout(timeout)
+        .build()?;
+
+    let statuses: Vec<_> = futures::stream::iter(photos)
+        .map(|(typ, id, url)| {
+            let client = &client;
+            async move {
+                let status = status(client, &url).await;
+                (typ, id, url, status)
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    Ok(statuses
+        .into_iter()
+        .filter_map(|(typ, id, url, status)| {
+            status.map(|status| DeadPhoto {
+                typ,
+                id,
+                url,
+                status,
+            })
+        })
+        .collect())
+}
+
+/// `None` if `url` answers with success, else what went wrong.
+async fn status(client: &Client, url: &str) -> Option<PhotoStatus> {
+    let mut response = client.head(url).send().await;
+    // Some servers do not implement HEAD; they may still serve the image.
+    if matches!(&response, Ok(r) if r.status() == StatusCode::METHOD_NOT_ALLOWED) {
+        response = client.get(url).send().await;
+    }
+
+    match response {
+        Ok(r) if r.status().is_success() => None,
+        Ok(r) => Some(PhotoStatus::Code(r.status().as_u16())),
+        Err(e) if e.is_timeout() => Some(PhotoStatus::Failed("timeout".to_string())),
+        Err(e) => Some(PhotoStatus::Failed(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::StatusCode, routing::get};
+
+    #[tokio::test]
+    async fn test_check() {
+        let app = Router::new()
+            .route("/ok.jpg", get(|| async { "jpeg" }))
+            .route("/gone.jpg", get(|| async { StatusCode::GONE }))
+            .route(
+                "/slow.jpg",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "jpeg"
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let photo = |id: &str, path: &str| {
+            (
+                dto::EntityType::Person,
+                id.to_string(),
+                format!("{}/{}", base, path),
+            )
+        };
+        let photos = vec![
+            photo("a", "ok.jpg"),
+            photo("b", "gone.jpg"),
+            photo("c", "missing.jpg"),
+            photo("d", "slow.jpg"),
+        ];
+
+        let dead = check(photos, 2, Duration::from_millis(500)).await.unwrap();
+        let statuses: Vec<_> = dead.iter().map(|p| (p.id.as_str(), &p.status)).collect();
+        assert_eq!(
+            statuses,
+            [
+                ("b", &PhotoStatus::Code(410)),
+                ("c", &PhotoStatus::Code(404)),
+                ("d", &PhotoStatus::Failed("timeout".to_string())),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&dead[0]).unwrap(),
+            format!(
+                r#"{{"type":"person","id":"b","url":"{}/gone.jpg","status":410}}"#,
+                base
+            )
+        );
+    }
+}
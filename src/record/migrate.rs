@@ -0,0 +1,63 @@
+use rusqlite::Connection;
+
+use crate::MigrateSql;
+
+/// The version of the index tables, and of what the records index into them,
+/// that this build writes. Databases behind it are migrated and reindexed on
+/// open.
+pub const INDEX_VERSION: u32 = 8;
+
+/// The index version of `conn`, kept in SQLite's `user_version`, which is 0
+/// for databases from before it was tracked.
+pub fn index_version(conn: &Connection) -> Result<u32, rusqlite::Error> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+pub fn set_index_version(conn: &Connection, version: u32) -> Result<(), rusqlite::Error> {
+    conn.pragma_update(None, "user_version", version)
+}
+
+/// Add whatever the index tables of `conn` lack compared to the current
+/// schema. Every step leaves an up to date database as it is.
+pub fn ensure_index_tables(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.ensure_office_lifecycle()?;
+    ensure_office_supervisor_key(conn)?;
+    // Names used to be searched for as they are shown.
+    if !has_column(conn, "entity", "search_name")? {
+        conn.add_entity_search_name()?;
+        crate::ingest::create_entity_index(conn, crate::theme::config().search_tokenizer)?;
+    }
+    // Tenures could not be acting in the first databases.
+    if !has_column(conn, "person_office_tenure", "acting")? {
+        conn.add_tenure_acting()?;
+    }
+    // Nor did photos credit their license and source page.
+    if !has_column(conn, "entity_photo", "license")? {
+        conn.add_photo_credit()?;
+    }
+    conn.ensure_office_jurisdiction()?;
+    conn.ensure_person_honorific()?;
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        (table, column),
+        |row| row.get(0),
+    )
+}
+
+/// Databases created before an office could have several supervisors per
+/// relation key `office_supervisor` on the office and relation alone.
+fn ensure_office_supervisor_key(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let keyed: bool = conn.query_row(
+        "SELECT pk > 0 FROM pragma_table_info('office_supervisor') WHERE name = 'supervisor_office_id'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !keyed {
+        conn.rekey_office_supervisor()?;
+    }
+    Ok(())
+}
@@ -1,16 +1,19 @@
+pub mod migrate;
 pub mod sqlitebe;
 
-use crate::{WriteSql, repo::RepoRef};
+use crate::{SchemaSql, WriteSql, repo::RepoRef};
 use chrono::NaiveDate;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::marker::PhantomData;
 use std::str::FromStr;
 use thiserror::Error;
 
 use crate::{
     data, dto,
-    repo::{Hash, Repo, RepoError, RepoRefType},
+    repo::{Commit, Hash, Limits, Repo, RepoError, RepoRefType},
+    theme,
 };
 use sqlitebe::{SqliteBackend, SqliteBackendError};
 
@@ -30,6 +33,24 @@ pub enum RecordRepoError {
 
     #[error("invalid path: {0}")]
     InvalidPath(String),
+
+    #[error("key is not valid UTF-8: {0}")]
+    InvalidKey(String),
+
+    #[error("entity not found: {0}")]
+    EntityNotFound(String),
+
+    #[error("entity already exists: {0}")]
+    EntityExists(String),
+
+    #[error("no commit matches `{0}`")]
+    UnknownCommit(String),
+
+    #[error("commit {0} has no parent to revert against")]
+    NoParent(String),
+
+    #[error("changed since the commit, not reverting: {}", .0.join(", "))]
+    RevertConflict(Vec<String>),
 }
 
 impl From<SqliteBackendError> for RecordRepoError {
@@ -45,7 +66,11 @@ pub enum RecordValue {
     Photo(data::Photo),
     Contact(String),
     Supervisor(String),
-    Tenure(Option<NaiveDate>),
+    Tenure(TenureValue),
+    Establishment(NaiveDate),
+    Abolition(NaiveDate),
+    Jurisdiction(String),
+    Honorific(String),
 }
 
 impl std::fmt::Display for RecordValue {
@@ -56,12 +81,20 @@ impl std::fmt::Display for RecordValue {
             RecordValue::Contact(v) => write!(f, "{}", v),
             RecordValue::Supervisor(v) => write!(f, "{}", v),
             RecordValue::Tenure(v) => {
-                if let Some(date) = v {
-                    write!(f, "{}", date)
+                if let Some(date) = v.end {
+                    write!(f, "{}", date)?;
                 } else {
-                    write!(f, "Present")
+                    write!(f, "Present")?;
                 }
+                if v.acting {
+                    write!(f, " (acting)")?;
+                }
+                Ok(())
             }
+            RecordValue::Establishment(v) => write!(f, "{}", v),
+            RecordValue::Abolition(v) => write!(f, "{}", v),
+            RecordValue::Jurisdiction(v) => write!(f, "{}", v),
+            RecordValue::Honorific(v) => write!(f, "{}", v),
         }
     }
 }
@@ -72,7 +105,11 @@ pub enum RecordKey {
     Photo(Key<PhotoPath, data::Photo>),
     Contact(Key<ContactPath, String>),
     Supervisor(Key<SupervisorPath, String>),
-    Tenure(Key<TenurePath, Option<NaiveDate>>),
+    Tenure(Key<TenurePath, TenureValue>),
+    Establishment(Key<EstablishmentPath, NaiveDate>),
+    Abolition(Key<AbolitionPath, NaiveDate>),
+    Jurisdiction(Key<JurisdictionPath, String>),
+    Honorific(Key<HonorificPath, String>),
 }
 
 impl RecordKey {
@@ -83,6 +120,10 @@ impl RecordKey {
             RecordKey::Contact(k) => &k.path,
             RecordKey::Supervisor(k) => &k.path,
             RecordKey::Tenure(k) => &k.path,
+            RecordKey::Establishment(k) => &k.path,
+            RecordKey::Abolition(k) => &k.path,
+            RecordKey::Jurisdiction(k) => &k.path,
+            RecordKey::Honorific(k) => &k.path,
         }
     }
 
@@ -103,6 +144,26 @@ impl RecordKey {
             RecordKey::Contact(k) => (k.entity_type, k.entity_id.clone()),
             RecordKey::Supervisor(k) => (k.entity_type, k.entity_id.clone()),
             RecordKey::Tenure(k) => (k.entity_type, k.entity_id.clone()),
+            RecordKey::Establishment(k) => (k.entity_type, k.entity_id.clone()),
+            RecordKey::Abolition(k) => (k.entity_type, k.entity_id.clone()),
+            RecordKey::Jurisdiction(k) => (k.entity_type, k.entity_id.clone()),
+            RecordKey::Honorific(k) => (k.entity_type, k.entity_id.clone()),
+        }
+    }
+
+    /// The kind of record this is, e.g. `photo` or `contact/email`: the
+    /// field without what tells records of it apart.
+    pub fn kind(&self) -> String {
+        match self {
+            RecordKey::Name(_) => "name".to_string(),
+            RecordKey::Photo(_) => "photo".to_string(),
+            RecordKey::Contact(k) => format!("contact/{}", k.state.typ),
+            RecordKey::Supervisor(k) => format!("supervisor/{}", k.state.relation),
+            RecordKey::Tenure(_) => "tenure".to_string(),
+            RecordKey::Establishment(_) => "establishment".to_string(),
+            RecordKey::Abolition(_) => "abolition".to_string(),
+            RecordKey::Jurisdiction(_) => "jurisdiction".to_string(),
+            RecordKey::Honorific(_) => "honorific".to_string(),
         }
     }
 
@@ -117,6 +178,12 @@ impl RecordKey {
             (RecordKey::Contact(k), RecordValue::Contact(v)) => k.update_index(conn, v),
             (RecordKey::Supervisor(k), RecordValue::Supervisor(v)) => k.update_index(conn, v),
             (RecordKey::Tenure(k), RecordValue::Tenure(v)) => k.update_index(conn, v),
+            (RecordKey::Establishment(k), RecordValue::Establishment(v)) => {
+                k.update_index(conn, v)
+            }
+            (RecordKey::Abolition(k), RecordValue::Abolition(v)) => k.update_index(conn, v),
+            (RecordKey::Jurisdiction(k), RecordValue::Jurisdiction(v)) => k.update_index(conn, v),
+            (RecordKey::Honorific(k), RecordValue::Honorific(v)) => k.update_index(conn, v),
             _ => Err(RecordRepoError::InvalidPath(
                 "Key/Value type mismatch".to_string(),
             )),
@@ -130,6 +197,10 @@ impl RecordKey {
             RecordKey::Contact(k) => k.delete_index(conn),
             RecordKey::Supervisor(k) => k.delete_index(conn),
             RecordKey::Tenure(k) => k.delete_index(conn),
+            RecordKey::Establishment(k) => k.delete_index(conn),
+            RecordKey::Abolition(k) => k.delete_index(conn),
+            RecordKey::Jurisdiction(k) => k.delete_index(conn),
+            RecordKey::Honorific(k) => k.delete_index(conn),
         }
     }
 }
@@ -151,6 +222,29 @@ impl RecordDiff {
     }
 }
 
+/// A line like `+ person/p1/name "Alice"`, with the values as JSON.
+impl std::fmt::Display for RecordDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = |v: &RecordValue| serde_json::to_string(v).map_err(|_| std::fmt::Error);
+        match self {
+            RecordDiff::Added(k, v) => write!(f, "+ {} {}", k.path(), json(v)?),
+            RecordDiff::Changed(k, old, new) => {
+                write!(f, "~ {} {} -> {}", k.path(), json(old)?, json(new)?)
+            }
+            RecordDiff::Removed(k, v) => write!(f, "- {} {}", k.path(), json(v)?),
+        }
+    }
+}
+
+/// How complete the records of one entity type are.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FieldStats {
+    /// Entities with any record.
+    pub entities: usize,
+    /// Entities with at least one record of each [`RecordKey::kind`].
+    pub fields: BTreeMap<String, usize>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Key<State, Schema> {
     pub entity_type: dto::EntityType,
@@ -161,10 +255,12 @@ pub struct Key<State, Schema> {
 }
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct PersonPath {
     pub id: Option<String>,
 }
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct OfficePath {
     pub id: Option<String>,
 }
@@ -179,12 +275,24 @@ pub struct ContactPath {
 #[derive(Clone, Debug)]
 pub struct SupervisorPath {
     pub relation: data::SupervisingRelation,
+    /// `None` for records written before an office could have several
+    /// supervisors per relation, whose path ends at the relation, until the
+    /// key is resolved against the supervisor the record holds.
+    pub supervisor_office_id: Option<String>,
 }
 #[derive(Clone, Debug)]
 pub struct TenurePath {
     pub office_id: String,
     pub start: Option<NaiveDate>,
 }
+#[derive(Clone, Copy, Debug)]
+pub struct EstablishmentPath;
+#[derive(Clone, Copy, Debug)]
+pub struct AbolitionPath;
+#[derive(Clone, Copy, Debug)]
+pub struct JurisdictionPath;
+#[derive(Clone, Copy, Debug)]
+pub struct HonorificPath;
 
 pub trait ParseKeyState: Sized {
     fn parse(parts: &[&str]) -> Result<Self, RecordRepoError>;
@@ -231,7 +339,7 @@ impl ParseKeyState for ContactPath {
 
 impl ParseKeyState for SupervisorPath {
     fn parse(parts: &[&str]) -> Result<Self, RecordRepoError> {
-        if parts.len() != 2 || parts[0] != "supervisor" {
+        if !(2..=3).contains(&parts.len()) || parts[0] != "supervisor" {
             return Err(RecordRepoError::InvalidPath(format!(
                 "Invalid supervisor path: {:?}",
                 parts
@@ -240,7 +348,11 @@ impl ParseKeyState for SupervisorPath {
         let relation = data::SupervisingRelation::from_str(parts[1]).map_err(|_| {
             RecordRepoError::InvalidPath(format!("Invalid supervisor relation: {}", parts[1]))
         })?;
-        Ok(SupervisorPath { relation })
+        let supervisor_office_id = parts.get(2).map(|id| id.to_string());
+        Ok(SupervisorPath {
+            relation,
+            supervisor_office_id,
+        })
     }
 }
 
@@ -264,6 +376,110 @@ impl ParseKeyState for TenurePath {
     }
 }
 
+impl Key<TenurePath, TenureValue> {
+    /// The tenure this key and its value describe together.
+    pub fn to_tenure(&self, value: TenureValue) -> data::Tenure {
+        data::Tenure {
+            office_id: self.state.office_id.clone(),
+            start: self.state.start.map(|d| d.to_string()),
+            end: value.end.map(|d| d.to_string()),
+            acting: value.acting,
+        }
+    }
+}
+
+/// The value of a tenure record: when it ended, if it has, and whether the
+/// office was only held in an acting capacity.
+///
+/// Tenures that are not acting are stored as the bare end date, which is all
+/// a tenure record held before the flag was added. Acting tenures append the
+/// flag, so existing records and their hashes stay as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenureValue {
+    pub end: Option<NaiveDate>,
+    pub acting: bool,
+}
+
+impl From<Option<NaiveDate>> for TenureValue {
+    fn from(end: Option<NaiveDate>) -> Self {
+        TenureValue { end, acting: false }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ActingTenure {
+    end: Option<NaiveDate>,
+    acting: bool,
+}
+
+/// The JSON accepted for a tenure: a bare end date, or one with the flag.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TenureJson {
+    End(Option<NaiveDate>),
+    Full(ActingTenure),
+}
+
+impl TenureValue {
+    fn from_postcard(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        // An end date alone leaves nothing to read the flag from.
+        match postcard::from_bytes::<(Option<NaiveDate>, bool)>(bytes) {
+            Ok((end, acting)) => Ok(TenureValue { end, acting }),
+            Err(postcard::Error::DeserializeUnexpectedEnd) => {
+                Ok(postcard::from_bytes::<Option<NaiveDate>>(bytes)?.into())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(match serde_json::from_str(json)? {
+            TenureJson::End(end) => end.into(),
+            TenureJson::Full(ActingTenure { end, acting }) => TenureValue { end, acting },
+        })
+    }
+}
+
+impl Serialize for TenureValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.acting {
+            self.end.serialize(serializer)
+        } else if serializer.is_human_readable() {
+            ActingTenure {
+                end: self.end,
+                acting: self.acting,
+            }
+            .serialize(serializer)
+        } else {
+            (self.end, self.acting).serialize(serializer)
+        }
+    }
+}
+
+impl ParseKeyState for EstablishmentPath {
+    fn parse(_parts: &[&str]) -> Result<Self, RecordRepoError> {
+        Ok(EstablishmentPath)
+    }
+}
+
+impl ParseKeyState for AbolitionPath {
+    fn parse(_parts: &[&str]) -> Result<Self, RecordRepoError> {
+        Ok(AbolitionPath)
+    }
+}
+
+impl ParseKeyState for JurisdictionPath {
+    fn parse(_parts: &[&str]) -> Result<Self, RecordRepoError> {
+        Ok(JurisdictionPath)
+    }
+}
+
+impl ParseKeyState for HonorificPath {
+    fn parse(_parts: &[&str]) -> Result<Self, RecordRepoError> {
+        Ok(HonorificPath)
+    }
+}
+
 pub trait EntityPathTrait: ParseKeyState {}
 
 impl Key<PersonPath, ()> {
@@ -293,7 +509,7 @@ impl Key<PersonPath, ()> {
         &self,
         office_id: &str,
         start: Option<NaiveDate>,
-    ) -> Key<TenurePath, Option<NaiveDate>> {
+    ) -> Key<TenurePath, TenureValue> {
         Key {
             entity_type: self.entity_type,
             entity_id: self.entity_id.clone(),
@@ -310,6 +526,16 @@ impl Key<PersonPath, ()> {
             _marker: PhantomData,
         }
     }
+
+    pub fn honorific(&self) -> Key<HonorificPath, String> {
+        Key {
+            entity_type: self.entity_type,
+            entity_id: self.entity_id.clone(),
+            path: format!("{}/honorific", self.path),
+            state: HonorificPath,
+            _marker: PhantomData,
+        }
+    }
 }
 
 impl EntityPathTrait for PersonPath {}
@@ -337,12 +563,49 @@ impl Key<OfficePath, ()> {
         }
     }
 
-    pub fn supervisor(&self, relation: data::SupervisingRelation) -> Key<SupervisorPath, String> {
+    pub fn supervisor(
+        &self,
+        relation: data::SupervisingRelation,
+        supervisor_office_id: &str,
+    ) -> Key<SupervisorPath, String> {
+        Key {
+            entity_type: self.entity_type,
+            entity_id: self.entity_id.clone(),
+            path: format!("{}/supervisor/{}/{}", self.path, relation, supervisor_office_id),
+            state: SupervisorPath {
+                relation,
+                supervisor_office_id: Some(supervisor_office_id.to_string()),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn establishment(&self) -> Key<EstablishmentPath, NaiveDate> {
+        Key {
+            entity_type: self.entity_type,
+            entity_id: self.entity_id.clone(),
+            path: format!("{}/establishment", self.path),
+            state: EstablishmentPath,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn abolition(&self) -> Key<AbolitionPath, NaiveDate> {
+        Key {
+            entity_type: self.entity_type,
+            entity_id: self.entity_id.clone(),
+            path: format!("{}/abolition", self.path),
+            state: AbolitionPath,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn jurisdiction(&self) -> Key<JurisdictionPath, String> {
         Key {
             entity_type: self.entity_type,
             entity_id: self.entity_id.clone(),
-            path: format!("{}/supervisor/{}", self.path, relation),
-            state: SupervisorPath { relation },
+            path: format!("{}/jurisdiction", self.path),
+            state: JurisdictionPath,
             _marker: PhantomData,
         }
     }
@@ -390,7 +653,9 @@ pub trait ValueIndexer<T> {
 
 impl ValueIndexer<String> for Key<NamePath, String> {
     fn update_index(&self, conn: &Connection, value: &String) -> Result<(), RecordRepoError> {
-        conn.save_entity_name(&self.entity_type, &self.entity_id, value)?;
+        // Shown as written, but searched for in its normalized form.
+        let search_name = crate::ingest::normalize_for_search(value);
+        conn.save_entity_name(&self.entity_type, &self.entity_id, value, &search_name)?;
         Ok(())
     }
     fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
@@ -406,6 +671,8 @@ impl ValueIndexer<data::Photo> for Key<PhotoPath, data::Photo> {
             &self.entity_id,
             &value.url,
             value.attribution.as_deref(),
+            value.license.as_deref(),
+            value.source_url.as_deref(),
         )?;
         Ok(())
     }
@@ -415,9 +682,55 @@ impl ValueIndexer<data::Photo> for Key<PhotoPath, data::Photo> {
     }
 }
 
+/// Check that `value` is a contact of type `typ`, where one is entered by
+/// hand. Contacts already stored are not checked again when indexed.
+pub fn validate_contact(typ: &data::ContactType, value: &str) -> Result<(), RecordRepoError> {
+    typ.validate(value)
+        .map_err(|e| RecordRepoError::InvalidPath(format!("{}: {}", typ, e)))
+}
+
+/// `raw` in E.164 form, e.g. `+911123012345`, so that the index holds each
+/// number one way however it was typed. Numbers without a country code are
+/// taken to be Indian. Returned as given if it does not look like a number.
+fn normalize_phone(raw: &str) -> String {
+    let trimmed = raw.trim();
+    let international = trimmed.starts_with('+');
+    let mut digits = String::new();
+    for c in trimmed.trim_start_matches('+').chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return raw.to_string(),
+        }
+    }
+
+    let national = if international {
+        None
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        digits = rest.to_string();
+        None
+    } else {
+        Some(digits.strip_prefix('0').unwrap_or(&digits))
+    };
+    let number = match national {
+        Some(national) if national.len() == 10 => format!("91{}", national),
+        Some(_) => return raw.to_string(),
+        None => digits,
+    };
+    if !(8..=15).contains(&number.len()) {
+        return raw.to_string();
+    }
+
+    format!("+{}", number)
+}
+
 impl ValueIndexer<String> for Key<ContactPath, String> {
     fn update_index(&self, conn: &Connection, value: &String) -> Result<(), RecordRepoError> {
-        conn.save_entity_contact(&self.entity_type, &self.entity_id, &self.state.typ, value)?;
+        let value = match self.state.typ {
+            data::ContactType::Phone => normalize_phone(value),
+            _ => value.clone(),
+        };
+        conn.save_entity_contact(&self.entity_type, &self.entity_id, &self.state.typ, &value)?;
         Ok(())
     }
     fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
@@ -428,26 +741,40 @@ impl ValueIndexer<String> for Key<ContactPath, String> {
 
 impl ValueIndexer<String> for Key<SupervisorPath, String> {
     fn update_index(&self, conn: &Connection, value: &String) -> Result<(), RecordRepoError> {
+        // A record of the old shape may be given another supervisor, whose
+        // row replaces the one it held before.
+        if let Some(indexed) = &self.state.supervisor_office_id
+            && indexed != value
+        {
+            conn.delete_office_supervisor(&self.entity_id, &self.state.relation, indexed)?;
+        }
         conn.save_office_supervisor(&self.entity_id, &self.state.relation, value)?;
         Ok(())
     }
     fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
-        conn.delete_office_supervisor(&self.entity_id, &self.state.relation)?;
+        // An unresolved key of the old shape has no record, so no row either.
+        if let Some(supervisor_office_id) = &self.state.supervisor_office_id {
+            conn.delete_office_supervisor(
+                &self.entity_id,
+                &self.state.relation,
+                supervisor_office_id,
+            )?;
+        }
         Ok(())
     }
 }
 
-impl ValueIndexer<Option<NaiveDate>> for Key<TenurePath, Option<NaiveDate>> {
-    fn update_index(
-        &self,
-        conn: &Connection,
-        value: &Option<NaiveDate>,
-    ) -> Result<(), RecordRepoError> {
+impl ValueIndexer<TenureValue> for Key<TenurePath, TenureValue> {
+    fn update_index(&self, conn: &Connection, value: &TenureValue) -> Result<(), RecordRepoError> {
+        // The table has no key to upsert on, so a replaced tenure would
+        // otherwise be indexed twice.
+        self.delete_index(conn)?;
         conn.save_tenure(
             &self.entity_id,
             &self.state.office_id,
             self.state.start.as_ref(),
-            value.as_ref(),
+            value.end.as_ref(),
+            value.acting,
         )?;
         Ok(())
     }
@@ -461,15 +788,307 @@ impl ValueIndexer<Option<NaiveDate>> for Key<TenurePath, Option<NaiveDate>> {
     }
 }
 
+impl ValueIndexer<NaiveDate> for Key<EstablishmentPath, NaiveDate> {
+    fn update_index(&self, conn: &Connection, value: &NaiveDate) -> Result<(), RecordRepoError> {
+        conn.save_office_establishment(&self.entity_id, value)?;
+        Ok(())
+    }
+    fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
+        conn.delete_office_establishment(&self.entity_id)?;
+        Ok(())
+    }
+}
+
+impl ValueIndexer<NaiveDate> for Key<AbolitionPath, NaiveDate> {
+    fn update_index(&self, conn: &Connection, value: &NaiveDate) -> Result<(), RecordRepoError> {
+        conn.save_office_abolition(&self.entity_id, value)?;
+        Ok(())
+    }
+    fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
+        conn.delete_office_abolition(&self.entity_id)?;
+        Ok(())
+    }
+}
+
+impl ValueIndexer<String> for Key<JurisdictionPath, String> {
+    fn update_index(&self, conn: &Connection, value: &String) -> Result<(), RecordRepoError> {
+        conn.save_office_jurisdiction(&self.entity_id, value)?;
+        Ok(())
+    }
+    fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
+        conn.delete_office_jurisdiction(&self.entity_id)?;
+        Ok(())
+    }
+}
+
+impl ValueIndexer<String> for Key<HonorificPath, String> {
+    fn update_index(&self, conn: &Connection, value: &String) -> Result<(), RecordRepoError> {
+        conn.save_person_honorific(&self.entity_id, value)?;
+        Ok(())
+    }
+    fn delete_index(&self, conn: &Connection) -> Result<(), RecordRepoError> {
+        conn.delete_person_honorific(&self.entity_id)?;
+        Ok(())
+    }
+}
+
+/// The tables records are indexed into, the ones `clear_index` empties.
+const INDEX_TABLES: &[&str] = &[
+    "entity",
+    "entity_photo",
+    "entity_contact",
+    "office_supervisor",
+    "office_lifecycle",
+    "office_jurisdiction",
+    "person_office_tenure",
+    "person_honorific",
+];
+
+/// A hash of the rows in the index tables of `conn`, whatever order they were
+/// written in.
+pub fn index_hash(conn: &Connection) -> Result<Hash, rusqlite::Error> {
+    use rusqlite::types::ValueRef;
+
+    let mut hasher = blake3::Hasher::new();
+    for table in INDEX_TABLES {
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+        let columns = stmt.column_count();
+        let mut rows = Vec::new();
+        let mut query = stmt.query([])?;
+        while let Some(row) = query.next()? {
+            let mut bytes = Vec::new();
+            for i in 0..columns {
+                let (tag, value): (u8, &[u8]) = match row.get_ref(i)? {
+                    ValueRef::Null => (0, &[]),
+                    ValueRef::Integer(n) => (1, &n.to_le_bytes()),
+                    ValueRef::Real(f) => (2, &f.to_le_bytes()),
+                    ValueRef::Text(t) => (3, t),
+                    ValueRef::Blob(b) => (4, b),
+                };
+                bytes.push(tag);
+                bytes.extend((value.len() as u64).to_le_bytes());
+                bytes.extend(value);
+            }
+            rows.push(bytes);
+        }
+        rows.sort();
+
+        hasher.update(table.as_bytes());
+        hasher.update(&(rows.len() as u64).to_le_bytes());
+        for row in rows {
+            hasher.update(&row);
+        }
+    }
+
+    Ok(Hash(*hasher.finalize().as_bytes()))
+}
+
+/// What [`RecordRepo::verify_index`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexReport {
+    /// The tree the index claims to be in line with.
+    pub indexed: Hash,
+    /// Whether the index holds exactly what that tree implies.
+    pub index_matches: bool,
+    /// How many commits had a snapshot to check.
+    pub snapshots: usize,
+    /// The commits whose tree no longer derives to their snapshot.
+    pub mismatched_snapshots: Vec<Hash>,
+}
+
+/// Environment variable holding the author recorded with commits when none is
+/// given explicitly.
+pub const AUTHOR_ENV: &str = "TUDGOI_AUTHOR";
+
+pub fn default_author() -> Option<String> {
+    std::env::var(AUTHOR_ENV).ok().filter(|author| !author.is_empty())
+}
+
 pub struct RecordRepo<'a> {
     repo: Repo<SqliteBackend<'a>>,
 }
 
 impl<'a> RecordRepo<'a> {
+    /// A repository over `conn` that bounds writes by [`theme::limits`].
     pub fn new(conn: &'a Connection) -> Self {
         RecordRepo {
             repo: Repo::new(SqliteBackend::new(conn)),
         }
+        .with_limits(theme::limits())
+    }
+
+    /// Bound the keys and values written through this repository, instead of
+    /// the configured [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.repo = self.repo.with_limits(limits);
+        self
+    }
+
+    /// Like `new`, but first migrates the index tables of an older database
+    /// and repairs the index if an earlier write was interrupted between
+    /// moving the working ref and updating the index.
+    pub fn open(conn: &'a Connection) -> Result<Self, RecordRepoError> {
+        let repo = Self::new(conn);
+        repo.migrate()?;
+        repo.recover()?;
+        Ok(repo)
+    }
+
+    /// Bring the index tables up to [`migrate::INDEX_VERSION`] if the
+    /// database trails behind. What the records index into changes with them,
+    /// so the index is rebuilt from the working tree and the index snapshots
+    /// of commits are derived again. Returns whether anything was migrated.
+    pub fn migrate(&self) -> Result<bool, RecordRepoError> {
+        let conn = self.repo.backend.conn;
+        if migrate::index_version(conn)? >= migrate::INDEX_VERSION {
+            return Ok(false);
+        }
+        atomically(conn, || {
+            migrate::ensure_index_tables(conn)?;
+            let working = self.working()?.commit_id()?;
+            self.rebuild_index(&working)?;
+            for (hash, commit) in self.log()? {
+                if self.repo.index_snapshot(&hash)?.is_some() {
+                    let derived = self.derived_index_hash(&commit.root)?;
+                    self.repo.set_index_snapshot(&hash, &derived)?;
+                }
+            }
+            Ok(migrate::set_index_version(conn, migrate::INDEX_VERSION)?)
+        })?;
+        Ok(true)
+    }
+
+    /// Bring the index in line with the working tree if it trails behind.
+    /// Returns whether anything had to be repaired.
+    pub fn recover(&self) -> Result<bool, RecordRepoError> {
+        let working = self.working()?.commit_id()?;
+        match self.repo.get_ref(RepoRefType::Indexed) {
+            Ok(indexed) if indexed.hash == working => Ok(false),
+            Ok(indexed) => {
+                let from = indexed.hash;
+                atomically(self.repo.backend.conn, || self.reindex_from(&from))?;
+                Ok(true)
+            }
+            // Databases from before the index was tracked are assumed to be
+            // consistent.
+            Err(RepoError::RefNotFound(_)) => {
+                self.repo.set_ref(RepoRefType::Indexed, &working)?;
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply the difference between the tree at `from` and the working tree
+    /// to the index and mark the working tree as indexed. Returns the number
+    /// of records updated. The caller is responsible for the transaction.
+    pub fn reindex_from(&self, from: &Hash) -> Result<usize, RecordRepoError> {
+        let working = self.working()?;
+        let diffs = self
+            .get_at(from)?
+            .iterate_diff(&working)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let updated = apply_diffs(self.repo.backend.conn, diffs)?;
+        self.repo
+            .set_ref(RepoRefType::Indexed, &working.commit_id()?)?;
+
+        Ok(updated)
+    }
+
+    /// Empty the index and fill it again from every record in the tree at
+    /// `hash`, which becomes the indexed tree. Returns the number of records
+    /// indexed. The caller is responsible for the transaction.
+    pub fn rebuild_index(&self, hash: &Hash) -> Result<usize, RecordRepoError> {
+        let conn = self.repo.backend.conn;
+        conn.clear_index()?;
+        let indexed = self.derive_index(hash, conn)?;
+        self.repo.set_ref(RepoRefType::Indexed, hash)?;
+
+        Ok(indexed)
+    }
+
+    /// Index every record in the tree at `hash` into `conn`, whose index
+    /// tables are expected to be empty. Returns the number of records
+    /// indexed.
+    fn derive_index(&self, hash: &Hash, conn: &Connection) -> Result<usize, RecordRepoError> {
+        let tree = self.get_at(hash)?;
+        let mut records = tree
+            .repo_ref
+            .iter_prefix(b"")?
+            .map(|item| {
+                let (k, v) = item?;
+                let path = key_to_path(k)?;
+                tree.parse_record(&path, &v)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        records.sort_by_key(|(key, _)| index_order(matches!(key, RecordKey::Name(_)), false));
+
+        for (key, value) in &records {
+            key.update_index(conn, value)?;
+        }
+
+        Ok(records.len())
+    }
+
+    /// The [`index_hash`] of the index the tree at `hash` implies, derived
+    /// afresh in a database of its own.
+    pub fn derived_index_hash(&self, hash: &Hash) -> Result<Hash, RecordRepoError> {
+        let conn = Connection::open_in_memory()?;
+        conn.create_entity_tables()?;
+        conn.create_property_tables()?;
+        self.derive_index(hash, &conn)?;
+
+        Ok(index_hash(&conn)?)
+    }
+
+    /// Store the hash of the index as it stands with the latest commit, for
+    /// [`RecordRepo::verify_index`] to check against the commit's tree later.
+    /// Returns the hash, or `None` if nothing has been committed yet.
+    pub fn snapshot_index(&self) -> Result<Option<Hash>, RecordRepoError> {
+        let Some(head) = self.repo.head()? else {
+            return Ok(None);
+        };
+        let hash = index_hash(self.repo.backend.conn)?;
+        self.repo.set_index_snapshot(&head, &hash)?;
+
+        Ok(Some(hash))
+    }
+
+    /// Derive the index again from the records and compare it with the one
+    /// in the database, and with the snapshots stored with commits.
+    pub fn verify_index(&self) -> Result<IndexReport, RecordRepoError> {
+        let indexed = match self.repo.get_ref(RepoRefType::Indexed) {
+            Ok(indexed) => indexed.hash,
+            Err(RepoError::RefNotFound(_)) => self.working()?.commit_id()?,
+            Err(e) => return Err(e.into()),
+        };
+        let index_matches =
+            index_hash(self.repo.backend.conn)? == self.derived_index_hash(&indexed)?;
+
+        let mut snapshots = 0;
+        let mut mismatched_snapshots = Vec::new();
+        for (hash, commit) in self.log()? {
+            let Some(snapshot) = self.repo.index_snapshot(&hash)? else {
+                continue;
+            };
+            snapshots += 1;
+            if self.derived_index_hash(&commit.root)? != snapshot {
+                mismatched_snapshots.push(hash);
+            }
+        }
+
+        Ok(IndexReport {
+            indexed,
+            index_matches,
+            snapshots,
+            mismatched_snapshots,
+        })
+    }
+
+    /// The root the ref points at.
+    pub fn root_hash(&self, ref_type: RepoRefType) -> Result<Hash, RecordRepoError> {
+        Ok(self.repo.root_hash(ref_type)?)
     }
 
     pub fn working(&self) -> Result<RecordRepoRef<'_, 'a>, RecordRepoError> {
@@ -478,14 +1097,65 @@ impl<'a> RecordRepo<'a> {
         })
     }
 
+    /// Run `f` on the working tree inside one SQLite transaction, so that the
+    /// records it writes, their index rows and the working ref are all kept,
+    /// or all dropped if `f` fails. Within it, the writes of each record only
+    /// add nodes and move the ref in the transaction; readers see none of it
+    /// until the end.
+    pub fn transaction<T, E: From<RecordRepoError>>(
+        &self,
+        f: impl FnOnce(&mut RecordRepoRef<'_, 'a>) -> Result<T, E>,
+    ) -> Result<T, E> {
+        // Like `atomically`, but `f` may fail with errors of its own.
+        let conn = self.repo.backend.conn;
+        let tx = if conn.is_autocommit() {
+            Some(conn.unchecked_transaction().map_err(RecordRepoError::from)?)
+        } else {
+            None
+        };
+        let mut working = self.working()?;
+        let value = f(&mut working)?;
+        if let Some(tx) = tx {
+            tx.commit().map_err(RecordRepoError::from)?;
+        }
+        Ok(value)
+    }
+
     pub fn committed(&self) -> Result<RecordRepoRef<'_, 'a>, RecordRepoError> {
         Ok(RecordRepoRef {
             repo_ref: self.repo.get_ref(RepoRefType::Committed)?,
         })
     }
 
-    pub fn commit(&mut self) -> Result<(), RecordRepoError> {
-        Ok(self.repo.commit()?)
+    pub fn commit(
+        &mut self,
+        author: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<(), RecordRepoError> {
+        atomically(self.repo.backend.conn, || {
+            Ok(self.repo.commit(author, message)?)
+        })
+    }
+
+    /// The commits made so far, newest first.
+    pub fn log(&self) -> Result<Vec<(Hash, Commit)>, RecordRepoError> {
+        Ok(self.repo.log()?)
+    }
+
+    /// The commits that changed the record at `path`, newest first.
+    pub fn history(&self, path: &str) -> Result<Vec<(Hash, Commit)>, RecordRepoError> {
+        let log = self.repo.log()?;
+        let values = log
+            .iter()
+            .map(|(_, commit)| Ok(self.get_at(&commit.root)?.repo_ref.read(path.as_bytes())?))
+            .collect::<Result<Vec<_>, RecordRepoError>>()?;
+
+        Ok(log
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| values[*i] != values.get(i + 1).cloned().flatten())
+            .map(|(_, entry)| entry)
+            .collect())
     }
 
     pub fn abandon(&mut self) -> Result<(), RecordRepoError> {
@@ -493,45 +1163,263 @@ impl<'a> RecordRepo<'a> {
     }
 
     pub fn init(&self) -> Result<(), RecordRepoError> {
-        Ok(self.repo.init()?)
+        let conn = self.repo.backend.conn;
+        atomically(conn, || {
+            self.repo.init()?;
+            let working = self.working()?.commit_id()?;
+            self.repo.set_ref(RepoRefType::Indexed, &working)?;
+            Ok(migrate::set_index_version(conn, migrate::INDEX_VERSION)?)
+        })
     }
 
-    pub fn get_at(&self, hash: &Hash) -> Result<RecordRepoRef<'_, 'a>, RecordRepoError> {
-        Ok(RecordRepoRef {
-            repo_ref: RepoRef {
-                repo: &self.repo,
-                hash: hash.clone(),
-                name: "detached".to_string(),
-            },
+    /// Start over with an empty tree and a new secret, emptying the index,
+    /// whether or not the repository was initialized before.
+    pub fn reinit(&self) -> Result<(), RecordRepoError> {
+        let conn = self.repo.backend.conn;
+        atomically(conn, || {
+            migrate::ensure_index_tables(conn)?;
+            conn.clear_index()?;
+            self.repo.reinit()?;
+            let working = self.working()?.commit_id()?;
+            self.repo.set_ref(RepoRefType::Indexed, &working)?;
+            Ok(migrate::set_index_version(conn, migrate::INDEX_VERSION)?)
         })
     }
 
-    pub fn iterate_diff(
+    /// Fold the entity `drop` into `keep` in the working copy.
+    ///
+    /// Records of `drop` move over to `keep` unless `keep` already has a
+    /// value at the same path, in which case `keep`'s value wins. For offices,
+    /// tenures held in `drop` and supervisor relations pointing at it are
+    /// repointed to `keep`. Finally `drop` is deleted. Returns a line per
+    /// change made.
+    pub fn merge_entity(
         &self,
-    ) -> Result<Box<dyn Iterator<Item = Result<RecordDiff, RecordRepoError>> + '_>, RecordRepoError>
-    {
-        let working = self.working()?;
-        let committed = self.committed()?;
-
-        let diffs: Vec<Result<RecordDiff, RecordRepoError>> =
-            committed.iterate_diff(&working)?.collect();
-        Ok(Box::new(diffs.into_iter()))
+        typ: dto::EntityType,
+        keep: &str,
+        drop: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        atomically(self.repo.backend.conn, || self.merge_entity_inner(typ, keep, drop))
     }
 
-    fn parse_key<P: ParseKeyState, T>(path: &str) -> Result<Key<P, T>, RecordRepoError> {
-        let parts: Vec<&str> = path.split('/').collect();
-        if parts.len() < 2 {
-            return Err(RecordRepoError::InvalidPath(path.to_string()));
+    fn merge_entity_inner(
+        &self,
+        typ: dto::EntityType,
+        keep: &str,
+        drop: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        let keep_prefix = format!("{}/{}/", typ, keep);
+        let drop_prefix = format!("{}/{}/", typ, drop);
+        let mut working = self.working()?;
+        for prefix in [&keep_prefix, &drop_prefix] {
+            let name = format!("{}name", prefix);
+            if working.repo_ref.read(name.as_bytes())?.is_none() {
+                return Err(RecordRepoError::EntityNotFound(
+                    prefix.trim_end_matches('/').to_string(),
+                ));
+            }
         }
-        let entity_type = parts[0];
-        let entity_id = parts[1];
-        let suffix = &parts[2..];
 
-        let state = P::parse(suffix)?;
+        let mut changes = Vec::new();
+        let records = working
+            .repo_ref
+            .iter_prefix(drop_prefix.as_bytes())?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (key, bytes) in &records {
+            let path = key_to_path(key.clone())?;
+            if path.ends_with("/name") {
+                continue;
+            }
+            let target = format!("{}{}", keep_prefix, &path[drop_prefix.len()..]);
+            if working.repo_ref.read(target.as_bytes())?.is_some() {
+                changes.push(format!("dropped {} ({} is kept)", path, target));
+            } else {
+                working.restore(&target, bytes.clone())?;
+                changes.push(format!("moved {} -> {}", path, target));
+            }
+            working.delete_path(&path)?;
+        }
 
-        Ok(Key {
-            entity_type: match entity_type {
-                "person" => dto::EntityType::Person,
+        if typ == dto::EntityType::Office {
+            changes.extend(working.repoint_office(drop, keep)?);
+        }
+
+        working.delete_path(&format!("{}name", drop_prefix))?;
+        changes.push(format!("deleted {}/{}", typ, drop));
+
+        Ok(changes)
+    }
+
+    /// Find the commit whose hash starts with the given hex prefix.
+    pub fn find_commit(&self, prefix: &str) -> Result<Hash, RecordRepoError> {
+        let mut matches = self
+            .repo
+            .log()?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .filter(|hash| !prefix.is_empty() && hash.to_hex().starts_with(prefix));
+
+        match (matches.next(), matches.next()) {
+            (Some(hash), None) => Ok(hash),
+            _ => Err(RecordRepoError::UnknownCommit(prefix.to_string())),
+        }
+    }
+
+    /// Undo the changes the given commit made to one entity, writing the
+    /// inverse into the working copy. `entity` is a `{type}/{id}` path.
+    ///
+    /// If any of the affected paths has changed again since, nothing is
+    /// written and the paths are returned in a `RevertConflict`. Returns the
+    /// reverted paths.
+    pub fn revert_entity(
+        &self,
+        commit: &Hash,
+        entity: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        atomically(self.repo.backend.conn, || self.revert_entity_inner(commit, entity))
+    }
+
+    fn revert_entity_inner(
+        &self,
+        commit: &Hash,
+        entity: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        use crate::repo::Diff;
+
+        let target = self.repo.read_commit(commit)?;
+        let parent = target
+            .parent
+            .as_ref()
+            .ok_or_else(|| RecordRepoError::NoParent(commit.to_hex()))?;
+        let parent = self.repo.read_commit(parent)?;
+
+        let before = self.get_at(&parent.root)?;
+        let after = self.get_at(&target.root)?;
+        let prefix = format!("{}/", entity.trim_end_matches('/'));
+
+        // (path, value before the commit, value introduced by the commit)
+        let mut changes = Vec::new();
+        for diff in before.repo_ref.iterate_diff(&after.repo_ref)? {
+            let (key, old, new) = match diff? {
+                Diff::Added(k, v) => (k, None, Some(v)),
+                Diff::Changed(k, old_v, new_v) => (k, Some(old_v), Some(new_v)),
+                Diff::Removed(k, v) => (k, Some(v), None),
+            };
+            if key.starts_with(prefix.as_bytes()) {
+                changes.push((key_to_path(key)?, old, new));
+            }
+        }
+
+        let mut working = self.working()?;
+        let mut conflicts = Vec::new();
+        for (path, _, new) in &changes {
+            if working.repo_ref.read(path.as_bytes())? != *new {
+                conflicts.push(path.clone());
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(RecordRepoError::RevertConflict(conflicts));
+        }
+
+        changes.sort_by_key(|(path, old, _)| index_order(path.ends_with("/name"), old.is_none()));
+
+        let mut reverted = Vec::new();
+        for (path, old, _) in changes {
+            match old {
+                Some(bytes) => working.restore(&path, bytes)?,
+                None => working.delete_path(&path)?,
+            }
+            reverted.push(path);
+        }
+
+        Ok(reverted)
+    }
+
+    pub fn get_at(&self, hash: &Hash) -> Result<RecordRepoRef<'_, 'a>, RecordRepoError> {
+        Ok(RecordRepoRef {
+            repo_ref: RepoRef {
+                repo: &self.repo,
+                hash: hash.clone(),
+                name: "detached".to_string(),
+            },
+        })
+    }
+
+    /// The tree `name` refers to: `working`, `committed`, or a commit or
+    /// tree node given by its hash or the start of it.
+    pub fn resolve(&self, name: &str) -> Result<RecordRepoRef<'_, 'a>, RecordRepoError> {
+        match name {
+            "working" => self.working(),
+            "committed" => self.committed(),
+            _ => {
+                let root = match self.find_commit(name) {
+                    Ok(commit) => self.repo.read_commit(&commit)?.root,
+                    Err(RecordRepoError::UnknownCommit(_)) => self.repo.find_node(name)?,
+                    Err(e) => return Err(e),
+                };
+                self.get_at(&root)
+            }
+        }
+    }
+
+    pub fn iterate_diff(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<RecordDiff, RecordRepoError>> + '_>, RecordRepoError>
+    {
+        let working = self.working()?;
+        let committed = self.committed()?;
+
+        let diffs: Vec<Result<RecordDiff, RecordRepoError>> =
+            committed.iterate_diff(&working)?.collect();
+        Ok(Box::new(diffs.into_iter()))
+    }
+
+    /// [`FieldStats`] for each entity type in the committed tree, in the
+    /// order of their paths.
+    pub fn field_stats(&self) -> Result<Vec<(dto::EntityType, FieldStats)>, RecordRepoError> {
+        let tree = self.committed()?;
+        let mut stats: Vec<(dto::EntityType, FieldStats)> = Vec::new();
+        let mut entities = HashSet::new();
+        let mut fields = HashSet::new();
+        for item in tree.repo_ref.iter_prefix(b"")? {
+            let (k, v) = item?;
+            let (key, _) = tree.parse_record(&key_to_path(k)?, &v)?;
+            let (typ, id) = key.entity_info();
+            let kind = key.kind();
+
+            let i = match stats.iter().position(|(t, _)| *t == typ) {
+                Some(i) => i,
+                None => {
+                    stats.push((typ, FieldStats::default()));
+                    stats.len() - 1
+                }
+            };
+            let typ_stats = &mut stats[i].1;
+            if entities.insert((typ, id.clone())) {
+                typ_stats.entities += 1;
+            }
+            if fields.insert((typ, id, kind.clone())) {
+                *typ_stats.fields.entry(kind).or_default() += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn parse_key<P: ParseKeyState, T>(path: &str) -> Result<Key<P, T>, RecordRepoError> {
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.len() < 2 {
+            return Err(RecordRepoError::InvalidPath(path.to_string()));
+        }
+        let entity_type = parts[0];
+        let entity_id = parts[1];
+        let suffix = &parts[2..];
+
+        let state = P::parse(suffix)?;
+
+        Ok(Key {
+            entity_type: match entity_type {
+                "person" => dto::EntityType::Person,
                 "office" => dto::EntityType::Office,
                 _ => {
                     return Err(RecordRepoError::InvalidPath(format!(
@@ -558,26 +1446,127 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
         key: Key<P, T>,
         value: &T,
     ) -> Result<(), RecordRepoError>
+    where
+        Key<P, T>: ValueIndexer<T>,
+    {
+        self.write(key, value, |_| Ok(()))
+    }
+
+    /// Write `value` at `key` and index it, passing the bytes it replaced to
+    /// `decode` before the transaction is committed. If the record already
+    /// holds `value`, neither the tree nor the index is touched.
+    fn write<P, T: Serialize, R>(
+        &mut self,
+        key: Key<P, T>,
+        value: &T,
+        decode: impl FnOnce(Option<Vec<u8>>) -> Result<R, RecordRepoError>,
+    ) -> Result<R, RecordRepoError>
     where
         Key<P, T>: ValueIndexer<T>,
     {
         let bytes = postcard::to_stdvec(value)?;
-        self.repo_ref.write(key.path.as_bytes().to_vec(), bytes)?;
-        key.update_index(self.repo_ref.repo.backend.conn, value)?;
+        // Postcard encodes equal values the same, so comparing bytes suffices.
+        if let Some(old) = self.repo_ref.read(key.path.as_bytes())?
+            && old == bytes
+        {
+            return decode(Some(old));
+        }
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            let old = self.repo_ref.write(key.path.as_bytes().to_vec(), bytes)?;
+            let old = decode(old)?;
+            key.update_index(conn, value)?;
+            self.mark_indexed()?;
+            Ok(old)
+        })
+    }
 
-        Ok(())
+    /// Like [`save`](Self::save) for each record in turn, but the tree is
+    /// rewritten once for the lot, in one transaction. Records that already
+    /// hold their value are left alone.
+    pub fn save_many<P, T: Serialize>(
+        &mut self,
+        records: Vec<(Key<P, T>, T)>,
+    ) -> Result<(), RecordRepoError>
+    where
+        Key<P, T>: ValueIndexer<T>,
+    {
+        let mut items = Vec::new();
+        let mut changed = Vec::new();
+        for (key, value) in records {
+            let bytes = postcard::to_stdvec(&value)?;
+            if self.repo_ref.read(key.path.as_bytes())?.as_ref() == Some(&bytes) {
+                continue;
+            }
+            items.push((key.path.as_bytes().to_vec(), bytes));
+            changed.push((key, value));
+        }
+        if items.is_empty() {
+            return Ok(());
+        }
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            self.repo_ref.write_many(items)?;
+            for (key, value) in &changed {
+                key.update_index(conn, value)?;
+            }
+            self.mark_indexed()
+        })
+    }
+
+    /// End the tenure of `person_id` in `office_id` that began on `start`,
+    /// on `end`, so that they are no longer its incumbent. An acting tenure
+    /// stays acting.
+    pub fn close_tenure(
+        &mut self,
+        person_id: &str,
+        office_id: &str,
+        start: Option<NaiveDate>,
+        end: NaiveDate,
+    ) -> Result<(), RecordRepoError> {
+        let key = Key::<PersonPath, ()>::new(person_id).tenure(office_id, start);
+        let Some(RecordValue::Tenure(tenure)) = self.get(&key.path)? else {
+            return Err(RecordRepoError::EntityNotFound(key.path));
+        };
+        if start.is_some_and(|start| end < start) {
+            return Err(RecordRepoError::InvalidPath(format!(
+                "{}: cannot end on {}, before it began",
+                key.path, end
+            )));
+        }
+
+        self.save(
+            key,
+            &TenureValue {
+                end: Some(end),
+                ..tenure
+            },
+        )
     }
 
     pub fn delete<P, T>(&mut self, key: Key<P, T>) -> Result<(), RecordRepoError>
     where
         Key<P, T>: ValueIndexer<T>,
     {
-        self.repo_ref.remove(key.path.as_bytes())?;
-        key.delete_index(self.repo_ref.repo.backend.conn)?;
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            self.repo_ref.remove(key.path.as_bytes())?;
+            key.delete_index(conn)?;
+            self.mark_indexed()
+        })
+    }
 
+    /// Record that the index matches this tree, if it is the working one.
+    fn mark_indexed(&self) -> Result<(), RecordRepoError> {
+        if self.repo_ref.name == RepoRefType::Working.as_str() {
+            self.repo_ref
+                .repo
+                .set_ref(RepoRefType::Indexed, &self.repo_ref.hash)?;
+        }
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn load<P, T: for<'de> Deserialize<'de>>(
         &self,
         key: Key<P, T>,
@@ -591,6 +1580,11 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
         Ok(value)
     }
 
+    /// Whether a record is stored at `path`.
+    pub fn exists(&self, path: &str) -> Result<bool, RecordRepoError> {
+        Ok(self.repo_ref.contains_key(path.as_bytes())?)
+    }
+
     pub fn get(&self, path: &str) -> Result<Option<RecordValue>, RecordRepoError> {
         if let Some(bytes) = self.repo_ref.read(path.as_bytes())? {
             let (_, value) = self.parse_record(path, &bytes)?;
@@ -600,39 +1594,280 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
         }
     }
 
+    /// Index the records of one entity again without writing to the tree,
+    /// for when the indexing logic changed. Returns the number of records
+    /// indexed.
+    pub fn reindex_entity(
+        &self,
+        typ: dto::EntityType,
+        id: &str,
+    ) -> Result<usize, RecordRepoError> {
+        let prefix = format!("{}/{}/", typ, id);
+        let mut records = self
+            .repo_ref
+            .iter_prefix(prefix.as_bytes())?
+            .map(|item| {
+                let (k, v) = item?;
+                let path = key_to_path(k)?;
+                self.parse_record(&path, &v)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        records.sort_by_key(|(key, _)| index_order(matches!(key, RecordKey::Name(_)), false));
+
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            // Not every index write is an upsert, so the old rows are
+            // removed before the records are indexed again, in the order
+            // deletes take.
+            for (key, _) in records.iter().rev() {
+                key.delete_index(conn)?;
+            }
+            for (key, value) in &records {
+                key.update_index(conn, value)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(records.len())
+    }
+
+    pub fn get_tenure(
+        &self,
+        person_id: &str,
+        office_id: &str,
+        start: Option<NaiveDate>,
+    ) -> Result<Option<data::Tenure>, RecordRepoError> {
+        let key = Key::<PersonPath, ()>::new(person_id).tenure(office_id, start);
+        match self.get(&key.path)? {
+            Some(RecordValue::Tenure(value)) => Ok(Some(key.to_tenure(value))),
+            _ => Ok(None),
+        }
+    }
+
+    /// All tenures of a person, ordered by office and start date.
+    pub fn list_tenures(
+        &self,
+        person_id: &str,
+    ) -> Result<impl Iterator<Item = Result<data::Tenure, RecordRepoError>> + '_, RecordRepoError>
+    {
+        let dir = format!("{}/tenure", Key::<PersonPath, ()>::new(person_id).path);
+
+        Ok(self
+            .records_under(&dir)?
+            .filter_map(|record| match record {
+                Ok((RecordKey::Tenure(key), RecordValue::Tenure(value))) => {
+                    Some(Ok(key.to_tenure(value)))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            }))
+    }
+
     pub fn save_from_json(&mut self, path: &str, json: &str) -> Result<(), RecordRepoError> {
-        if path.ends_with("/name") {
+        self.replace_from_json(path, json).map(|_| ())
+    }
+
+    /// Like [`save_from_json`](Self::save_from_json), but returns the record
+    /// that was replaced, if any.
+    pub fn replace_from_json(
+        &mut self,
+        path: &str,
+        json: &str,
+    ) -> Result<Option<RecordValue>, RecordRepoError> {
+        let old = if path.ends_with("/name") {
             let value: String = serde_json::from_str(json)
                 .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
             let key = RecordRepo::parse_key::<NamePath, String>(path)?;
-            self.save(key, &value)
+            self.write(key, &value, Ok)?
         } else if path.ends_with("/photo") {
             let value: data::Photo = serde_json::from_str(json)
                 .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
             let key = RecordRepo::parse_key::<PhotoPath, data::Photo>(path)?;
-            self.save(key, &value)
+            self.write(key, &value, Ok)?
         } else if path.contains("/contact/") {
             let value: String = serde_json::from_str(json)
                 .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
             let key = RecordRepo::parse_key::<ContactPath, String>(path)?;
-            self.save(key, &value)
+            validate_contact(&key.state.typ, &value)?;
+            self.write(key, &value, Ok)?
         } else if path.contains("/supervisor/") {
             let value: String = serde_json::from_str(json)
                 .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
-            let key = RecordRepo::parse_key::<SupervisorPath, String>(path)?;
-            self.save(key, &value)
+            let key = self.resolve_supervisor(RecordRepo::parse_key(path)?)?;
+            self.write(key, &value, Ok)?
         } else if path.contains("/tenure/") {
-            let value: Option<NaiveDate> = serde_json::from_str(json)
+            let value = TenureValue::from_json(json)
                 .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
-            let key = RecordRepo::parse_key::<TenurePath, Option<NaiveDate>>(path)?;
-            self.save(key, &value)
+            let key = RecordRepo::parse_key::<TenurePath, TenureValue>(path)?;
+            self.write(key, &value, Ok)?
+        } else if path.ends_with("/establishment") {
+            let value: NaiveDate = serde_json::from_str(json)
+                .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
+            let key = RecordRepo::parse_key::<EstablishmentPath, NaiveDate>(path)?;
+            self.write(key, &value, Ok)?
+        } else if path.ends_with("/abolition") {
+            let value: NaiveDate = serde_json::from_str(json)
+                .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
+            let key = RecordRepo::parse_key::<AbolitionPath, NaiveDate>(path)?;
+            self.write(key, &value, Ok)?
+        } else if path.ends_with("/jurisdiction") {
+            let value: String = serde_json::from_str(json)
+                .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
+            let key = RecordRepo::parse_key::<JurisdictionPath, String>(path)?;
+            self.write(key, &value, Ok)?
+        } else if path.ends_with("/honorific") {
+            let value: String = serde_json::from_str(json)
+                .map_err(|e| RecordRepoError::InvalidPath(e.to_string()))?;
+            let key = RecordRepo::parse_key::<HonorificPath, String>(path)?;
+            self.write(key, &value, Ok)?
         } else {
-            Err(RecordRepoError::UnknownRecordType(path.to_string()))
+            return Err(RecordRepoError::UnknownRecordType(path.to_string()));
+        };
+
+        old.map(|bytes| Ok(self.parse_record(path, &bytes)?.1)).transpose()
+    }
+
+    /// Point tenures in and supervisor relations to office `from` at office
+    /// `to` instead.
+    fn repoint_office(&mut self, from: &str, to: &str) -> Result<Vec<String>, RecordRepoError> {
+        let mut changes = Vec::new();
+
+        let tenures = self
+            .scan(Key::<PersonPath, ()>::all())?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (key, value) in tenures {
+            if let (RecordKey::Tenure(key), RecordValue::Tenure(tenure)) = (key, value)
+                && key.state.office_id == from
+            {
+                let target = Key::<PersonPath, ()>::new(&key.entity_id).tenure(to, key.state.start);
+                if self.repo_ref.read(target.path.as_bytes())?.is_some() {
+                    changes.push(format!("dropped {} ({} is kept)", key.path, target.path));
+                    self.delete(key)?;
+                } else {
+                    changes.push(format!("repointed {} -> {}", key.path, target.path));
+                    self.delete(key)?;
+                    self.save(target, &tenure)?;
+                }
+            }
+        }
+
+        let supervisors = self
+            .scan(Key::<OfficePath, ()>::all())?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (key, value) in supervisors {
+            if let (RecordKey::Supervisor(key), RecordValue::Supervisor(office_id)) = (key, value)
+                && office_id == from
+            {
+                let target = Key::<OfficePath, ()>::new(&key.entity_id)
+                    .supervisor(key.state.relation.clone(), to);
+                changes.push(format!("repointed {} -> {}", key.path, target.path));
+                self.delete(key)?;
+                self.save(target, &to.to_string())?;
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Move every record of `{typ}/{old_id}` to `{typ}/{new_id}`, in the
+    /// index too. For offices, tenures held in it and supervisor relations
+    /// pointing at it follow. Fails if `new_id` has records already. Returns
+    /// a line per change made.
+    pub fn rename_entity(
+        &mut self,
+        typ: dto::EntityType,
+        old_id: &str,
+        new_id: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        if new_id.is_empty() || new_id.contains('/') {
+            return Err(RecordRepoError::InvalidPath(format!(
+                "Invalid entity id: {:?}",
+                new_id
+            )));
+        }
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || self.rename_entity_inner(typ, old_id, new_id))
+    }
+
+    fn rename_entity_inner(
+        &mut self,
+        typ: dto::EntityType,
+        old_id: &str,
+        new_id: &str,
+    ) -> Result<Vec<String>, RecordRepoError> {
+        let old_prefix = format!("{}/{}/", typ, old_id);
+        let new_prefix = format!("{}/{}/", typ, new_id);
+        let name = format!("{}name", old_prefix);
+        if self.repo_ref.read(name.as_bytes())?.is_none() {
+            let entity = format!("{}/{}", typ, old_id);
+            return Err(RecordRepoError::EntityNotFound(entity));
+        }
+        let mut taken = self.repo_ref.iter_prefix(new_prefix.as_bytes())?;
+        if taken.next().is_some() {
+            return Err(RecordRepoError::EntityExists(format!("{}/{}", typ, new_id)));
+        }
+
+        let mut records = self
+            .repo_ref
+            .iter_prefix(old_prefix.as_bytes())?
+            .map(|item| {
+                let (k, v) = item?;
+                Ok((key_to_path(k)?, v))
+            })
+            .collect::<Result<Vec<_>, RecordRepoError>>()?;
+        records.sort_by_key(|(path, _)| index_order(path.ends_with("/name"), false));
+
+        let mut changes = Vec::new();
+        for (path, bytes) in records {
+            let target = format!("{}{}", new_prefix, &path[old_prefix.len()..]);
+            self.restore(&target, bytes)?;
+            changes.push(format!("moved {} -> {}", path, target));
+        }
+
+        if typ == dto::EntityType::Office {
+            changes.extend(self.repoint_office(old_id, new_id)?);
         }
+        self.delete_entity(&format!("{}/{}", typ, old_id))?;
+
+        Ok(changes)
+    }
+
+    /// Delete every record under `{type}/{id}` and its index rows, the name
+    /// last, all in one transaction.
+    fn delete_entity(&mut self, entity: &str) -> Result<(), RecordRepoError> {
+        let mut records = self
+            .records_under(entity)?
+            .collect::<Result<Vec<_>, _>>()?;
+        records.sort_by_key(|(key, _)| index_order(matches!(key, RecordKey::Name(_)), true));
+
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            for (key, _) in &records {
+                self.repo_ref.remove(key.path().as_bytes())?;
+                key.delete_index(conn)?;
+            }
+            self.mark_indexed()
+        })
+    }
+
+    /// Write an already serialized value back and index it.
+    fn restore(&mut self, path: &str, bytes: Vec<u8>) -> Result<(), RecordRepoError> {
+        let (key, value) = self.parse_record(path, &bytes)?;
+        let conn = self.repo_ref.repo.backend.conn;
+        atomically(conn, || {
+            self.repo_ref.write(path.as_bytes().to_vec(), bytes)?;
+            key.update_index(conn, &value)?;
+            self.mark_indexed()
+        })
     }
 
+    /// Delete the record at `path`, or every record of an entity when `path`
+    /// is just `{type}/{id}`. Records of other entities that refer to a
+    /// deleted one are left alone.
     pub fn delete_path(&mut self, path: &str) -> Result<(), RecordRepoError> {
-        if path.ends_with("/name") {
+        if dto::parse_entity(path).is_ok() {
+            self.delete_entity(path)
+        } else if path.ends_with("/name") {
             let key = RecordRepo::parse_key::<NamePath, String>(path)?;
             self.delete(key)
         } else if path.ends_with("/photo") {
@@ -642,10 +1877,22 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
             let key = RecordRepo::parse_key::<ContactPath, String>(path)?;
             self.delete(key)
         } else if path.contains("/supervisor/") {
-            let key = RecordRepo::parse_key::<SupervisorPath, String>(path)?;
+            let key = self.resolve_supervisor(RecordRepo::parse_key(path)?)?;
             self.delete(key)
         } else if path.contains("/tenure/") {
-            let key = RecordRepo::parse_key::<TenurePath, Option<NaiveDate>>(path)?;
+            let key = RecordRepo::parse_key::<TenurePath, TenureValue>(path)?;
+            self.delete(key)
+        } else if path.ends_with("/establishment") {
+            let key = RecordRepo::parse_key::<EstablishmentPath, NaiveDate>(path)?;
+            self.delete(key)
+        } else if path.ends_with("/abolition") {
+            let key = RecordRepo::parse_key::<AbolitionPath, NaiveDate>(path)?;
+            self.delete(key)
+        } else if path.ends_with("/jurisdiction") {
+            let key = RecordRepo::parse_key::<JurisdictionPath, String>(path)?;
+            self.delete(key)
+        } else if path.ends_with("/honorific") {
+            let key = RecordRepo::parse_key::<HonorificPath, String>(path)?;
             self.delete(key)
         } else {
             Err(RecordRepoError::UnknownRecordType(path.to_string()))
@@ -664,35 +1911,86 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
 
         Ok(iter.map(|item| {
             let (k, v) = item?;
-            let path = String::from_utf8(k).map_err(|_| {
-                RecordRepoError::Repo(RepoError::HashParse("Key is not valid UTF-8".to_string()))
-            })?;
+            let path = key_to_path(k)?;
 
             self.parse_record(&path, &v)
         }))
     }
 
-    pub fn list(
+    /// At most `limit` records under `prefix`, starting after the path
+    /// `after` when given. Pass `usize::MAX` and no cursor to list them all.
+    pub fn list_page(
         &self,
         prefix: &str,
+        after: Option<&str>,
+        limit: usize,
     ) -> Result<
         impl Iterator<Item = Result<(String, RecordValue), RecordRepoError>> + '_,
         RecordRepoError,
     > {
-        let prefix_bytes = prefix.as_bytes().to_vec();
-        let iter = self.repo_ref.iter_prefix(&prefix_bytes)?;
+        let mut iter = self.repo_ref.iter_prefix(prefix.as_bytes())?;
+        if let Some(after) = after {
+            iter = iter.seek_after(after.as_bytes());
+        }
 
-        Ok(iter.map(|item| {
+        Ok(iter.take(limit).map(|item| {
             let (k, v) = item?;
-            let path = String::from_utf8(k).map_err(|_| {
-                RecordRepoError::Repo(RepoError::HashParse("Key is not valid UTF-8".to_string()))
-            })?;
+            let path = key_to_path(k)?;
 
             let (_, value) = self.parse_record(&path, &v)?;
             Ok((path, value))
         }))
     }
 
+    /// The paths of the records under `prefix`, without decoding their
+    /// values.
+    pub fn list_keys(
+        &self,
+        prefix: &str,
+    ) -> Result<impl Iterator<Item = Result<String, RecordRepoError>> + '_, RecordRepoError> {
+        let iter = self.repo_ref.iter_prefix(prefix.as_bytes())?;
+
+        Ok(iter.map(|item| key_to_path(item?.0)))
+    }
+
+    /// Like [`list_keys`](Self::list_keys), but only `depth` levels below
+    /// `prefix`: a path going deeper is cut after its `depth`th `/` past the
+    /// prefix, and each cut path is listed once. With `prefix` `person/p1/`
+    /// and a depth of 1, `person/p1/contact/email` is listed as
+    /// `person/p1/contact/`.
+    pub fn list_keys_depth(
+        &self,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<impl Iterator<Item = Result<String, RecordRepoError>> + '_, RecordRepoError> {
+        let prefix_len = prefix.len();
+        let mut last: Option<String> = None;
+
+        Ok(self.list_keys(prefix)?.filter_map(move |item| {
+            let mut path = match item {
+                Ok(path) => path,
+                Err(e) => return Some(Err(e)),
+            };
+            let cut = match depth {
+                0 => Some(0),
+                _ => path[prefix_len..]
+                    .match_indices('/')
+                    .nth(depth - 1)
+                    .map(|(i, _)| i + 1),
+            };
+            if let Some(cut) = cut {
+                path.truncate(prefix_len + cut);
+            }
+            // The paths cut to the same one are adjacent, since they all
+            // start with it.
+            if last.as_ref() == Some(&path) {
+                return None;
+            }
+            last = Some(path.clone());
+            Some(Ok(path))
+        }))
+    }
+
     pub fn commit_id(&self) -> Result<Hash, RecordRepoError> {
         Ok(self.repo_ref.commit_id()?)
     }
@@ -709,30 +2007,18 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
             let diff = item?;
             match diff {
                 Diff::Added(k, v) => {
-                    let path = String::from_utf8(k).map_err(|_| {
-                        RecordRepoError::Repo(RepoError::HashParse(
-                            "Key is not valid UTF-8".to_string(),
-                        ))
-                    })?;
+                    let path = key_to_path(k)?;
                     let (rk, rv) = self.parse_record(&path, &v)?;
                     Ok(RecordDiff::Added(rk, rv))
                 }
                 Diff::Changed(k, old_v, new_v) => {
-                    let path = String::from_utf8(k).map_err(|_| {
-                        RecordRepoError::Repo(RepoError::HashParse(
-                            "Key is not valid UTF-8".to_string(),
-                        ))
-                    })?;
+                    let path = key_to_path(k)?;
                     let (rk, rv_old) = self.parse_record(&path, &old_v)?;
                     let (_, rv_new) = self.parse_record(&path, &new_v)?;
                     Ok(RecordDiff::Changed(rk, rv_old, rv_new))
                 }
                 Diff::Removed(k, v) => {
-                    let path = String::from_utf8(k).map_err(|_| {
-                        RecordRepoError::Repo(RepoError::HashParse(
-                            "Key is not valid UTF-8".to_string(),
-                        ))
-                    })?;
+                    let path = key_to_path(k)?;
                     let (rk, rv) = self.parse_record(&path, &v)?;
                     Ok(RecordDiff::Removed(rk, rv))
                 }
@@ -740,6 +2026,39 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
         }))
     }
 
+    /// Fill in the supervisor of a key of the old shape from the record at
+    /// its path, if there is one, since that is what its row is keyed on.
+    fn resolve_supervisor(
+        &self,
+        mut key: Key<SupervisorPath, String>,
+    ) -> Result<Key<SupervisorPath, String>, RecordRepoError> {
+        if key.state.supervisor_office_id.is_none()
+            && let Some(bytes) = self.repo_ref.read(key.path.as_bytes())?
+        {
+            key.state.supervisor_office_id = Some(postcard::from_bytes(&bytes)?);
+        }
+        Ok(key)
+    }
+
+    /// Every record below the path `dir`. Only whole segments match, so
+    /// `person/p1` does not take in the records of `person/p10`.
+    fn records_under(
+        &self,
+        dir: &str,
+    ) -> Result<
+        impl Iterator<Item = Result<(RecordKey, RecordValue), RecordRepoError>> + use<'_, 'a, 'b>,
+        RecordRepoError,
+    > {
+        let prefix = format!("{}/", dir);
+        let iter = self.repo_ref.iter_prefix(prefix.as_bytes())?;
+
+        Ok(iter.map(|item| {
+            let (k, v) = item?;
+            let path = key_to_path(k)?;
+            self.parse_record(&path, &v)
+        }))
+    }
+
     fn parse_record(
         &self,
         path: &str,
@@ -750,7 +2069,7 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
             let key = RecordRepo::parse_key::<NamePath, String>(path)?;
             Ok((RecordKey::Name(key), RecordValue::Name(value)))
         } else if path.ends_with("/photo") {
-            let value: data::Photo = postcard::from_bytes(v)?;
+            let value = photo_from_postcard(v)?;
             let key = RecordRepo::parse_key::<PhotoPath, data::Photo>(path)?;
             Ok((RecordKey::Photo(key), RecordValue::Photo(value)))
         } else if path.contains("/contact/") {
@@ -759,68 +2078,127 @@ impl<'a, 'b> RecordRepoRef<'a, 'b> {
             Ok((RecordKey::Contact(key), RecordValue::Contact(value)))
         } else if path.contains("/supervisor/") {
             let value: String = postcard::from_bytes(v)?;
-            let key = RecordRepo::parse_key::<SupervisorPath, String>(path)?;
+            let mut key = RecordRepo::parse_key::<SupervisorPath, String>(path)?;
+            key.state
+                .supervisor_office_id
+                .get_or_insert_with(|| value.clone());
             Ok((RecordKey::Supervisor(key), RecordValue::Supervisor(value)))
         } else if path.contains("/tenure/") {
-            let value: Option<NaiveDate> = postcard::from_bytes(v)?;
-            let key = RecordRepo::parse_key::<TenurePath, Option<NaiveDate>>(path)?;
+            let value = TenureValue::from_postcard(v)?;
+            let key = RecordRepo::parse_key::<TenurePath, TenureValue>(path)?;
             Ok((RecordKey::Tenure(key), RecordValue::Tenure(value)))
+        } else if path.ends_with("/establishment") {
+            let value: NaiveDate = postcard::from_bytes(v)?;
+            let key = RecordRepo::parse_key::<EstablishmentPath, NaiveDate>(path)?;
+            Ok((RecordKey::Establishment(key), RecordValue::Establishment(value)))
+        } else if path.ends_with("/abolition") {
+            let value: NaiveDate = postcard::from_bytes(v)?;
+            let key = RecordRepo::parse_key::<AbolitionPath, NaiveDate>(path)?;
+            Ok((RecordKey::Abolition(key), RecordValue::Abolition(value)))
+        } else if path.ends_with("/jurisdiction") {
+            let value: String = postcard::from_bytes(v)?;
+            let key = RecordRepo::parse_key::<JurisdictionPath, String>(path)?;
+            Ok((RecordKey::Jurisdiction(key), RecordValue::Jurisdiction(value)))
+        } else if path.ends_with("/honorific") {
+            let value: String = postcard::from_bytes(v)?;
+            let key = RecordRepo::parse_key::<HonorificPath, String>(path)?;
+            Ok((RecordKey::Honorific(key), RecordValue::Honorific(value)))
         } else {
             Err(RecordRepoError::UnknownRecordType(path.to_string()))
         }
     }
 }
 
-pub fn abandon_changes(conn: &mut Connection) -> Result<(), RecordRepoError> {
-    let mut repo = RecordRepo::new(conn);
-    let old_hash = repo.working()?.commit_id()?;
-
-    repo.abandon()?;
+/// Decode a photo record, including one written before photos had a license
+/// and source URL.
+fn photo_from_postcard(bytes: &[u8]) -> Result<data::Photo, postcard::Error> {
+    match postcard::from_bytes::<data::Photo>(bytes) {
+        Err(postcard::Error::DeserializeUnexpectedEnd) => {
+            let (url, attribution) = postcard::from_bytes(bytes)?;
+            Ok(data::Photo {
+                url,
+                attribution,
+                license: None,
+                source_url: None,
+            })
+        }
+        result => result,
+    }
+}
 
-    let diffs = {
-        let new_working = repo.working()?;
-        let new_hash = new_working.commit_id()?;
+/// Record keys are ASCII paths, so a key that is not UTF-8 means the store is
+/// corrupt. The raw bytes are reported in hex to help track it down.
+fn key_to_path(key: Vec<u8>) -> Result<String, RecordRepoError> {
+    String::from_utf8(key).map_err(|e| {
+        let hex: String = e.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        RecordRepoError::InvalidKey(hex)
+    })
+}
 
-        if old_hash != new_hash {
-            let old_working = repo
-                .get_at(&old_hash)
-                .map_err(|e| RecordRepoError::Repo(RepoError::HashParse(e.to_string())))?;
+pub fn abandon_changes(conn: &mut Connection) -> Result<(), RecordRepoError> {
+    let tx = conn.transaction()?;
+    {
+        let mut repo = RecordRepo::open(&tx)?;
+        let old_hash = repo.working()?.commit_id()?;
+        repo.abandon()?;
+        repo.reindex_from(&old_hash)?;
+    }
+    tx.commit()?;
 
-            old_working
-                .iterate_diff(&new_working)?
-                .collect::<Result<Vec<_>, _>>()?
-        } else {
-            Vec::new()
-        }
-    };
+    Ok(())
+}
 
-    if !diffs.is_empty() {
-        let mut diffs = diffs;
-        diffs.sort_by_key(|diff| {
-            match diff {
-                RecordDiff::Added(RecordKey::Name(_), _)
-                | RecordDiff::Changed(RecordKey::Name(_), _, _) => 0,
+/// Where a record goes among a batch of index writes. Names are written
+/// first and removed last so that the index never holds properties of an
+/// entity it does not know.
+pub fn index_order(is_name: bool, removed: bool) -> u8 {
+    match (removed, is_name) {
+        (false, true) => 0,
+        (false, false) => 1,
+        (true, false) => 2,
+        (true, true) => 3,
+    }
+}
 
-                RecordDiff::Added(_, _) | RecordDiff::Changed(_, _, _) => 1,
+/// Apply `diffs` to the index in whatever order they come, sorted by
+/// [`index_order`]. Returns the number of records updated. The caller is
+/// responsible for the transaction.
+pub fn apply_diffs(
+    conn: &Connection,
+    mut diffs: Vec<RecordDiff>,
+) -> Result<usize, RecordRepoError> {
+    diffs.sort_by_key(|diff| match diff {
+        RecordDiff::Added(k, _) | RecordDiff::Changed(k, _, _) => {
+            index_order(matches!(k, RecordKey::Name(_)), false)
+        }
+        RecordDiff::Removed(k, _) => index_order(matches!(k, RecordKey::Name(_)), true),
+    });
 
-                RecordDiff::Removed(RecordKey::Name(_), _) => 3,
+    for diff in &diffs {
+        match diff {
+            RecordDiff::Added(k, v) => k.update_index(conn, v)?,
+            RecordDiff::Changed(k, _, v) => k.update_index(conn, v)?,
+            RecordDiff::Removed(k, _) => k.delete_index(conn)?,
+        }
+    }
 
-                RecordDiff::Removed(_, _) => 2,
-            }
-        });
+    Ok(diffs.len())
+}
 
-        let tx = conn.transaction()?;
-        for diff in diffs {
-            match diff {
-                RecordDiff::Added(k, v) => k.update_index(&tx, &v)?,
-                RecordDiff::Changed(k, _, v) => k.update_index(&tx, &v)?,
-                RecordDiff::Removed(k, _) => k.delete_index(&tx)?,
-            }
-        }
-        tx.commit()?;
+/// Run `f` in a transaction, unless `conn` is already inside one that covers
+/// it.
+fn atomically<T>(
+    conn: &Connection,
+    f: impl FnOnce() -> Result<T, RecordRepoError>,
+) -> Result<T, RecordRepoError> {
+    if !conn.is_autocommit() {
+        return f();
     }
 
-    Ok(())
+    let tx = conn.unchecked_transaction()?;
+    let value = f()?;
+    tx.commit()?;
+    Ok(value)
 }
 
 #[cfg(test)]
@@ -847,6 +2225,7 @@ mod tests {
               type TEXT NOT NULL,
               id TEXT NOT NULL,
               name TEXT NOT NULL,
+              search_name TEXT NOT NULL,
               PRIMARY KEY(type, id)
             );
             CREATE TABLE entity_photo (
@@ -854,16 +2233,32 @@ mod tests {
               entity_id TEXT NOT NULL,
               url TEXT NOT NULL,
               attribution TEXT,
+              license TEXT,
+              source_url TEXT,
               PRIMARY KEY(entity_type, entity_id)
             );
             CREATE TABLE person_office_tenure (
               person_id TEXT NOT NULL,
               office_id TEXT NOT NULL,
               start TEXT,
-              end TEXT
+              end TEXT,
+              acting INTEGER NOT NULL DEFAULT 0
             );
-        "#,
-        )
+            CREATE TABLE office_lifecycle (
+              office_id TEXT NOT NULL PRIMARY KEY,
+              establishment TEXT,
+              abolition TEXT
+            );
+            CREATE TABLE office_jurisdiction (
+              office_id TEXT NOT NULL PRIMARY KEY,
+              jurisdiction TEXT NOT NULL
+            );
+            CREATE TABLE person_honorific (
+              person_id TEXT NOT NULL PRIMARY KEY,
+              honorific TEXT NOT NULL
+            );
+        "#,
+        )
         .unwrap();
     }
 
@@ -884,13 +2279,15 @@ mod tests {
         let photo = data::Photo {
             url: "http://example.com/p1.jpg".to_string(),
             attribution: Some("Attr".to_string()),
+            license: None,
+            source_url: None,
         };
         repo.working().unwrap().save(p1.photo(), &photo).unwrap();
 
         let t1 = p1.tenure("o1", None);
         repo.working()
             .unwrap()
-            .save(t1, &Some(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()))
+            .save(t1, &Some(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()).into())
             .unwrap();
 
         let items: Vec<_> = repo
@@ -922,7 +2319,8 @@ mod tests {
                 (RecordKey::Tenure(k), RecordValue::Tenure(t)) => {
                     assert_eq!(k.entity_id, "p1");
                     assert_eq!(k.state.office_id, "o1");
-                    assert_eq!(t, Some(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()));
+                    assert_eq!(t.end, Some(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()));
+                    assert!(!t.acting);
                     found_tenure = true;
                 }
                 _ => panic!("Unexpected type mismatch or unknown type"),
@@ -933,6 +2331,840 @@ mod tests {
         assert!(found_tenure);
     }
 
+    #[test]
+    fn test_tenures() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(p1.tenure("o1", Some(d(2020, 1, 1))), &Some(d(2021, 1, 1)).into())
+            .unwrap();
+        working
+            .save(
+                p1.tenure("o2", None),
+                &TenureValue {
+                    end: None,
+                    acting: true,
+                },
+            )
+            .unwrap();
+        working
+            .save(
+                Key::<PersonPath, ()>::new("p10").tenure("o3", None),
+                &TenureValue::default(),
+            )
+            .unwrap();
+
+        let working = repo.working().unwrap();
+        assert_eq!(
+            working.get_tenure("p1", "o1", Some(d(2020, 1, 1))).unwrap(),
+            Some(data::Tenure {
+                office_id: "o1".to_string(),
+                start: Some("2020-01-01".to_string()),
+                end: Some("2021-01-01".to_string()),
+                acting: false,
+            })
+        );
+        assert_eq!(working.get_tenure("p1", "o1", None).unwrap(), None);
+        assert!(working.get_tenure("p1", "o2", None).unwrap().unwrap().acting);
+        let acting: i64 = conn
+            .query_row(
+                "SELECT acting FROM person_office_tenure WHERE office_id = 'o2'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(acting, 1);
+
+        let tenures = working
+            .list_tenures("p1")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let offices: Vec<_> = tenures.iter().map(|t| t.office_id.as_str()).collect();
+        assert_eq!(offices, vec!["o1", "o2"]);
+        assert_eq!(tenures[1].start, None);
+        assert_eq!(tenures[1].end, None);
+    }
+
+    #[test]
+    fn test_photo_encoding() {
+        let photo = data::Photo {
+            url: "http://example.com/p1.jpg".to_string(),
+            attribution: Some("Attr".to_string()),
+            license: None,
+            source_url: None,
+        };
+
+        // Records written before photos had a license or source.
+        let old = postcard::to_stdvec(&(&photo.url, &photo.attribution)).unwrap();
+        assert_eq!(postcard::to_stdvec(&photo).unwrap(), old);
+        assert_eq!(photo_from_postcard(&old).unwrap(), photo);
+
+        let credited = data::Photo {
+            license: Some("CC BY-SA 4.0".to_string()),
+            source_url: Some("http://example.com/p1".to_string()),
+            ..photo
+        };
+        let bytes = postcard::to_stdvec(&credited).unwrap();
+        assert_eq!(photo_from_postcard(&bytes).unwrap(), credited);
+        let licensed = data::Photo {
+            source_url: None,
+            ..credited.clone()
+        };
+        let bytes = postcard::to_stdvec(&licensed).unwrap();
+        assert_eq!(photo_from_postcard(&bytes).unwrap(), licensed);
+
+        let json = serde_json::to_value(&licensed).unwrap();
+        assert_eq!(json["license"], "CC BY-SA 4.0");
+        assert!(json.get("source_url").is_none());
+    }
+
+    #[test]
+    fn test_migrate() {
+        use crate::LibrarySql;
+
+        /// A change to the index tables, or to what records index into.
+        struct Change {
+            name: &'static str,
+            /// The `user_version` of databases from before the change.
+            version: u32,
+            save: fn(&mut RecordRepoRef),
+            /// Takes a database written by this version back to what one
+            /// from before the change looked like.
+            before: &'static str,
+            /// What must hold once the old database has been opened.
+            check: fn(&Connection),
+        }
+
+        fn photo() -> data::Photo {
+            data::Photo {
+                url: "http://example.com/p1.jpg".to_string(),
+                attribution: Some("Attr".to_string()),
+                license: Some("CC BY-SA 4.0".to_string()),
+                source_url: Some("http://example.com/p1".to_string()),
+            }
+        }
+        fn triggers(conn: &Connection, table: &str) -> usize {
+            conn.query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'trigger' AND tbl_name = ?1",
+                [table],
+                |row| row.get(0),
+            )
+            .unwrap()
+        }
+
+        let changes = [
+            Change {
+                name: "office lifecycle",
+                version: 0,
+                save: |working| {
+                    let o1 = Key::<OfficePath, ()>::new("o1");
+                    working.save(o1.name(), &"Office One".to_string()).unwrap();
+                    let established = NaiveDate::from_ymd_opt(1950, 1, 26).unwrap();
+                    working.save(o1.establishment(), &established).unwrap();
+                },
+                before: "DROP TABLE office_lifecycle;",
+                check: |conn| {
+                    let establishment: Option<NaiveDate> = conn
+                        .query_row(
+                            "SELECT establishment FROM office_lifecycle WHERE office_id = 'o1'",
+                            [],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(establishment, NaiveDate::from_ymd_opt(1950, 1, 26));
+                    assert_eq!(triggers(conn, "office_lifecycle"), 2);
+                },
+            },
+            Change {
+                name: "several supervisors per relation",
+                version: 1,
+                save: |working| {
+                    let mayor = Key::<OfficePath, ()>::new("mayor");
+                    working.save(mayor.name(), &"Mayor".to_string()).unwrap();
+                    for council in ["council", "committee"] {
+                        let key = mayor.supervisor(data::SupervisingRelation::MemberOf, council);
+                        working.save(key, &council.to_string()).unwrap();
+                    }
+                },
+                before: r#"
+                    DROP TABLE office_supervisor;
+                    CREATE TABLE office_supervisor (
+                      office_id TEXT NOT NULL,
+                      relation TEXT NOT NULL,
+                      supervisor_office_id TEXT NOT NULL,
+                      PRIMARY KEY(office_id, relation)
+                    );
+                "#,
+                check: |conn| {
+                    let mut supervisors = Vec::new();
+                    conn.get_office_supervising_offices("mayor", |row| {
+                        supervisors.push(row.get::<_, String>(1)?);
+                        Ok(())
+                    })
+                    .unwrap();
+                    assert_eq!(supervisors, vec!["committee", "council"]);
+                    assert_eq!(triggers(conn, "office_supervisor"), 3);
+                },
+            },
+            Change {
+                name: "acting tenures",
+                version: 2,
+                save: |working| {
+                    let p1 = Key::<PersonPath, ()>::new("p1");
+                    working.save(p1.name(), &"Person One".to_string()).unwrap();
+                    let acting = TenureValue {
+                        end: None,
+                        acting: true,
+                    };
+                    working.save(p1.tenure("o1", None), &acting).unwrap();
+                },
+                before: r#"
+                    DROP VIEW person_office_incumbent;
+                    DROP VIEW person_office_quondam;
+                    DROP TABLE person_office_tenure;
+                    CREATE TABLE person_office_tenure (
+                      person_id TEXT NOT NULL,
+                      office_id TEXT NOT NULL,
+                      start TEXT,
+                      end TEXT
+                    );
+                    CREATE VIEW person_office_incumbent (person_id, office_id, start) AS
+                    SELECT person_id, office_id, start FROM person_office_tenure WHERE end IS NULL;
+                    CREATE VIEW person_office_quondam (person_id, office_id, start, end) AS
+                    SELECT person_id, office_id, start, end FROM person_office_tenure WHERE end IS NOT NULL;
+                "#,
+                check: |conn| {
+                    let incumbent: (String, bool) = conn
+                        .query_row(
+                            "SELECT office_id, acting FROM person_office_incumbent WHERE person_id = 'p1'",
+                            [],
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )
+                        .unwrap();
+                    assert_eq!(incumbent, ("o1".to_string(), true));
+                },
+            },
+            Change {
+                name: "photo credit",
+                version: 3,
+                save: |working| {
+                    let p1 = Key::<PersonPath, ()>::new("p1");
+                    working.save(p1.name(), &"Person One".to_string()).unwrap();
+                    working.save(p1.photo(), &photo()).unwrap();
+                },
+                before: r#"
+                    DROP TABLE entity_photo;
+                    CREATE TABLE entity_photo (
+                      entity_type TEXT NOT NULL,
+                      entity_id TEXT NOT NULL,
+                      url TEXT NOT NULL,
+                      attribution TEXT,
+                      PRIMARY KEY(entity_type, entity_id)
+                    );
+                "#,
+                check: |conn| {
+                    let indexed = conn
+                        .get_entity_photo(&dto::EntityType::Person, "p1", |row| {
+                            Ok(data::Photo {
+                                url: row.get(0)?,
+                                attribution: row.get(1)?,
+                                license: row.get(2)?,
+                                source_url: row.get(3)?,
+                            })
+                        })
+                        .unwrap();
+                    assert_eq!(indexed, photo());
+                },
+            },
+            Change {
+                name: "office jurisdiction",
+                version: 4,
+                save: |working| {
+                    let o1 = Key::<OfficePath, ()>::new("o1");
+                    working.save(o1.name(), &"Office One".to_string()).unwrap();
+                    working
+                        .save(o1.jurisdiction(), &"Kerala".to_string())
+                        .unwrap();
+                },
+                before: "DROP TABLE office_jurisdiction;",
+                check: |conn| {
+                    let jurisdiction: String = conn
+                        .query_row(
+                            "SELECT jurisdiction FROM office_jurisdiction WHERE office_id = 'o1'",
+                            [],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(jurisdiction, "Kerala");
+                },
+            },
+            Change {
+                name: "person honorific",
+                version: 5,
+                save: |working| {
+                    let p1 = Key::<PersonPath, ()>::new("p1");
+                    working
+                        .save(p1.name(), &"B. R. Ambedkar".to_string())
+                        .unwrap();
+                    working.save(p1.honorific(), &"Dr.".to_string()).unwrap();
+                },
+                before: "DROP TABLE person_honorific;",
+                check: |conn| {
+                    let honorific: String = conn
+                        .query_row(
+                            "SELECT honorific FROM person_honorific WHERE person_id = 'p1'",
+                            [],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(honorific, "Dr.");
+                },
+            },
+            Change {
+                name: "search name",
+                version: 6,
+                save: |working| {
+                    let p1 = Key::<PersonPath, ()>::new("p1");
+                    working
+                        .save(p1.name(), &"Ｒａｊｅｓｈ Kumar".to_string())
+                        .unwrap();
+                },
+                // The normalized name was indexed in place of the one shown.
+                before: r#"
+                    DROP TRIGGER entity_ai_fts;
+                    DROP TRIGGER entity_ad_fts;
+                    DROP TRIGGER entity_au_fts;
+                    DROP TABLE entity_idx;
+                    ALTER TABLE entity DROP COLUMN search_name;
+                    UPDATE entity SET name = 'Rajesh Kumar';
+                    CREATE VIRTUAL TABLE entity_idx USING fts5(id, name, content = 'entity');
+                    INSERT INTO entity_idx(entity_idx) VALUES ('rebuild');
+                "#,
+                check: |conn| {
+                    let found: String = conn
+                        .query_row(
+                            "SELECT e.name FROM entity_idx('rajesh') AS fts JOIN entity AS e ON fts.rowid = e.rowid",
+                            [],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(found, "Ｒａｊｅｓｈ Kumar");
+                },
+            },
+            Change {
+                name: "phone format",
+                version: 7,
+                save: |working| {
+                    let p1 = Key::<PersonPath, ()>::new("p1");
+                    working.save(p1.name(), &"Person One".to_string()).unwrap();
+                    working
+                        .save(
+                            p1.contact(data::ContactType::Phone),
+                            &"011-2301-2345".to_string(),
+                        )
+                        .unwrap();
+                },
+                // Numbers were indexed as given.
+                before: "UPDATE entity_contact SET value = '011-2301-2345';",
+                check: |conn| {
+                    let phone: String = conn
+                        .query_row("SELECT value FROM entity_contact", [], |row| row.get(0))
+                        .unwrap();
+                    assert_eq!(phone, "+911123012345");
+                },
+            },
+        ];
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| change.version)
+                .collect::<Vec<_>>(),
+            (0..migrate::INDEX_VERSION).collect::<Vec<_>>(),
+            "each index version needs a row to migrate from it"
+        );
+
+        for change in changes {
+            let conn = Connection::open_in_memory().unwrap();
+            conn.create_entity_tables().unwrap();
+            conn.create_property_tables().unwrap();
+            let mut repo = RecordRepo::new(&conn);
+            repo.init().unwrap();
+            (change.save)(&mut repo.working().unwrap());
+            repo.commit(None, None).unwrap();
+            conn.execute_batch(change.before).unwrap();
+            migrate::set_index_version(&conn, change.version).unwrap();
+            // Old layouts that still have every index table get a snapshot
+            // of their stale index, which opening must derive again.
+            let snapshotted = repo.snapshot_index().is_ok();
+
+            let repo = RecordRepo::open(&conn).unwrap();
+            assert_eq!(
+                migrate::index_version(&conn).unwrap(),
+                migrate::INDEX_VERSION,
+                "{}",
+                change.name
+            );
+            assert!(!repo.migrate().unwrap(), "{}", change.name);
+            let report = repo.verify_index().unwrap();
+            assert!(report.index_matches, "{}", change.name);
+            assert_eq!(
+                report.snapshots,
+                usize::from(snapshotted),
+                "{}",
+                change.name
+            );
+            assert!(report.mismatched_snapshots.is_empty(), "{}", change.name);
+            (change.check)(&conn);
+        }
+    }
+
+    #[test]
+    fn test_tenure_value_encoding() {
+        let end = NaiveDate::from_ymd_opt(2021, 1, 1);
+        let plain = TenureValue::from(end);
+        let acting = TenureValue { end, acting: true };
+
+        // Records written before the flag existed hold only the end date.
+        let old = postcard::to_stdvec(&end).unwrap();
+        assert_eq!(postcard::to_stdvec(&plain).unwrap(), old);
+        assert_eq!(TenureValue::from_postcard(&old).unwrap(), plain);
+        let bytes = postcard::to_stdvec(&acting).unwrap();
+        assert_eq!(TenureValue::from_postcard(&bytes).unwrap(), acting);
+        let none = postcard::to_stdvec(&TenureValue::default()).unwrap();
+        assert_eq!(TenureValue::from_postcard(&none).unwrap(), TenureValue::default());
+
+        assert_eq!(serde_json::to_string(&plain).unwrap(), "\"2021-01-01\"");
+        let json = serde_json::to_string(&acting).unwrap();
+        assert_eq!(json, r#"{"end":"2021-01-01","acting":true}"#);
+        assert_eq!(TenureValue::from_json(&json).unwrap(), acting);
+        assert_eq!(TenureValue::from_json("null").unwrap(), TenureValue::default());
+    }
+
+    #[test]
+    fn test_office_lifecycle() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let o1 = Key::<OfficePath, ()>::new("o1");
+        let established = NaiveDate::from_ymd_opt(1950, 1, 26).unwrap();
+        let abolished = NaiveDate::from_ymd_opt(2019, 10, 31).unwrap();
+
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(o1.establishment(), &established).unwrap();
+        working.save(o1.abolition(), &abolished).unwrap();
+
+        let lifecycle = || -> (Option<NaiveDate>, Option<NaiveDate>) {
+            conn.query_row(
+                "SELECT establishment, abolition FROM office_lifecycle WHERE office_id = 'o1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        assert_eq!(lifecycle(), (Some(established), Some(abolished)));
+
+        let working = repo.working().unwrap();
+        assert_eq!(
+            working.get("office/o1/abolition").unwrap(),
+            Some(RecordValue::Abolition(abolished))
+        );
+        assert_eq!(
+            working.get("office/o1/establishment").unwrap(),
+            Some(RecordValue::Establishment(established))
+        );
+
+        repo.working().unwrap().delete_path("office/o1/abolition").unwrap();
+        assert_eq!(lifecycle(), (Some(established), None));
+    }
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(normalize_phone("+91 11 2301 2345"), "+911123012345");
+        assert_eq!(normalize_phone("011-2301-2345"), "+911123012345");
+        assert_eq!(normalize_phone("98765 43210"), "+919876543210");
+        assert_eq!(normalize_phone("0044 20 7946 0958"), "+442079460958");
+        assert_eq!(normalize_phone("+1 (202) 456-1111"), "+12024561111");
+        // Left alone where the country cannot be told.
+        assert_eq!(normalize_phone("2301 2345"), "2301 2345");
+        assert_eq!(normalize_phone("ext. 12"), "ext. 12");
+    }
+
+    #[test]
+    fn test_save_from_json_validates_contact() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let mut working = repo.working().unwrap();
+        working
+            .save_from_json("person/p1/name", "\"Person One\"")
+            .unwrap();
+        let err = working
+            .save_from_json("person/p1/contact/email", "\"p1.example.org\"")
+            .unwrap_err();
+        assert!(matches!(err, RecordRepoError::InvalidPath(_)));
+        assert!(err.to_string().contains("email"));
+        assert!(!working.exists("person/p1/contact/email").unwrap());
+
+        working
+            .save_from_json("person/p1/contact/email", "\"p1@example.org\"")
+            .unwrap();
+        assert!(working.exists("person/p1/contact/email").unwrap());
+    }
+
+    #[test]
+    fn test_field_stats() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        let o1 = Key::<OfficePath, ()>::new("o1");
+        let photo = data::Photo {
+            url: "http://example.com/p1.jpg".to_string(),
+            attribution: None,
+            license: None,
+            source_url: None,
+        };
+
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working.save(p1.photo(), &photo).unwrap();
+        working
+            .save(p1.tenure("o1", None), &TenureValue::default())
+            .unwrap();
+        working
+            .save(
+                p1.tenure("o1", NaiveDate::from_ymd_opt(2019, 5, 30)),
+                &TenureValue::default(),
+            )
+            .unwrap();
+        working.save(p2.name(), &"Person Two".to_string()).unwrap();
+        working
+            .save(
+                p2.contact(data::ContactType::Email),
+                &"p2@example.org".to_string(),
+            )
+            .unwrap();
+        repo.commit(None, None).unwrap();
+        // Only the committed tree is counted.
+        repo.working().unwrap().save(p2.photo(), &photo).unwrap();
+
+        let stats = repo.field_stats().unwrap();
+        let fields = |pairs: &[(&str, usize)]| {
+            pairs
+                .iter()
+                .map(|(field, count)| (field.to_string(), *count))
+                .collect()
+        };
+        assert_eq!(
+            stats,
+            [
+                (
+                    dto::EntityType::Office,
+                    FieldStats {
+                        entities: 1,
+                        fields: fields(&[("name", 1)]),
+                    }
+                ),
+                (
+                    dto::EntityType::Person,
+                    FieldStats {
+                        entities: 2,
+                        fields: fields(&[
+                            ("contact/email", 1),
+                            ("name", 2),
+                            ("photo", 1),
+                            ("tenure", 1),
+                        ]),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_tenure() {
+        use crate::LibrarySql;
+        use rusqlite::OptionalExtension;
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let o1 = Key::<OfficePath, ()>::new("o1");
+        let start = NaiveDate::from_ymd_opt(2019, 5, 30);
+        let end = NaiveDate::from_ymd_opt(2024, 6, 9).unwrap();
+
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(
+                p1.tenure("o1", start),
+                &TenureValue {
+                    end: None,
+                    acting: true,
+                },
+            )
+            .unwrap();
+        let incumbent = || {
+            conn.get_office_incumbent("o1", |row| row.get::<_, String>(0))
+                .optional()
+                .unwrap()
+        };
+        assert_eq!(incumbent(), Some("p1".to_string()));
+
+        let before = NaiveDate::from_ymd_opt(2019, 1, 1).unwrap();
+        assert!(matches!(
+            working.close_tenure("p1", "o1", start, before),
+            Err(RecordRepoError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            working.close_tenure("p1", "o2", start, end),
+            Err(RecordRepoError::EntityNotFound(_))
+        ));
+
+        working.close_tenure("p1", "o1", start, end).unwrap();
+        assert_eq!(
+            working.get("person/p1/tenure/o1/2019-05-30").unwrap(),
+            Some(RecordValue::Tenure(TenureValue {
+                end: Some(end),
+                acting: true,
+            }))
+        );
+        assert_eq!(incumbent(), None);
+        let mut quondams = Vec::new();
+        conn.get_office_quondams("o1", |row| {
+            quondams.push((row.get::<_, String>(0)?, row.get::<_, NaiveDate>(3)?));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(quondams, [("p1".to_string(), end)]);
+    }
+
+    #[test]
+    fn test_phone_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(
+                p1.contact(data::ContactType::Phone),
+                &"011-2301 2345".to_string(),
+            )
+            .unwrap();
+        working
+            .save(
+                p1.contact(data::ContactType::Email),
+                &"p1@example.org".to_string(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            working.get("person/p1/contact/phone").unwrap(),
+            Some(RecordValue::Contact("011-2301 2345".to_string()))
+        );
+        let indexed = |typ: &str| -> String {
+            conn.query_row(
+                "SELECT value FROM entity_contact WHERE type = ?1",
+                [typ],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(indexed("phone"), "+911123012345");
+        assert_eq!(indexed("email"), "p1@example.org");
+    }
+
+    #[test]
+    fn test_delete_entity() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p10 = Key::<PersonPath, ()>::new("p10");
+
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(p1.contact(data::ContactType::Email), &"p1@example.org".to_string())
+            .unwrap();
+        working
+            .save(p1.tenure("o1", None), &TenureValue::default())
+            .unwrap();
+        working.save(p10.name(), &"Person Ten".to_string()).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("entity_contact"), 1);
+
+        working.delete_path("person/p1").unwrap();
+        let working = repo.working().unwrap();
+        assert!(!working.exists("person/p1/name").unwrap());
+        assert!(working.exists("person/p10/name").unwrap());
+        assert_eq!(count("entity"), 1);
+        assert_eq!(count("entity_contact"), 0);
+        assert_eq!(count("person_office_tenure"), 0);
+        assert_eq!(
+            repo.root_hash(RepoRefType::Indexed).unwrap(),
+            repo.root_hash(RepoRefType::Working).unwrap()
+        );
+
+        assert!(matches!(
+            repo.working().unwrap().delete_path("person"),
+            Err(RecordRepoError::UnknownRecordType(_))
+        ));
+    }
+
+    #[test]
+    fn test_person_honorific() {
+        use crate::LibrarySql;
+        use rusqlite::OptionalExtension;
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let honorific = || -> Option<String> {
+            conn.get_person_honorific("p1", |row| row.get(0))
+                .optional()
+                .unwrap()
+        };
+
+        let mut working = repo.working().unwrap();
+        working
+            .save(p1.name(), &"B. R. Ambedkar".to_string())
+            .unwrap();
+        working.save(p1.honorific(), &"Dr.".to_string()).unwrap();
+        assert_eq!(honorific().as_deref(), Some("Dr."));
+        // The honorific leads the name where it is shown, but the name
+        // itself is left as it is.
+        let name: String = conn
+            .query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(name, "B. R. Ambedkar");
+
+        working
+            .save(p1.honorific(), &"Bharat Ratna".to_string())
+            .unwrap();
+        assert_eq!(honorific().as_deref(), Some("Bharat Ratna"));
+        assert_eq!(
+            working.get("person/p1/honorific").unwrap(),
+            Some(RecordValue::Honorific("Bharat Ratna".to_string()))
+        );
+
+        working.delete_path("person/p1").unwrap();
+        assert_eq!(honorific(), None);
+    }
+
+    #[test]
+    fn test_office_jurisdiction() {
+        use rusqlite::OptionalExtension;
+
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let o1 = Key::<OfficePath, ()>::new("o1");
+
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(o1.jurisdiction(), &"Kerala".to_string()).unwrap();
+
+        let jurisdiction = || -> Option<String> {
+            conn.query_row(
+                "SELECT jurisdiction FROM office_jurisdiction WHERE office_id = 'o1'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+        };
+        assert_eq!(jurisdiction().as_deref(), Some("Kerala"));
+        assert_eq!(
+            repo.working().unwrap().get("office/o1/jurisdiction").unwrap(),
+            Some(RecordValue::Jurisdiction("Kerala".to_string()))
+        );
+
+        repo.working().unwrap().delete_path("office/o1/jurisdiction").unwrap();
+        assert_eq!(jurisdiction(), None);
+    }
+
+    #[test]
+    fn test_verify_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        assert_eq!(repo.snapshot_index().unwrap(), None);
+
+        let o1 = Key::<OfficePath, ()>::new("o1");
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(o1.jurisdiction(), &"Kerala".to_string()).unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        repo.commit(None, Some("first")).unwrap();
+        let snapshot = repo.snapshot_index().unwrap().unwrap();
+        let head = repo.log().unwrap()[0].clone();
+        // The index built up write by write is the one the tree implies.
+        assert_eq!(snapshot, repo.derived_index_hash(&head.1.root).unwrap());
+
+        let report = repo.verify_index().unwrap();
+        assert!(report.index_matches);
+        assert_eq!(report.snapshots, 1);
+        assert!(report.mismatched_snapshots.is_empty());
+
+        conn.execute("UPDATE entity SET name = 'Drifted' WHERE id = 'p1'", [])
+            .unwrap();
+        assert!(!repo.verify_index().unwrap().index_matches);
+
+        let bogus = Hash(*blake3::hash(b"bogus").as_bytes());
+        repo.repo.set_index_snapshot(&head.0, &bogus).unwrap();
+        assert_eq!(repo.verify_index().unwrap().mismatched_snapshots, [head.0]);
+
+        let indexed = repo.working().unwrap().commit_id().unwrap();
+        repo.rebuild_index(&indexed).unwrap();
+        assert!(repo.verify_index().unwrap().index_matches);
+    }
+
     #[test]
     fn test_list() {
         let conn = Connection::open_in_memory().unwrap();
@@ -950,7 +3182,7 @@ mod tests {
         let items: Vec<_> = repo
             .working()
             .unwrap()
-            .list("person/p1/")
+            .list_page("person/p1/", None, usize::MAX)
             .expect("List failed")
             .collect::<Result<Vec<_>, _>>()
             .expect("Iteration failed");
@@ -960,6 +3192,83 @@ mod tests {
         assert_eq!(items[0].1, RecordValue::Name("Person One".to_string()));
     }
 
+    #[test]
+    fn test_list_page() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+        for i in 0..5 {
+            let person = Key::<PersonPath, ()>::new(&format!("p{}", i));
+            working.save(person.name(), &format!("Person {}", i)).unwrap();
+        }
+
+        let page = |after: Option<&str>| -> Vec<String> {
+            repo.working()
+                .unwrap()
+                .list_page("person/", after, 2)
+                .unwrap()
+                .map(|item| item.unwrap().0)
+                .collect()
+        };
+        assert_eq!(page(None), ["person/p0/name", "person/p1/name"]);
+        assert_eq!(page(Some("person/p1/name")), ["person/p2/name", "person/p3/name"]);
+        assert_eq!(page(Some("person/p3/name")), ["person/p4/name"]);
+        assert!(page(Some("person/p4/name")).is_empty());
+    }
+
+    #[test]
+    fn test_list_keys() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        for office in ["o1", "o2"] {
+            working
+                .save(p1.tenure(office, None), &TenureValue::from(None))
+                .unwrap();
+        }
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        working.save(p2.name(), &"Person Two".to_string()).unwrap();
+
+        let working = repo.working().unwrap();
+        let keys = |prefix: &str, depth: Option<usize>| -> Vec<String> {
+            match depth {
+                Some(depth) => working
+                    .list_keys_depth(prefix, depth)
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect(),
+                None => working.list_keys(prefix).unwrap().map(Result::unwrap).collect(),
+            }
+        };
+        assert_eq!(
+            keys("person/p1/", None),
+            [
+                "person/p1/name",
+                "person/p1/tenure/o1/",
+                "person/p1/tenure/o2/"
+            ]
+        );
+        assert_eq!(
+            keys("person/p1/", Some(1)),
+            ["person/p1/name", "person/p1/tenure/"]
+        );
+        assert_eq!(keys("person/", Some(1)), ["person/p1/", "person/p2/"]);
+        assert_eq!(
+            keys("person/", Some(2)),
+            ["person/p1/name", "person/p1/tenure/", "person/p2/name"]
+        );
+        assert_eq!(keys("person/", Some(0)), ["person/"]);
+        assert!(keys("office/", Some(1)).is_empty());
+    }
+
     #[test]
     fn test_iterate_diff() {
         let conn = Connection::open_in_memory().unwrap();
@@ -972,7 +3281,7 @@ mod tests {
             .unwrap()
             .save(p1.name(), &"Person One".to_string())
             .unwrap();
-        repo.commit().unwrap();
+        repo.commit(None, None).unwrap();
 
         repo.working()
             .unwrap()
@@ -1016,4 +3325,874 @@ mod tests {
         assert!(found_added);
         assert!(found_changed);
     }
+
+    #[test]
+    fn test_list_skips_invalid_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .repo_ref
+            .write(b"person/\xff/name".to_vec(), b"x".to_vec())
+            .unwrap();
+        let photo = data::Photo {
+            url: "http://example.com/p1.jpg".to_string(),
+            attribution: None,
+            license: None,
+            source_url: None,
+        };
+        working.save(p1.photo(), &photo).unwrap();
+
+        let items: Vec<_> = working.list_page("person/", None, usize::MAX).unwrap().collect();
+        assert_eq!(items.len(), 3);
+
+        let errors: Vec<_> = items.iter().filter_map(|item| item.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], RecordRepoError::InvalidKey(hex) if hex.contains("ff")));
+
+        let paths: Vec<_> = items
+            .into_iter()
+            .filter_map(|item| item.ok())
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec!["person/p1/name", "person/p1/photo"]);
+    }
+
+    #[test]
+    fn test_revert_entity() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person One".to_string())
+            .unwrap();
+        repo.commit(None, None).unwrap();
+
+        let photo = data::Photo {
+            url: "http://example.com/p1.jpg".to_string(),
+            attribution: None,
+            license: None,
+            source_url: None,
+        };
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Wrong Name".to_string()).unwrap();
+        working.save(p1.photo(), &photo).unwrap();
+        working.save(p2.name(), &"Person Two".to_string()).unwrap();
+        repo.commit(None, Some("bad edit")).unwrap();
+
+        let (bad, _) = repo.log().unwrap().remove(0);
+        let prefix = &bad.to_hex()[..8];
+        assert_eq!(repo.find_commit(prefix).unwrap(), bad);
+
+        let reverted = repo.revert_entity(&bad, "person/p1").unwrap();
+        assert_eq!(reverted, vec!["person/p1/name", "person/p1/photo"]);
+
+        let working = repo.working().unwrap();
+        assert_eq!(
+            working.get("person/p1/name").unwrap(),
+            Some(RecordValue::Name("Person One".to_string()))
+        );
+        assert_eq!(working.get("person/p1/photo").unwrap(), None);
+        assert_eq!(
+            working.get("person/p2/name").unwrap(),
+            Some(RecordValue::Name("Person Two".to_string()))
+        );
+        let name: String = conn
+            .query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "Person One");
+    }
+
+    #[test]
+    fn test_revert_entity_conflict() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person One".to_string())
+            .unwrap();
+        repo.commit(None, None).unwrap();
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person Uno".to_string())
+            .unwrap();
+        repo.commit(None, None).unwrap();
+        let (commit, _) = repo.log().unwrap().remove(0);
+
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person 1".to_string())
+            .unwrap();
+
+        let result = repo.revert_entity(&commit, "person/p1");
+        assert!(
+            matches!(result, Err(RecordRepoError::RevertConflict(paths)) if paths == vec!["person/p1/name"])
+        );
+        assert_eq!(
+            repo.working().unwrap().get("person/p1/name").unwrap(),
+            Some(RecordValue::Name("Person 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        use crate::SchemaSql;
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let o1 = Key::<OfficePath, ()>::new("o1");
+        let mut working = repo.working().unwrap();
+        working.save(o1.name(), &"Office One".to_string()).unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(p1.contact(data::ContactType::Email), &"p1@example.com".to_string())
+            .unwrap();
+        working.save(p1.tenure("o1", None), &TenureValue::default()).unwrap();
+
+        // Let the index drift from the records.
+        conn.execute_batch(
+            r#"
+            DELETE FROM entity_contact;
+            INSERT INTO entity (type, id, name, search_name) VALUES ('person', 'ghost', 'Ghost', 'Ghost');
+            INSERT INTO person_office_tenure (person_id, office_id) VALUES ('p1', 'o1');
+            "#,
+        )
+        .unwrap();
+
+        let hash = repo.working().unwrap().commit_id().unwrap();
+        assert_eq!(repo.rebuild_index(&hash).unwrap(), 4);
+
+        let count = |table: &str| -> usize {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("entity"), 2);
+        assert_eq!(count("entity_contact"), 1);
+        assert_eq!(count("person_office_tenure"), 1);
+        assert!(!repo.recover().unwrap());
+    }
+
+    #[test]
+    fn test_apply_diffs() {
+        use crate::SchemaSql;
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        // Out of order writes would trip the foreign keys.
+        conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        let email = |email: &str| RecordValue::Contact(email.to_string());
+        let name = |name: &str| RecordValue::Name(name.to_string());
+        apply_diffs(
+            &conn,
+            vec![
+                RecordDiff::Added(
+                    RecordKey::Contact(p1.contact(data::ContactType::Email)),
+                    email("p1@example.com"),
+                ),
+                RecordDiff::Added(RecordKey::Name(p1.name()), name("Person One")),
+            ],
+        )
+        .unwrap();
+
+        let updated = apply_diffs(
+            &conn,
+            vec![
+                RecordDiff::Removed(RecordKey::Name(p1.name()), name("Person One")),
+                RecordDiff::Added(
+                    RecordKey::Contact(p2.contact(data::ContactType::Email)),
+                    email("p2@example.com"),
+                ),
+                RecordDiff::Removed(
+                    RecordKey::Contact(p1.contact(data::ContactType::Email)),
+                    email("p1@example.com"),
+                ),
+                RecordDiff::Added(RecordKey::Name(p2.name()), name("Person Two")),
+            ],
+        )
+        .unwrap();
+        assert_eq!(updated, 4);
+
+        let ids = |table: &str, column: &str| -> Vec<String> {
+            let mut stmt = conn
+                .prepare(&format!("SELECT {} FROM {} ORDER BY 1", column, table))
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(ids("entity", "id"), ["p2"]);
+        assert_eq!(ids("entity_contact", "entity_id"), ["p2"]);
+    }
+
+    #[test]
+    fn test_reindex_entity() {
+        use crate::SchemaSql;
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p10 = Key::<PersonPath, ()>::new("p10");
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(p1.contact(data::ContactType::Email), &"p1@example.com".to_string())
+            .unwrap();
+        working.save(p1.tenure("o1", None), &TenureValue::default()).unwrap();
+        working.save(p10.name(), &"Person Ten".to_string()).unwrap();
+        working
+            .save(p10.contact(data::ContactType::Email), &"p10@example.com".to_string())
+            .unwrap();
+
+        conn.execute_batch(
+            r#"
+            DELETE FROM entity_contact;
+            INSERT INTO person_office_tenure (person_id, office_id) VALUES ('p1', 'o1');
+            "#,
+        )
+        .unwrap();
+
+        let root = repo.working().unwrap().commit_id().unwrap();
+        let indexed = repo
+            .working()
+            .unwrap()
+            .reindex_entity(dto::EntityType::Person, "p1")
+            .unwrap();
+        assert_eq!(indexed, 3);
+        assert_eq!(repo.working().unwrap().commit_id().unwrap(), root);
+
+        let count = |sql: &str| -> usize { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            count("SELECT COUNT(*) FROM entity_contact WHERE entity_id = 'p1'"),
+            1
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM person_office_tenure"), 1);
+        // Only the requested entity is touched.
+        assert_eq!(
+            count("SELECT COUNT(*) FROM entity_contact WHERE entity_id = 'p10'"),
+            0
+        );
+    }
+
+    #[test]
+    fn test_supervisors() {
+        use crate::{LibrarySql, SchemaSql};
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let mayor = Key::<OfficePath, ()>::new("mayor");
+        let mut working = repo.working().unwrap();
+        working.save(mayor.name(), &"Mayor".to_string()).unwrap();
+        for council in ["council", "committee"] {
+            working
+                .save(
+                    mayor.supervisor(data::SupervisingRelation::MemberOf, council),
+                    &council.to_string(),
+                )
+                .unwrap();
+        }
+        // A record from before several supervisors were allowed, next to one
+        // of the new shape under the same relation.
+        working
+            .save_from_json("office/mayor/supervisor/responsible_to", "\"governor\"")
+            .unwrap();
+        working
+            .save(
+                mayor.supervisor(data::SupervisingRelation::ResponsibleTo, "cabinet"),
+                &"cabinet".to_string(),
+            )
+            .unwrap();
+
+        let supervisors = |conn: &Connection| {
+            let mut supervisors = Vec::new();
+            conn.get_office_supervising_offices("mayor", |row| {
+                let relation: data::SupervisingRelation = row.get(0)?;
+                let id: String = row.get(1)?;
+                supervisors.push(format!("{}/{}", relation, id));
+                Ok(())
+            })
+            .unwrap();
+            supervisors
+        };
+        assert_eq!(
+            supervisors(&conn),
+            vec![
+                "responsible_to/cabinet",
+                "member_of/committee",
+                "member_of/council",
+                "responsible_to/governor"
+            ]
+        );
+
+        let mut working = repo.working().unwrap();
+        working
+            .save_from_json("office/mayor/supervisor/responsible_to", "\"lg\"")
+            .unwrap();
+        assert_eq!(
+            supervisors(&conn),
+            vec![
+                "responsible_to/cabinet",
+                "member_of/committee",
+                "member_of/council",
+                "responsible_to/lg"
+            ]
+        );
+
+        working
+            .delete(mayor.supervisor(data::SupervisingRelation::MemberOf, "council"))
+            .unwrap();
+        working
+            .delete_path("office/mayor/supervisor/responsible_to")
+            .unwrap();
+        assert_eq!(
+            supervisors(&conn),
+            vec!["responsible_to/cabinet", "member_of/committee"]
+        );
+    }
+
+    #[test]
+    fn test_merge_entity() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+        conn.execute_batch(
+            r#"
+            CREATE TABLE office_supervisor (
+              office_id TEXT NOT NULL,
+              relation TEXT NOT NULL,
+              supervisor_office_id TEXT NOT NULL,
+              PRIMARY KEY(office_id, relation, supervisor_office_id)
+            );
+        "#,
+        )
+        .unwrap();
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let keep = Key::<OfficePath, ()>::new("cm");
+        let drop = Key::<OfficePath, ()>::new("cm-dup");
+        let minister = Key::<OfficePath, ()>::new("minister");
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let photo = data::Photo {
+            url: "http://example.com/cm.jpg".to_string(),
+            attribution: None,
+            license: None,
+            source_url: None,
+        };
+
+        let mut working = repo.working().unwrap();
+        working.save(keep.name(), &"Chief Minister".to_string()).unwrap();
+        working.save(drop.name(), &"Chief Minister.".to_string()).unwrap();
+        working.save(drop.photo(), &photo).unwrap();
+        working.save(minister.name(), &"Minister".to_string()).unwrap();
+        working
+            .save(
+                minister.supervisor(data::SupervisingRelation::ResponsibleTo, "cm-dup"),
+                &"cm-dup".to_string(),
+            )
+            .unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save(p1.tenure("cm-dup", None), &TenureValue::default())
+            .unwrap();
+
+        let changes = repo
+            .merge_entity(dto::EntityType::Office, "cm", "cm-dup")
+            .unwrap();
+        assert_eq!(changes.len(), 4);
+
+        let working = repo.working().unwrap();
+        assert!(working.list_page("office/cm-dup/", None, usize::MAX).unwrap().next().is_none());
+        assert_eq!(
+            working.get("office/cm/photo").unwrap(),
+            Some(RecordValue::Photo(photo))
+        );
+        assert_eq!(
+            working.get("office/minister/supervisor/responsible_to/cm").unwrap(),
+            Some(RecordValue::Supervisor("cm".to_string()))
+        );
+        assert_eq!(
+            working.get("office/minister/supervisor/responsible_to/cm-dup").unwrap(),
+            None
+        );
+        assert_eq!(
+            working.get("person/p1/tenure/cm/").unwrap(),
+            Some(RecordValue::Tenure(TenureValue::default()))
+        );
+        assert_eq!(working.get("person/p1/tenure/cm-dup/").unwrap(), None);
+
+        let entities: i64 = conn
+            .query_row("SELECT COUNT(*) FROM entity WHERE id = 'cm-dup'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(entities, 0);
+    }
+
+    #[test]
+    fn test_save_many() {
+        let names: Vec<_> = (0..50)
+            .map(|i| {
+                let key = Key::<PersonPath, ()>::new(&format!("p{}", i)).name();
+                (key, format!("Person {}", i))
+            })
+            .collect();
+
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+        for (key, name) in names.clone() {
+            working.save(key, &name).unwrap();
+        }
+        let one_by_one = working.commit_id().unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+        working.save_many(names).unwrap();
+        assert_eq!(working.commit_id().unwrap(), one_by_one);
+        assert_eq!(repo.root_hash(RepoRefType::Indexed).unwrap(), one_by_one);
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM entity", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 50);
+
+        // p0 is saved again unchanged below, so its row must keep this name
+        // while p1, which does change, gets its new one.
+        conn.execute("UPDATE entity SET name = 'Stale' WHERE id = 'p0'", [])
+            .unwrap();
+        let mut names: Vec<_> = (0..2)
+            .map(|i| {
+                let key = Key::<PersonPath, ()>::new(&format!("p{}", i)).name();
+                (key, format!("Person {}", i))
+            })
+            .collect();
+        working.save_many(names.clone()).unwrap();
+        assert_eq!(working.commit_id().unwrap(), one_by_one);
+        names[1].1 = "Person Uno".to_string();
+        working.save_many(names).unwrap();
+        assert_ne!(working.commit_id().unwrap(), one_by_one);
+        let name = |id: &str| -> String {
+            conn.query_row("SELECT name FROM entity WHERE id = ?1", [id], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(name("p0"), "Stale");
+        assert_eq!(name("p1"), "Person Uno");
+    }
+
+    #[test]
+    fn test_rename_entity() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let cm = Key::<OfficePath, ()>::new("cm");
+        let minister = Key::<OfficePath, ()>::new("minister");
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1);
+
+        let email = cm.contact(data::ContactType::Email);
+        let mut working = repo.working().unwrap();
+        working.save(cm.name(), &"CM".to_string()).unwrap();
+        working.save(email, &"cm@gov.in".to_string()).unwrap();
+        working.save(minister.name(), &"Min".to_string()).unwrap();
+        working
+            .save(
+                minister.supervisor(data::SupervisingRelation::ResponsibleTo, "cm"),
+                &"cm".to_string(),
+            )
+            .unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        let tenure = p1.tenure("cm", start);
+        working.save(tenure, &TenureValue::default()).unwrap();
+
+        assert!(matches!(
+            working.rename_entity(dto::EntityType::Office, "cm", "minister"),
+            Err(RecordRepoError::EntityExists(_))
+        ));
+        assert!(matches!(
+            working.rename_entity(dto::EntityType::Office, "pm", "prime-minister"),
+            Err(RecordRepoError::EntityNotFound(_))
+        ));
+
+        let changes = working
+            .rename_entity(dto::EntityType::Office, "cm", "chief-minister")
+            .unwrap();
+        assert_eq!(changes.len(), 4);
+
+        let keys: Vec<_> = working
+            .list_keys("office/")
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|path| path != "office/minister/name")
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "office/chief-minister/contact/email",
+                "office/chief-minister/name",
+                "office/minister/supervisor/responsible_to/chief-minister",
+            ]
+        );
+        let tenure = working.get("person/p1/tenure/chief-minister/2020-01-01");
+        assert_eq!(
+            tenure.unwrap(),
+            Some(RecordValue::Tenure(TenureValue::default()))
+        );
+        assert_eq!(working.get("person/p1/tenure/cm/2020-01-01").unwrap(), None);
+
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM entity WHERE id = 'cm'"), 0);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM entity WHERE id = 'chief-minister'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM entity_contact WHERE entity_id = 'chief-minister'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM person_office_tenure WHERE office_id = 'chief-minister'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM office_supervisor WHERE supervisor_office_id = 'cm'"),
+            0
+        );
+        assert!(repo.verify_index().unwrap().index_matches);
+    }
+
+    #[test]
+    fn test_recover_interrupted_write() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person One".to_string())
+            .unwrap();
+        assert!(!repo.recover().unwrap());
+
+        // Simulate a crash after the working ref moved but before the index
+        // was updated.
+        let mut working = repo.working().unwrap();
+        working
+            .repo_ref
+            .write(
+                b"person/p1/name".to_vec(),
+                postcard::to_stdvec("Person Uno").unwrap(),
+            )
+            .unwrap();
+        working
+            .repo_ref
+            .write(
+                b"person/p2/name".to_vec(),
+                postcard::to_stdvec("Person Two").unwrap(),
+            )
+            .unwrap();
+
+        let names = |conn: &Connection| -> Vec<String> {
+            let mut stmt = conn.prepare("SELECT name FROM entity ORDER BY id").unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(names(&conn), vec!["Person One"]);
+
+        let repo = RecordRepo::open(&conn).unwrap();
+        assert_eq!(names(&conn), vec!["Person Uno", "Person Two"]);
+        assert!(!repo.recover().unwrap());
+    }
+
+    #[test]
+    fn test_replace() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let mut working = repo.working().unwrap();
+        assert_eq!(
+            working
+                .replace_from_json("person/p1/name", "\"One\"")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            working
+                .replace_from_json("person/p1/name", "\"Uno\"")
+                .unwrap(),
+            Some(RecordValue::Name("One".to_string()))
+        );
+        assert_eq!(
+            working.replace_from_json("person/p1/name", "\"Person One\"").unwrap(),
+            Some(RecordValue::Name("Uno".to_string()))
+        );
+        assert_eq!(
+            working
+                .replace_from_json("office/o1/establishment", "\"1950-01-26\"")
+                .unwrap(),
+            None
+        );
+
+        let name: String = conn
+            .query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "Person One");
+    }
+
+    #[test]
+    fn test_save_unchanged() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person One".to_string())
+            .unwrap();
+        let before = repo.root_hash(RepoRefType::Working).unwrap();
+        // Saves of the name it already has must leave both the tree and this
+        // row alone; only the save that changes it rewrites the row.
+        conn.execute("UPDATE entity SET name = 'Stale' WHERE id = 'p1'", [])
+            .unwrap();
+        let name = || -> String {
+            conn.query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        working
+            .save_from_json("person/p1/name", "\"Person One\"")
+            .unwrap();
+        assert_eq!(
+            working
+                .replace_from_json("person/p1/name", "\"Person One\"")
+                .unwrap(),
+            Some(RecordValue::Name("Person One".to_string()))
+        );
+        assert_eq!(repo.root_hash(RepoRefType::Working).unwrap(), before);
+        assert_eq!(name(), "Stale");
+
+        working.save(p1.name(), &"Person Uno".to_string()).unwrap();
+        assert_ne!(repo.root_hash(RepoRefType::Working).unwrap(), before);
+        assert_eq!(name(), "Person Uno");
+    }
+
+    #[test]
+    fn test_gc_holds_write_lock() {
+        use crate::repo::backend::Backend;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let conn = Connection::open(&path).unwrap();
+        setup_db(&conn);
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let other = Connection::open(&path).unwrap();
+        other.busy_timeout(std::time::Duration::ZERO).unwrap();
+        let write = || other.execute("DELETE FROM refs WHERE name = 'nonesuch'", []);
+
+        let blocked = repo
+            .repo
+            .backend
+            .exclusively(|| Ok(write().is_err()))
+            .unwrap();
+        assert!(blocked);
+        assert!(conn.is_autocommit());
+        write().unwrap();
+
+        repo.repo.gc().unwrap();
+        assert!(conn.is_autocommit());
+    }
+
+    #[test]
+    fn test_stats_without_commits_table() {
+        use crate::repo::backend::KeyType;
+
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let stats = repo.repo.stats(b"").unwrap();
+        assert_eq!(stats.stored[&KeyType::Commit].count, 0);
+        assert_eq!(stats.stored[&KeyType::IndexSnapshot].count, 0);
+        assert_eq!(stats.stored[&KeyType::Secret].count, 1);
+    }
+
+    #[test]
+    fn test_save_over_limit() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn).with_limits(Limits {
+            max_key_len: 64,
+            max_value_size: 16,
+        });
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Person One".to_string()).unwrap();
+        let err = working
+            .save(p1.name(), &"A name longer than sixteen bytes".to_string())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RecordRepoError::Repo(RepoError::ValueTooLarge { max: 16, .. })
+        ));
+
+        let working = repo.working().unwrap();
+        assert_eq!(
+            working.get("person/p1/name").unwrap(),
+            Some(RecordValue::Name("Person One".to_string()))
+        );
+        let name: String = conn
+            .query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "Person One");
+    }
+
+    #[test]
+    fn test_transaction() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        repo.working()
+            .unwrap()
+            .save(p1.name(), &"Person One".to_string())
+            .unwrap();
+        let before = repo.root_hash(RepoRefType::Working).unwrap();
+        let entities = || -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM entity", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let result: Result<(), RecordRepoError> = repo.transaction(|working| {
+            working.save(p1.name(), &"Uno".to_string())?;
+            working.save(p2.name(), &"Person Two".to_string())?;
+            Err(RecordRepoError::InvalidPath("forced".to_string()))
+        });
+        assert!(matches!(result, Err(RecordRepoError::InvalidPath(_))));
+        assert_eq!(repo.root_hash(RepoRefType::Working).unwrap(), before);
+        assert_eq!(repo.root_hash(RepoRefType::Indexed).unwrap(), before);
+        assert_eq!(entities(), 1);
+        let name: String = conn
+            .query_row("SELECT name FROM entity WHERE id = 'p1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "Person One");
+
+        repo.transaction(|working| {
+            working.save(p1.name(), &"Uno".to_string())?;
+            working.save(p2.name(), &"Person Two".to_string())
+        })
+        .unwrap();
+        assert_eq!(entities(), 2);
+        assert_eq!(
+            repo.working().unwrap().get("person/p1/name").unwrap(),
+            Some(RecordValue::Name("Uno".to_string()))
+        );
+        assert_eq!(
+            repo.root_hash(RepoRefType::Indexed).unwrap(),
+            repo.root_hash(RepoRefType::Working).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_db(&conn);
+
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let p1 = Key::<PersonPath, ()>::new("p1");
+        let p2 = Key::<PersonPath, ()>::new("p2");
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Alice".to_string()).unwrap();
+        working.save(p2.name(), &"Bob".to_string()).unwrap();
+        repo.commit(None, None).unwrap();
+        let (first, commit) = repo.log().unwrap().remove(0);
+
+        let mut working = repo.working().unwrap();
+        working.save(p1.name(), &"Alicia".to_string()).unwrap();
+        working.delete_path("person/p2/name").unwrap();
+        let photo = data::Photo {
+            url: "http://example.com/p1.jpg".to_string(),
+            attribution: None,
+            license: None,
+            source_url: None,
+        };
+        working.save(p1.photo(), &photo).unwrap();
+
+        let from = repo.resolve("committed").unwrap();
+        let to = repo.resolve("working").unwrap();
+        let lines: Vec<_> = from
+            .iterate_diff(&to)
+            .unwrap()
+            .map(|diff| diff.unwrap().to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"~ person/p1/name "Alice" -> "Alicia""#,
+                r#"+ person/p1/photo {"url":"http://example.com/p1.jpg","attribution":null}"#,
+                r#"- person/p2/name "Bob""#,
+            ]
+        );
+
+        let by_commit = repo.resolve(&first.to_hex()[..8]).unwrap();
+        assert_eq!(by_commit.commit_id().unwrap(), commit.root);
+        let by_root = repo.resolve(&commit.root.to_hex()[..8]).unwrap();
+        assert_eq!(by_root.commit_id().unwrap(), commit.root);
+        assert!(repo.resolve("nothing").is_err());
+    }
 }
@@ -29,6 +29,41 @@ impl<'a> SqliteBackend<'a> {
     pub fn new(conn: &'a Connection) -> Self {
         Self { conn }
     }
+
+    /// Run `f` in a transaction of its own, or as part of the caller's if
+    /// one is open.
+    fn atomically<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, SqliteBackendError>,
+    ) -> Result<T, SqliteBackendError> {
+        if !self.conn.is_autocommit() {
+            return f();
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        let value = f()?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    /// Databases created before commit objects existed lack the `commits`
+    /// table, so create it on first use.
+    fn ensure_commits_table(&self) -> Result<(), SqliteBackendError> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS commits (hash BLOB NOT NULL PRIMARY KEY, blob BLOB NOT NULL)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Index snapshots came later still, so their table is created on first
+    /// use too.
+    fn ensure_index_snapshots_table(&self) -> Result<(), SqliteBackendError> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_snapshots (hash BLOB NOT NULL PRIMARY KEY, blob BLOB NOT NULL)",
+            [],
+        )?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -147,6 +182,26 @@ impl<'a> Backend for SqliteBackend<'a> {
                     .optional()
                     .map_err(SqliteBackendError::from)
             }
+            KeyType::Commit => {
+                self.ensure_commits_table()?;
+                self.conn
+                    .query_row("SELECT blob FROM commits WHERE hash = ?1", [key], |row| {
+                        row.get(0)
+                    })
+                    .optional()
+                    .map_err(SqliteBackendError::from)
+            }
+            KeyType::IndexSnapshot => {
+                self.ensure_index_snapshots_table()?;
+                self.conn
+                    .query_row(
+                        "SELECT blob FROM index_snapshots WHERE hash = ?1",
+                        [key],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                    .map_err(SqliteBackendError::from)
+            }
         }
     }
 
@@ -179,6 +234,22 @@ impl<'a> Backend for SqliteBackend<'a> {
                 )?;
                 Ok(())
             }
+            KeyType::Commit => {
+                self.ensure_commits_table()?;
+                self.conn.execute(
+                    "INSERT OR IGNORE INTO commits (hash, blob) VALUES (?1, ?2)",
+                    (key, value),
+                )?;
+                Ok(())
+            }
+            KeyType::IndexSnapshot => {
+                self.ensure_index_snapshots_table()?;
+                self.conn.execute(
+                    "INSERT OR REPLACE INTO index_snapshots (hash, blob) VALUES (?1, ?2)",
+                    (key, value),
+                )?;
+                Ok(())
+            }
         }
     }
 
@@ -217,6 +288,28 @@ impl<'a> Backend for SqliteBackend<'a> {
                 }
                 Ok(secrets)
             }
+            KeyType::Commit => {
+                self.ensure_commits_table()?;
+                let mut stmt = self.conn.prepare("SELECT hash FROM commits")?;
+                let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+
+                let mut hashes = Vec::new();
+                for h in rows {
+                    hashes.push(h?);
+                }
+                Ok(hashes)
+            }
+            KeyType::IndexSnapshot => {
+                self.ensure_index_snapshots_table()?;
+                let mut stmt = self.conn.prepare("SELECT hash FROM index_snapshots")?;
+                let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+
+                let mut hashes = Vec::new();
+                for h in rows {
+                    hashes.push(h?);
+                }
+                Ok(hashes)
+            }
         }
     }
 
@@ -226,52 +319,76 @@ impl<'a> Backend for SqliteBackend<'a> {
                 if keys.is_empty() {
                     return Ok(0);
                 }
-                let tx = self.conn.unchecked_transaction()?;
-                let mut deleted = 0;
-                {
-                    let mut stmt = tx.prepare("DELETE FROM repo WHERE hash = ?1")?;
+                self.atomically(|| {
+                    let mut deleted = 0;
+                    let mut stmt = self.conn.prepare("DELETE FROM repo WHERE hash = ?1")?;
                     for key in keys {
                         deleted += stmt.execute([key])?;
                     }
-                }
-                tx.commit()?;
-                Ok(deleted)
+                    Ok(deleted)
+                })
             }
             KeyType::Ref => {
                 if keys.is_empty() {
                     return Ok(0);
                 }
-                let tx = self.conn.unchecked_transaction()?;
-                let mut deleted = 0;
-                {
-                    let mut stmt = tx.prepare("DELETE FROM refs WHERE name = ?1")?;
+                self.atomically(|| {
+                    let mut deleted = 0;
+                    let mut stmt = self.conn.prepare("DELETE FROM refs WHERE name = ?1")?;
                     for key in keys {
                         let key_str = std::str::from_utf8(key).map_err(|e| {
                             SqliteBackendError::Parse(format!("Invalid UTF-8 in ref key: {}", e))
                         })?;
                         deleted += stmt.execute([key_str])?;
                     }
-                }
-                tx.commit()?;
-                Ok(deleted)
+                    Ok(deleted)
+                })
             }
             KeyType::Secret => {
                 if keys.is_empty() {
                     return Ok(0);
                 }
-                let tx = self.conn.unchecked_transaction()?;
-                let mut deleted = 0;
-                {
-                    let mut stmt = tx.prepare("DELETE FROM secrets WHERE name = ?1")?;
+                self.atomically(|| {
+                    let mut deleted = 0;
+                    let mut stmt = self.conn.prepare("DELETE FROM secrets WHERE name = ?1")?;
                     for key in keys {
                         let key_str = std::str::from_utf8(key).map_err(|e| {
                             SqliteBackendError::Parse(format!("Invalid UTF-8 in secret key: {}", e))
                         })?;
                         deleted += stmt.execute([key_str])?;
                     }
+                    Ok(deleted)
+                })
+            }
+            KeyType::Commit => {
+                if keys.is_empty() {
+                    return Ok(0);
                 }
-                tx.commit()?;
-                Ok(deleted)
+                self.ensure_commits_table()?;
+                self.atomically(|| {
+                    let mut deleted = 0;
+                    let mut stmt = self.conn.prepare("DELETE FROM commits WHERE hash = ?1")?;
+                    for key in keys {
+                        deleted += stmt.execute([key])?;
+                    }
+                    Ok(deleted)
+                })
+            }
+            KeyType::IndexSnapshot => {
+                if keys.is_empty() {
+                    return Ok(0);
+                }
+                self.ensure_index_snapshots_table()?;
+                self.atomically(|| {
+                    let mut deleted = 0;
+                    let mut stmt = self
+                        .conn
+                        .prepare("DELETE FROM index_snapshots WHERE hash = ?1")?;
+                    for key in keys {
+                        deleted += stmt.execute([key])?;
+                    }
+                    Ok(deleted)
+                })
             }
         }
     }
@@ -281,26 +398,52 @@ impl<'a> Backend for SqliteBackend<'a> {
         Ok(())
     }
 
+    /// Takes the lock with `BEGIN IMMEDIATE`, unless the caller's
+    /// transaction already holds it.
+    fn exclusively<T>(&self, f: impl FnOnce() -> Result<T, RepoError>) -> Result<T, RepoError> {
+        if !self.conn.is_autocommit() {
+            return f();
+        }
+        let tx = rusqlite::Transaction::new_unchecked(
+            self.conn,
+            rusqlite::TransactionBehavior::Immediate,
+        )
+        .map_err(|e| SqliteBackendError::from(e).to_repo_error())?;
+        let value = f()?;
+        tx.commit()
+            .map_err(|e| SqliteBackendError::from(e).to_repo_error())?;
+        Ok(value)
+    }
+
     fn stats(
         &self,
         key_type: KeyType,
     ) -> Result<(usize, std::collections::BTreeMap<usize, usize>), Self::Error> {
-        if key_type == KeyType::Node {
-            let mut stmt = self.conn.prepare("SELECT length(blob) as size FROM repo")?;
-            let rows = stmt.query_map([], |row| row.get::<_, usize>(0))?;
-
-            let mut count = 0;
-            let mut distribution = std::collections::BTreeMap::new();
-
-            for size in rows {
-                let size = size?;
-                count += 1;
-                *distribution.entry(size).or_insert(0) += 1;
+        let sql = match key_type {
+            KeyType::Node => "SELECT length(blob) as size FROM repo",
+            KeyType::Ref => "SELECT length(hash) as size FROM refs",
+            KeyType::Secret => "SELECT length(value) as size FROM secrets",
+            KeyType::Commit => {
+                self.ensure_commits_table()?;
+                "SELECT length(blob) as size FROM commits"
+            }
+            KeyType::IndexSnapshot => {
+                self.ensure_index_snapshots_table()?;
+                "SELECT length(blob) as size FROM index_snapshots"
             }
+        };
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map([], |row| row.get::<_, usize>(0))?;
 
-            Ok((count, distribution))
-        } else {
-            Ok((0, std::collections::BTreeMap::new()))
+        let mut count = 0;
+        let mut distribution = std::collections::BTreeMap::new();
+
+        for size in rows {
+            let size = size?;
+            count += 1;
+            *distribution.entry(size).or_insert(0) += 1;
         }
+
+        Ok((count, distribution))
     }
 }
@@ -2,23 +2,50 @@ use anyhow::{Context, Result};
 use askama::Template;
 use axum::extract::{self, State};
 use rusqlite::Connection;
+use serde::Serialize;
 use serve::StaticDir;
+use std::collections::HashSet;
 use std::path::Path;
 use std::{fs, sync::Arc};
 
-use crate::record::RecordRepo;
-use crate::{CONFIG, LibrarySql, SchemaSql};
+use crate::record::{RecordDiff, RecordKey, RecordRepo, RecordValue};
+use crate::repo::Hash;
+use crate::{LibrarySql, SchemaSql, theme};
 use crate::{
     dto,
-    serve::{self, AppState},
+    serve::{self, AppState, SearchIndex},
 };
 
-pub async fn run(db: &Path, output: &Path) -> Result<()> {
-    let state = Arc::new(AppState::new(
-        db.to_path_buf(),
-        false,
-        CONFIG.base_url.to_string(),
-    )?);
+/// An entity with a page, by type and id.
+type Page = (dto::EntityType, String);
+
+/// The file in the output directory holding the committed root the pages
+/// were last rendered from.
+const COMMIT_ID_FILE: &str = "commit_id";
+
+#[derive(Serialize)]
+struct SearchIndexEntry {
+    #[serde(rename = "type")]
+    typ: dto::EntityType,
+    id: String,
+    name: String,
+    /// Alternate names to match on. Entities carry only a single name for now.
+    aliases: Vec<String>,
+}
+
+/// Render the site into `output`. When `incremental` and `output` holds an
+/// earlier render, only the pages of entities whose records changed since then,
+/// and of the entities showing them, are rewritten, and those of removed
+/// entities deleted. Otherwise every page is.
+pub async fn run(
+    db: &Path,
+    output: &Path,
+    search_index: SearchIndex,
+    incremental: bool,
+) -> Result<()> {
+    let mut state = AppState::new(db.to_path_buf(), false, theme::config().base_url.to_string())?;
+    state.search_index = search_index;
+    let state = Arc::new(state);
     let conn = state.db_pool.get()?;
 
     let repo = RecordRepo::new(&conn);
@@ -29,31 +56,71 @@ pub async fn run(db: &Path, output: &Path) -> Result<()> {
         anyhow::bail!("There are uncommitted changes in the database. Please commit them first.");
     }
 
-    fs::create_dir(output).with_context(|| format!("could not create output dir {:?}", output))?;
+    let rendered = if incremental {
+        rendered_commit(output)?
+    } else {
+        None
+    };
+    if let Some(previous) = rendered {
+        let (changed, removed) = changed_pages(&conn, &previous)?;
+        let counts = (changed.len(), removed.len());
+        for (typ, id) in changed {
+            render_page(&conn, &State(state.clone()), output, typ, &id)?;
+        }
+        for (typ, id) in removed {
+            let output_path = page_path(output, typ, &id);
+            if output_path.exists() {
+                fs::remove_file(&output_path)
+                    .with_context(|| format!("could not remove page {:?}", output_path))?;
+            }
+        }
+        println!("Rendered {} pages, removed {}.", counts.0, counts.1);
+    } else {
+        fs::create_dir_all(output)
+            .with_context(|| format!("could not create output dir {:?}", output))?;
+        // Pages of entities removed since are not known, so start over.
+        for typ in [dto::EntityType::Person, dto::EntityType::Office] {
+            let dir = output.join(typ.as_str());
+            if dir.exists() {
+                fs::remove_dir_all(&dir).with_context(|| format!("could not clear {:?}", dir))?;
+            }
+        }
 
-    // persons
-    render_persons(&conn, State(state.clone()), output)
-        .await
-        .context("could not render persons")?;
+        // persons
+        render_persons(&conn, State(state.clone()), output)
+            .await
+            .context("could not render persons")?;
 
-    // offices
-    render_offices(&conn, State(state.clone()), output)
-        .await
-        .context("could not render offices")?;
+        // offices
+        render_offices(&conn, State(state.clone()), output)
+            .await
+            .context("could not render offices")?;
+    }
 
     // render index
-    let template = serve::handler::index(State(state.clone())).await?;
+    let template = serve::handler::index(State(state.clone()), extract::Query(Default::default())).await?;
     let str = template.render()?;
     let output_path = output.join("index.html");
     fs::write(output_path.as_path(), str)
         .with_context(|| format!("could not write rendered file {:?}", output_path))?;
 
-    let search_db_path = output.join("search.db");
-    create_search_database(&search_db_path, db)?;
+    match search_index {
+        SearchIndex::Sqlite => {
+            let search_db_path = output.join("search.db");
+            if search_db_path.exists() {
+                fs::remove_file(&search_db_path)?;
+            }
+            create_search_database(&search_db_path, db)?;
+        }
+        SearchIndex::Json => {
+            let search_index_path = output.join("search-index.json");
+            create_search_index(&search_index_path, &conn)?;
+        }
+    }
 
     // write static files to output
     let static_dir = output.join("static");
-    fs::create_dir(&static_dir)?;
+    fs::create_dir_all(&static_dir)?;
     for item in StaticDir::iter() {
         let file_path = item.as_ref();
         let content = StaticDir::get(file_path).context("could not get static file")?;
@@ -67,6 +134,125 @@ pub async fn run(db: &Path, output: &Path) -> Result<()> {
             .with_context(|| format!("could not write static file {:?}", output_path))?;
     }
 
+    let commit_id_path = output.join(COMMIT_ID_FILE);
+    fs::write(&commit_id_path, committed_hash.to_hex())
+        .with_context(|| format!("could not write {:?}", commit_id_path))?;
+
+    Ok(())
+}
+
+/// The committed root that `output` was last rendered from, if any.
+fn rendered_commit(output: &Path) -> Result<Option<Hash>> {
+    let path = output.join(COMMIT_ID_FILE);
+    if !path.try_exists()? {
+        return Ok(None);
+    }
+    let hex = fs::read_to_string(&path).with_context(|| format!("could not read {:?}", path))?;
+    let hash = Hash::from_hex(hex.trim()).map_err(anyhow::Error::msg)?;
+
+    Ok(Some(hash))
+}
+
+/// The entities whose pages are out of date since the committed root was
+/// `previous`, split into those to render and those that no longer exist.
+///
+/// Besides the entities with changed records, these are the ones within two
+/// relations of them, which covers, for example, the subordinate offices
+/// listing an office's incumbent.
+fn changed_pages(conn: &Connection, previous: &Hash) -> Result<(Vec<Page>, Vec<Page>)> {
+    let repo = RecordRepo::new(conn);
+    let diffs = repo
+        .get_at(previous)?
+        .iterate_diff(&repo.committed()?)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut affected: HashSet<Page> = diffs.iter().flat_map(mentioned).collect();
+    let mut frontier: Vec<_> = affected.iter().cloned().collect();
+    for _ in 0..2 {
+        let mut next = Vec::new();
+        for (typ, id) in &frontier {
+            conn.get_related_entities(typ, id, |row| {
+                let entity = (row.get(0)?, row.get(1)?);
+                if affected.insert(entity.clone()) {
+                    next.push(entity);
+                }
+                Ok(())
+            })?;
+        }
+        frontier = next;
+    }
+
+    let mut affected: Vec<_> = affected.into_iter().collect();
+    affected.sort_by(|a, b| (a.0.as_str(), &a.1).cmp(&(b.0.as_str(), &b.1)));
+    let mut changed = Vec::new();
+    let mut removed = Vec::new();
+    for (typ, id) in affected {
+        if conn.exists_entity(&typ, &id, |row| row.get(0))? {
+            changed.push((typ, id));
+        } else {
+            removed.push((typ, id));
+        }
+    }
+
+    Ok((changed, removed))
+}
+
+/// The entity a record belongs to, and the one it points at, if any. A change
+/// to a tenure or a supervisor shows on the pages at both ends.
+fn mentioned(diff: &RecordDiff) -> Vec<Page> {
+    let mut entities = vec![diff.key().entity_info()];
+    match diff.key() {
+        RecordKey::Tenure(k) => {
+            entities.push((dto::EntityType::Office, k.state.office_id.clone()));
+        }
+        RecordKey::Supervisor(_) => {
+            let values = match diff {
+                RecordDiff::Added(_, v) | RecordDiff::Removed(_, v) => vec![v],
+                RecordDiff::Changed(_, old, new) => vec![old, new],
+            };
+            for value in values {
+                if let RecordValue::Supervisor(id) = value {
+                    entities.push((dto::EntityType::Office, id.clone()));
+                }
+            }
+        }
+        _ => {}
+    }
+    entities
+}
+
+fn page_path(output: &Path, typ: dto::EntityType, id: &str) -> std::path::PathBuf {
+    output.join(typ.as_str()).join(format!("{}.html", id))
+}
+
+fn render_page(
+    conn: &Connection,
+    state: &State<Arc<AppState>>,
+    output: &Path,
+    typ: dto::EntityType,
+    id: &str,
+) -> Result<()> {
+    let str = match typ {
+        dto::EntityType::Person => {
+            serve::handler::person::load_page(
+                conn,
+                id,
+                &serve::handler::person::TenuresParams::all(),
+                state.page_context(),
+            )?
+            .render()?
+        }
+        dto::EntityType::Office => {
+            serve::handler::office::load_page(conn, id, state.page_context())
+                .with_context(|| format!("could not render office for {}", id))?
+                .render()?
+        }
+    };
+    let output_path = page_path(output, typ, id);
+    fs::create_dir_all(output.join(typ.as_str()))?;
+    fs::write(output_path.as_path(), str)
+        .with_context(|| format!("could not write rendered file {:?}", output_path))?;
+
     Ok(())
 }
 
@@ -86,13 +272,7 @@ async fn render_persons(
     })?;
 
     for id in ids {
-        let template =
-            serve::handler::person::page(state.clone(), extract::Path(format!("{}.html", id)))
-                .await?;
-        let str = template.render()?;
-        let output_path = person_path.join(format!("{}.html", id));
-        fs::write(output_path.as_path(), str)
-            .with_context(|| format!("could not write rendered file {:?}", output_path))?;
+        render_page(conn, &state, output, dto::EntityType::Person, &id)?;
     }
 
     Ok(())
@@ -114,14 +294,7 @@ async fn render_offices(
     })?;
 
     for id in ids {
-        let template =
-            serve::handler::office::page(state.clone(), extract::Path(format!("{}.html", id)))
-                .await
-                .with_context(|| format!("could not render office for {}", id))?;
-        let str = template.render()?;
-        let output_path = office_path.join(format!("{}.html", id));
-        fs::write(output_path.as_path(), str)
-            .with_context(|| format!("could not write rendered file {:?}", output_path))?;
+        render_page(conn, &state, output, dto::EntityType::Office, &id)?;
     }
 
     Ok(())
@@ -130,6 +303,7 @@ async fn render_offices(
 pub fn create_search_database(search_db_path: &Path, db_path: &Path) -> Result<()> {
     let conn = Connection::open(search_db_path).context("could not create search database")?;
     conn.create_entity_tables()?;
+    crate::ingest::create_entity_index(&conn, theme::config().search_tokenizer)?;
     let db_path_str = db_path
         .to_str()
         .with_context(|| format!("could not convert path {:?}", db_path))?;
@@ -144,3 +318,113 @@ pub fn create_search_database(search_db_path: &Path, db_path: &Path) -> Result<(
 
     Ok(())
 }
+
+pub fn create_search_index(search_index_path: &Path, conn: &Connection) -> Result<()> {
+    let mut entries = Vec::new();
+    conn.get_entities(|row| {
+        entries.push(SearchIndexEntry {
+            typ: row.get(0)?,
+            id: row.get(1)?,
+            name: row.get(2)?,
+            aliases: Vec::new(),
+        });
+        Ok(())
+    })?;
+
+    let json = serde_json::to_string(&entries)?;
+    fs::write(search_index_path, json)
+        .with_context(|| format!("could not write search index {:?}", search_index_path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::SupervisingRelation;
+    use crate::record::{Key, OfficePath, PersonPath};
+
+    #[test]
+    fn test_changed_pages() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let person = Key::<PersonPath, ()>::new;
+        let office = Key::<OfficePath, ()>::new;
+        let mut working = repo.working().unwrap();
+        for id in ["alice", "bob", "carol", "dave", "eve"] {
+            working.save(person(id).name(), &id.to_string()).unwrap();
+        }
+        for id in ["pm", "minister", "clerk"] {
+            working.save(office(id).name(), &id.to_string()).unwrap();
+        }
+        for (person_id, office_id) in [("alice", "pm"), ("bob", "pm"), ("carol", "minister")] {
+            let start = chrono::NaiveDate::from_ymd_opt(2000, 1, 1);
+            working
+                .save(person(person_id).tenure(office_id, start), &None.into())
+                .unwrap();
+        }
+        for (office_id, supervisor) in [("minister", "pm"), ("clerk", "minister")] {
+            working
+                .save(
+                    office(office_id).supervisor(SupervisingRelation::ResponsibleTo, supervisor),
+                    &supervisor.to_string(),
+                )
+                .unwrap();
+        }
+        repo.commit(None, None).unwrap();
+        let previous = repo.committed().unwrap().commit_id().unwrap();
+
+        let mut working = repo.working().unwrap();
+        working
+            .save(person("alice").name(), &"Alice".to_string())
+            .unwrap();
+        working.delete(person("eve").name()).unwrap();
+        repo.commit(None, None).unwrap();
+
+        let (changed, removed) = changed_pages(&conn, &previous).unwrap();
+        let ids = |pages: Vec<Page>| -> Vec<String> {
+            pages
+                .into_iter()
+                .map(|(typ, id)| format!("{}/{}", typ.as_str(), id))
+                .collect()
+        };
+        assert_eq!(
+            ids(changed),
+            ["office/minister", "office/pm", "person/alice", "person/bob"]
+        );
+        assert_eq!(ids(removed), ["person/eve"]);
+
+        let committed = repo.committed().unwrap().commit_id().unwrap();
+        let (changed, removed) = changed_pages(&conn, &committed).unwrap();
+        assert!(changed.is_empty() && removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_first_incremental_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("directory.db");
+        crate::import::init(&db, false).unwrap();
+        {
+            let conn = Connection::open(&db).unwrap();
+            let mut repo = RecordRepo::new(&conn);
+            let mut working = repo.working().unwrap();
+            working
+                .save(Key::<PersonPath, ()>::new("alice").name(), &"Alice".to_string())
+                .unwrap();
+            working
+                .save(Key::<OfficePath, ()>::new("pm").name(), &"PM".to_string())
+                .unwrap();
+            repo.commit(None, None).unwrap();
+        }
+
+        let output = dir.path().join("output");
+        run(&db, &output, SearchIndex::Json, true).await.unwrap();
+        assert!(page_path(&output, dto::EntityType::Person, "alice").exists());
+        assert!(page_path(&output, dto::EntityType::Office, "pm").exists());
+        assert!(rendered_commit(&output).unwrap().is_some());
+    }
+}
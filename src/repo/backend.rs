@@ -1,10 +1,17 @@
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use strum_macros::VariantArray;
+
+use super::RepoError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, VariantArray)]
 pub enum KeyType {
     Node,
     Ref,
     Secret,
+    Commit,
+    /// The hash of the index a commit's tree implies, keyed by the commit.
+    IndexSnapshot,
 }
 
 impl Display for KeyType {
@@ -13,6 +20,8 @@ impl Display for KeyType {
             KeyType::Node => write!(f, "node"),
             KeyType::Ref => write!(f, "ref"),
             KeyType::Secret => write!(f, "secret"),
+            KeyType::Commit => write!(f, "commit"),
+            KeyType::IndexSnapshot => write!(f, "index snapshot"),
         }
     }
 }
@@ -25,6 +34,14 @@ pub trait Backend {
     fn list(&self, key_type: KeyType) -> Result<Vec<Vec<u8>>, Self::Error>;
     fn delete(&self, key_type: KeyType, keys: &[&[u8]]) -> Result<usize, Self::Error>;
     fn vacuum(&self) -> Result<(), Self::Error>;
+    /// Run `f` holding the write lock, so that no other writer can move a
+    /// ref or store a node until it returns. Backends that are never shared
+    /// between writers just run it.
+    fn exclusively<T>(&self, f: impl FnOnce() -> Result<T, RepoError>) -> Result<T, RepoError> {
+        f()
+    }
+    /// The number of entries of `key_type` and how many there are of each
+    /// value size.
     fn stats(
         &self,
         key_type: KeyType,
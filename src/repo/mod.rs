@@ -8,6 +8,7 @@ use crate::repo::{
 };
 use iroh::SecretKey;
 use serde::{Deserialize, Serialize};
+use strum::VariantArray;
 use thiserror::Error;
 
 pub mod backend;
@@ -21,18 +22,32 @@ pub mod test_backend;
 mod tests;
 
 const IROH_SECRET: &str = "iroh";
+/// Version of the node encoding, stored as the first byte of every node
+/// written. Bump it, and branch on it in [`decode_node`], when the encoding
+/// changes.
+const NODE_FORMAT: u8 = 2;
+/// The format of nodes written before [`mst::MstNode::level`] was stored.
+const NODE_FORMAT_UNLEVELED: u8 = 1;
+/// Largest uncompressed node [`decode_node`] takes to be versioned.
+const MAX_NODE_SIZE: u32 = 1 << 24;
+/// Ref pointing at the latest commit object.
+const HEAD: &str = "head";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RepoRefType {
     Working,
     Committed,
+    /// The tree the SQLite index was last brought in line with. It trails
+    /// `Working` only if a write was interrupted before the index caught up.
+    Indexed,
 }
 
 impl RepoRefType {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             RepoRefType::Working => "working",
             RepoRefType::Committed => "committed",
+            RepoRefType::Indexed => "indexed",
         }
     }
 }
@@ -96,6 +111,30 @@ pub enum RepoError {
     RefNotFound(String),
     #[error("hash parsing error: {0}")]
     HashParse(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("hash mismatch for node {0}")]
+    HashMismatch(Hash),
+    #[error("node {0} is neither in the delta nor in the repository")]
+    MissingNode(Hash),
+    #[error("commit {0} not found")]
+    MissingCommit(Hash),
+    #[error("`{ref_name}` ref was moved by another writer")]
+    Conflict { ref_name: String },
+    #[error("head moved on since the commit was started")]
+    NotFastForward,
+    #[error("refs moved during garbage collection; run it again once writers are done")]
+    GcRaced,
+    #[error("no node matches `{0}`")]
+    UnknownNode(String),
+    #[error("`{prefix}` matches {count} nodes; give more of the hash")]
+    AmbiguousNode { prefix: String, count: usize },
+    #[error("repository is already initialized")]
+    AlreadyInitialized,
+    #[error("key is {len} bytes long, more than the limit of {max}")]
+    KeyTooLong { len: usize, max: usize },
+    #[error("value for `{key}` is {size} bytes, more than the limit of {max}")]
+    ValueTooLarge { key: String, size: usize, max: usize },
 }
 
 impl RepoError {
@@ -112,13 +151,118 @@ pub struct RepoStats {
     pub key_value_count: usize,
     pub total_value_size: usize,
     pub value_size_distribution: std::collections::BTreeMap<usize, usize>,
-    pub node_count: usize,
-    pub total_node_size: usize,
-    pub node_size_distribution: std::collections::BTreeMap<usize, usize>,
+    /// What the backend holds of each key type.
+    pub stored: std::collections::BTreeMap<KeyType, StoredStats>,
+}
+
+/// What [`Repo::verify`] found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// How many distinct nodes were checked.
+    pub nodes: usize,
+    /// Each node found wrong, with what is wrong with it.
+    pub problems: Vec<(Hash, NodeProblem)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NodeProblem {
+    #[error("node is missing")]
+    Missing,
+    #[error("stored bytes do not hash to the node's key")]
+    HashMismatch,
+    #[error("node cannot be decoded: {0}")]
+    Undecodable(String),
+    #[error("keys are not strictly sorted or lie outside the range of the parent")]
+    Unsorted,
+    #[error("key levels do not match the node's level {0}")]
+    MixedLevels(u32),
+    #[error("level {level} is not below the parent's level {parent}")]
+    Level { level: u32, parent: u32 },
+}
+
+/// Figures for the entries of one [`KeyType`] in the backend.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StoredStats {
+    pub count: usize,
+    pub total_size: usize,
+    pub size_distribution: std::collections::BTreeMap<usize, usize>,
+}
+
+/// Every ref's name and the hash it points at.
+type Refs = std::collections::BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// What [`Repo::gc`] would delete.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Garbage {
+    pub nodes: usize,
+    /// The stored, compressed size of the nodes.
+    pub bytes: usize,
+}
+
+/// A self-contained set of compressed nodes together with the root they
+/// belong to. Produced by `Repo::export_delta` and consumed by
+/// `Repo::import_delta`.
+#[derive(Serialize, Deserialize)]
+struct Delta {
+    root: Hash,
+    nodes: Vec<(Hash, Vec<u8>)>,
+}
+
+/// A commit object, stored under `KeyType::Commit` keyed by the hash of its
+/// serialized form.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// Root of the tree that was committed.
+    pub root: Hash,
+    /// The commit this one follows, if any.
+    pub parent: Option<Hash>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    pub author: Option<String>,
+    pub message: Option<String>,
+}
+
+/// Bounds on what [`RepoRef::write`] accepts, so that a stray blob cannot
+/// bloat the nodes it lands in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest key, in bytes.
+    pub max_key_len: usize,
+    /// Largest serialized value, in bytes.
+    pub max_value_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_key_len: 1024,
+            max_value_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    pub fn check(&self, key: &[u8], value: &[u8]) -> Result<(), RepoError> {
+        if key.len() > self.max_key_len {
+            return Err(RepoError::KeyTooLong {
+                len: key.len(),
+                max: self.max_key_len,
+            });
+        }
+        if value.len() > self.max_value_size {
+            return Err(RepoError::ValueTooLarge {
+                key: String::from_utf8_lossy(key).into_owned(),
+                size: value.len(),
+                max: self.max_value_size,
+            });
+        }
+        Ok(())
+    }
 }
 
 pub struct Repo<B: Backend> {
     pub backend: B,
+    pub limits: Limits,
 }
 
 impl<B: Backend> Repo<B>
@@ -126,10 +270,55 @@ where
     B::Error: ToRepoError,
 {
     pub fn new(backend: B) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
+    /// Point the working and committed refs at an empty tree and generate
+    /// the iroh secret. Fails with [`RepoError::AlreadyInitialized`] if any of
+    /// them is already set, as replacing them would orphan the data and change
+    /// the endpoint ID.
     pub fn init(&self) -> Result<(), RepoError> {
+        if self.is_initialized()? {
+            return Err(RepoError::AlreadyInitialized);
+        }
+
+        self.reinit()
+    }
+
+    /// Whether [`Repo::init`] has been run, even partly.
+    pub fn is_initialized(&self) -> Result<bool, RepoError> {
+        let keys = [
+            (KeyType::Ref, RepoRefType::Working.as_str()),
+            (KeyType::Ref, RepoRefType::Committed.as_str()),
+            (KeyType::Ref, HEAD),
+            (KeyType::Secret, IROH_SECRET),
+        ];
+        for (key_type, key) in keys {
+            if self
+                .backend
+                .get(key_type, key.as_bytes())
+                .map_err(|e| e.to_repo_error())?
+                .is_some()
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Like [`Repo::init`], but replaces the refs and the secret if they are
+    /// already set. The commit history is dropped with them, so the next
+    /// commit starts a new one.
+    pub fn reinit(&self) -> Result<(), RepoError> {
         let empty_node = MstNode::empty();
         let hash = self.write_node(&empty_node)?;
         self.backend
@@ -146,6 +335,9 @@ where
                 &hash.0,
             )
             .map_err(|e| e.to_repo_error())?;
+        self.backend
+            .delete(KeyType::Ref, &[HEAD.as_bytes()])
+            .map_err(|e| e.to_repo_error())?;
 
         let iroh_secret = SecretKey::generate(&mut rand::rng());
         self.backend
@@ -159,30 +351,197 @@ where
     }
 
     pub fn get_ref(&self, ref_type: RepoRefType) -> Result<RepoRef<'_, B>, RepoError> {
+        Ok(RepoRef {
+            repo: self,
+            hash: self.root_hash(ref_type)?,
+            name: ref_type.as_str().to_string(),
+        })
+    }
+
+    /// The root the ref points at, without the [`RepoRef`] around it.
+    pub fn root_hash(&self, ref_type: RepoRefType) -> Result<Hash, RepoError> {
         let ref_name = ref_type.as_str();
         let hash_bytes = self
             .backend
             .get(KeyType::Ref, ref_name.as_bytes())
             .map_err(|e| e.to_repo_error())?
             .ok_or_else(|| RepoError::RefNotFound(ref_name.to_string()))?;
-        let hash = Hash(
-            hash_bytes
-                .try_into()
-                .map_err(|_| RepoError::HashParse("Invalid hash length in ref".to_string()))?,
-        );
-        Ok(RepoRef {
-            repo: self,
-            hash,
-            name: ref_name.to_string(),
-        })
+        Ok(Hash(hash_bytes.try_into().map_err(|_| {
+            RepoError::HashParse("Invalid hash length in ref".to_string())
+        })?))
+    }
+
+    pub fn set_ref(&self, ref_type: RepoRefType, hash: &Hash) -> Result<(), RepoError> {
+        self.backend
+            .set(KeyType::Ref, ref_type.as_str().as_bytes(), &hash.0)
+            .map_err(|e| e.to_repo_error())
     }
 
-    pub fn commit(&mut self) -> Result<(), RepoError> {
+    /// Point the ref `name` at `new`, provided it still points at `expected`
+    /// (or does not exist yet, for `None`). Fails with `Conflict` otherwise.
+    ///
+    /// The check and the write are only atomic together if the backend runs
+    /// them in one transaction.
+    pub fn compare_and_set_ref(
+        &self,
+        name: &str,
+        expected: Option<&Hash>,
+        new: &Hash,
+    ) -> Result<(), RepoError> {
+        let current = self
+            .backend
+            .get(KeyType::Ref, name.as_bytes())
+            .map_err(|e| e.to_repo_error())?;
+        if current.as_deref() != expected.map(|hash| hash.0.as_slice()) {
+            return Err(RepoError::Conflict {
+                ref_name: name.to_string(),
+            });
+        }
+        self.backend
+            .set(KeyType::Ref, name.as_bytes(), &new.0)
+            .map_err(|e| e.to_repo_error())
+    }
+
+    /// Write the nodes reachable from `to` but not from `from` to `writer`.
+    ///
+    /// Without `from` every node reachable from `to` is exported. Returns the
+    /// number of nodes written.
+    pub fn export_delta<W: std::io::Write>(
+        &self,
+        from: Option<&Hash>,
+        to: &Hash,
+        writer: W,
+    ) -> Result<usize, RepoError> {
+        let nodes = self.delta_nodes(from, to)?;
+        let count = nodes.len();
+        postcard::to_io(
+            &Delta {
+                root: to.clone(),
+                nodes,
+            },
+            writer,
+        )?;
+
+        Ok(count)
+    }
+
+    /// The nodes reachable from `to` but not from `from`, as stored, each
+    /// before the nodes below it.
+    pub fn delta_nodes(
+        &self,
+        from: Option<&Hash>,
+        to: &Hash,
+    ) -> Result<Vec<(Hash, Vec<u8>)>, RepoError> {
+        let mut known = std::collections::HashSet::new();
+        if let Some(from) = from {
+            self.traverse_reachable(from, &mut known)?;
+        }
+
+        let mut nodes = Vec::new();
+        let mut stack = vec![to.clone()];
+        while let Some(hash) = stack.pop() {
+            // Everything below a known node is known as well.
+            if !known.insert(hash.clone()) {
+                continue;
+            }
+
+            let compressed = self
+                .backend
+                .get(KeyType::Node, &hash.0)
+                .map_err(|e| e.to_repo_error())?
+                .ok_or_else(|| RepoError::MissingNode(hash.clone()))?;
+            let node = decode_node(&compressed)?;
+
+            stack.extend(node.left);
+            stack.extend(node.items.into_iter().filter_map(|item| item.right));
+            nodes.push((hash, compressed));
+        }
+
+        Ok(nodes)
+    }
+
+    /// Read a delta written by `export_delta` and store its nodes.
+    ///
+    /// Every node is checked against its hash, and every child it refers to
+    /// must be present either in the delta or in the repository. Nothing is
+    /// written unless the whole delta is valid. Refs are left untouched; the
+    /// root of the delta is returned.
+    pub fn import_delta<R: std::io::Read>(&self, mut reader: R) -> Result<Hash, RepoError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let delta: Delta = postcard::from_bytes(&bytes)?;
+
+        let mut children = Vec::new();
+        for (hash, compressed) in &delta.nodes {
+            if blake3::hash(compressed).as_bytes() != &hash.0 {
+                return Err(RepoError::HashMismatch(hash.clone()));
+            }
+            let node = decode_node(compressed)?;
+            children.extend(node.left);
+            children.extend(node.items.into_iter().filter_map(|item| item.right));
+        }
+
+        let included: std::collections::HashSet<&Hash> =
+            delta.nodes.iter().map(|(hash, _)| hash).collect();
+        for hash in children.iter().chain(std::iter::once(&delta.root)) {
+            if !included.contains(hash)
+                && self
+                    .backend
+                    .get(KeyType::Node, &hash.0)
+                    .map_err(|e| e.to_repo_error())?
+                    .is_none()
+            {
+                return Err(RepoError::MissingNode(hash.clone()));
+            }
+        }
+
+        for (hash, compressed) in &delta.nodes {
+            self.backend
+                .set(KeyType::Node, &hash.0, compressed)
+                .map_err(|e| e.to_repo_error())?;
+        }
+
+        Ok(delta.root)
+    }
+
+    /// Point the committed ref at the working tree and record a commit object
+    /// for it. Fails with `NotFastForward` if another commit landed in the
+    /// meantime.
+    pub fn commit(
+        &mut self,
+        author: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<(), RepoError> {
         let root_hash_bytes = self
             .backend
             .get(KeyType::Ref, RepoRefType::Working.as_str().as_bytes())
             .map_err(|e| e.to_repo_error())?;
         if let Some(h_bytes) = root_hash_bytes {
+            let root = Hash(
+                h_bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| RepoError::HashParse("Invalid hash length in ref".to_string()))?,
+            );
+            let parent = self.head()?;
+            let commit = Commit {
+                root,
+                parent: parent.clone(),
+                timestamp: chrono::Utc::now().timestamp(),
+                author: author.map(str::to_string),
+                message: message.map(str::to_string),
+            };
+            let bytes = postcard::to_stdvec(&commit)?;
+            let hash = Hash(*blake3::hash(&bytes).as_bytes());
+
+            self.backend
+                .set(KeyType::Commit, &hash.0, &bytes)
+                .map_err(|e| e.to_repo_error())?;
+            self.compare_and_set_ref(HEAD, parent.as_ref(), &hash)
+                .map_err(|e| match e {
+                    RepoError::Conflict { .. } => RepoError::NotFastForward,
+                    e => e,
+                })?;
             self.backend
                 .set(
                     KeyType::Ref,
@@ -194,6 +553,63 @@ where
         Ok(())
     }
 
+    /// The hash of the latest commit object, if anything has been committed
+    /// since commit objects were introduced.
+    pub fn head(&self) -> Result<Option<Hash>, RepoError> {
+        self.backend
+            .get(KeyType::Ref, HEAD.as_bytes())
+            .map_err(|e| e.to_repo_error())?
+            .map(|bytes| {
+                bytes
+                    .try_into()
+                    .map(Hash)
+                    .map_err(|_| RepoError::HashParse("Invalid hash length in ref".to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn read_commit(&self, hash: &Hash) -> Result<Commit, RepoError> {
+        let bytes = self
+            .backend
+            .get(KeyType::Commit, &hash.0)
+            .map_err(|e| e.to_repo_error())?
+            .ok_or_else(|| RepoError::MissingCommit(hash.clone()))?;
+        Ok(postcard::from_bytes(&bytes)?)
+    }
+
+    /// Record `index` as the hash of the index the tree of `commit` implies.
+    pub fn set_index_snapshot(&self, commit: &Hash, index: &Hash) -> Result<(), RepoError> {
+        self.backend
+            .set(KeyType::IndexSnapshot, &commit.0, &index.0)
+            .map_err(|e| e.to_repo_error())
+    }
+
+    /// The hash recorded with [`Repo::set_index_snapshot`] for `commit`, if
+    /// any.
+    pub fn index_snapshot(&self, commit: &Hash) -> Result<Option<Hash>, RepoError> {
+        self.backend
+            .get(KeyType::IndexSnapshot, &commit.0)
+            .map_err(|e| e.to_repo_error())?
+            .map(|bytes| {
+                bytes.try_into().map(Hash).map_err(|_| {
+                    RepoError::HashParse("Invalid hash length in index snapshot".to_string())
+                })
+            })
+            .transpose()
+    }
+
+    /// The commits leading up to `head`, newest first.
+    pub fn log(&self) -> Result<Vec<(Hash, Commit)>, RepoError> {
+        let mut commits = Vec::new();
+        let mut next = self.head()?;
+        while let Some(hash) = next {
+            let commit = self.read_commit(&hash)?;
+            next = commit.parent.clone();
+            commits.push((hash, commit));
+        }
+        Ok(commits)
+    }
+
     pub fn abandon(&mut self) -> Result<(), RepoError> {
         let root_hash_bytes = self
             .backend
@@ -211,7 +627,9 @@ where
         Ok(())
     }
 
-    pub fn stats(&self) -> Result<RepoStats, RepoError> {
+    /// Statistics for the working tree. Key and value figures cover only the
+    /// keys starting with `prefix`; stored figures are for the whole store.
+    pub fn stats(&self, prefix: &[u8]) -> Result<RepoStats, RepoError> {
         let root_hash_bytes = self
             .backend
             .get(KeyType::Ref, RepoRefType::Working.as_str().as_bytes())
@@ -226,51 +644,115 @@ where
                     .try_into()
                     .map_err(|_| RepoError::HashParse("Invalid hash length".to_string()))?,
             );
-            self.traverse_stats(&h, &mut kv_count, &mut total_value_size, &mut value_sizes)?;
+            self.traverse_stats(
+                &h,
+                prefix,
+                &mut kv_count,
+                &mut total_value_size,
+                &mut value_sizes,
+            )?;
         }
 
-        let (node_count, node_sizes) = self
-            .backend
-            .stats(KeyType::Node)
-            .map_err(|e| e.to_repo_error())?;
-        let total_node_size = node_sizes.iter().map(|(s, c)| s * c).sum();
+        let mut stored = std::collections::BTreeMap::new();
+        for &key_type in KeyType::VARIANTS {
+            let (count, sizes) = self
+                .backend
+                .stats(key_type)
+                .map_err(|e| e.to_repo_error())?;
+            stored.insert(
+                key_type,
+                StoredStats {
+                    count,
+                    total_size: sizes.iter().map(|(s, c)| s * c).sum(),
+                    size_distribution: sizes,
+                },
+            );
+        }
 
         Ok(RepoStats {
             key_value_count: kv_count,
             total_value_size,
             value_size_distribution: value_sizes,
-            node_count,
-            total_node_size,
-            node_size_distribution: node_sizes,
+            stored,
         })
     }
 
+    /// Delete the nodes that neither a ref nor a commit leads to.
+    ///
+    /// This must not run alongside writers: nodes a writer has stored but
+    /// not yet pointed a ref at look unreachable. As a guard, only nodes that
+    /// were stored before the walk are candidates, and the refs are read
+    /// again before deleting, with the write lock held until the delete is
+    /// done; if any of them moved, nothing is deleted and
+    /// [`RepoError::GcRaced`] is returned.
     pub fn gc(&self) -> Result<usize, RepoError> {
-        let mut reachable = std::collections::HashSet::new();
-        let ref_names = self
-            .backend
-            .list(KeyType::Ref)
-            .map_err(|e| e.to_repo_error())?;
+        let (refs, to_delete) = self.unreachable_nodes()?;
+
+        let deleted = self.backend.exclusively(|| {
+            if self.read_refs()? != refs {
+                return Err(RepoError::GcRaced);
+            }
+            if to_delete.is_empty() {
+                return Ok(0);
+            }
+            let keys: Vec<&[u8]> = to_delete.iter().map(|s| s.as_slice()).collect();
+            self.backend
+                .delete(KeyType::Node, &keys)
+                .map_err(|e| e.to_repo_error())
+        })?;
 
-        for name in ref_names {
-            if let Some(hash_bytes) = self
+        self.backend.vacuum().map_err(|e| e.to_repo_error())?;
+
+        Ok(deleted)
+    }
+
+    /// What [`gc`](Self::gc) would delete now, changing nothing.
+    pub fn gc_preview(&self) -> Result<Garbage, RepoError> {
+        let (_, unreachable) = self.unreachable_nodes()?;
+        let mut garbage = Garbage::default();
+        for key in &unreachable {
+            if let Some(blob) = self
                 .backend
-                .get(KeyType::Ref, &name)
+                .get(KeyType::Node, key)
                 .map_err(|e| e.to_repo_error())?
             {
-                let hash = Hash(
-                    hash_bytes
-                        .try_into()
-                        .map_err(|_| RepoError::HashParse("Invalid hash length".to_string()))?,
-                );
-                self.traverse_reachable(&hash, &mut reachable)?;
+                garbage.nodes += 1;
+                garbage.bytes += blob.len();
             }
         }
 
+        Ok(garbage)
+    }
+
+    /// The refs as they were read, and the keys of the stored nodes that
+    /// neither they nor a commit lead to.
+    fn unreachable_nodes(&self) -> Result<(Refs, Vec<Vec<u8>>), RepoError> {
+        let refs = self.read_refs()?;
         let all_hashes = self
             .backend
             .list(KeyType::Node)
             .map_err(|e| e.to_repo_error())?;
+
+        let mut reachable = std::collections::HashSet::new();
+        for (name, hash_bytes) in &refs {
+            // `head` points at a commit object rather than a node; the trees
+            // of all commits are kept below.
+            if name == HEAD.as_bytes() {
+                continue;
+            }
+            let hash = Hash(
+                hash_bytes
+                    .clone()
+                    .try_into()
+                    .map_err(|_| RepoError::HashParse("Invalid hash length".to_string()))?,
+            );
+            self.traverse_reachable(&hash, &mut reachable)?;
+        }
+
+        for (_, commit) in self.log()? {
+            self.traverse_reachable(&commit.root, &mut reachable)?;
+        }
+
         let mut to_delete: Vec<Vec<u8>> = Vec::new();
 
         for hash_bytes in all_hashes {
@@ -285,18 +767,208 @@ where
             }
         }
 
-        let deleted = if to_delete.is_empty() {
-            0
-        } else {
-            let refs: Vec<&[u8]> = to_delete.iter().map(|s| s.as_slice()).collect();
-            self.backend
-                .delete(KeyType::Node, &refs)
+        Ok((refs, to_delete))
+    }
+
+    /// The stored node whose hex hash starts with `prefix`, provided every
+    /// node below it is stored too.
+    pub fn find_node(&self, prefix: &str) -> Result<Hash, RepoError> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut matches = Vec::new();
+        if !prefix.is_empty() {
+            for key in self
+                .backend
+                .list(KeyType::Node)
                 .map_err(|e| e.to_repo_error())?
-        };
+            {
+                if let Ok(bytes) = <[u8; 32]>::try_from(key) {
+                    let hash = Hash(bytes);
+                    if hash.to_hex().starts_with(&prefix) {
+                        matches.push(hash);
+                    }
+                }
+            }
+        }
 
-        self.backend.vacuum().map_err(|e| e.to_repo_error())?;
+        match matches.len() {
+            0 => Err(RepoError::UnknownNode(prefix)),
+            1 => {
+                let hash = matches.remove(0);
+                self.traverse_reachable(&hash, &mut std::collections::HashSet::new())?;
+                Ok(hash)
+            }
+            count => Err(RepoError::AmbiguousNode { prefix, count }),
+        }
+    }
 
-        Ok(deleted)
+    /// Check every node of the trees the refs and commits point at: that it
+    /// hashes to its key, that its keys are sorted and lie between those of
+    /// its parent around it, that they all have the node's level and that
+    /// it is below the parent's.
+    pub fn verify(&self) -> Result<IntegrityReport, RepoError> {
+        let mut roots = Vec::new();
+        for (name, hash_bytes) in self.read_refs()? {
+            // `head` points at a commit object rather than a node.
+            if name == HEAD.as_bytes() {
+                continue;
+            }
+            roots.push(Hash(hash_bytes.try_into().map_err(|_| {
+                RepoError::HashParse("Invalid hash length".to_string())
+            })?));
+        }
+        roots.extend(self.log()?.into_iter().map(|(_, commit)| commit.root));
+
+        struct Visit {
+            hash: Hash,
+            parent_level: Option<u32>,
+            lower: Option<Vec<u8>>,
+            upper: Option<Vec<u8>>,
+        }
+        let mut report = IntegrityReport::default();
+        let mut seen = std::collections::HashSet::new();
+        let mut stack: Vec<Visit> = roots
+            .into_iter()
+            .map(|hash| Visit {
+                hash,
+                parent_level: None,
+                lower: None,
+                upper: None,
+            })
+            .collect();
+        while let Some(visit) = stack.pop() {
+            if !seen.insert(visit.hash.clone()) {
+                continue;
+            }
+            report.nodes += 1;
+            let mut problem = |problem| report.problems.push((visit.hash.clone(), problem));
+
+            let Some(stored) = self
+                .backend
+                .get(KeyType::Node, &visit.hash.0)
+                .map_err(|e| e.to_repo_error())?
+            else {
+                problem(NodeProblem::Missing);
+                continue;
+            };
+            if blake3::hash(&stored).as_bytes() != &visit.hash.0 {
+                problem(NodeProblem::HashMismatch);
+            }
+            let node = match decode_node(&stored) {
+                Ok(node) => node,
+                Err(e) => {
+                    problem(NodeProblem::Undecodable(e.to_string()));
+                    continue;
+                }
+            };
+
+            let keys = node.items.iter().map(|item| item.key.as_slice());
+            let bounded = visit
+                .lower
+                .as_deref()
+                .into_iter()
+                .chain(keys.clone())
+                .chain(visit.upper.as_deref());
+            if bounded.clone().zip(bounded.skip(1)).any(|(a, b)| a >= b) {
+                problem(NodeProblem::Unsorted);
+            }
+            if keys.clone().any(|key| mst::key_level(key) != node.level) {
+                problem(NodeProblem::MixedLevels(node.level));
+            }
+            // Nodes without items are only left over from old deletes, and
+            // have no level of their own.
+            let level = node.estimate_level();
+            if let (Some(level), Some(parent)) = (level, visit.parent_level)
+                && level >= parent
+            {
+                problem(NodeProblem::Level { level, parent });
+            }
+
+            for idx in 0..=node.items.len() {
+                if let Some(child) = node.get_child_hash(idx) {
+                    stack.push(Visit {
+                        hash: child.clone(),
+                        parent_level: level.or(visit.parent_level),
+                        lower: match idx {
+                            0 => visit.lower.clone(),
+                            _ => Some(node.items[idx - 1].key.clone()),
+                        },
+                        upper: match node.items.get(idx) {
+                            Some(item) => Some(item.key.clone()),
+                            None => visit.upper.clone(),
+                        },
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Rewrite the working and indexed trees without nodes that hold no
+    /// items, so that they are shallower and the nodes they leave behind can
+    /// be collected once nothing else refers to them. The committed tree is
+    /// left as it is, since the head commit pins it, and so are the trees of
+    /// past commits. Fails with [`RepoError::Conflict`] if a ref moves in the
+    /// meantime. Returns the number of nodes dropped.
+    pub fn prune_empty(&self) -> Result<usize, RepoError> {
+        let mut pruned = 0;
+        let mut rewritten: std::collections::HashMap<Hash, Hash> = std::collections::HashMap::new();
+        for ref_type in [RepoRefType::Working, RepoRefType::Indexed] {
+            let name = ref_type.as_str();
+            let Some(hash_bytes) = self
+                .backend
+                .get(KeyType::Ref, name.as_bytes())
+                .map_err(|e| e.to_repo_error())?
+            else {
+                continue;
+            };
+            let hash = Hash(
+                hash_bytes
+                    .try_into()
+                    .map_err(|_| RepoError::HashParse("Invalid hash length".to_string()))?,
+            );
+
+            let new_hash = match rewritten.get(&hash) {
+                Some(new_hash) => new_hash.clone(),
+                None => {
+                    let mut dropped = 0;
+                    let new_hash = match MstNode::prune_empty(self, &hash, &mut dropped)? {
+                        Some(new_hash) => new_hash,
+                        None => self.write_node(&MstNode::empty())?,
+                    };
+                    // An empty tree is left as the empty root it already was.
+                    if new_hash != hash {
+                        pruned += dropped;
+                    }
+                    rewritten.insert(hash.clone(), new_hash.clone());
+                    new_hash
+                }
+            };
+            if new_hash != hash {
+                self.compare_and_set_ref(name, Some(&hash), &new_hash)?;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Every ref with the value it holds.
+    fn read_refs(&self) -> Result<Refs, RepoError> {
+        let names = self
+            .backend
+            .list(KeyType::Ref)
+            .map_err(|e| e.to_repo_error())?;
+        let mut refs = std::collections::BTreeMap::new();
+        for name in names {
+            if let Some(value) = self
+                .backend
+                .get(KeyType::Ref, &name)
+                .map_err(|e| e.to_repo_error())?
+            {
+                refs.insert(name, value);
+            }
+        }
+        Ok(refs)
     }
 
     fn traverse_reachable(
@@ -324,26 +996,67 @@ where
         Ok(())
     }
 
+    /// The nodes visited while looking up `key` in the tree at `root`, for
+    /// debugging. Each node comes with the index of the child the lookup went
+    /// on to, except the last: that is the node holding the key, with the
+    /// index of its item, or the node without the child the key would be in.
+    pub fn trace_get(&self, root: &Hash, key: &[u8]) -> Result<Vec<(Hash, usize)>, RepoError> {
+        let mut trace = Vec::new();
+        let mut hash = root.clone();
+        loop {
+            let node = self.read_node(&hash)?;
+            match node.find(key) {
+                Ok(idx) => {
+                    trace.push((hash, idx));
+                    break;
+                }
+                Err(idx) => {
+                    let child = node.get_child_hash(idx).cloned();
+                    trace.push((hash, idx));
+                    match child {
+                        Some(child) => hash = child,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Ok(trace)
+    }
+
+    /// Count the items under `hash` whose keys start with `prefix`, only
+    /// descending into children whose key range can hold such keys.
     fn traverse_stats(
         &self,
         hash: &Hash,
+        prefix: &[u8],
         kv_count: &mut usize,
         total_value_size: &mut usize,
         value_sizes: &mut std::collections::BTreeMap<usize, usize>,
     ) -> Result<(), RepoError> {
         let node = self.read_node(hash)?;
-        if let Some(ref h) = node.left {
-            self.traverse_stats(h, kv_count, total_value_size, value_sizes)?;
-        }
+        // The child at `idx` holds keys between the items either side of it.
+        let may_match = |idx: usize| {
+            let below = idx.checked_sub(1).map(|i| node.items[i].key.as_slice());
+            let above = node.items.get(idx).map(|item| item.key.as_slice());
+            above.is_none_or(|key| key > prefix)
+                && below.is_none_or(|key| key <= prefix || key.starts_with(prefix))
+        };
 
-        for item in node.items {
-            *kv_count += 1;
-            let size = item.value.len();
-            *total_value_size += size;
-            *value_sizes.entry(size).or_insert(0) += 1;
+        for idx in 0..=node.items.len() {
+            if let Some(h) = node.get_child_hash(idx)
+                && may_match(idx)
+            {
+                self.traverse_stats(h, prefix, kv_count, total_value_size, value_sizes)?;
+            }
 
-            if let Some(ref h) = item.right {
-                self.traverse_stats(h, kv_count, total_value_size, value_sizes)?;
+            if let Some(item) = node.items.get(idx)
+                && item.key.starts_with(prefix)
+            {
+                *kv_count += 1;
+                let size = item.value.len();
+                *total_value_size += size;
+                *value_sizes.entry(size).or_insert(0) += 1;
             }
         }
 
@@ -377,19 +1090,42 @@ where
         root_node.get(self.repo, key)
     }
 
+    /// Whether `key` is set, without copying out its value.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, RepoError> {
+        let root_node = self.repo.read_node(&self.hash)?;
+        root_node.contains_key(self.repo, key)
+    }
+
     pub fn iter_prefix(&self, prefix: &[u8]) -> Result<PrefixIterator<'a, Repo<B>>, RepoError> {
         let root_node = self.repo.read_node(&self.hash)?;
         Ok(PrefixIterator::new(self.repo, prefix, Some(root_node)))
     }
 
-    pub fn write(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), RepoError> {
+    /// Set `key` to `value`, returning the value it replaced, if any. Fails
+    /// without writing anything if either is over the repository's [`Limits`].
+    pub fn write(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<Option<Vec<u8>>, RepoError> {
+        self.repo.limits.check(&key, &value)?;
         let mut root_node = self.repo.read_node(&self.hash)?;
 
-        let new_root_hash = root_node.upsert(self.repo, key, value)?;
+        let (new_root_hash, old_value) = root_node.upsert(self.repo, key, value)?;
         self.repo
-            .backend
-            .set(KeyType::Ref, self.name.as_bytes(), &new_root_hash.0)
-            .map_err(|e| e.to_repo_error())?;
+            .compare_and_set_ref(&self.name, Some(&self.hash), &new_root_hash)?;
+        self.hash = new_root_hash;
+        Ok(old_value)
+    }
+
+    /// Set every key of `items` to its value, writing each changed node once
+    /// rather than once per key. Fails without writing anything if any pair
+    /// is over the repository's [`Limits`].
+    pub fn write_many(&mut self, items: Vec<(Vec<u8>, Vec<u8>)>) -> Result<(), RepoError> {
+        for (key, value) in &items {
+            self.repo.limits.check(key, value)?;
+        }
+        let mut root_node = self.repo.read_node(&self.hash)?;
+
+        let new_root_hash = root_node.upsert_many(self.repo, items)?;
+        self.repo
+            .compare_and_set_ref(&self.name, Some(&self.hash), &new_root_hash)?;
         self.hash = new_root_hash;
         Ok(())
     }
@@ -399,9 +1135,7 @@ where
 
         let (new_root_hash, value) = root_node.remove(self.repo, key)?;
         self.repo
-            .backend
-            .set(KeyType::Ref, self.name.as_bytes(), &new_root_hash.0)
-            .map_err(|e| e.to_repo_error())?;
+            .compare_and_set_ref(&self.name, Some(&self.hash), &new_root_hash)?;
         self.hash = new_root_hash;
         Ok(value)
     }
@@ -421,8 +1155,14 @@ where
     B::Error: ToRepoError,
 {
     fn write_node(&self, node: &MstNode) -> Result<Hash, RepoError> {
+        debug_assert_eq!(
+            node.estimate_level(),
+            node.items.first().map(|item| mst::key_level(&item.key)),
+            "stale level",
+        );
         let bytes = postcard::to_stdvec(node)?;
-        let compressed = lz4_flex::compress_prepend_size(&bytes);
+        let mut compressed = vec![NODE_FORMAT];
+        compressed.extend(lz4_flex::compress_prepend_size(&bytes));
         let hasher = blake3::hash(&compressed);
         let hash = Hash(*hasher.as_bytes());
 
@@ -439,12 +1179,39 @@ where
             .get(KeyType::Node, &hash.0)
             .map_err(|e| e.to_repo_error())?
             .ok_or_else(|| RepoError::HashParse(format!("node not found: {}", hash)))?;
-        let decompressed = lz4_flex::decompress_size_prepended(&compressed)?;
-        let node = postcard::from_bytes(&decompressed)?;
-        Ok(node)
+        decode_node(&compressed)
     }
 }
 
+/// Decode a node as stored, which is its [`NODE_FORMAT`] followed by the
+/// LZ4-compressed node prefixed with its size.
+///
+/// Nodes written before the format was recorded start with the size
+/// instead. Even when its first byte happens to match a format, the
+/// following bytes of such a node then read as a size of at least
+/// [`MAX_NODE_SIZE`], since its top byte is zero and the first LZ4 token
+/// of a block always has literals. Nodes without a stored level have it
+/// worked out as they are read.
+pub(crate) fn decode_node(stored: &[u8]) -> Result<MstNode, RepoError> {
+    let (format, compressed) = match stored.split_first() {
+        Some((&format, rest))
+            if (format == NODE_FORMAT || format == NODE_FORMAT_UNLEVELED)
+                && rest.len() >= 4
+                && u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) < MAX_NODE_SIZE =>
+        {
+            (format, rest)
+        }
+        _ => (NODE_FORMAT_UNLEVELED, stored),
+    };
+    let decompressed = lz4_flex::decompress_size_prepended(compressed)?;
+    let node = if format == NODE_FORMAT {
+        postcard::from_bytes(&decompressed)?
+    } else {
+        postcard::from_bytes::<mst::LegacyMstNode>(&decompressed)?.into()
+    };
+    Ok(node)
+}
+
 pub struct DiffIterator<'a, B: Backend> {
     repo: &'a Repo<B>,
     stack: Vec<DiffIterState>,
@@ -1,11 +1,21 @@
 use crate::repo::Store;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 use super::{Hash, RepoError};
 
+#[cfg(test)]
+thread_local! {
+    /// Calls to [`key_level`] on this thread, for tests counting hashes.
+    pub static KEY_LEVELS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 /// Calculates the level of a key based on its hash.
 /// The level is the number of leading zero nibbles in the BLAKE3 hash.
 pub fn key_level(key: &[u8]) -> u32 {
+    #[cfg(test)]
+    KEY_LEVELS.with(|calls| calls.set(calls.get() + 1));
     let hash = blake3::hash(key);
     let mut level = 0;
 
@@ -43,6 +53,24 @@ pub struct MstNode {
     pub left: Option<Hash>,
     #[serde(with = "serde_impl")]
     pub items: Vec<MstItem>,
+    /// The [`key_level`] shared by the keys of `items`, stored so that it
+    /// need not be hashed out again each time the node is read. Meaningless
+    /// while `items` is empty.
+    pub level: u32,
+}
+
+/// A node as written before [`MstNode::level`] was stored.
+#[derive(Deserialize)]
+pub(crate) struct LegacyMstNode {
+    left: Option<Hash>,
+    #[serde(with = "serde_impl")]
+    items: Vec<MstItem>,
+}
+
+impl From<LegacyMstNode> for MstNode {
+    fn from(node: LegacyMstNode) -> Self {
+        MstNode::new(node.left, node.items)
+    }
 }
 
 impl MstNode {
@@ -51,6 +79,12 @@ impl MstNode {
         Self::default()
     }
 
+    /// A node of `items`, whose level is worked out from its first key.
+    pub fn new(left: Option<Hash>, items: Vec<MstItem>) -> Self {
+        let level = items.first().map_or(0, |item| key_level(&item.key));
+        Self { left, items, level }
+    }
+
     /// Gets the hash of the child node at the given index.
     /// Index 0 is the `left` child, index `i > 0` is the `right` child of `items[i-1]`.
     pub(crate) fn get_child_hash(&self, idx: usize) -> Option<&Hash> {
@@ -70,19 +104,22 @@ impl MstNode {
         }
     }
 
-    /// Inserts or updates a key-value pair in the MST rooted at this node.
+    /// Inserts or updates a key-value pair in the MST rooted at this node,
+    /// returning the hash of the new root and the value the key had, if any.
     pub fn upsert<S: Store>(
         &mut self,
         store: &S,
         key: Vec<u8>,
         value: Vec<u8>,
-    ) -> Result<Hash, RepoError> {
+    ) -> Result<(Hash, Option<Vec<u8>>), RepoError> {
         let req_level = key_level(&key);
         let node_level = self.estimate_level().unwrap_or(req_level);
 
-        match req_level.cmp(&node_level) {
+        let old_value = match req_level.cmp(&node_level) {
             std::cmp::Ordering::Equal => {
-                self.upsert_local(store, key, value)?;
+                // The node may have been empty.
+                self.level = req_level;
+                self.upsert_local(store, key, value)?
             }
             std::cmp::Ordering::Less => {
                 // Find where the key should go
@@ -97,64 +134,95 @@ impl MstNode {
                     None => MstNode::empty(),
                 };
 
-                let new_child_hash = child_node.upsert(store, key, value)?;
+                let (new_child_hash, old_value) = child_node.upsert(store, key, value)?;
                 self.set_child_hash(idx, Some(new_child_hash));
+                old_value
             }
+            // A key lives at the level its hash gives it, so one above this
+            // node's level cannot be in the tree yet.
             std::cmp::Ordering::Greater => {
                 // Higher level: split current node around the new key
                 let (l_hash, r_hash) = self.split(store, &key)?;
                 self.items.clear();
                 self.left = l_hash;
+                self.level = req_level;
                 self.items.push(MstItem {
                     key,
                     value,
                     right: r_hash,
                 });
+                None
             }
+        };
+
+        Ok((store.write_node(self)?, old_value))
+    }
+
+    /// Inserts or updates every pair of `items` in the MST rooted at this
+    /// node, returning the hash of the new root. A key given more than once
+    /// takes its last value.
+    ///
+    /// The pairs are applied in key order to nodes held in memory, and only
+    /// the nodes of the final tree are written to `store`, each once. The
+    /// tree is the same as upserting the pairs one at a time would make.
+    pub fn upsert_many<S: Store>(
+        &mut self,
+        store: &S,
+        mut items: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<Hash, RepoError> {
+        // The sort is stable, so later values of a key still win.
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let staged = Staged {
+            store,
+            nodes: RefCell::new(HashMap::new()),
+        };
+        let mut root = None;
+        for (key, value) in items {
+            root = Some(self.upsert(&staged, key, value)?.0);
         }
 
-        store.write_node(self)
+        match root {
+            Some(root) => staged.flush(&root),
+            None => store.write_node(self),
+        }
     }
 
+    /// Removes `key` from the MST rooted at this node, returning the hash of
+    /// the new root and the removed value, if the key was present.
+    ///
+    /// The new root need not be this node: when removal leaves it without
+    /// items, its only child takes its place, so the tree keeps the shape it
+    /// would have had if the key had never been inserted.
     pub fn remove<S: Store>(
         &mut self,
         store: &S,
         key: &[u8],
     ) -> Result<(Hash, Option<Vec<u8>>), RepoError> {
-        match self
-            .items
-            .binary_search_by(|item| item.key.as_slice().cmp(key))
-        {
+        let (hash, value) = self.remove_inner(store, key)?;
+        let hash = match hash {
+            Some(hash) => hash,
+            None => store.write_node(&MstNode::empty())?,
+        };
+        Ok((hash, value))
+    }
+
+    /// Like [`remove`](Self::remove), but an empty result is `None` so that a
+    /// parent drops its pointer instead of keeping one to an empty node.
+    fn remove_inner<S: Store>(
+        &mut self,
+        store: &S,
+        key: &[u8],
+    ) -> Result<(Option<Hash>, Option<Vec<u8>>), RepoError> {
+        match self.find(key) {
             Ok(idx) => {
                 // Found the key in this node.
                 let item = self.items.remove(idx);
                 let value = Some(item.value);
 
-                // We need to merge the left child (at idx) and the right child (item.right).
-                // Before removal: child[idx] < item < child[idx+1] (which was item.right).
-                // After removal, the gap needs to be filled by merging these two children.
-
-                // Get the left child hash (it was at idx).
-                // Note: since we removed item at idx, get_child_hash(idx) now refers to
-                // what was previously at idx+1 (if we didn't adjust).
-                // But we need the ORIGINAL children.
-                // The left child of the removed item was `self.get_child_hash(idx)` BEFORE removal.
-                // But we already removed the item.
-                // Let's re-think access.
-
-                // We should have retrieved children before removing?
-                // `items` is a Vec.
-                // item at `idx` has `right` field.
-                // `left` field of `items[idx]` is implicitly `items[idx-1].right` or `self.left`.
-
-                // Let's undo remove for a second conceptually.
-                // left_child = if idx == 0 { self.left } else { self.items[idx-1].right }
-                // right_child = item.right
-
-                // Since we removed `item` at `idx`:
-                // If idx == 0, `self.left` is the left child.
-                // If idx > 0, `self.items[idx-1].right` is the left child.
-
+                // The children on either side of the removed item now share a
+                // gap and are merged into one. The left one hangs off the
+                // previous item, or off `left` if the item was first.
                 let left_child_hash = if idx == 0 {
                     self.left.clone()
                 } else {
@@ -171,34 +239,25 @@ impl MstNode {
                     self.items[idx - 1].right = merged_hash;
                 }
 
-                // If the node becomes empty, we should return its only child (if any) or empty.
+                // A node without items is replaced by its only child, if any.
                 if self.items.is_empty() {
-                    if let Some(h) = self.left.clone() {
-                        return Ok((h, value));
-                    }
+                    return Ok((self.left.clone(), value));
                 }
 
-                Ok((store.write_node(self)?, value))
+                Ok((Some(store.write_node(self)?), value))
             }
             Err(idx) => {
                 // Key not in this node, try child.
-                let child_hash = self.get_child_hash(idx).cloned();
-
-                if let Some(h) = child_hash {
-                    let mut child_node = store.read_node(&h)?;
-                    let (new_child_hash, removed_val) = child_node.remove(store, key)?;
-
-                    self.set_child_hash(idx, Some(new_child_hash));
+                let Some(h) = self.get_child_hash(idx).cloned() else {
+                    // Key not found
+                    return Ok((Some(store.write_node(self)?), None));
+                };
 
-                    // Cleanup: If child became empty/merged, we might want to do something?
-                    // But MST properties are generally self-maintaining via merge/split.
-                    // We just updated the pointer.
+                let mut child_node = store.read_node(&h)?;
+                let (new_child_hash, removed_val) = child_node.remove_inner(store, key)?;
+                self.set_child_hash(idx, new_child_hash);
 
-                    Ok((store.write_node(self)?, removed_val))
-                } else {
-                    // Key not found
-                    Ok((store.write_node(self)?, None))
-                }
+                Ok((Some(store.write_node(self)?), removed_val))
             }
         }
     }
@@ -256,11 +315,52 @@ impl MstNode {
         }
     }
 
-    pub fn get<S: Store>(&self, store: &S, key: &[u8]) -> Result<Option<Vec<u8>>, RepoError> {
-        match self
-            .items
+    /// Rewrites the tree at `hash` without the nodes that hold no items, each
+    /// of which can only have a `left` child and is replaced by it. Trees
+    /// written before removal collapsed such nodes can still have them.
+    /// Returns the new root, `None` if the tree holds no items, and counts
+    /// the nodes dropped in `pruned`.
+    pub fn prune_empty<S: Store>(
+        store: &S,
+        hash: &Hash,
+        pruned: &mut usize,
+    ) -> Result<Option<Hash>, RepoError> {
+        let mut node = store.read_node(hash)?;
+        if node.items.is_empty() {
+            *pruned += 1;
+            return match &node.left {
+                Some(left) => Self::prune_empty(store, left, pruned),
+                None => Ok(None),
+            };
+        }
+
+        let mut changed = false;
+        for idx in 0..=node.items.len() {
+            if let Some(child) = node.get_child_hash(idx).cloned() {
+                let new_child = Self::prune_empty(store, &child, pruned)?;
+                if new_child.as_ref() != Some(&child) {
+                    node.set_child_hash(idx, new_child);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            Ok(Some(store.write_node(&node)?))
+        } else {
+            Ok(Some(hash.clone()))
+        }
+    }
+
+    /// Looks up `key` among the items of this node. `Ok` holds the index of
+    /// the item with the key, `Err` the index of the child it would be under.
+    pub(crate) fn find(&self, key: &[u8]) -> Result<usize, usize> {
+        self.items
             .binary_search_by(|item| item.key.as_slice().cmp(key))
-        {
+    }
+
+    pub fn get<S: Store>(&self, store: &S, key: &[u8]) -> Result<Option<Vec<u8>>, RepoError> {
+        match self.find(key) {
             Ok(idx) => Ok(Some(self.items[idx].value.clone())),
             Err(idx) => match self.get_child_hash(idx) {
                 Some(h) => {
@@ -272,17 +372,27 @@ impl MstNode {
         }
     }
 
+    /// Whether `key` is in the MST rooted at this node. Unlike
+    /// [`get`](Self::get), the value is not copied out.
+    pub fn contains_key<S: Store>(&self, store: &S, key: &[u8]) -> Result<bool, RepoError> {
+        match self.find(key) {
+            Ok(_) => Ok(true),
+            Err(idx) => match self.get_child_hash(idx) {
+                Some(h) => store.read_node(h)?.contains_key(store, key),
+                None => Ok(false),
+            },
+        }
+    }
+
     /// Inserts a key-value pair directly into the current node.
     fn upsert_local<S: Store>(
         &mut self,
         store: &S,
         key: Vec<u8>,
         value: Vec<u8>,
-    ) -> Result<(), RepoError> {
+    ) -> Result<Option<Vec<u8>>, RepoError> {
         match self.items.binary_search_by(|item| item.key.cmp(&key)) {
-            Ok(idx) => {
-                self.items[idx].value = value;
-            }
+            Ok(idx) => Ok(Some(std::mem::replace(&mut self.items[idx].value, value))),
             Err(idx) => {
                 // Split child at insertion point
                 let child_hash = self.get_child_hash(idx).cloned();
@@ -297,9 +407,9 @@ impl MstNode {
                         right: r_hash,
                     },
                 );
+                Ok(None)
             }
         }
-        Ok(())
     }
 
     /// Splits the node into two nodes based on a split key.
@@ -322,6 +432,7 @@ impl MstNode {
         let mut left_node = MstNode {
             left: self.left.take(),
             items: left_items,
+            level: self.level,
         };
 
         // Fix the rightmost pointer of left_node
@@ -334,16 +445,20 @@ impl MstNode {
         let right_node = MstNode {
             left: mid_r,
             items: right_items,
+            level: self.level,
         };
 
-        let l_hash = if left_node.items.is_empty() && left_node.left.is_none() {
-            None
+        // A side left without items of this node is just its child, if any.
+        // Writing it as a node of its own would leave an empty link in the
+        // tree that an insert in another order would not have made.
+        let l_hash = if left_node.items.is_empty() {
+            left_node.left
         } else {
             Some(store.write_node(&left_node)?)
         };
 
-        let r_hash = if right_node.items.is_empty() && right_node.left.is_none() {
-            None
+        let r_hash = if right_node.items.is_empty() {
+            right_node.left
         } else {
             Some(store.write_node(&right_node)?)
         };
@@ -365,9 +480,53 @@ impl MstNode {
         }
     }
 
-    /// Estimates the level of the current node based on the keys it contains.
+    /// The level of the keys the node contains, if it has any.
     pub fn estimate_level(&self) -> Option<u32> {
-        self.items.first().map(|item| key_level(&item.key))
+        (!self.items.is_empty()).then_some(self.level)
+    }
+}
+
+/// A [`Store`] keeping the nodes written to it in memory until
+/// [`flush`](Staged::flush)ed, reading through to `store` for the rest.
+///
+/// Staged nodes are known by a stand-in hash of their contents: their real
+/// hash depends on those of their children, which are not settled until the
+/// batch is done.
+struct Staged<'a, S> {
+    store: &'a S,
+    nodes: RefCell<HashMap<Hash, MstNode>>,
+}
+
+impl<S: Store> Staged<'_, S> {
+    /// Writes the staged tree at `hash` to the store, children first, and
+    /// returns its real hash. Staged nodes the tree no longer uses are never
+    /// written.
+    fn flush(&self, hash: &Hash) -> Result<Hash, RepoError> {
+        let Some(mut node) = self.nodes.borrow_mut().remove(hash) else {
+            return Ok(hash.clone());
+        };
+        for idx in 0..=node.items.len() {
+            if let Some(child) = node.get_child_hash(idx).cloned() {
+                let child = self.flush(&child)?;
+                node.set_child_hash(idx, Some(child));
+            }
+        }
+        self.store.write_node(&node)
+    }
+}
+
+impl<S: Store> Store for Staged<'_, S> {
+    fn write_node(&self, node: &MstNode) -> Result<Hash, RepoError> {
+        let hash = Hash(*blake3::hash(&postcard::to_stdvec(node)?).as_bytes());
+        self.nodes.borrow_mut().insert(hash.clone(), node.clone());
+        Ok(hash)
+    }
+
+    fn read_node(&self, hash: &Hash) -> Result<MstNode, RepoError> {
+        match self.nodes.borrow().get(hash) {
+            Some(node) => Ok(node.clone()),
+            None => self.store.read_node(hash),
+        }
     }
 }
 
@@ -435,6 +594,8 @@ mod serde_impl {
 pub struct PrefixIterator<'a, S> {
     store: &'a S,
     prefix: Vec<u8>,
+    /// Only keys strictly greater than this are yielded.
+    after: Option<Vec<u8>>,
     stack: Vec<IterState>,
 }
 
@@ -457,9 +618,23 @@ impl<'a, S: Store> PrefixIterator<'a, S> {
         Self {
             store,
             prefix: prefix.to_vec(),
+            after: None,
             stack,
         }
     }
+
+    /// Resume after `key`, skipping the subtrees that only hold keys up to it
+    /// rather than walking through them.
+    pub fn seek_after(mut self, key: &[u8]) -> Self {
+        self.after = Some(key.to_vec());
+        self
+    }
+}
+
+/// Whether `key` falls at or before the cursor, so it and everything under
+/// the child to its left can be skipped.
+fn at_or_before(key: &[u8], after: Option<&[u8]>) -> bool {
+    after.is_some_and(|after| key <= after)
 }
 
 impl<'a, S: Store> Iterator for PrefixIterator<'a, S> {
@@ -477,7 +652,10 @@ impl<'a, S: Store> Iterator for PrefixIterator<'a, S> {
                     frame.child_processed = true;
 
                     // Optimization: If item.key < prefix, then child[idx] < item < prefix.
-                    if item.key.as_slice() < self.prefix.as_slice() {
+                    // Likewise for keys up to the cursor.
+                    if item.key.as_slice() < self.prefix.as_slice()
+                        || at_or_before(&item.key, self.after.as_deref())
+                    {
                         continue;
                     }
 
@@ -507,8 +685,10 @@ impl<'a, S: Store> Iterator for PrefixIterator<'a, S> {
                     frame.index += 1;
                     frame.child_processed = false;
 
-                    // item.key < prefix -> skip
-                    if item.key.as_slice() < self.prefix.as_slice() {
+                    // item.key < prefix, or not past the cursor -> skip
+                    if item.key.as_slice() < self.prefix.as_slice()
+                        || at_or_before(&item.key, self.after.as_deref())
+                    {
                         continue;
                     }
 
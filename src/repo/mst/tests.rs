@@ -1,55 +1,8 @@
 use std::collections::BTreeMap;
-use thiserror::Error;
 
 use super::*;
 use crate::repo::{Hash, PrefixIterator, RepoError, Store};
 
-#[derive(Debug, Error)]
-pub enum TestBackendError {
-    #[error("test error")]
-    Test,
-}
-
-struct TestStore {
-    nodes: BTreeMap<Hash, MstNode>,
-}
-
-impl TestStore {
-    fn new() -> Self {
-        Self {
-            nodes: BTreeMap::new(),
-        }
-    }
-}
-
-impl Store for TestStore {
-    fn write_node(&self, _node: &MstNode) -> Result<Hash, RepoError> {
-        // This is a bit tricky because TestStore is not mutable in Store trait.
-        // But the original code also had this issue if it was supposed to be mutable.
-        // Looking at the original code:
-        /*
-        impl Store for TestStore {
-            fn write_node(&self, node: &MstNode) -> Result<Hash, RepoError> {
-                let bytes = postcard::to_stdvec(node)?;
-                let hasher = blake3::hash(&bytes);
-                let hash = Hash(*hasher.as_bytes());
-                // self.nodes.insert(hash.clone(), node.clone()); // Cannot insert into &self
-                Ok(hash)
-            }
-        */
-        // It seems the original TestStore was also broken or used Interior Mutability.
-        // Let's check the original content of src/repo/mst/tests.rs
-        panic!("Use TestStoreMut for tests");
-    }
-
-    fn read_node(&self, hash: &Hash) -> Result<MstNode, RepoError> {
-        self.nodes
-            .get(hash)
-            .cloned()
-            .ok_or_else(|| RepoError::HashParse("hash not found".to_string()))
-    }
-}
-
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -94,7 +47,7 @@ fn test_upsert_empty_tree() {
     let key = b"key1".to_vec();
     let value = b"value1".to_vec();
 
-    let hash = node.upsert(&store, key.clone(), value.clone()).unwrap();
+    let (hash, _) = node.upsert(&store, key.clone(), value.clone()).unwrap();
     let root = store.read_node(&hash).unwrap();
 
     assert_eq!(root.items.len(), 1);
@@ -108,7 +61,7 @@ fn test_get() {
     let mut node = MstNode::empty();
     node.upsert(&store, b"k1".to_vec(), b"v1".to_vec()).unwrap();
     node.upsert(&store, b"k2".to_vec(), b"v2".to_vec()).unwrap();
-    let hash = node.upsert(&store, b"k3".to_vec(), b"v3".to_vec()).unwrap();
+    let (hash, _) = node.upsert(&store, b"k3".to_vec(), b"v3".to_vec()).unwrap();
 
     let root = store.read_node(&hash).unwrap();
     assert_eq!(root.get(&store, b"k1").unwrap(), Some(b"v1".to_vec()));
@@ -117,6 +70,19 @@ fn test_get() {
     assert_eq!(root.get(&store, b"k4").unwrap(), None);
 }
 
+#[test]
+fn test_contains_key() {
+    let store = TestStoreMut::new();
+    let all = keys(0..300);
+    let root = store.read_node(&build(&store, &all)).unwrap();
+
+    for key in &all {
+        assert!(root.contains_key(&store, key).unwrap());
+    }
+    assert!(!root.contains_key(&store, b"missing").unwrap());
+    assert!(!MstNode::empty().contains_key(&store, b"k1").unwrap());
+}
+
 #[test]
 fn test_upsert_same_level_between() {
     let store = TestStoreMut::new();
@@ -128,10 +94,10 @@ fn test_upsert_same_level_between() {
     let k3 = b"abc2".to_vec();
 
     node.upsert(&store, k1.clone(), b"v1".to_vec()).unwrap();
-    let hash2 = node.upsert(&store, k2.clone(), b"v2".to_vec()).unwrap();
+    let (hash2, _) = node.upsert(&store, k2.clone(), b"v2".to_vec()).unwrap();
 
     let root2 = store.read_node(&hash2).unwrap();
-    let hash3 = root2
+    let (hash3, _) = root2
         .clone()
         .upsert(&store, k3.clone(), b"v3".to_vec())
         .unwrap();
@@ -156,7 +122,7 @@ fn test_upsert_same_level_beginning() {
 
     node.upsert(&store, k1, b"v1".to_vec()).unwrap();
     node.upsert(&store, k2, b"v2".to_vec()).unwrap();
-    let hash3 = node.upsert(&store, k3, b"v3".to_vec()).unwrap();
+    let (hash3, _) = node.upsert(&store, k3, b"v3".to_vec()).unwrap();
 
     let root3 = store.read_node(&hash3).unwrap();
     let keys: Vec<_> = root3.items.iter().map(|i| i.key.clone()).collect();
@@ -176,7 +142,7 @@ fn test_upsert_same_level_ending() {
 
     node.upsert(&store, k1, b"v1".to_vec()).unwrap();
     node.upsert(&store, k2, b"v2".to_vec()).unwrap();
-    let hash3 = node.upsert(&store, k3, b"v3".to_vec()).unwrap();
+    let (hash3, _) = node.upsert(&store, k3, b"v3".to_vec()).unwrap();
 
     let root3 = store.read_node(&hash3).unwrap();
     let keys: Vec<_> = root3.items.iter().map(|i| i.key.clone()).collect();
@@ -190,14 +156,34 @@ fn test_upsert_existing_changed_value() {
     let store = TestStoreMut::new();
     let mut node = MstNode::empty();
 
-    node.upsert(&store, b"k1".to_vec(), b"v1".to_vec()).unwrap();
-    let hash2 = node.upsert(&store, b"k1".to_vec(), b"v2".to_vec()).unwrap();
+    let (_, old) = node.upsert(&store, b"k1".to_vec(), b"v1".to_vec()).unwrap();
+    assert_eq!(old, None);
+    let (hash2, old) = node.upsert(&store, b"k1".to_vec(), b"v2".to_vec()).unwrap();
+    assert_eq!(old, Some(b"v1".to_vec()));
 
     let root2 = store.read_node(&hash2).unwrap();
     assert_eq!(root2.items.len(), 1);
     assert_eq!(root2.items[0].value, b"v2".to_vec());
 }
 
+#[test]
+fn test_upsert_returns_old_value_at_any_level() {
+    let store = TestStoreMut::new();
+    let keys: Vec<Vec<u8>> = (0..300)
+        .map(|i| format!("k/{:03}", i).into_bytes())
+        .collect();
+    let mut hash = build(&store, &keys);
+
+    for k in &keys {
+        let mut root = store.read_node(&hash).unwrap();
+        let old;
+        (hash, old) = root.upsert(&store, k.clone(), b"new".to_vec()).unwrap();
+        assert_eq!(old.as_ref(), Some(k));
+    }
+    let root = store.read_node(&hash).unwrap();
+    assert_eq!(root.get(&store, b"k/150").unwrap(), Some(b"new".to_vec()));
+}
+
 #[test]
 fn test_upsert_lower_level() {
     let store = TestStoreMut::new();
@@ -222,7 +208,7 @@ fn test_upsert_lower_level() {
     let mut node = MstNode::empty();
     node.upsert(&store, k_high.clone(), b"v_high".to_vec())
         .unwrap();
-    let hash2 = node
+    let (hash2, _) = node
         .upsert(&store, k_low.clone(), b"v_low".to_vec())
         .unwrap();
 
@@ -262,7 +248,7 @@ fn test_upsert_higher_level_split() {
         }
     }
 
-    let mut keys = vec![k_low1.clone(), k_low2.clone()];
+    let mut keys = [k_low1.clone(), k_low2.clone()];
     keys.sort();
     k_low1 = keys[0].clone();
     k_low2 = keys[1].clone();
@@ -273,9 +259,9 @@ fn test_upsert_higher_level_split() {
 
     let mut node = MstNode::empty();
     node.upsert(&store, k_low1.clone(), b"v1".to_vec()).unwrap();
-    let _hash2 = node.upsert(&store, k_low2.clone(), b"v2".to_vec()).unwrap();
+    let (_hash2, _) = node.upsert(&store, k_low2.clone(), b"v2".to_vec()).unwrap();
 
-    let hash3 = node
+    let (hash3, _) = node
         .upsert(&store, k_high.clone(), b"v_high".to_vec())
         .unwrap();
     let root3 = store.read_node(&hash3).unwrap();
@@ -341,7 +327,7 @@ fn test_upsert_lower_level_right_child() {
     let mut node = MstNode::empty();
     node.upsert(&store, k_high.clone(), b"v_high".to_vec())
         .unwrap();
-    let hash2 = node
+    let (hash2, _) = node
         .upsert(&store, k_low.clone(), b"v_low".to_vec())
         .unwrap();
 
@@ -365,7 +351,7 @@ fn test_iter_prefix() {
     for k in &keys {
         node.upsert(&store, k.clone(), b"val".to_vec()).unwrap();
     }
-    let hash = node
+    let (hash, _) = node
         .upsert(&store, b"c/2".to_vec(), b"val".to_vec())
         .unwrap();
     let root = store.read_node(&hash).unwrap();
@@ -388,6 +374,328 @@ fn test_iter_prefix() {
     assert_eq!(results_none.len(), 0);
 }
 
+#[test]
+fn test_iter_prefix_seek_after() {
+    let store = TestStoreMut::new();
+    let mut node = MstNode::empty();
+    let mut hash = store.write_node(&node).unwrap();
+
+    let mut keys: Vec<Vec<u8>> = (0..300)
+        .map(|i| format!("a/{:03}", i).into_bytes())
+        .collect();
+    keys.push(b"b/000".to_vec());
+    for k in &keys {
+        hash = node.upsert(&store, k.clone(), b"val".to_vec()).unwrap().0;
+        node = store.read_node(&hash).unwrap();
+    }
+    let root = store.read_node(&hash).unwrap();
+    assert!(
+        root.items.iter().any(|item| item.right.is_some()) || root.left.is_some(),
+        "tree should have more than one level"
+    );
+
+    let mut pages = Vec::new();
+    let mut after: Option<Vec<u8>> = None;
+    loop {
+        let mut iter = PrefixIterator::new(&store, b"a/", Some(root.clone()));
+        if let Some(after) = &after {
+            iter = iter.seek_after(after);
+        }
+        let page: Vec<Vec<u8>> = iter.take(7).map(|item| item.unwrap().0).collect();
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(last.clone());
+        pages.extend(page);
+    }
+    assert_eq!(pages, keys[..300]);
+
+    // A cursor between keys resumes at the next one.
+    let iter = PrefixIterator::new(&store, b"a/", Some(root)).seek_after(b"a/149x");
+    let next = iter.map(|item| item.unwrap().0).next();
+    assert_eq!(next.as_deref(), Some(b"a/150".as_slice()));
+}
+
+/// Build a tree holding `keys` by inserting them in order into an empty tree.
+fn build(store: &TestStoreMut, keys: &[Vec<u8>]) -> Hash {
+    let mut node = MstNode::empty();
+    let mut hash = store.write_node(&node).unwrap();
+    for k in keys {
+        hash = node.upsert(store, k.clone(), k.clone()).unwrap().0;
+    }
+    hash
+}
+
+/// Remove `key` from the tree at `hash`, returning the new root.
+fn remove(store: &TestStoreMut, hash: &Hash, key: &[u8]) -> Hash {
+    let mut root = store.read_node(hash).unwrap();
+    let (hash, value) = root.remove(store, key).unwrap();
+    assert_eq!(
+        value.as_deref(),
+        Some(key),
+        "{:?} should have been removed",
+        key
+    );
+    hash
+}
+
+/// Check that no node below the root is empty and that every node's items
+/// share a level, higher than that of the nodes beneath it.
+fn assert_canonical(store: &TestStoreMut, hash: &Hash, is_root: bool, max: Option<u32>) {
+    let node = store.read_node(hash).unwrap();
+    if node.items.is_empty() {
+        assert!(is_root, "empty node below the root");
+        assert!(node.left.is_none(), "empty root with a child");
+        return;
+    }
+    let level = node.estimate_level().unwrap();
+    for item in &node.items {
+        assert_eq!(
+            key_level(&item.key),
+            level,
+            "items of a node differ in level"
+        );
+    }
+    if let Some(max) = max {
+        assert!(level < max, "child is not below its parent");
+    }
+    for idx in 0..=node.items.len() {
+        if let Some(h) = node.get_child_hash(idx) {
+            assert_canonical(store, h, false, Some(level));
+        }
+    }
+}
+
+fn keys(range: std::ops::Range<usize>) -> Vec<Vec<u8>> {
+    range.map(|i| format!("key{}", i).into_bytes()).collect()
+}
+
+fn highest_key(keys: &[Vec<u8>]) -> Vec<u8> {
+    keys.iter().max_by_key(|k| key_level(k)).unwrap().clone()
+}
+
+#[test]
+fn test_upsert_order_independent() {
+    let store = TestStoreMut::new();
+    let all = keys(0..2000);
+
+    let sorted = build(&store, &all);
+    assert_canonical(&store, &sorted, true, None);
+    let shuffled: Vec<Vec<u8>> = (0..all.len())
+        .map(|i| all[i * 7919 % all.len()].clone())
+        .collect();
+    let hash = build(&store, &shuffled);
+    assert_canonical(&store, &hash, true, None);
+    assert_eq!(hash, sorted);
+}
+
+#[test]
+fn test_upsert_many() {
+    let all = keys(0..2000);
+    let one_by_one = build(&TestStoreMut::new(), &all);
+
+    let store = TestStoreMut::new();
+    let mut node = MstNode::empty();
+    let shuffled: Vec<_> = (0..all.len())
+        .map(|i| {
+            let key = all[i * 7919 % all.len()].clone();
+            (key.clone(), key)
+        })
+        .collect();
+    let hash = node.upsert_many(&store, shuffled).unwrap();
+    assert_eq!(hash, one_by_one);
+    assert_canonical(&store, &hash, true, None);
+    // Nothing but the final tree was written.
+    let mut reachable = std::collections::HashSet::new();
+    let mut pending = vec![hash.clone()];
+    while let Some(hash) = pending.pop() {
+        let node = store.read_node(&hash).unwrap();
+        pending.extend((0..=node.items.len()).filter_map(|i| node.get_child_hash(i).cloned()));
+        reachable.insert(hash);
+    }
+    assert_eq!(store.nodes.lock().unwrap().len(), reachable.len());
+
+    // On top of an existing tree, with a key given twice.
+    let mut node = store.read_node(&hash).unwrap();
+    let batch = vec![
+        (b"key5".to_vec(), b"first".to_vec()),
+        (b"key2000".to_vec(), b"new".to_vec()),
+        (b"key5".to_vec(), b"last".to_vec()),
+    ];
+    let hash = node.upsert_many(&store, batch).unwrap();
+    let root = store.read_node(&hash).unwrap();
+    assert_eq!(root.get(&store, b"key5").unwrap(), Some(b"last".to_vec()));
+    assert_eq!(root.get(&store, b"key2000").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(root.get(&store, b"key6").unwrap(), Some(b"key6".to_vec()));
+    assert_canonical(&store, &hash, true, None);
+
+    let mut node = store.read_node(&hash).unwrap();
+    assert_eq!(node.upsert_many(&store, Vec::new()).unwrap(), hash);
+}
+
+#[test]
+fn test_upsert_higher_level_over_left_child() {
+    let store = TestStoreMut::new();
+    let find = |pred: &dyn Fn(&[u8]) -> bool| {
+        (0..)
+            .map(|i| format!("key{}", i).into_bytes())
+            .find(|k| pred(k))
+            .unwrap()
+    };
+    let high = find(&|k| key_level(k) >= 2);
+    let mid = find(&|k| key_level(k) == 1 && k > high.as_slice());
+    let low = find(&|k| key_level(k) == 0 && k < high.as_slice());
+
+    // The root holds `mid` with `low` to its left. Splitting it around
+    // `high` leaves nothing of the root's own items on the left.
+    let hash = build(&store, &[low.clone(), mid.clone(), high.clone()]);
+    assert_canonical(&store, &hash, true, None);
+    let root = store.read_node(&hash).unwrap();
+    assert_eq!(root.items[0].key, high);
+    let left = store.read_node(root.left.as_ref().unwrap()).unwrap();
+    assert_eq!(left.items[0].key, low);
+}
+
+#[test]
+fn test_remove_root_item() {
+    let store = TestStoreMut::new();
+    let all = keys(0..100);
+    let top = highest_key(&all);
+    assert!(key_level(&top) > 0);
+
+    let hash = remove(&store, &build(&store, &all), &top);
+    assert_canonical(&store, &hash, true, None);
+
+    let rest: Vec<Vec<u8>> = all.into_iter().filter(|k| *k != top).collect();
+    assert_eq!(hash, build(&store, &rest));
+    let root = store.read_node(&hash).unwrap();
+    assert_eq!(root.get(&store, &top).unwrap(), None);
+    for k in &rest {
+        assert_eq!(root.get(&store, k).unwrap().as_ref(), Some(k));
+    }
+}
+
+#[test]
+fn test_remove_merges_across_levels() {
+    let store = TestStoreMut::new();
+    let all = keys(0..300);
+    let mut hash = build(&store, &all);
+
+    // Removing every key above level 0 makes the subtrees between them merge.
+    let (high, low): (Vec<_>, Vec<_>) = all.into_iter().partition(|k| key_level(k) > 0);
+    assert!(!high.is_empty());
+    for k in &high {
+        hash = remove(&store, &hash, k);
+        assert_canonical(&store, &hash, true, None);
+    }
+    assert_eq!(hash, build(&store, &low));
+
+    let root = store.read_node(&hash).unwrap();
+    assert!(root.left.is_none());
+    assert!(root.items.iter().all(|item| item.right.is_none()));
+}
+
+#[test]
+fn test_remove_down_to_empty() {
+    let store = TestStoreMut::new();
+    let all = keys(0..200);
+    let empty = store.write_node(&MstNode::empty()).unwrap();
+
+    let shuffled = (0..all.len())
+        .map(|i| all[i * 73 % all.len()].clone())
+        .collect();
+    for order in [all.clone(), all.iter().rev().cloned().collect(), shuffled] {
+        let mut hash = build(&store, &order);
+        assert_canonical(&store, &hash, true, None);
+        for (i, k) in order.iter().enumerate() {
+            hash = remove(&store, &hash, k);
+            assert_canonical(&store, &hash, true, None);
+            if i % 20 == 0 {
+                let mut rest = order[i + 1..].to_vec();
+                rest.sort();
+                assert_eq!(hash, build(&store, &rest));
+            }
+        }
+        assert_eq!(hash, empty);
+    }
+}
+
+/// Every key in the tree at `hash`, in order.
+fn key_set(store: &TestStoreMut, hash: &Hash) -> Vec<Vec<u8>> {
+    let root = store.read_node(hash).unwrap();
+    PrefixIterator::new(store, b"", Some(root))
+        .map(|item| item.unwrap().0)
+        .collect()
+}
+
+/// How many nodes the tree at `hash` is made of.
+fn node_count(store: &TestStoreMut, hash: &Hash) -> usize {
+    let node = store.read_node(hash).unwrap();
+    1 + (0..=node.items.len())
+        .filter_map(|idx| node.get_child_hash(idx))
+        .map(|h| node_count(store, h))
+        .sum::<usize>()
+}
+
+/// Put an empty node above every child of the node at `hash`, and above the
+/// node itself, as removals used to leave behind. Returns the new root.
+fn wrap_in_empty(store: &TestStoreMut, hash: &Hash) -> Hash {
+    let mut node = store.read_node(hash).unwrap();
+    for idx in 0..=node.items.len() {
+        if let Some(child) = node.get_child_hash(idx).cloned() {
+            let wrapped = wrap_in_empty(store, &child);
+            node.set_child_hash(idx, Some(wrapped));
+        }
+    }
+    let hash = store.write_node(&node).unwrap();
+    store
+        .write_node(&MstNode {
+            left: Some(hash),
+            items: Vec::new(),
+            ..Default::default()
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_prune_empty() {
+    let store = TestStoreMut::new();
+    let all = keys(0..500);
+
+    // A delete-heavy workload: most keys are removed again.
+    let mut canonical = build(&store, &all);
+    for k in all.iter().filter(|k| k[k.len() - 1] != b'7') {
+        canonical = remove(&store, &canonical, k);
+    }
+    let mut pruned = 0;
+    assert_eq!(
+        MstNode::prune_empty(&store, &canonical, &mut pruned).unwrap(),
+        Some(canonical.clone())
+    );
+    assert_eq!(pruned, 0);
+
+    let bloated = wrap_in_empty(&store, &canonical);
+    assert_canonical(&store, &canonical, true, None);
+    let before = node_count(&store, &bloated);
+
+    let mut pruned = 0;
+    let hash = MstNode::prune_empty(&store, &bloated, &mut pruned)
+        .unwrap()
+        .unwrap();
+    assert_eq!(hash, canonical);
+    assert_eq!(key_set(&store, &hash), key_set(&store, &bloated));
+    assert_eq!(node_count(&store, &hash), before - pruned);
+    assert!(pruned > 1);
+
+    let empty = store.write_node(&MstNode::empty()).unwrap();
+    let mut pruned = 0;
+    assert_eq!(
+        MstNode::prune_empty(&store, &empty, &mut pruned).unwrap(),
+        None
+    );
+    assert_eq!(pruned, 1);
+}
+
 #[test]
 fn generate_nonces() {
     // Helper to find keys with specific levels for testing
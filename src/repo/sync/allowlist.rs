@@ -0,0 +1,105 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use iroh::EndpointId;
+
+use super::server::SyncError;
+
+/// The peers allowed to sync with this one. Stored as a text file with an
+/// endpoint ID per line; blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    peers: BTreeSet<EndpointId>,
+}
+
+impl Allowlist {
+    pub fn parse(text: &str) -> Result<Self, SyncError> {
+        let mut peers = BTreeSet::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let peer = line
+                .parse()
+                .map_err(|_| SyncError::InvalidPeer(line.to_string()))?;
+            peers.insert(peer);
+        }
+
+        Ok(Self { peers })
+    }
+
+    /// Read the allowlist at `path`. A missing file is an empty allowlist.
+    pub fn load(path: &Path) -> Result<Self, SyncError> {
+        if !path.try_exists()? {
+            return Ok(Self::default());
+        }
+
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SyncError> {
+        let text: String = self
+            .peers
+            .iter()
+            .map(|peer| format!("{}\n", peer))
+            .collect();
+        fs::write(path, text)?;
+
+        Ok(())
+    }
+
+    pub fn contains(&self, peer: &EndpointId) -> bool {
+        self.peers.contains(peer)
+    }
+
+    /// Add `peer`, returning whether it was not allowed already.
+    pub fn insert(&mut self, peer: EndpointId) -> bool {
+        self.peers.insert(peer)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EndpointId> {
+        self.peers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    fn peer() -> EndpointId {
+        SecretKey::generate(&mut rand::rng()).public()
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let (alice, bob, carol) = (peer(), peer(), peer());
+        let list =
+            Allowlist::parse(&format!("# office laptop\n{}\n\n  {}  \n", alice, bob)).unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.contains(&alice));
+        assert!(list.contains(&bob));
+        assert!(!list.contains(&carol));
+
+        assert!(matches!(
+            Allowlist::parse("not-a-peer"),
+            Err(SyncError::InvalidPeer(line)) if line == "not-a-peer"
+        ));
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("peers");
+        assert!(Allowlist::load(&path).unwrap().is_empty());
+        let mut list = list;
+        assert!(list.insert(carol));
+        assert!(!list.insert(carol));
+        list.save(&path).unwrap();
+        assert_eq!(Allowlist::load(&path).unwrap(), list);
+    }
+}
@@ -1,14 +1,21 @@
 use iroh::{Endpoint, EndpointId, discovery::mdns::MdnsDiscovery};
 use std::collections::VecDeque;
+use std::future::Future;
+use std::time::Duration;
 use thiserror::Error;
 
 use crate::repo::{
-    Backend, Hash, RepoRefType, ToRepoError,
+    Backend, Hash, Repo, RepoRefType, ToRepoError,
     backend::KeyType,
-    mst::MstNode,
-    sync::server::{ALPN, RepoRequest, RepoResponse},
+    decode_node,
+    sync::server::{ALPN, RepoRequest, RepoResponse, secret_key},
 };
 
+/// Most node bytes sent to the peer in one request, staying well under what
+/// the server reads of a request.
+const MAX_PUSH_BATCH: usize = 4 * 1024 * 1024;
+
+/// What went wrong pulling from or pushing to a peer.
 #[derive(Error, Debug)]
 pub enum PullError {
     #[error("backend error: {0}")]
@@ -25,10 +32,69 @@ pub enum PullError {
     Postcard(#[from] postcard::Error),
     #[error("node not found: {0}")]
     NodeNotFound(Hash),
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+impl PullError {
+    /// Whether trying again may get a different result.
+    fn is_transient(&self) -> bool {
+        matches!(self, PullError::Connection(_) | PullError::Timeout(_))
+    }
+}
+
+/// How often and how long a client tries to reach a peer.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts after the first one fails.
+    pub retries: u32,
+    /// How long an attempt may take to connect and fetch the remote root.
+    pub timeout: Duration,
+    /// The wait before the first retry, doubled before each one after.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            timeout: Duration::from_secs(30),
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `attempt` until it succeeds, fails in a way that is not
+    /// transient, or the retries run out.
+    async fn run<T, F: Future<Output = Result<T, PullError>>>(
+        &self,
+        mut attempt: impl FnMut() -> F,
+    ) -> Result<T, PullError> {
+        let mut backoff = self.backoff;
+        for n in 1.. {
+            match attempt().await {
+                Err(e) if e.is_transient() && n <= self.retries => {
+                    eprintln!(
+                        "Attempt {} of {} failed: {}. Retrying in {:?}...",
+                        n,
+                        self.retries + 1,
+                        e,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+        unreachable!()
+    }
 }
 
 pub struct RepoClient<B: Backend> {
     backend: B,
+    retry: RetryPolicy,
 }
 
 impl<B: Backend> RepoClient<B>
@@ -37,11 +103,48 @@ where
     B: Clone + Send + Sync + std::fmt::Debug + 'static,
 {
     pub fn new(backend: B) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        Self { retry, ..self }
     }
 
     pub async fn pull(&self, peer_id: EndpointId) -> Result<(), PullError> {
         // 1. Check for uncommitted changes
+        self.committed_root()?;
+
+        // 2. Connect to remote peer
+        let endpoint = self.bind().await?;
+
+        // Nodes are stored under their hash, so an attempt cut short leaves
+        // only nodes that the next one skips fetching.
+        self.retry.run(|| self.fetch(&endpoint, peer_id)).await
+    }
+
+    /// Send the nodes of the committed tree the peer is missing and make the
+    /// tree the peer's working copy, for its owner to review and commit.
+    /// Returns the number of nodes sent, or `None` if the peer's committed
+    /// tree is this one already.
+    pub async fn push(&self, peer_id: EndpointId) -> Result<Option<usize>, PullError> {
+        let root = self
+            .committed_root()?
+            .ok_or_else(|| PullError::Sync("nothing has been committed".to_string()))?;
+        let endpoint = self.bind().await?;
+
+        // The peer stores nodes no matter which attempt sent them, and only
+        // moves its ref if it has not moved since this attempt looked.
+        self.retry
+            .run(|| self.send(&endpoint, peer_id, &root))
+            .await
+    }
+
+    /// The root of the committed tree, if any. Fails if the working tree
+    /// differs from it.
+    fn committed_root(&self) -> Result<Option<Hash>, PullError> {
         let working_hash = self
             .backend
             .get(KeyType::Ref, RepoRefType::Working.as_str().as_bytes())
@@ -55,21 +158,53 @@ where
             return Err(PullError::UncommittedChanges);
         }
 
-        // 2. Connect to remote peer
+        committed_hash
+            .map(|bytes| {
+                bytes
+                    .try_into()
+                    .map(Hash)
+                    .map_err(|_| PullError::Backend("invalid hash length in ref".to_string()))
+            })
+            .transpose()
+    }
+
+    /// An endpoint with this repository's identity, so that a peer's
+    /// allowlist can recognize it.
+    async fn bind(&self) -> Result<Endpoint, PullError> {
+        let secret_key =
+            secret_key(&self.backend).map_err(|e| PullError::Backend(e.to_string()))?;
         let mdns = MdnsDiscovery::builder();
-        let endpoint = Endpoint::builder()
+        Endpoint::builder()
             .discovery(mdns)
+            .secret_key(secret_key)
             .bind()
             .await
-            .map_err(|e| PullError::Connection(e.to_string()))?;
+            .map_err(|e| PullError::Connection(e.to_string()))
+    }
+
+    /// Connect to the peer and ask for its root, within the timeout.
+    async fn connect(
+        &self,
+        endpoint: &Endpoint,
+        peer_id: EndpointId,
+    ) -> Result<(iroh::endpoint::Connection, Option<Hash>), PullError> {
+        let handshake = async {
+            let connection = endpoint
+                .connect(peer_id, ALPN)
+                .await
+                .map_err(|e| PullError::Connection(e.to_string()))?;
 
-        let connection = endpoint
-            .connect(peer_id, ALPN)
+            let remote_root = self.get_remote_root(&connection).await?;
+            Ok::<_, PullError>((connection, remote_root))
+        };
+        tokio::time::timeout(self.retry.timeout, handshake)
             .await
-            .map_err(|e| PullError::Connection(e.to_string()))?;
+            .map_err(|_| PullError::Timeout(self.retry.timeout))?
+    }
 
+    async fn fetch(&self, endpoint: &Endpoint, peer_id: EndpointId) -> Result<(), PullError> {
         // 3. Get remote root
-        let remote_root = self.get_remote_root(&connection).await?;
+        let (connection, remote_root) = self.connect(endpoint, peer_id).await?;
         let remote_root = match remote_root {
             Some(h) => h,
             None => return Ok(()), // Empty repo on remote?
@@ -89,10 +224,9 @@ where
                 let node_data = self.get_remote_node(&connection, &hash).await?;
                 let node_data = node_data.ok_or_else(|| PullError::NodeNotFound(hash.clone()))?;
 
-                // Decompress to find children
-                let decompressed = lz4_flex::decompress_size_prepended(&node_data)
-                    .map_err(|e| PullError::Sync(format!("decompression error: {}", e)))?;
-                let node: MstNode = postcard::from_bytes(&decompressed)?;
+                // Decode to find children
+                let node = decode_node(&node_data)
+                    .map_err(|e| PullError::Sync(format!("could not decode node: {}", e)))?;
 
                 // Add children to queue
                 if let Some(h) = node.left {
@@ -123,17 +257,77 @@ where
         Ok(())
     }
 
-    async fn get_remote_root(
+    async fn send(
+        &self,
+        endpoint: &Endpoint,
+        peer_id: EndpointId,
+        root: &Hash,
+    ) -> Result<Option<usize>, PullError> {
+        let (connection, remote_root) = self.connect(endpoint, peer_id).await?;
+        if remote_root.as_ref() == Some(root) {
+            return Ok(None);
+        }
+
+        // The peer has every node below its root. If the root is known here
+        // too, so is everything below it, and none of that needs sending.
+        let known = match &remote_root {
+            Some(hash) => self
+                .backend
+                .get(KeyType::Node, &hash.0)
+                .map_err(|e| PullError::Backend(e.to_string()))?
+                .map(|_| hash),
+            None => None,
+        };
+        let mut nodes = Repo::new(self.backend.clone())
+            .delta_nodes(known, root)
+            .map_err(|e| PullError::Backend(e.to_string()))?;
+        // The peer takes a node only once it has the nodes below it.
+        nodes.reverse();
+        let count = nodes.len();
+
+        let mut nodes = nodes.into_iter().peekable();
+        while nodes.peek().is_some() {
+            let mut batch = Vec::new();
+            let mut size = 0;
+            while let Some((_, data)) = nodes.peek()
+                && (batch.is_empty() || size + data.len() <= MAX_PUSH_BATCH)
+            {
+                size += data.len();
+                batch.extend(nodes.next());
+            }
+            match self
+                .request(&connection, &RepoRequest::PutNodes(batch))
+                .await?
+            {
+                RepoResponse::Stored(_) => {}
+                RepoResponse::Error(e) => return Err(PullError::Sync(e)),
+                _ => return Err(PullError::Sync("unexpected response".to_string())),
+            }
+        }
+
+        let req = RepoRequest::SetRoot {
+            expected: remote_root,
+            root: root.clone(),
+        };
+        match self.request(&connection, &req).await? {
+            RepoResponse::Updated => Ok(Some(count)),
+            RepoResponse::Error(e) => Err(PullError::Sync(e)),
+            _ => Err(PullError::Sync("unexpected response".to_string())),
+        }
+    }
+
+    /// Send `req` on a stream of its own and read the response.
+    async fn request(
         &self,
         connection: &iroh::endpoint::Connection,
-    ) -> Result<Option<Hash>, PullError> {
+        req: &RepoRequest,
+    ) -> Result<RepoResponse, PullError> {
         let (mut send, mut recv) = connection
             .open_bi()
             .await
             .map_err(|e| PullError::Connection(e.to_string()))?;
 
-        let req = RepoRequest::GetRoot;
-        let req_bytes = postcard::to_stdvec(&req)?;
+        let req_bytes = postcard::to_stdvec(req)?;
         send.write_all(&req_bytes)
             .await
             .map_err(|e| PullError::Connection(e.to_string()))?;
@@ -141,12 +335,18 @@ where
             .map_err(|e| PullError::Connection(e.to_string()))?;
 
         let resp_bytes = recv
-            .read_to_end(1024)
+            .read_to_end(10 * 1024 * 1024)
             .await
             .map_err(|e| PullError::Connection(e.to_string()))?;
-        let resp: RepoResponse = postcard::from_bytes(&resp_bytes)?;
 
-        match resp {
+        Ok(postcard::from_bytes(&resp_bytes)?)
+    }
+
+    async fn get_remote_root(
+        &self,
+        connection: &iroh::endpoint::Connection,
+    ) -> Result<Option<Hash>, PullError> {
+        match self.request(connection, &RepoRequest::GetRoot).await? {
             RepoResponse::Root(h) => Ok(h),
             RepoResponse::Error(e) => Err(PullError::Sync(e)),
             _ => Err(PullError::Sync("unexpected response".to_string())),
@@ -158,29 +358,53 @@ where
         connection: &iroh::endpoint::Connection,
         hash: &Hash,
     ) -> Result<Option<Vec<u8>>, PullError> {
-        let (mut send, mut recv) = connection
-            .open_bi()
-            .await
-            .map_err(|e| PullError::Connection(e.to_string()))?;
-
         let req = RepoRequest::GetNode(hash.clone());
-        let req_bytes = postcard::to_stdvec(&req)?;
-        send.write_all(&req_bytes)
-            .await
-            .map_err(|e| PullError::Connection(e.to_string()))?;
-        send.finish()
-            .map_err(|e| PullError::Connection(e.to_string()))?;
-
-        let resp_bytes = recv
-            .read_to_end(10 * 1024 * 1024)
-            .await
-            .map_err(|e| PullError::Connection(e.to_string()))?;
-        let resp: RepoResponse = postcard::from_bytes(&resp_bytes)?;
-
-        match resp {
+        match self.request(connection, &req).await? {
             RepoResponse::Node(data) => Ok(data),
             RepoResponse::Error(e) => Err(PullError::Sync(e)),
             _ => Err(PullError::Sync("unexpected response".to_string())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy {
+            retries: 2,
+            timeout: Duration::from_secs(1),
+            backoff: Duration::ZERO,
+        };
+        let attempts = Cell::new(0);
+        let failing = |until: u32, error: fn() -> PullError| {
+            attempts.set(0);
+            let attempts = &attempts;
+            move || {
+                attempts.set(attempts.get() + 1);
+                let n = attempts.get();
+                async move { if n < until { Err(error()) } else { Ok(n) } }
+            }
+        };
+        let connection = || PullError::Connection("reset".to_string());
+
+        assert_eq!(policy.run(failing(3, connection)).await.unwrap(), 3);
+        assert!(matches!(
+            policy.run(failing(4, connection)).await,
+            Err(PullError::Connection(_))
+        ));
+        assert_eq!(attempts.get(), 3);
+
+        // Errors that would not go away are not retried.
+        assert!(matches!(
+            policy
+                .run(failing(2, || PullError::Sync("bad".to_string())))
+                .await,
+            Err(PullError::Sync(_))
+        ));
+        assert_eq!(attempts.get(), 1);
+    }
+}
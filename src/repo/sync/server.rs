@@ -1,13 +1,18 @@
 use iroh::{
     Endpoint, EndpointId, SecretKey,
     endpoint::Connection,
-    protocol::{AcceptError, ProtocolHandler},
+    protocol::{AcceptError, AccessLimit, ProtocolHandler},
 };
 use serde::{Deserialize, Serialize};
 use std::future::Future;
+use std::sync::Arc;
 use thiserror::Error;
 
-use crate::repo::{Backend, Hash, IROH_SECRET, RepoRefType, ToRepoError, backend::KeyType};
+use super::allowlist::Allowlist;
+use crate::repo::{
+    Backend, Hash, IROH_SECRET, Repo, RepoError, RepoRefType, ToRepoError, backend::KeyType,
+    decode_node,
+};
 use iroh::discovery::mdns::MdnsDiscovery;
 
 pub const ALPN: &[u8] = b"pika/sync/0";
@@ -16,6 +21,15 @@ pub const ALPN: &[u8] = b"pika/sync/0";
 pub enum RepoRequest {
     GetNode(Hash),
     GetRoot,
+    /// Store nodes pushed by the peer. Each node must come after the nodes
+    /// below it, unless those are stored already.
+    PutNodes(Vec<(Hash, Vec<u8>)>),
+    /// Make `root` the working tree, if the committed one is still
+    /// `expected` and there are no uncommitted changes.
+    SetRoot {
+        expected: Option<Hash>,
+        root: Hash,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,8 +37,14 @@ pub enum RepoResponse {
     Node(Option<Vec<u8>>),
     Root(Option<Hash>),
     Error(String),
+    /// The number of nodes stored.
+    Stored(usize),
+    Updated,
 }
 
+/// Called after a push moved the working ref, to bring the index in line.
+pub type PushHook = Arc<dyn Fn() + Send + Sync>;
+
 #[derive(Error, Debug)]
 pub enum SyncError {
     #[error("backend error: {0}")]
@@ -39,10 +59,14 @@ pub enum SyncError {
     SecretNotFound,
     #[error("invalid secret")]
     InvalidSecret,
+    #[error("invalid peer endpoint ID: {0}")]
+    InvalidPeer(String),
 }
 
 pub struct RepoServer<B: Backend> {
     backend: B,
+    allowlist: Option<Allowlist>,
+    on_push: Option<PushHook>,
 }
 
 impl<B: Backend> RepoServer<B>
@@ -51,22 +75,29 @@ where
     B: Clone + Send + Sync + std::fmt::Debug + 'static,
 {
     pub fn new(backend: B) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            allowlist: None,
+            on_push: None,
+        }
     }
 
-    pub async fn start(&self) -> Result<(EndpointId, iroh::protocol::Router), SyncError> {
-        let mdns = MdnsDiscovery::builder();
+    /// Only sync with the peers in `allowlist`. Connections from any other
+    /// peer are closed before a request is read. Pushes are only accepted
+    /// with an allowlist.
+    pub fn with_allowlist(mut self, allowlist: Allowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
 
-        let secret_bytes = self
-            .backend
-            .get(KeyType::Secret, IROH_SECRET.as_bytes())
-            .map_err(|e| SyncError::Backend(e.to_string()))?
-            .ok_or(SyncError::SecretNotFound)?;
+    pub fn on_push(mut self, hook: PushHook) -> Self {
+        self.on_push = Some(hook);
+        self
+    }
 
-        let secret: [u8; 32] = secret_bytes
-            .try_into()
-            .map_err(|_| SyncError::InvalidSecret)?;
-        let secret_key = SecretKey::from_bytes(&secret);
+    pub async fn start(&self) -> Result<(EndpointId, iroh::protocol::Router), SyncError> {
+        let mdns = MdnsDiscovery::builder();
+        let secret_key = secret_key(&self.backend)?;
 
         let endpoint = Endpoint::builder()
             .discovery(mdns)
@@ -78,7 +109,15 @@ where
 
         let handler = RepoProtocolHandler {
             backend: self.backend.clone(),
+            accept_push: self.allowlist.is_some(),
+            on_push: self.on_push.clone(),
         };
+        let allowlist = self.allowlist.clone();
+        let handler = AccessLimit::new(handler, move |peer| {
+            allowlist
+                .as_ref()
+                .is_none_or(|allowlist| allowlist.contains(&peer))
+        });
 
         let router = iroh::protocol::Router::builder(endpoint)
             .accept(ALPN, handler)
@@ -88,9 +127,33 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
+/// The key this repository identifies itself to peers with.
+pub(super) fn secret_key<B: Backend>(backend: &B) -> Result<SecretKey, SyncError> {
+    let secret_bytes = backend
+        .get(KeyType::Secret, IROH_SECRET.as_bytes())
+        .map_err(|e| SyncError::Backend(e.to_string()))?
+        .ok_or(SyncError::SecretNotFound)?;
+
+    let secret: [u8; 32] = secret_bytes
+        .try_into()
+        .map_err(|_| SyncError::InvalidSecret)?;
+    Ok(SecretKey::from_bytes(&secret))
+}
+
+#[derive(Clone)]
 struct RepoProtocolHandler<B: Backend> {
     backend: B,
+    accept_push: bool,
+    on_push: Option<PushHook>,
+}
+
+impl<B: Backend + std::fmt::Debug> std::fmt::Debug for RepoProtocolHandler<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RepoProtocolHandler")
+            .field("backend", &self.backend)
+            .field("accept_push", &self.accept_push)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<B: Backend> ProtocolHandler for RepoProtocolHandler<B>
@@ -102,32 +165,17 @@ where
         &self,
         connection: Connection,
     ) -> impl Future<Output = Result<(), AcceptError>> + std::marker::Send {
-        let backend = self.backend.clone();
+        let handler = self.clone();
         Box::pin(async move {
             while let Ok((mut send, mut recv)) = connection.accept_bi().await {
-                let backend = backend.clone();
+                let handler = handler.clone();
                 tokio::spawn(async move {
-                    if let Ok(buf) = recv.read_to_end(10 * 1024 * 1024).await {
-                        if let Ok(req) = postcard::from_bytes::<RepoRequest>(&buf) {
-                            let resp = match req {
-                                RepoRequest::GetNode(hash) => RepoResponse::Node(
-                                    backend.get(KeyType::Node, &hash.0).ok().flatten(),
-                                ),
-                                RepoRequest::GetRoot => RepoResponse::Root(
-                                    backend
-                                        .get(
-                                            KeyType::Ref,
-                                            RepoRefType::Committed.as_str().as_bytes(),
-                                        )
-                                        .ok()
-                                        .flatten()
-                                        .and_then(|bytes| bytes.try_into().ok())
-                                        .map(Hash),
-                                ),
-                            };
-                            if let Ok(resp_bytes) = postcard::to_stdvec(&resp) {
-                                let _ = send.write_all(&resp_bytes).await;
-                            }
+                    if let Ok(buf) = recv.read_to_end(10 * 1024 * 1024).await
+                        && let Ok(req) = postcard::from_bytes::<RepoRequest>(&buf)
+                    {
+                        let resp = handler.respond(req);
+                        if let Ok(resp_bytes) = postcard::to_stdvec(&resp) {
+                            let _ = send.write_all(&resp_bytes).await;
                         }
                     }
                     let _ = send.finish();
@@ -137,3 +185,299 @@ where
         })
     }
 }
+
+impl<B: Backend + Clone> RepoProtocolHandler<B>
+where
+    B::Error: ToRepoError,
+{
+    fn respond(&self, req: RepoRequest) -> RepoResponse {
+        let backend = &self.backend;
+        match req {
+            RepoRequest::GetNode(hash) => {
+                RepoResponse::Node(backend.get(KeyType::Node, &hash.0).ok().flatten())
+            }
+            RepoRequest::GetRoot => RepoResponse::Root(
+                backend
+                    .get(KeyType::Ref, RepoRefType::Committed.as_str().as_bytes())
+                    .ok()
+                    .flatten()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(Hash),
+            ),
+            RepoRequest::PutNodes(_) | RepoRequest::SetRoot { .. } if !self.accept_push => {
+                RepoResponse::Error(
+                    "pushes are only accepted by servers with a peer allowlist".to_string(),
+                )
+            }
+            RepoRequest::PutNodes(nodes) => match self.put_nodes(nodes) {
+                Ok(stored) => RepoResponse::Stored(stored),
+                Err(e) => RepoResponse::Error(e.to_string()),
+            },
+            RepoRequest::SetRoot { expected, root } => match self.set_root(expected, root) {
+                Ok(()) => {
+                    if let Some(hook) = &self.on_push {
+                        hook();
+                    }
+                    RepoResponse::Updated
+                }
+                Err(e) => RepoResponse::Error(e),
+            },
+        }
+    }
+
+    /// Check each node against its hash and that the nodes below it are
+    /// stored, then store it, so that a stored node is never missing any
+    /// of its tree.
+    fn put_nodes(&self, nodes: Vec<(Hash, Vec<u8>)>) -> Result<usize, RepoError> {
+        let has = |hash: &Hash| -> Result<bool, RepoError> {
+            Ok(self
+                .backend
+                .get(KeyType::Node, &hash.0)
+                .map_err(|e| e.to_repo_error())?
+                .is_some())
+        };
+
+        let mut stored = 0;
+        for (hash, compressed) in nodes {
+            if blake3::hash(&compressed).as_bytes() != &hash.0 {
+                return Err(RepoError::HashMismatch(hash));
+            }
+            let node = decode_node(&compressed)?;
+            let children = node
+                .left
+                .iter()
+                .chain(node.items.iter().filter_map(|item| item.right.as_ref()));
+            for child in children {
+                if !has(child)? {
+                    return Err(RepoError::MissingNode(child.clone()));
+                }
+            }
+            if !has(&hash)? {
+                self.backend
+                    .set(KeyType::Node, &hash.0, &compressed)
+                    .map_err(|e| e.to_repo_error())?;
+                stored += 1;
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Move the working ref to `root`. The checks and the move run holding
+    /// the write lock, and the move is a compare-and-set, so that a local
+    /// edit landing in between is refused rather than overwritten.
+    fn set_root(&self, expected: Option<Hash>, root: Hash) -> Result<(), String> {
+        let repo = Repo::new(self.backend.clone());
+        let get_ref = |ref_type: RepoRefType| {
+            self.backend
+                .get(KeyType::Ref, ref_type.as_str().as_bytes())
+                .map_err(|e| e.to_repo_error())
+        };
+        self.backend
+            .exclusively(|| {
+                let working = get_ref(RepoRefType::Working)?;
+                // The response to an earlier attempt may have been lost.
+                if working.as_deref() == Some(root.0.as_slice()) {
+                    return Ok(Ok(()));
+                }
+                let committed = get_ref(RepoRefType::Committed)?;
+                if committed.as_deref() != expected.as_ref().map(|hash| hash.0.as_slice()) {
+                    return Ok(Err("the peer has committed since; pull first".to_string()));
+                }
+                if working != committed {
+                    return Ok(Err("the peer has uncommitted changes".to_string()));
+                }
+                let stored = self
+                    .backend
+                    .get(KeyType::Node, &root.0)
+                    .map_err(|e| e.to_repo_error())?;
+                if stored.is_none() {
+                    return Err(RepoError::MissingNode(root.clone()));
+                }
+
+                // The working ref is `expected`, as it matches the committed one.
+                repo.compare_and_set_ref(RepoRefType::Working.as_str(), expected.as_ref(), &root)
+                    .map(Ok)
+            })
+            .map_err(|e| e.to_string())?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::test_backend::TestBackend;
+
+    fn handler(backend: TestBackend, accept_push: bool) -> RepoProtocolHandler<TestBackend> {
+        RepoProtocolHandler {
+            backend,
+            accept_push,
+            on_push: None,
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        let local = Repo::new(TestBackend::new());
+        local.init().unwrap();
+        let peer = Repo::new(TestBackend::new());
+        peer.init().unwrap();
+        let committed = peer.root_hash(RepoRefType::Committed).unwrap();
+
+        let mut working = local.get_ref(RepoRefType::Working).unwrap();
+        for i in 0..200 {
+            working
+                .write(format!("key-{}", i).into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        let root = working.commit_id().unwrap();
+        let mut nodes = local.delta_nodes(None, &root).unwrap();
+        assert!(nodes.len() > 1);
+
+        let refused = handler(peer.backend.clone(), false);
+        assert!(matches!(
+            refused.respond(RepoRequest::PutNodes(nodes.clone())),
+            RepoResponse::Error(_)
+        ));
+
+        let handler = handler(peer.backend.clone(), true);
+        // Parents before children are refused.
+        assert!(matches!(
+            handler.respond(RepoRequest::PutNodes(nodes.clone())),
+            RepoResponse::Error(_)
+        ));
+        let mut forged = nodes.clone();
+        forged.reverse();
+        forged[0].1.push(0);
+        assert!(matches!(
+            handler.respond(RepoRequest::PutNodes(forged)),
+            RepoResponse::Error(_)
+        ));
+
+        nodes.reverse();
+        let count = nodes.len();
+        assert!(matches!(
+            handler.respond(RepoRequest::PutNodes(nodes.clone())),
+            RepoResponse::Stored(stored) if stored == count
+        ));
+        assert!(matches!(
+            handler.respond(RepoRequest::PutNodes(nodes)),
+            RepoResponse::Stored(0)
+        ));
+
+        assert!(matches!(
+            handler.respond(RepoRequest::SetRoot {
+                expected: Some(root.clone()),
+                root: root.clone(),
+            }),
+            RepoResponse::Error(_)
+        ));
+        assert!(matches!(
+            handler.respond(RepoRequest::SetRoot {
+                expected: Some(committed.clone()),
+                root: root.clone(),
+            }),
+            RepoResponse::Updated
+        ));
+        assert_eq!(peer.root_hash(RepoRefType::Working).unwrap(), root);
+        assert_eq!(
+            peer.get_ref(RepoRefType::Working)
+                .unwrap()
+                .read(b"key-7")
+                .unwrap(),
+            Some(b"value".to_vec())
+        );
+        // Done again, as when the response got lost.
+        assert!(matches!(
+            handler.respond(RepoRequest::SetRoot {
+                expected: Some(committed),
+                root,
+            }),
+            RepoResponse::Updated
+        ));
+    }
+
+    /// Moves the working ref the first time a node is looked up, as a local
+    /// edit landing between the checks of a push and its move would.
+    #[derive(Clone)]
+    struct RacingBackend {
+        inner: TestBackend,
+        raced: std::cell::Cell<bool>,
+    }
+
+    impl Backend for RacingBackend {
+        type Error = <TestBackend as Backend>::Error;
+
+        fn get(&self, key_type: KeyType, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+            if key_type == KeyType::Node && !self.raced.replace(true) {
+                let writer = Repo::new(self.inner.clone());
+                let mut working = writer.get_ref(RepoRefType::Working).unwrap();
+                working.write(b"local".to_vec(), b"edit".to_vec()).unwrap();
+            }
+            self.inner.get(key_type, key)
+        }
+
+        fn set(&self, key_type: KeyType, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+            self.inner.set(key_type, key, value)
+        }
+
+        fn list(&self, key_type: KeyType) -> Result<Vec<Vec<u8>>, Self::Error> {
+            self.inner.list(key_type)
+        }
+
+        fn delete(&self, key_type: KeyType, keys: &[&[u8]]) -> Result<usize, Self::Error> {
+            self.inner.delete(key_type, keys)
+        }
+
+        fn vacuum(&self) -> Result<(), Self::Error> {
+            self.inner.vacuum()
+        }
+
+        fn stats(
+            &self,
+            key_type: KeyType,
+        ) -> Result<(usize, std::collections::BTreeMap<usize, usize>), Self::Error> {
+            self.inner.stats(key_type)
+        }
+    }
+
+    #[test]
+    fn test_push_refused_when_working_moves() {
+        let local = Repo::new(TestBackend::new());
+        local.init().unwrap();
+        let peer = Repo::new(TestBackend::new());
+        peer.init().unwrap();
+        let committed = peer.root_hash(RepoRefType::Committed).unwrap();
+
+        let mut working = local.get_ref(RepoRefType::Working).unwrap();
+        working
+            .write(b"pushed".to_vec(), b"value".to_vec())
+            .unwrap();
+        let root = working.commit_id().unwrap();
+        let mut nodes = local.delta_nodes(None, &root).unwrap();
+        nodes.reverse();
+        assert!(matches!(
+            handler(peer.backend.clone(), true).respond(RepoRequest::PutNodes(nodes)),
+            RepoResponse::Stored(_)
+        ));
+
+        let racing = RepoProtocolHandler {
+            backend: RacingBackend {
+                inner: peer.backend.clone(),
+                raced: std::cell::Cell::new(false),
+            },
+            accept_push: true,
+            on_push: None,
+        };
+        assert!(matches!(
+            racing.respond(RepoRequest::SetRoot {
+                expected: Some(committed),
+                root: root.clone(),
+            }),
+            RepoResponse::Error(e) if e.contains("moved by another writer")
+        ));
+        let working = peer.get_ref(RepoRefType::Working).unwrap();
+        assert_ne!(working.hash, root);
+        assert_eq!(working.read(b"local").unwrap(), Some(b"edit".to_vec()));
+    }
+}
@@ -10,6 +10,7 @@ use thiserror::Error;
 
 #[derive(Debug, Error, Clone, Copy)]
 pub enum TestBackendError {
+    #[allow(dead_code)]
     #[error("test backend error")]
     Test,
 }
@@ -20,9 +21,11 @@ impl ToRepoError for TestBackendError {
     }
 }
 
+type KeyValues = BTreeMap<Vec<u8>, Vec<u8>>;
+
 #[derive(Clone)]
 pub struct TestBackend {
-    data: Arc<Mutex<BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>>,
+    data: Arc<Mutex<BTreeMap<String, KeyValues>>>,
 }
 
 impl TestBackend {
@@ -2,7 +2,11 @@
 // the convention in rust. Currently I have a few modules where unit tests
 // are in separate tests.rs file.
 
-use crate::repo::{Backend, Repo, RepoRefType, test_backend::TestBackend};
+use crate::repo::{
+    Backend, Hash, IROH_SECRET, Limits, Repo, RepoError, RepoRef, RepoRefType, Store,
+    backend::KeyType, test_backend::TestBackend,
+};
+use strum::VariantArray;
 
 #[test]
 fn test_repo() {
@@ -189,7 +193,7 @@ fn test_committed_ref() {
     assert!(committed_hash.is_some());
     assert_ne!(root_hash, committed_hash);
 
-    repo.commit().unwrap();
+    repo.commit(None, None).unwrap();
     let committed_hash = repo
         .backend
         .get(KeyType::Ref, RepoRefType::Committed.as_str().as_bytes())
@@ -229,7 +233,7 @@ fn test_iterate_diff() {
         .unwrap()
         .write(b"b".to_vec(), b"2".to_vec())
         .unwrap();
-    repo.commit().unwrap();
+    repo.commit(None, None).unwrap();
 
     // Modifications
     repo.get_ref(RepoRefType::Working)
@@ -292,7 +296,7 @@ fn test_iterate_diff_different_levels() {
         .unwrap()
         .write(b"k0".to_vec(), b"v0".to_vec())
         .unwrap();
-    repo.commit().unwrap();
+    repo.commit(None, None).unwrap();
 
     // Tree 2: k0 and k75 (Level 1 root, k0 is a child)
     repo.get_ref(RepoRefType::Working)
@@ -335,7 +339,7 @@ fn test_iterate_diff_changed_different_height() {
         .unwrap()
         .write(b"k0".to_vec(), b"v1".to_vec())
         .unwrap();
-    repo.commit().unwrap();
+    repo.commit(None, None).unwrap();
 
     // Tree 2: k0=v2 and k75=v75 (L1 root)
     repo.get_ref(RepoRefType::Working)
@@ -380,3 +384,695 @@ fn test_iterate_diff_changed_different_height() {
     assert!(found_changed, "Should have found Changed(k0)");
     assert!(found_added, "Should have found Added(k75)");
 }
+
+#[test]
+fn test_export_import_delta() {
+    use crate::repo::RepoError;
+
+    let mut repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+
+    for i in 0..500 {
+        repo.get_ref(RepoRefType::Working)
+            .unwrap()
+            .write(format!("k{}", i).into_bytes(), b"v".to_vec())
+            .unwrap();
+    }
+    repo.commit(None, None).unwrap();
+    let first = repo.get_ref(RepoRefType::Committed).unwrap().hash;
+
+    repo.get_ref(RepoRefType::Working)
+        .unwrap()
+        .write(b"k7".to_vec(), b"changed".to_vec())
+        .unwrap();
+    repo.commit(None, None).unwrap();
+    let second = repo.get_ref(RepoRefType::Committed).unwrap().hash;
+
+    let mut full = Vec::new();
+    let full_count = repo.export_delta(None, &first, &mut full).unwrap();
+    let mut delta = Vec::new();
+    let delta_count = repo.export_delta(Some(&first), &second, &mut delta).unwrap();
+    assert!(delta_count > 0);
+    assert!(delta_count < full_count);
+
+    let peer = Repo::new(TestBackend::new());
+    peer.init().unwrap();
+
+    // The delta alone refers to nodes the peer does not have yet.
+    assert!(matches!(
+        peer.import_delta(delta.as_slice()),
+        Err(RepoError::MissingNode(_))
+    ));
+
+    assert_eq!(peer.import_delta(full.as_slice()).unwrap(), first);
+    assert_eq!(peer.import_delta(delta.as_slice()).unwrap(), second);
+
+    peer.set_ref(RepoRefType::Working, &second).unwrap();
+    let working = peer.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(working.read(b"k7").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(working.read(b"k42").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn test_import_delta_rejects_tampered_node() {
+    use crate::repo::RepoError;
+
+    let repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+    repo.get_ref(RepoRefType::Working)
+        .unwrap()
+        .write(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    let root = repo.get_ref(RepoRefType::Working).unwrap().hash;
+
+    let mut delta = Vec::new();
+    repo.export_delta(None, &root, &mut delta).unwrap();
+    let last = delta.len() - 1;
+    delta[last] ^= 0xff;
+
+    let peer = Repo::new(TestBackend::new());
+    peer.init().unwrap();
+    assert!(matches!(
+        peer.import_delta(delta.as_slice()),
+        Err(RepoError::HashMismatch(_))
+    ));
+}
+
+#[test]
+fn test_commit_log() {
+    let mut repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+    assert!(repo.log().unwrap().is_empty());
+
+    repo.get_ref(RepoRefType::Working)
+        .unwrap()
+        .write(b"k1".to_vec(), b"v1".to_vec())
+        .unwrap();
+    repo.commit(Some("alice"), Some("add k1")).unwrap();
+    let first_root = repo.get_ref(RepoRefType::Committed).unwrap().hash;
+
+    repo.get_ref(RepoRefType::Working)
+        .unwrap()
+        .write(b"k1".to_vec(), b"v2".to_vec())
+        .unwrap();
+    repo.commit(None, None).unwrap();
+
+    let log = repo.log().unwrap();
+    assert_eq!(log.len(), 2);
+    let (first_hash, first) = &log[1];
+    let (_, second) = &log[0];
+    assert_eq!(first.author.as_deref(), Some("alice"));
+    assert_eq!(first.message.as_deref(), Some("add k1"));
+    assert_eq!(first.root, first_root);
+    assert_eq!(first.parent, None);
+    assert_eq!(second.parent.as_ref(), Some(first_hash));
+    assert_eq!(second.author, None);
+
+    // The tree of an earlier commit survives garbage collection.
+    repo.gc().unwrap();
+    let old = crate::repo::RepoRef {
+        repo: &repo,
+        hash: first_root,
+        name: "detached".to_string(),
+    };
+    assert_eq!(old.read(b"k1").unwrap(), Some(b"v1".to_vec()));
+}
+
+#[test]
+fn test_find_node() {
+    let mut repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..300 {
+        working
+            .write(format!("key-{:03}", i).into_bytes(), b"v".to_vec())
+            .unwrap();
+    }
+    repo.commit(None, None).unwrap();
+    let root = repo.root_hash(RepoRefType::Working).unwrap();
+    let hex = root.to_hex();
+
+    assert_eq!(repo.find_node(&hex).unwrap(), root);
+    assert_eq!(repo.find_node(&hex[..12].to_uppercase()).unwrap(), root);
+    assert!(matches!(repo.find_node(""), Err(RepoError::UnknownNode(_))));
+    // More than sixteen nodes are stored, so some share their first digit.
+    assert!((0..16).any(|d| matches!(
+        repo.find_node(&format!("{:x}", d)),
+        Err(RepoError::AmbiguousNode { .. })
+    )));
+
+    // A tree missing a node below its root cannot be checked out.
+    let bytes = repo.backend.get(KeyType::Node, &root.0).unwrap().unwrap();
+    let node = crate::repo::decode_node(&bytes).unwrap();
+    let child = node.left.unwrap();
+    repo.backend.delete(KeyType::Node, &[&child.0]).unwrap();
+    assert!(repo.find_node(&hex).is_err());
+    assert!(matches!(
+        repo.find_node(&child.to_hex()),
+        Err(RepoError::UnknownNode(_))
+    ));
+}
+
+#[test]
+fn test_commit_log_chain() {
+    let mut repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+
+    let mut roots = Vec::new();
+    for value in [b"v1", b"v2", b"v3"] {
+        repo.get_ref(RepoRefType::Working)
+            .unwrap()
+            .write(b"k1".to_vec(), value.to_vec())
+            .unwrap();
+        repo.commit(None, None).unwrap();
+        roots.push(repo.root_hash(RepoRefType::Committed).unwrap());
+    }
+
+    // Newest first, each commit following the one after it.
+    let log = repo.log().unwrap();
+    let logged: Vec<_> = log.iter().map(|(_, commit)| commit.root.clone()).collect();
+    roots.reverse();
+    assert_eq!(logged, roots);
+    assert_eq!(log[0].1.parent.as_ref(), Some(&log[1].0));
+    assert_eq!(log[1].1.parent.as_ref(), Some(&log[2].0));
+    assert_eq!(log[2].1.parent, None);
+}
+
+#[test]
+fn test_trace_get() {
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend);
+    repo.init().unwrap();
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..200 {
+        working
+            .write(format!("key-{}", i).into_bytes(), vec![i as u8])
+            .unwrap();
+    }
+    let root = working.commit_id().unwrap();
+
+    for key in ["key-7", "key-150", "missing"] {
+        let trace = repo.trace_get(&root, key.as_bytes()).unwrap();
+        assert_eq!(trace[0].0, root);
+        for window in trace.windows(2) {
+            let node = repo.read_node(&window[0].0).unwrap();
+            assert_eq!(node.get_child_hash(window[0].1), Some(&window[1].0));
+        }
+
+        let (last, idx) = trace.last().unwrap();
+        let node = repo.read_node(last).unwrap();
+        if key == "missing" {
+            assert!(node.items.iter().all(|item| item.key != key.as_bytes()));
+            assert!(node.get_child_hash(*idx).is_none());
+        } else {
+            assert_eq!(node.items[*idx].key, key.as_bytes());
+        }
+    }
+    // A tree of 200 keys is more than one node deep.
+    assert!(
+        ["key-7", "key-150"]
+            .iter()
+            .any(|key| repo.trace_get(&root, key.as_bytes()).unwrap().len() > 1)
+    );
+}
+
+#[test]
+fn test_stale_write_conflicts() {
+    let repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+
+    let mut first = repo.get_ref(RepoRefType::Working).unwrap();
+    let mut second = repo.get_ref(RepoRefType::Working).unwrap();
+    first.write(b"k1".to_vec(), b"v1".to_vec()).unwrap();
+
+    // `second` still points at the tree from before `first` wrote.
+    let err = second.write(b"k2".to_vec(), b"v2".to_vec()).unwrap_err();
+    assert!(matches!(
+        err,
+        crate::repo::RepoError::Conflict { ref ref_name } if ref_name == "working"
+    ));
+    assert!(matches!(
+        second.remove(b"k1"),
+        Err(crate::repo::RepoError::Conflict { .. })
+    ));
+
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(working.hash, first.hash);
+    assert_eq!(working.read(b"k2").unwrap(), None);
+}
+
+#[test]
+fn test_compare_and_set_ref() {
+    let repo = Repo::new(TestBackend::new());
+    repo.init().unwrap();
+    let working = repo.get_ref(RepoRefType::Working).unwrap().hash;
+    let other = crate::repo::Hash([1; 32]);
+
+    assert!(matches!(
+        repo.compare_and_set_ref("working", Some(&other), &other),
+        Err(crate::repo::RepoError::Conflict { .. })
+    ));
+    repo.compare_and_set_ref("working", Some(&working), &other).unwrap();
+    assert_eq!(repo.get_ref(RepoRefType::Working).unwrap().hash, other);
+
+    // A ref that does not exist yet is only created when none is expected.
+    assert!(repo.compare_and_set_ref("new", Some(&working), &other).is_err());
+    repo.compare_and_set_ref("new", None, &other).unwrap();
+}
+
+#[test]
+fn test_stats_prefix() {
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend);
+    repo.init().unwrap();
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..300 {
+        working
+            .write(format!("office/{}/name", i).into_bytes(), vec![0; 4])
+            .unwrap();
+        working
+            .write(format!("person/{}/name", i).into_bytes(), vec![0; 10])
+            .unwrap();
+    }
+    working
+        .write(b"person/1/photo".to_vec(), vec![0; 100])
+        .unwrap();
+
+    let all = repo.stats(b"").unwrap();
+    assert_eq!(all.key_value_count, 601);
+
+    let persons = repo.stats(b"person/").unwrap();
+    assert_eq!(persons.key_value_count, 301);
+    assert_eq!(persons.total_value_size, 300 * 10 + 100);
+    assert_eq!(persons.value_size_distribution[&10], 300);
+
+    let person = repo.stats(b"person/1/").unwrap();
+    assert_eq!(person.key_value_count, 2);
+    assert_eq!(repo.stats(b"tenure/").unwrap().key_value_count, 0);
+}
+
+#[test]
+fn test_stats_key_types() {
+    let backend = TestBackend::new();
+    let mut repo = Repo::new(backend);
+    repo.init().unwrap();
+
+    let stats = repo.stats(b"").unwrap();
+    assert_eq!(stats.stored.len(), KeyType::VARIANTS.len());
+    assert_eq!(stats.stored[&KeyType::Secret].count, 1);
+    assert_eq!(stats.stored[&KeyType::Secret].total_size, 32);
+    assert_eq!(stats.stored[&KeyType::Commit].count, 0);
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    working.write(b"k".to_vec(), b"v".to_vec()).unwrap();
+    repo.commit(None, Some("first")).unwrap();
+
+    let stats = repo.stats(b"").unwrap();
+    let commits = &stats.stored[&KeyType::Commit];
+    assert_eq!(commits.count, 1);
+    assert_eq!(commits.size_distribution.values().sum::<usize>(), 1);
+    assert_eq!(
+        stats.stored[&KeyType::Ref].total_size,
+        stats.stored[&KeyType::Ref].count * 32
+    );
+    assert!(stats.stored[&KeyType::Node].count >= 2);
+}
+
+#[test]
+fn test_init_already_initialized() {
+    let backend = TestBackend::new();
+    let mut repo = Repo::new(backend);
+    repo.init().unwrap();
+    let secret = |repo: &Repo<TestBackend>| {
+        repo.backend
+            .get(KeyType::Secret, IROH_SECRET.as_bytes())
+            .unwrap()
+            .unwrap()
+    };
+    let before = secret(&repo);
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    working.write(b"k".to_vec(), b"v".to_vec()).unwrap();
+    let root = repo.get_ref(RepoRefType::Working).unwrap().hash;
+    repo.commit(None, None).unwrap();
+
+    assert!(matches!(repo.init(), Err(RepoError::AlreadyInitialized)));
+    assert_eq!(repo.get_ref(RepoRefType::Working).unwrap().hash, root);
+    assert_eq!(secret(&repo), before);
+
+    repo.reinit().unwrap();
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(working.read(b"k").unwrap(), None);
+    assert_ne!(secret(&repo), before);
+    assert_eq!(repo.head().unwrap(), None);
+
+    // A head left behind on its own still counts as initialized.
+    repo.commit(None, None).unwrap();
+    let head = repo.head().unwrap().unwrap();
+    assert_eq!(repo.read_commit(&head).unwrap().parent, None);
+    repo.backend
+        .delete(
+            KeyType::Ref,
+            &[
+                RepoRefType::Working.as_str().as_bytes(),
+                RepoRefType::Committed.as_str().as_bytes(),
+            ],
+        )
+        .unwrap();
+    repo.backend
+        .delete(KeyType::Secret, &[IROH_SECRET.as_bytes()])
+        .unwrap();
+    assert!(repo.is_initialized().unwrap());
+}
+
+#[test]
+fn test_write_limits() {
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend).with_limits(Limits {
+        max_key_len: 4,
+        max_value_size: 8,
+    });
+    repo.init().unwrap();
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    let root = working.hash.clone();
+    assert!(matches!(
+        working.write(b"long-key".to_vec(), b"v".to_vec()),
+        Err(RepoError::KeyTooLong { len: 8, max: 4 })
+    ));
+    assert!(matches!(
+        working.write(b"k".to_vec(), vec![0; 9]),
+        Err(RepoError::ValueTooLarge { size: 9, max: 8, .. })
+    ));
+    assert_eq!(working.hash, root);
+    assert_eq!(repo.get_ref(RepoRefType::Working).unwrap().hash, root);
+
+    working.write(b"k".to_vec(), vec![0; 8]).unwrap();
+    assert_eq!(working.read(b"k").unwrap(), Some(vec![0; 8]));
+}
+
+#[test]
+fn test_index_snapshot() {
+    let backend = TestBackend::new();
+    let mut repo = Repo::new(backend);
+    repo.init().unwrap();
+    repo.commit(None, Some("first")).unwrap();
+    let head = repo.head().unwrap().unwrap();
+    assert_eq!(repo.index_snapshot(&head).unwrap(), None);
+
+    let index = Hash(*blake3::hash(b"index").as_bytes());
+    repo.set_index_snapshot(&head, &index).unwrap();
+    assert_eq!(repo.index_snapshot(&head).unwrap(), Some(index));
+
+    // Snapshots are not refs, so collecting garbage leaves them be.
+    repo.gc().unwrap();
+    assert!(repo.index_snapshot(&head).unwrap().is_some());
+}
+
+#[test]
+fn test_gc_preview() {
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend.clone());
+    repo.init().unwrap();
+    assert_eq!(repo.gc_preview().unwrap(), Default::default());
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..50 {
+        working
+            .write(format!("k{}", i).into_bytes(), b"v1".to_vec())
+            .unwrap();
+    }
+    working.write(b"k0".to_vec(), b"v2".to_vec()).unwrap();
+    let nodes_before = backend.list(KeyType::Node).unwrap().len();
+
+    let preview = repo.gc_preview().unwrap();
+    assert!(preview.nodes > 0);
+    assert!(preview.bytes > 0);
+    assert_eq!(backend.list(KeyType::Node).unwrap().len(), nodes_before);
+
+    assert_eq!(repo.gc().unwrap(), preview.nodes);
+    assert_eq!(
+        backend.list(KeyType::Node).unwrap().len(),
+        nodes_before - preview.nodes
+    );
+    assert_eq!(repo.gc_preview().unwrap(), Default::default());
+}
+
+#[test]
+fn test_gc_prune_empty() {
+    use crate::repo::mst::MstNode;
+
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend.clone());
+    repo.init().unwrap();
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..400 {
+        working
+            .write(format!("k{}", i).into_bytes(), b"v".to_vec())
+            .unwrap();
+    }
+    for i in (0..400).filter(|i| i % 10 != 0) {
+        working.remove(format!("k{}", i).as_bytes()).unwrap();
+    }
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    let keys = |hash: &Hash| -> Vec<Vec<u8>> {
+        let tree = RepoRef {
+            repo: &repo,
+            hash: hash.clone(),
+            name: "detached".to_string(),
+        };
+        tree.iter_prefix(b"")
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect()
+    };
+    let expected = keys(&working.hash);
+
+    // An empty node above the root, as deletes used to leave.
+    let bloated = repo
+        .write_node(&MstNode {
+            left: Some(working.hash.clone()),
+            items: Vec::new(),
+            ..Default::default()
+        })
+        .unwrap();
+    repo.set_ref(RepoRefType::Working, &bloated).unwrap();
+    repo.gc().unwrap();
+    let nodes_before = backend.list(KeyType::Node).unwrap().len();
+
+    assert_eq!(repo.prune_empty().unwrap(), 1);
+    assert_eq!(repo.gc().unwrap(), 1);
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(keys(&working.hash), expected);
+    assert_eq!(backend.list(KeyType::Node).unwrap().len(), nodes_before - 1);
+    assert_eq!(repo.prune_empty().unwrap(), 0);
+
+    // The committed tree is pinned by the head commit, so it keeps the empty
+    // node and so does the store.
+    let bloated = repo
+        .write_node(&MstNode {
+            left: Some(working.hash.clone()),
+            items: Vec::new(),
+            ..Default::default()
+        })
+        .unwrap();
+    repo.set_ref(RepoRefType::Working, &bloated).unwrap();
+    repo.set_ref(RepoRefType::Committed, &bloated).unwrap();
+    assert_eq!(repo.prune_empty().unwrap(), 1);
+    assert_eq!(repo.get_ref(RepoRefType::Committed).unwrap().hash, bloated);
+    let pruned = repo.get_ref(RepoRefType::Working).unwrap().hash;
+    assert_eq!(pruned, working.hash);
+    repo.gc().unwrap();
+    assert_eq!(keys(&bloated), expected);
+}
+
+/// Moves the working ref the first time the nodes are listed, as a writer
+/// committing in the middle of a gc would.
+struct RacingBackend {
+    inner: TestBackend,
+    raced: std::cell::Cell<bool>,
+}
+
+impl Backend for RacingBackend {
+    type Error = <TestBackend as Backend>::Error;
+
+    fn get(&self, key_type: KeyType, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.get(key_type, key)
+    }
+
+    fn set(&self, key_type: KeyType, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.inner.set(key_type, key, value)
+    }
+
+    fn list(&self, key_type: KeyType) -> Result<Vec<Vec<u8>>, Self::Error> {
+        let listed = self.inner.list(key_type)?;
+        if key_type == KeyType::Node && !self.raced.replace(true) {
+            let writer = Repo::new(self.inner.clone());
+            let mut working = writer.get_ref(RepoRefType::Working).unwrap();
+            working.write(b"late".to_vec(), b"v".to_vec()).unwrap();
+        }
+        Ok(listed)
+    }
+
+    fn delete(&self, key_type: KeyType, keys: &[&[u8]]) -> Result<usize, Self::Error> {
+        self.inner.delete(key_type, keys)
+    }
+
+    fn vacuum(&self) -> Result<(), Self::Error> {
+        self.inner.vacuum()
+    }
+
+    fn stats(
+        &self,
+        key_type: KeyType,
+    ) -> Result<(usize, std::collections::BTreeMap<usize, usize>), Self::Error> {
+        self.inner.stats(key_type)
+    }
+}
+
+#[test]
+fn test_gc_refuses_when_refs_move() {
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend.clone());
+    repo.init().unwrap();
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    working.write(b"early".to_vec(), b"v".to_vec()).unwrap();
+
+    let racing = Repo::new(RacingBackend {
+        inner: backend.clone(),
+        raced: std::cell::Cell::new(false),
+    });
+    assert!(matches!(racing.gc(), Err(RepoError::GcRaced)));
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(working.read(b"late").unwrap(), Some(b"v".to_vec()));
+
+    // Once the writer is done, gc goes through.
+    assert!(racing.gc().unwrap() > 0);
+    let working = repo.get_ref(RepoRefType::Working).unwrap();
+    assert_eq!(working.read(b"early").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn test_diff_reads_stored_levels() {
+    use crate::repo::mst::KEY_LEVELS;
+    let backend = TestBackend::new();
+    let mut repo = Repo::new(backend);
+    repo.init().unwrap();
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..2000 {
+        working
+            .write(format!("key-{:04}", i).into_bytes(), b"1".to_vec())
+            .unwrap();
+    }
+    repo.commit(None, None).unwrap();
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in (0..2000).step_by(20) {
+        working
+            .write(format!("key-{:04}", i).into_bytes(), b"2".to_vec())
+            .unwrap();
+    }
+
+    // Walking the trees takes the level of each node from the node.
+    KEY_LEVELS.with(|calls| calls.set(0));
+    let committed = repo.get_ref(RepoRefType::Committed).unwrap();
+    let diffs = committed
+        .iterate_diff(&working)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(diffs.len(), 100);
+    assert_eq!(KEY_LEVELS.with(|calls| calls.get()), 0);
+}
+
+#[test]
+fn test_verify() {
+    use crate::repo::NodeProblem;
+    use crate::repo::mst::{MstItem, MstNode};
+    let backend = TestBackend::new();
+    let mut repo = Repo::new(backend.clone());
+    repo.init().unwrap();
+
+    let mut working = repo.get_ref(RepoRefType::Working).unwrap();
+    for i in 0..500 {
+        working
+            .write(format!("key-{:03}", i).into_bytes(), b"1".to_vec())
+            .unwrap();
+    }
+    repo.commit(None, None).unwrap();
+    let report = repo.verify().unwrap();
+    assert!(report.nodes > 1);
+    assert_eq!(report.problems, []);
+
+    // A node whose bytes were altered in place.
+    let root = repo.root_hash(RepoRefType::Working).unwrap();
+    let mut stored = backend.get(KeyType::Node, &root.0).unwrap().unwrap();
+    stored.push(0);
+    backend.set(KeyType::Node, &root.0, &stored).unwrap();
+    let report = repo.verify().unwrap();
+    assert_eq!(report.problems[0], (root.clone(), NodeProblem::HashMismatch));
+
+    let item = |key: &[u8]| MstItem {
+        key: key.to_vec(),
+        value: b"v".to_vec(),
+        right: None,
+    };
+    let unsorted = repo
+        .write_node(&MstNode::new(None, vec![item(b"b"), item(b"a")]))
+        .unwrap();
+    repo.set_ref(RepoRefType::Working, &unsorted).unwrap();
+    let problems = repo.verify().unwrap().problems;
+    assert!(problems.contains(&(unsorted, NodeProblem::Unsorted)));
+
+    let missing = Hash([7; 32]);
+    repo.set_ref(RepoRefType::Working, &missing).unwrap();
+    let problems = repo.verify().unwrap().problems;
+    assert!(problems.contains(&(missing, NodeProblem::Missing)));
+}
+
+#[test]
+fn test_node_format() {
+    use crate::repo::mst::{MstItem, MstNode};
+    use crate::repo::{NODE_FORMAT, NODE_FORMAT_UNLEVELED, decode_node};
+
+    let backend = TestBackend::new();
+    let repo = Repo::new(backend.clone());
+    let node = MstNode::new(
+        None,
+        vec![MstItem {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            right: None,
+        }],
+    );
+    let hash = repo.write_node(&node).unwrap();
+    let stored = backend.get(KeyType::Node, &hash.0).unwrap().unwrap();
+    assert_eq!(stored[0], NODE_FORMAT);
+    assert_eq!(decode_node(&stored).unwrap().level, node.level);
+
+    // Nodes from before the format was recorded are still read, including
+    // ones whose size happens to start with the format byte.
+    for len in 0..600 {
+        let node = MstNode::new(
+            None,
+            vec![MstItem {
+                key: b"k".to_vec(),
+                value: vec![7; len],
+                right: None,
+            }],
+        );
+        // Nodes then ended before the level, which is written as one byte.
+        let mut bytes = postcard::to_stdvec(&node).unwrap();
+        assert_eq!(bytes.pop(), Some(node.level as u8));
+        let legacy = lz4_flex::compress_prepend_size(&bytes);
+        let decoded = decode_node(&legacy).unwrap();
+        assert_eq!(decoded.items[0].value, node.items[0].value);
+        assert_eq!(decoded.level, node.level);
+
+        let mut versioned = vec![NODE_FORMAT_UNLEVELED];
+        versioned.extend(&legacy);
+        assert_eq!(decode_node(&versioned).unwrap().level, node.level);
+    }
+}
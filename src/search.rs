@@ -4,15 +4,46 @@ use anyhow::Result;
 
 use crate::{build, data::searcher::Searcher};
 
-pub async fn run(data_dir: &Path, query: &str) -> Result<()> {
-    build::run(data_dir).await?;
+pub async fn run(
+    data_dir: &Path,
+    query: &str,
+    offset: usize,
+    limit: usize,
+    snippets: bool,
+    fuzzy: Option<u8>,
+) -> Result<()> {
+    build::run(data_dir, None, false, None, false).await?;
 
     let output_dir = data_dir.join("output");
     let searcher = Searcher::open(&output_dir)?;
-    let results = searcher.search(&query)?;
+    if snippets {
+        for found in searcher.search_with_snippets(query, offset, limit)? {
+            let result = found.result;
+            println!(
+                "{:.3}\t{}/{}\t{}\t{}",
+                result.score,
+                result.type_str,
+                result.id,
+                result.full_name(),
+                found.snippet.unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let results = match fuzzy {
+        Some(max_distance) => searcher.search_fuzzy(query, max_distance, offset, limit)?,
+        None => searcher.search(query, offset, limit)?,
+    };
 
     for result in results {
-        println!("{}/{}", result.type_str, result.id);
+        println!(
+            "{:.3}\t{}/{}\t{}",
+            result.score,
+            result.type_str,
+            result.id,
+            result.full_name()
+        );
     }
 
     Ok(())
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::serve::{AppError, AppState};
+
+/// The cookie the token is issued in. It is readable from scripts so that
+/// the page can send it back in [`HEADER`].
+pub const COOKIE: &str = "csrf";
+/// The header requests that change anything must repeat the token in.
+pub const HEADER: &str = "x-csrf-token";
+
+/// Signs the tokens handed out, so that a cookie planted by another site
+/// cannot pass for one.
+pub struct CsrfKey([u8; 32]);
+
+impl CsrfKey {
+    /// A key of its own for this process. Tokens issued before a restart are
+    /// replaced with the next response.
+    pub fn generate() -> Self {
+        CsrfKey(rand::random())
+    }
+
+    /// A new token: a random nonce and its signature, in hex.
+    pub fn issue(&self) -> String {
+        let nonce: [u8; 16] = rand::random();
+        format!(
+            "{}{}",
+            hex(&nonce),
+            blake3::keyed_hash(&self.0, &nonce).to_hex()
+        )
+    }
+
+    pub fn verify(&self, token: &str) -> bool {
+        let Some((nonce, signature)) = token.split_at_checked(32) else {
+            return false;
+        };
+        let Some(nonce) = unhex(nonce) else {
+            return false;
+        };
+        // Comparing `blake3::Hash`es takes the same time however they differ.
+        blake3::Hash::from_hex(signature)
+            .is_ok_and(|signature| signature == blake3::keyed_hash(&self.0, &nonce))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Middleware refusing requests that change anything unless they repeat the
+/// token from their cookie in [`HEADER`], which another site's page cannot
+/// read to do. Responses carry a new token whenever the request had no valid
+/// one.
+pub async fn protect(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let (checked, issued) = check(&state.csrf, req.method(), req.headers());
+    let mut response = match checked {
+        Ok(()) => next.run(req).await,
+        Err(e) => e.into_response(),
+    };
+    if let Some(token) = issued {
+        let cookie = format!("{}={}; Path=/; SameSite=Strict", COOKIE, token);
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+/// Whether a request with `method` and `headers` may go ahead, and the token
+/// to issue with the response, if a new one is needed.
+fn check(
+    key: &CsrfKey,
+    method: &Method,
+    headers: &HeaderMap,
+) -> (Result<(), AppError>, Option<String>) {
+    let cookie = cookie(headers).filter(|token| key.verify(token));
+    let issued = cookie.is_none().then(|| key.issue());
+    if method.is_safe() {
+        return (Ok(()), issued);
+    }
+
+    let sent = headers.get(HEADER).and_then(|value| value.to_str().ok());
+    match (cookie, sent) {
+        (Some(cookie), Some(sent)) if cookie == sent => (Ok(()), issued),
+        _ => (
+            Err(AppError::Forbidden(
+                "missing or invalid CSRF token".to_string(),
+            )),
+            issued,
+        ),
+    }
+}
+
+/// The token in the request's [`COOKIE`], if it has one.
+fn cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == COOKIE).then_some(value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let key = CsrfKey::generate();
+        let token = key.issue();
+        assert!(key.verify(&token));
+        assert!(!CsrfKey::generate().verify(&token));
+
+        let mut forged = token.clone();
+        forged.replace_range(..2, if &token[..2] == "00" { "01" } else { "00" });
+        assert!(!key.verify(&forged));
+        assert!(!key.verify(""));
+        assert!(!key.verify(&token[..40]));
+    }
+
+    #[test]
+    fn test_check() {
+        let key = CsrfKey::generate();
+        let token = key.issue();
+        let headers = |cookie: Option<&str>, sent: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(cookie) = cookie {
+                let cookie = format!("theme=dark; {}={}", COOKIE, cookie);
+                headers.insert(header::COOKIE, cookie.parse().unwrap());
+            }
+            if let Some(sent) = sent {
+                headers.insert(HEADER, sent.parse().unwrap());
+            }
+            headers
+        };
+
+        // Reading needs no token, and gets one if it has none.
+        let (checked, issued) = check(&key, &Method::GET, &headers(None, None));
+        assert!(checked.is_ok());
+        assert!(key.verify(&issued.unwrap()));
+        let (checked, issued) = check(&key, &Method::GET, &headers(Some(&token), None));
+        assert!(checked.is_ok());
+        assert!(issued.is_none());
+
+        let (checked, issued) = check(&key, &Method::POST, &headers(Some(&token), Some(&token)));
+        assert!(checked.is_ok());
+        assert!(issued.is_none());
+        let (checked, _) = check(&key, &Method::PUT, &headers(Some(&token), None));
+        assert!(matches!(checked, Err(AppError::Forbidden(_))));
+        let (checked, _) = check(&key, &Method::POST, &headers(None, Some(&token)));
+        assert!(checked.is_err());
+        let other = key.issue();
+        let (checked, _) = check(&key, &Method::POST, &headers(Some(&token), Some(&other)));
+        assert!(checked.is_err());
+
+        // A token from before a restart is refused but replaced.
+        let stale = CsrfKey::generate().issue();
+        let (checked, issued) = check(&key, &Method::POST, &headers(Some(&stale), Some(&stale)));
+        assert!(checked.is_err());
+        assert!(key.verify(&issued.unwrap()));
+    }
+}
@@ -16,7 +16,7 @@ use strum::VariantArray;
 
 use crate::{
     LibrarySql, data, dto,
-    record::{Key, OfficePath, PersonPath, RecordRepo},
+    record::{Key, OfficePath, PersonPath, RecordRepo, RecordRepoError, validate_contact},
     serve::{AppError, AppState},
 };
 
@@ -36,7 +36,7 @@ pub async fn add(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<AddContactPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
     let mut contacts: HashSet<data::ContactType> = HashSet::new();
     conn.get_entity_contacts(&typ, &id, |row| {
         contacts.insert(row.get(0)?);
@@ -74,7 +74,7 @@ pub async fn edit(
     State(state): State<Arc<AppState>>,
     Path((typ, id, contact_type)): Path<(dto::EntityType, String, data::ContactType)>,
 ) -> Result<EditContactPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
     let mut value = String::new();
     conn.get_entity_contacts(&typ, &id, |row| {
         let ct: data::ContactType = row.get(0)?;
@@ -118,7 +118,7 @@ pub async fn view(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<ViewContactPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
 
     ViewContactPartial::new(&conn, typ, id)
 }
@@ -129,6 +129,28 @@ pub struct ContactEntry {
     pub value: String,
 }
 
+/// Save the contact in `entry` to the working copy, if it is a valid one.
+fn write(
+    conn: &Connection,
+    typ: &dto::EntityType,
+    id: &str,
+    entry: &ContactEntry,
+) -> Result<(), RecordRepoError> {
+    validate_contact(&entry.contact_type, &entry.value)?;
+    let repo = RecordRepo::new(conn);
+    let mut working = repo.working()?;
+    match typ {
+        dto::EntityType::Person => working.save(
+            Key::<PersonPath, ()>::new(id).contact(entry.contact_type.clone()),
+            &entry.value,
+        ),
+        dto::EntityType::Office => working.save(
+            Key::<OfficePath, ()>::new(id).contact(entry.contact_type.clone()),
+            &entry.value,
+        ),
+    }
+}
+
 #[axum::debug_handler]
 pub async fn save_add(
     State(state): State<Arc<AppState>>,
@@ -136,21 +158,7 @@ pub async fn save_add(
     Form(contact_form): Form<ContactEntry>,
 ) -> Result<Response, AppError> {
     let conn = state.get_conn()?;
-    let repo = RecordRepo::new(&conn);
-    let result = match typ {
-        dto::EntityType::Person => {
-            repo.working()?.save(
-                Key::<PersonPath, ()>::new(&id).contact(contact_form.contact_type.clone()),
-                &contact_form.value,
-            )
-        }
-        dto::EntityType::Office => {
-            repo.working()?.save(
-                Key::<OfficePath, ()>::new(&id).contact(contact_form.contact_type.clone()),
-                &contact_form.value,
-            )
-        }
-    };
+    let result = write(&conn, &typ, &id, &contact_form);
 
     match result {
         Ok(_) => {
@@ -192,21 +200,7 @@ pub async fn save_edit(
     Form(contact_form): Form<ContactEntry>,
 ) -> Result<Response, AppError> {
     let conn = state.get_conn()?;
-    let repo = RecordRepo::new(&conn);
-    let result = match typ {
-        dto::EntityType::Person => {
-            repo.working()?.save(
-                Key::<PersonPath, ()>::new(&id).contact(contact_form.contact_type.clone()),
-                &contact_form.value,
-            )
-        }
-        dto::EntityType::Office => {
-            repo.working()?.save(
-                Key::<OfficePath, ()>::new(&id).contact(contact_form.contact_type.clone()),
-                &contact_form.value,
-            )
-        }
-    };
+    let result = write(&conn, &typ, &id, &contact_form);
 
     match result {
         Ok(_) => {
@@ -14,7 +14,7 @@ use axum::{
 use serde::Deserialize;
 
 use crate::{
-    CONFIG,
+    LibrarySql,
     config::Config,
     context, dto,
     record::{Key, OfficePath, PersonPath, RecordRepo},
@@ -44,7 +44,7 @@ pub async fn new_form(
 ) -> Result<NewTemplate, AppError> {
     Ok(NewTemplate {
         typ,
-        config: &CONFIG,
+        config: crate::theme::config(),
         page: state.page_context(),
     })
 }
@@ -74,7 +74,12 @@ pub async fn new(
         }
     }
 
-    hx_redirect(&format!("/{}/{}/edit", typ, &form.id))
+    hx_redirect(&format!(
+        "{}{}/{}/edit",
+        state.page_context().base,
+        typ,
+        &form.id
+    ))
 }
 
 #[derive(Template, WebTemplate)]
@@ -97,7 +102,10 @@ pub async fn edit(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<EditTemplate, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
+    if !conn.exists_entity(&typ, &id, |row| row.get(0))? {
+        return Err(AppError::NotFound(format!("{}/{}", typ, id)));
+    }
     let name_partial = ViewNamePartial::new(&conn, typ, id.clone(), None)?;
     let photo_partial = ViewPhotoPartial::new(&conn, typ, id.clone())?;
     let contact_partial = ViewContactPartial::new(&conn, typ, id.clone())?;
@@ -112,7 +120,7 @@ pub async fn edit(
         contact_partial,
         tenure_partial,
         supervisor_partial,
-        config: &CONFIG,
+        config: crate::theme::config(),
         page: state.page_context(),
     })
 }
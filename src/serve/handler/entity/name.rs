@@ -7,7 +7,7 @@ use axum::{
     extract::{Path, State},
     response::IntoResponse,
 };
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 use serde::Deserialize;
 
 use crate::LibrarySql;
@@ -28,11 +28,14 @@ pub async fn edit(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<EditNamePartial, AppError> {
-    let conn = state.get_conn()?;
-    let name = conn.get_entity_name(&typ, &id, |row| {
-        let name: String = row.get(0)?;
-        Ok(name)
-    })?;
+    let conn = state.get_read_conn()?;
+    let name = conn
+        .get_entity_name(&typ, &id, |row| {
+            let name: String = row.get(0)?;
+            Ok(name)
+        })
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("{}/{}", typ, id)))?;
     Ok(EditNamePartial { id, typ, name })
 }
 
@@ -89,7 +92,7 @@ pub async fn view(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<ViewNamePartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
 
     ViewNamePartial::new(&conn, typ, id, None)
 }
@@ -163,5 +166,5 @@ pub async fn delete_handler(
         }
     }
 
-    crate::serve::hx_redirect("/")
+    crate::serve::hx_redirect(&state.page_context().base)
 }
@@ -23,6 +23,8 @@ pub struct EditPhotoPartial {
     id: String,
     url: String,
     attribution: String,
+    license: String,
+    source_url: String,
 }
 
 #[axum::debug_handler]
@@ -30,26 +32,31 @@ pub async fn edit(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<EditPhotoPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
     let photo = conn
         .get_entity_photo(&typ, &id, |row| {
             Ok(data::Photo {
                 url: row.get(0)?,
                 attribution: row.get(1)?,
+                license: row.get(2)?,
+                source_url: row.get(3)?,
             })
         })
         .optional()?;
-    let (url, attribution) = if let Some(photo) = photo {
-        (photo.url, photo.attribution.unwrap_or_default())
-    } else {
-        (String::new(), String::new())
-    };
+    let photo = photo.unwrap_or(data::Photo {
+        url: String::new(),
+        attribution: None,
+        license: None,
+        source_url: None,
+    });
 
     Ok(EditPhotoPartial {
         typ,
         id,
-        url,
-        attribution,
+        url: photo.url,
+        attribution: photo.attribution.unwrap_or_default(),
+        license: photo.license.unwrap_or_default(),
+        source_url: photo.source_url.unwrap_or_default(),
     })
 }
 
@@ -68,6 +75,8 @@ impl ViewPhotoPartial {
                 Ok(data::Photo {
                     url: row.get(0)?,
                     attribution: row.get(1)?,
+                    license: row.get(2)?,
+                    source_url: row.get(3)?,
                 })
             })
             .optional()?;
@@ -80,7 +89,7 @@ pub async fn view(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
 ) -> Result<ViewPhotoPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
 
     ViewPhotoPartial::new(&conn, typ, id)
 }
@@ -89,8 +98,16 @@ pub async fn view(
 pub async fn save(
     State(state): State<Arc<AppState>>,
     Path((typ, id)): Path<(dto::EntityType, String)>,
-    Form(photo_form): Form<data::Photo>, // Renamed to avoid conflict with `photo` variable below
+    Form(mut photo_form): Form<data::Photo>, // Renamed to avoid conflict with `photo` variable below
 ) -> Result<Response, AppError> {
+    // A field left empty in the form is one that is not known.
+    for field in [
+        &mut photo_form.attribution,
+        &mut photo_form.license,
+        &mut photo_form.source_url,
+    ] {
+        field.take_if(|value| value.is_empty());
+    }
     let conn = state.get_conn()?;
     let repo = RecordRepo::new(&conn);
     match typ {
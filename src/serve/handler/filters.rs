@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+
+use crate::data::ContactType;
+
 pub fn thumbnail<T: std::fmt::Display>(s: T, _: &dyn askama::Values) -> askama::Result<String> {
     const WIKIMEDIA_PREFIX: &str = "https://upload.wikimedia.org/wikipedia/commons/";
     let s = s.to_string();
@@ -17,3 +21,61 @@ pub fn thumbnail<T: std::fmt::Display>(s: T, _: &dyn askama::Values) -> askama::
 
     Ok(thumbnail)
 }
+
+/// The contacts in the display order the config gives, rather than in the
+/// order their types are declared in.
+pub fn contact_order<'a>(
+    contacts: &'a BTreeMap<ContactType, String>,
+    _: &dyn askama::Values,
+) -> askama::Result<Vec<(&'a ContactType, &'a String)>> {
+    Ok(in_order(contacts, &crate::theme::contact_order()))
+}
+
+/// The contacts of the types in `order` first, as they come in it, followed by
+/// the rest in declaration order.
+fn in_order<'a>(
+    contacts: &'a BTreeMap<ContactType, String>,
+    order: &[ContactType],
+) -> Vec<(&'a ContactType, &'a String)> {
+    let mut sorted: Vec<_> = contacts.iter().collect();
+    sorted.sort_by_key(|(typ, _)| order.iter().position(|o| o == *typ).unwrap_or(order.len()));
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_order() {
+        let contacts: BTreeMap<ContactType, String> = [
+            (ContactType::Address, "1 Road".to_string()),
+            (ContactType::Phone, "123".to_string()),
+            (ContactType::Website, "https://example.com".to_string()),
+            (ContactType::Wikidata, "Q1".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        let types = |order: &[ContactType]| -> Vec<ContactType> {
+            in_order(&contacts, order)
+                .into_iter()
+                .map(|(typ, _)| typ.clone())
+                .collect()
+        };
+        assert_eq!(
+            types(&[
+                ContactType::Website,
+                ContactType::Email,
+                ContactType::Address
+            ]),
+            [
+                ContactType::Website,
+                ContactType::Address,
+                ContactType::Phone,
+                ContactType::Wikidata
+            ]
+        );
+        assert_eq!(types(&[]), contacts.keys().cloned().collect::<Vec<_>>());
+    }
+}
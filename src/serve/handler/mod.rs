@@ -1,12 +1,18 @@
 use std::collections::HashMap;
+use std::io::Write;
 use std::sync::Arc;
 
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::extract::State;
+use axum::Form;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use flate2::{Compression, write::GzEncoder};
 use rusqlite::Connection;
+use serde::Deserialize;
 
-use crate::CONFIG;
 use crate::LibrarySql;
 use crate::SchemaSql;
 use crate::config::Config;
@@ -15,42 +21,122 @@ use crate::dto;
 use crate::record::RecordDiff;
 use crate::record::RecordKey;
 use crate::record::RecordRepo;
+use crate::record::default_author;
+use crate::repo::{Hash, RepoRefType};
 use crate::{
     context::{self},
-    serve::{AppError, AppState},
+    serve::{AppError, AppState, SearchIndex},
 };
 
 pub mod entity;
 pub mod filters;
 pub mod office;
 pub mod person;
+pub mod raw;
+
+/// How an entity page is to be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Representation {
+    Html,
+    Json,
+}
+
+/// Split the `{id}` path segment of an entity page into the ID and the
+/// representation asked for. A `.json` or `.html` suffix decides; otherwise
+/// JSON is served if the `Accept` header lists `application/json` ahead of
+/// `text/html`.
+pub fn negotiate<'a>(id_with_ext: &'a str, headers: &HeaderMap) -> (&'a str, Representation) {
+    if let Some(id) = id_with_ext.strip_suffix(".json") {
+        return (id, Representation::Json);
+    }
+    if let Some(id) = id_with_ext.strip_suffix(".html") {
+        return (id, Representation::Html);
+    }
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let position = |media_type: &str| {
+        accept
+            .split(',')
+            .position(|item| item.split(';').next().unwrap_or_default().trim() == media_type)
+    };
+    let representation = match (position("application/json"), position("text/html")) {
+        (Some(json), Some(html)) if json < html => Representation::Json,
+        (Some(_), None) => Representation::Json,
+        _ => Representation::Html,
+    };
+
+    (id_with_ext, representation)
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "not_found.html")]
+pub struct NotFoundTemplate {
+    pub typ: dto::EntityType,
+    pub id: String,
+    pub config: &'static Config,
+    pub page: context::Page,
+}
+
+/// The page served with a 404 when there is no entity of `typ` with `id`.
+pub fn not_found(typ: dto::EntityType, id: &str, page: context::Page) -> Response {
+    let template = NotFoundTemplate {
+        typ,
+        id: id.to_string(),
+        config: crate::theme::config(),
+        page,
+    };
+
+    (StatusCode::NOT_FOUND, template).into_response()
+}
+
+/// Serialize a hash as hex, rather than as the array of its bytes.
+pub fn serialize_hex<S: serde::Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash.to_hex())
+}
 
 #[derive(Template, WebTemplate)]
 #[template(path = "index.html")]
 pub struct IndexTemplate {
     pub persons: u32,
     pub offices: u32,
+    pub include_abolished: bool,
+    pub search_index: SearchIndex,
     pub config: &'static Config,
     pub page: context::Page,
     pub metadata: context::Metadata,
 }
 
-pub async fn index(State(state): State<Arc<AppState>>) -> Result<IndexTemplate, AppError> {
-    let conn = state.get_conn()?;
+#[derive(Deserialize, Default)]
+pub struct IndexParams {
+    /// Count abolished offices too.
+    #[serde(default)]
+    pub abolished: bool,
+}
+
+pub async fn index(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<IndexParams>,
+) -> Result<IndexTemplate, AppError> {
+    let conn = state.get_read_conn()?;
     let repo = RecordRepo::new(&conn);
 
-    let (persons, offices) = conn.get_entity_counts(|row| {
+    let (persons, offices) = conn.get_entity_counts(params.abolished, |row| {
         let persons: u32 = row.get(0)?;
         let offices: u32 = row.get(1)?;
 
         Ok((persons, offices))
     })?;
-    let commit_id = repo.working()?.commit_id()?;
+    let commit_id = repo.root_hash(RepoRefType::Working)?;
 
     Ok(IndexTemplate {
         persons,
         offices,
-        config: &CONFIG,
+        include_abolished: params.abolished,
+        search_index: state.search_index,
+        config: crate::theme::config(),
         page: state.page_context(),
         metadata: Metadata {
             commit_id,
@@ -62,75 +148,101 @@ pub async fn index(State(state): State<Arc<AppState>>) -> Result<IndexTemplate,
 #[derive(Debug, Clone)]
 pub struct EntityChange {
     pub entity: dto::Entity,
+    pub added: bool,
     pub removed: bool,
     pub diffs: Vec<RecordDiff>,
 }
 
-#[derive(Template, WebTemplate)]
-#[template(path = "uncommitted.html")]
-pub struct UncommittedTemplate {
-    pub changes: Vec<EntityChange>,
-    pub config: &'static Config,
-    pub page: context::Page,
-}
-
-#[axum::debug_handler]
-pub async fn uncommitted(
-    State(state): State<Arc<AppState>>,
-) -> Result<UncommittedTemplate, AppError> {
-    let conn = state.get_conn()?;
-    let repo = RecordRepo::new(&conn);
-    let mut changes = Vec::new();
-
-    let mut entity_changes: HashMap<(dto::EntityType, String), (bool, Vec<RecordDiff>)> =
+/// Group record diffs by the entity they belong to, sorted by entity name.
+///
+/// An entity counts as added or removed when its name record is.
+pub fn group_changes(
+    conn: &Connection,
+    diffs: impl Iterator<Item = RecordDiff>,
+) -> Vec<EntityChange> {
+    let mut entity_changes: HashMap<(dto::EntityType, String), (bool, bool, Vec<RecordDiff>)> =
         HashMap::new();
-    if let Ok(diff_iter) = repo.iterate_diff() {
-        for diff_result in diff_iter {
-            if let Ok(diff) = diff_result {
-                let (info, removed) = match &diff {
-                    RecordDiff::Added(rk, _) => (rk.entity_info(), false),
-                    RecordDiff::Changed(rk, _, _) => (rk.entity_info(), false),
-                    RecordDiff::Removed(rk, _) => {
-                        (rk.entity_info(), matches!(rk, RecordKey::Name(_)))
-                    }
-                };
-
-                let (_, current_diffs) =
-                    entity_changes.entry(info).or_insert((removed, Vec::new()));
-                current_diffs.push(diff);
-            }
-        }
+    for diff in diffs {
+        let (info, added, removed) = match &diff {
+            RecordDiff::Added(rk, _) => (rk.entity_info(), matches!(rk, RecordKey::Name(_)), false),
+            RecordDiff::Changed(rk, _, _) => (rk.entity_info(), false, false),
+            RecordDiff::Removed(rk, _) => (rk.entity_info(), false, matches!(rk, RecordKey::Name(_))),
+        };
+
+        let entry = entity_changes.entry(info).or_insert((false, false, Vec::new()));
+        entry.0 |= added;
+        entry.1 |= removed;
+        entry.2.push(diff);
     }
 
-    for ((typ, id), (removed, diffs)) in entity_changes {
+    let mut changes = Vec::new();
+    for ((typ, id), (added, removed, diffs)) in entity_changes {
         let name = conn
             .get_entity_name(&typ, &id, |row| row.get(0))
             .unwrap_or_else(|_| id.clone());
         changes.push(EntityChange {
             entity: dto::Entity { typ, id, name },
+            added,
             removed,
             diffs,
         });
     }
 
     changes.sort_by(|a, b| a.entity.name.cmp(&b.entity.name));
+    changes
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "uncommitted.html")]
+pub struct UncommittedTemplate {
+    pub changes: Vec<EntityChange>,
+    pub config: &'static Config,
+    pub page: context::Page,
+}
+
+#[axum::debug_handler]
+pub async fn uncommitted(
+    State(state): State<Arc<AppState>>,
+) -> Result<UncommittedTemplate, AppError> {
+    let conn = state.get_read_conn()?;
+    let repo = RecordRepo::new(&conn);
+    let changes = match repo.iterate_diff() {
+        Ok(diff_iter) => group_changes(&conn, diff_iter.flatten()),
+        Err(_) => Vec::new(),
+    };
 
     Ok(UncommittedTemplate {
         changes,
-        config: &CONFIG,
+        config: crate::theme::config(),
         page: state.page_context(),
     })
 }
 
+#[derive(Deserialize)]
+pub struct CommitForm {
+    pub message: Option<String>,
+}
+
 #[axum::debug_handler]
 pub async fn commit(
     State(state): State<Arc<AppState>>,
+    Form(form): Form<CommitForm>,
 ) -> Result<axum::response::Response, AppError> {
     let conn = state.get_conn()?;
     let mut repo = RecordRepo::new(&conn);
-    repo.commit()?;
+    let message = form.message.filter(|message| !message.trim().is_empty());
+    let previous = repo.committed()?.commit_id()?;
+    repo.commit(default_author().as_deref(), message.as_deref())?;
+    state.metrics.record_commit();
 
-    crate::serve::hx_redirect("/")
+    if let Some(url) = &state.webhook {
+        match crate::webhook::summarize(&conn, &previous) {
+            Ok(payload) => crate::webhook::spawn(url.clone(), payload),
+            Err(e) => eprintln!("could not summarize commit for webhook: {:#}", e),
+        }
+    }
+
+    crate::serve::hx_redirect(&state.page_context().base)
 }
 
 #[axum::debug_handler]
@@ -140,13 +252,51 @@ pub async fn abandon(
     let mut conn = state.get_conn()?;
     crate::record::abandon_changes(&mut conn)?;
 
-    crate::serve::hx_redirect("/")
+    crate::serve::hx_redirect(&state.page_context().base)
+}
+
+/// Whether the `Accept-Encoding` header allows a gzip response.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let Some(value) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+
+    value.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|p| {
+            p.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+/// Whether the `If-None-Match` header lists `etag`.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
 }
 
 #[axum::debug_handler]
-pub async fn search_db(State(state): State<Arc<AppState>>) -> Result<Vec<u8>, AppError> {
+pub async fn search_db(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     let conn = Connection::open_in_memory()?;
     conn.create_entity_tables()?;
+    crate::ingest::create_entity_index(&conn, crate::theme::config().search_tokenizer)?;
     let db_path_str = state
         .db
         .to_str()
@@ -156,5 +306,337 @@ pub async fn search_db(State(state): State<Arc<AppState>>) -> Result<Vec<u8>, Ap
     conn.detach_db()?;
     let db_bytes = conn.serialize("main")?;
 
-    Ok(db_bytes.to_vec())
+    let etag = format!("\"{}\"", blake3::hash(&db_bytes).to_hex());
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ETAG, HeaderValue::from_str(&etag)?);
+    response_headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    let body = if accepts_gzip(&headers) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&db_bytes)?;
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        encoder.finish()?
+    } else {
+        db_bytes.to_vec()
+    };
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/vnd.sqlite3"),
+    );
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+
+    Ok((response_headers, body).into_response())
+}
+
+/// The most results a search returns, whatever `limit` asks for.
+const MAX_SEARCH_LIMIT: u32 = 50;
+
+/// How the query of a search is matched against entity IDs and names.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Every word must match a whole word.
+    #[default]
+    Match,
+    /// The last word may be the start of a longer one, for type-ahead boxes.
+    Prefix,
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    pub q: String,
+    #[serde(rename = "type")]
+    pub typ: Option<dto::EntityType>,
+    /// Only offices in this jurisdiction.
+    pub jurisdiction: Option<String>,
+    #[serde(default = "default_search_limit")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+    #[serde(default)]
+    pub mode: SearchMode,
+}
+
+fn default_search_limit() -> u32 {
+    10
+}
+
+/// The entities matching `params`, best first. At most [`MAX_SEARCH_LIMIT`]
+/// are returned.
+pub fn search_entities(
+    conn: &Connection,
+    params: &SearchParams,
+) -> Result<Vec<dto::Entity>, rusqlite::Error> {
+    let mut entities = Vec::new();
+    if params.q.trim().is_empty() {
+        return Ok(entities);
+    }
+
+    let query = crate::ingest::escape_for_fts(params.q.trim(), params.mode == SearchMode::Prefix);
+    conn.search_entity(
+        params.typ.as_ref(),
+        params.jurisdiction.as_deref(),
+        &query,
+        params.limit.min(MAX_SEARCH_LIMIT),
+        params.offset,
+        |row| {
+            entities.push(dto::Entity {
+                typ: row.get(0)?,
+                id: row.get(1)?,
+                name: row.get(2)?,
+            });
+            Ok(())
+        },
+    )?;
+
+    Ok(entities)
+}
+
+#[axum::debug_handler]
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<dto::Entity>>, AppError> {
+    let conn = state.get_read_conn()?;
+
+    Ok(Json(search_entities(&conn, &params)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let none = HeaderMap::new();
+        assert_eq!(negotiate("alice", &none), ("alice", Representation::Html));
+        assert_eq!(negotiate("alice.html", &none), ("alice", Representation::Html));
+        assert_eq!(negotiate("alice.json", &none), ("alice", Representation::Json));
+
+        let json = accept("application/json");
+        assert_eq!(negotiate("alice", &json), ("alice", Representation::Json));
+        assert_eq!(negotiate("alice.html", &json), ("alice", Representation::Html));
+
+        let browser = accept("text/html,application/xhtml+xml,*/*;q=0.8");
+        assert_eq!(negotiate("alice", &browser), ("alice", Representation::Html));
+        let prefers_json = accept("application/json;q=0.9, text/html;q=0.5");
+        assert_eq!(negotiate("alice", &prefers_json), ("alice", Representation::Json));
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let encoding = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+            headers
+        };
+        assert!(!accepts_gzip(&HeaderMap::new()));
+        assert!(accepts_gzip(&encoding("gzip, deflate, br")));
+        assert!(accepts_gzip(&encoding("br;q=1.0, gzip;q=0.8")));
+        assert!(accepts_gzip(&encoding("*")));
+        assert!(!accepts_gzip(&encoding("gzip;q=0, br")));
+        assert!(!accepts_gzip(&encoding("identity")));
+    }
+
+    #[test]
+    fn test_search_entities() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO entity (type, id, name, search_name) VALUES ('person', 'narendra', 'Narendra Modi', 'Narendra Modi');
+            INSERT INTO entity (type, id, name, search_name) VALUES ('person', 'nara', 'Nara Lokesh', 'Nara Lokesh');
+            INSERT INTO entity (type, id, name, search_name) VALUES ('office', 'pm', 'Prime Minister', 'Prime Minister');
+            "#,
+        )
+        .unwrap();
+
+        let search = |q: &str, mode: SearchMode, limit: u32, offset: u32| -> Vec<String> {
+            let params = SearchParams {
+                q: q.to_string(),
+                typ: None,
+                jurisdiction: None,
+                limit,
+                offset,
+                mode,
+            };
+            let mut ids: Vec<String> = search_entities(&conn, &params)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert!(search("nar", SearchMode::Match, 10, 0).is_empty());
+        assert_eq!(search("nar", SearchMode::Prefix, 10, 0), ["nara", "narendra"]);
+        assert_eq!(search("narendra mo", SearchMode::Prefix, 10, 0), ["narendra"]);
+        // Quotes are taken literally rather than as FTS syntax.
+        assert_eq!(search("\"prime", SearchMode::Prefix, 10, 0), ["pm"]);
+        assert_eq!(search("prime", SearchMode::Match, 10, 0), ["pm"]);
+        assert_eq!(search("nar", SearchMode::Prefix, 1, 0).len(), 1);
+        assert_eq!(search("nar", SearchMode::Prefix, 10, 1).len(), 1);
+        assert!(search("  ", SearchMode::Prefix, 10, 0).is_empty());
+
+        let params = SearchParams {
+            q: "nar".to_string(),
+            typ: Some(dto::EntityType::Office),
+            jurisdiction: None,
+            limit: 10,
+            offset: 0,
+            mode: SearchMode::Prefix,
+        };
+        assert!(search_entities(&conn, &params).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_entities_normalized() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        crate::ingest::create_entity_index(&conn, crate::CONFIG.search_tokenizer).unwrap();
+        let repo = crate::record::RecordRepo::new(&conn);
+        repo.init().unwrap();
+        for (id, name) in [
+            ("trong", "Nguyễn Phú Trọng"),
+            ("modi", "नरेन्द्र मोदी"),
+            ("wide", "Ｒａｊｅｓｈ Kumar"),
+        ] {
+            repo.working()
+                .unwrap()
+                .save(
+                    crate::record::Key::<crate::record::PersonPath, ()>::new(id).name(),
+                    &name.to_string(),
+                )
+                .unwrap();
+        }
+
+        let search = |q: &str, mode: SearchMode| -> Vec<String> {
+            let params = SearchParams {
+                q: q.to_string(),
+                typ: None,
+                jurisdiction: None,
+                limit: 10,
+                offset: 0,
+                mode,
+            };
+            search_entities(&conn, &params)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        // Stacked diacritics fold away.
+        assert_eq!(search("nguyen", SearchMode::Match), ["trong"]);
+        assert_eq!(search("Nguyễn", SearchMode::Match), ["trong"]);
+        // Vowel signs stay part of the word instead of splitting it.
+        assert_eq!(search("मोदी", SearchMode::Match), ["modi"]);
+        assert_eq!(search("नरेन्", SearchMode::Prefix), ["modi"]);
+        assert!(search("मादा", SearchMode::Match).is_empty());
+        // Full-width letters are indexed and searched as plain ones.
+        assert_eq!(search("rajesh", SearchMode::Match), ["wide"]);
+        assert_eq!(search("ｋｕｍａｒ", SearchMode::Match), ["wide"]);
+        // But shown as written.
+        let params = SearchParams {
+            q: "rajesh".to_string(),
+            typ: None,
+            jurisdiction: None,
+            limit: 10,
+            offset: 0,
+            mode: SearchMode::Match,
+        };
+        let found = search_entities(&conn, &params).unwrap();
+        assert_eq!(found[0].name, "Ｒａｊｅｓｈ Kumar");
+    }
+
+    #[test]
+    fn test_search_entities_with_honorific() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO entity (type, id, name, search_name) VALUES ('person', 'ambedkar', 'B. R. Ambedkar', 'B. R. Ambedkar');
+            INSERT INTO person_honorific (person_id, honorific) VALUES ('ambedkar', 'Dr.');
+            "#,
+        )
+        .unwrap();
+
+        let search = |q: &str| -> Vec<String> {
+            let params = SearchParams {
+                q: q.to_string(),
+                typ: None,
+                jurisdiction: None,
+                limit: 10,
+                offset: 0,
+                mode: SearchMode::Match,
+            };
+            search_entities(&conn, &params)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect()
+        };
+        assert_eq!(search("ambedkar"), ["Dr. B. R. Ambedkar"]);
+        // Only the bare name is in the full-text index.
+        assert!(search("dr").is_empty());
+    }
+
+    #[test]
+    fn test_search_entities_in_jurisdiction() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO entity (type, id, name, search_name) VALUES ('office', 'cm-kerala', 'Chief Minister', 'Chief Minister');
+            INSERT INTO entity (type, id, name, search_name) VALUES ('office', 'cm-goa', 'Chief Minister', 'Chief Minister');
+            INSERT INTO entity (type, id, name, search_name) VALUES ('person', 'chief', 'Chief Justice', 'Chief Justice');
+            INSERT INTO office_jurisdiction (office_id, jurisdiction) VALUES ('cm-kerala', 'Kerala');
+            INSERT INTO office_jurisdiction (office_id, jurisdiction) VALUES ('cm-goa', 'Goa');
+            "#,
+        )
+        .unwrap();
+
+        let search = |jurisdiction: Option<&str>| -> Vec<String> {
+            let params = SearchParams {
+                q: "chief".to_string(),
+                typ: None,
+                jurisdiction: jurisdiction.map(str::to_string),
+                limit: 10,
+                offset: 0,
+                mode: SearchMode::Match,
+            };
+            let mut ids: Vec<String> = search_entities(&conn, &params)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(search(None), ["chief", "cm-goa", "cm-kerala"]);
+        assert_eq!(search(Some("Kerala")), ["cm-kerala"]);
+        assert!(search(Some("Punjab")).is_empty());
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let mut headers = HeaderMap::new();
+        assert!(!etag_matches(&headers, "\"abc\""));
+        headers.insert(header::IF_NONE_MATCH, "\"xyz\", W/\"abc\"".parse().unwrap());
+        assert!(etag_matches(&headers, "\"abc\""));
+        assert!(!etag_matches(&headers, "\"def\""));
+    }
 }
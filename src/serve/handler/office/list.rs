@@ -0,0 +1,341 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Query, State};
+use rusqlite::Connection;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::Config;
+use crate::serve::{AppError, AppState};
+use crate::{LibrarySql, context, data};
+
+/// How many offices a page lists when the request does not say.
+const DEFAULT_PAGE_SIZE: u32 = 50;
+/// The most offices a page lists, whatever the request asks for.
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OfficesParams {
+    /// Only offices in this jurisdiction.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub jurisdiction: Option<String>,
+    /// Only offices with a supervisor in this relation.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub relation: Option<data::SupervisingRelation>,
+    /// List the offices after this ID.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub after: Option<String>,
+    #[serde(default = "default_page_size")]
+    pub limit: u32,
+}
+
+fn default_page_size() -> u32 {
+    DEFAULT_PAGE_SIZE
+}
+
+impl Default for OfficesParams {
+    fn default() -> Self {
+        Self {
+            jurisdiction: None,
+            relation: None,
+            after: None,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+/// Take an empty parameter, as a form sends for one left unset, to be absent.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "offices.html")]
+pub struct OfficesTemplate {
+    pub offices: Vec<context::Office>,
+    /// Each jurisdiction with the number of offices in it that match the
+    /// relation filter.
+    pub jurisdictions: Vec<(String, u32)>,
+    /// Each relation with the number of offices having a supervisor in it
+    /// that match the jurisdiction filter.
+    pub relations: Vec<(data::SupervisingRelation, u32)>,
+    pub jurisdiction: Option<String>,
+    pub relation: Option<data::SupervisingRelation>,
+    /// The cursor for the next page, if there is one.
+    pub next: Option<String>,
+    pub limit: u32,
+
+    pub config: &'static Config,
+    pub page: context::Page,
+}
+
+impl OfficesTemplate {
+    /// A link to the listing with the given filters, starting after the
+    /// office `after` when given.
+    pub fn href(
+        &self,
+        jurisdiction: Option<&str>,
+        relation: Option<&data::SupervisingRelation>,
+        after: Option<&str>,
+    ) -> String {
+        let mut query = Vec::new();
+        if let Some(jurisdiction) = jurisdiction {
+            query.push(format!(
+                "jurisdiction={}",
+                urlencoding::encode(jurisdiction)
+            ));
+        }
+        if let Some(relation) = relation {
+            query.push(format!("relation={}", relation.as_str()));
+        }
+        if let Some(after) = after {
+            query.push(format!("after={}", urlencoding::encode(after)));
+        }
+        if self.limit != DEFAULT_PAGE_SIZE {
+            query.push(format!("limit={}", self.limit));
+        }
+
+        if query.is_empty() {
+            format!("{}offices", self.page.base)
+        } else {
+            format!("{}offices?{}", self.page.base, query.join("&"))
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn page(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<OfficesParams>,
+) -> Result<OfficesTemplate, AppError> {
+    let conn = state.get_read_conn()?;
+
+    load_page(&conn, &params, state.page_context())
+}
+
+/// Assemble a page of the office listing from the index tables, independent
+/// of the server.
+pub fn load_page(
+    conn: &Connection,
+    params: &OfficesParams,
+    page: context::Page,
+) -> Result<OfficesTemplate, AppError> {
+    let limit = params.limit.clamp(1, MAX_PAGE_SIZE);
+
+    // One more than shown, to tell whether there is a next page.
+    let mut offices = Vec::new();
+    conn.list_offices(
+        params.jurisdiction.as_deref(),
+        params.relation.as_ref(),
+        params.after.as_deref(),
+        limit + 1,
+        |row| {
+            offices.push(context::Office {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            });
+            Ok(())
+        },
+    )?;
+    let next = if offices.len() > limit as usize {
+        offices.truncate(limit as usize);
+        offices.last().map(|office| office.id.clone())
+    } else {
+        None
+    };
+
+    let mut jurisdictions = Vec::new();
+    conn.get_office_jurisdiction_counts(params.relation.as_ref(), |row| {
+        jurisdictions.push((row.get(0)?, row.get(1)?));
+        Ok(())
+    })?;
+
+    let mut relations = Vec::new();
+    conn.get_office_relation_counts(params.jurisdiction.as_deref(), |row| {
+        relations.push((row.get(0)?, row.get(1)?));
+        Ok(())
+    })?;
+
+    Ok(OfficesTemplate {
+        offices,
+        jurisdictions,
+        relations,
+        jurisdiction: params.jurisdiction.clone(),
+        relation: params.relation.clone(),
+        next,
+        limit,
+        config: crate::theme::config(),
+        page,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaSql;
+    use crate::record::{Key, OfficePath, RecordRepo};
+    use data::SupervisingRelation::*;
+
+    fn seed(conn: &Connection) {
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+
+        for (id, jurisdiction) in [
+            ("cm-goa", Some("Goa")),
+            ("cm-kerala", Some("Kerala")),
+            ("governor-kerala", Some("Kerala")),
+            ("minister-kerala", Some("Kerala")),
+            ("pm", None),
+        ] {
+            let office = Key::<OfficePath, ()>::new(id);
+            working.save(office.name(), &id.to_string()).unwrap();
+            if let Some(jurisdiction) = jurisdiction {
+                working
+                    .save(office.jurisdiction(), &jurisdiction.to_string())
+                    .unwrap();
+            }
+        }
+        for (id, relation, supervisor) in [
+            ("cm-goa", DuringThePleasureOf, "governor-goa"),
+            ("cm-kerala", DuringThePleasureOf, "governor-kerala"),
+            ("minister-kerala", ResponsibleTo, "cm-kerala"),
+            ("minister-kerala", DuringThePleasureOf, "governor-kerala"),
+        ] {
+            let office = Key::<OfficePath, ()>::new(id);
+            working
+                .save(
+                    office.supervisor(relation, supervisor),
+                    &supervisor.to_string(),
+                )
+                .unwrap();
+        }
+    }
+
+    fn page() -> context::Page {
+        context::Page {
+            base: "/".to_string(),
+            dynamic: true,
+        }
+    }
+
+    fn ids(template: &OfficesTemplate) -> Vec<&str> {
+        template.offices.iter().map(|o| o.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_load_page_filters_and_counts() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+
+        let all = load_page(&conn, &OfficesParams::default(), page()).unwrap();
+        assert_eq!(
+            ids(&all),
+            [
+                "cm-goa",
+                "cm-kerala",
+                "governor-kerala",
+                "minister-kerala",
+                "pm"
+            ]
+        );
+        assert_eq!(
+            all.jurisdictions,
+            [("Goa".to_string(), 1), ("Kerala".to_string(), 3)]
+        );
+        assert_eq!(
+            all.relations,
+            [(DuringThePleasureOf, 3), (ResponsibleTo, 1)]
+        );
+
+        let params = OfficesParams {
+            jurisdiction: Some("Kerala".to_string()),
+            relation: Some(DuringThePleasureOf),
+            ..OfficesParams::default()
+        };
+        let filtered = load_page(&conn, &params, page()).unwrap();
+        assert_eq!(ids(&filtered), ["cm-kerala", "minister-kerala"]);
+        // Each facet counts what the other one leaves.
+        assert_eq!(
+            filtered.jurisdictions,
+            [("Goa".to_string(), 1), ("Kerala".to_string(), 2)]
+        );
+        assert_eq!(
+            filtered.relations,
+            [(DuringThePleasureOf, 2), (ResponsibleTo, 1)]
+        );
+        assert_eq!(
+            filtered.href(Some("Kerala"), None, None),
+            "/offices?jurisdiction=Kerala"
+        );
+
+        let html = filtered.render().unwrap();
+        assert!(html.contains("<b>Kerala</b> (2)"));
+        assert!(html.contains("href=\"/offices?jurisdiction=Kerala\""));
+    }
+
+    #[test]
+    fn test_load_page_pagination() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+
+        let params = OfficesParams {
+            limit: 2,
+            ..OfficesParams::default()
+        };
+        let first = load_page(&conn, &params, page()).unwrap();
+        assert_eq!(ids(&first), ["cm-goa", "cm-kerala"]);
+        assert_eq!(first.next.as_deref(), Some("cm-kerala"));
+        assert_eq!(
+            first.href(None, None, first.next.as_deref()),
+            "/offices?after=cm-kerala&limit=2"
+        );
+
+        let params = OfficesParams {
+            after: first.next.clone(),
+            ..params
+        };
+        let second = load_page(&conn, &params, page()).unwrap();
+        assert_eq!(ids(&second), ["governor-kerala", "minister-kerala"]);
+
+        let params = OfficesParams {
+            after: second.next.clone(),
+            ..params
+        };
+        let last = load_page(&conn, &params, page()).unwrap();
+        assert_eq!(ids(&last), ["pm"]);
+        assert_eq!(last.next, None);
+    }
+
+    #[test]
+    fn test_params_take_empty_as_unset() {
+        let parse = |query: &str| {
+            let uri: axum::http::Uri = format!("/offices?{}", query).parse().unwrap();
+            Query::<OfficesParams>::try_from_uri(&uri).map(|Query(params)| params)
+        };
+
+        let params = parse("jurisdiction=&relation=&after=").unwrap();
+        assert_eq!(params.jurisdiction, None);
+        assert_eq!(params.relation, None);
+        assert_eq!(params.after, None);
+        assert_eq!(params.limit, DEFAULT_PAGE_SIZE);
+
+        let params = parse("jurisdiction=Tamil%20Nadu&relation=member_of").unwrap();
+        assert_eq!(params.jurisdiction.as_deref(), Some("Tamil Nadu"));
+        assert_eq!(params.relation, Some(MemberOf));
+        assert!(parse("relation=boss").is_err());
+    }
+}
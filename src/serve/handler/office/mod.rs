@@ -1,18 +1,27 @@
 use crate::config::Config;
 use crate::record::RecordRepo;
+use crate::repo::{Hash, RepoRefType};
 use crate::serve::handler::filters;
-use crate::{CONFIG, LibrarySql};
+use crate::LibrarySql;
 use crate::{
     context, data, dto,
     serve::{AppError, AppState},
 };
 use anyhow::Context;
+use chrono::NaiveDate;
 use askama::Template;
 use askama_web::WebTemplate;
+use axum::Json;
 use axum::extract::State;
-use rusqlite::OptionalExtension;
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
 use std::{collections::BTreeMap, sync::Arc};
 
+use super::{Representation, negotiate, not_found, serialize_hex};
+
+pub mod list;
 pub mod supervisor;
 
 #[derive(Template, WebTemplate)]
@@ -22,8 +31,13 @@ pub struct OfficePageTemplate {
     pub photo: Option<data::Photo>,
     pub contacts: Option<BTreeMap<data::ContactType, String>>,
     pub incumbent: Option<context::Person>,
+    pub preceded_by: Option<context::Person>,
     pub quondams: Option<Vec<context::Quondam>>,
-    pub supervisors: Option<BTreeMap<data::SupervisingRelation, context::Office>>,
+    pub succession: Option<Vec<context::Succession>>,
+    pub supervisors: Option<BTreeMap<data::SupervisingRelation, Vec<context::Office>>>,
+    pub establishment: Option<NaiveDate>,
+    pub abolition: Option<NaiveDate>,
+    pub jurisdiction: Option<String>,
 
     pub sources: Option<Vec<String>>,
     pub config: &'static Config,
@@ -31,24 +45,98 @@ pub struct OfficePageTemplate {
     pub metadata: context::Metadata,
 }
 
+/// What the office page shows, and what is served for it as JSON.
+#[derive(Serialize)]
+pub struct OfficeData {
+    pub office: context::Office,
+    pub photo: Option<data::Photo>,
+    pub contacts: Option<BTreeMap<data::ContactType, String>>,
+    pub incumbent: Option<context::Person>,
+    /// Who held the office before the incumbent.
+    pub preceded_by: Option<context::Person>,
+    pub quondams: Option<Vec<context::Quondam>>,
+    /// Every tenure of the office, earliest first.
+    pub succession: Option<Vec<context::Succession>>,
+    pub supervisors: Option<BTreeMap<data::SupervisingRelation, Vec<context::Office>>>,
+    pub establishment: Option<NaiveDate>,
+    pub abolition: Option<NaiveDate>,
+    /// The region the office has authority over.
+    pub jurisdiction: Option<String>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub commit_id: Hash,
+}
+
 #[axum::debug_handler]
 pub async fn page(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(id_with_ext): axum::extract::Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (id, representation) = negotiate(&id_with_ext, &headers);
+    let conn = state.get_read_conn()?;
+
+    let response = match representation {
+        Representation::Json => Json(load(&conn, id)?).into_response(),
+        Representation::Html => match load_page(&conn, id, state.page_context()) {
+            Err(AppError::NotFound(_)) => {
+                not_found(dto::EntityType::Office, id, state.page_context())
+            }
+            page => page?.into_response(),
+        },
+    };
+    Ok(([(header::VARY, "Accept")], response).into_response())
+}
+
+/// Assemble the office page from the index tables, independent of the server.
+pub fn load_page(
+    conn: &Connection,
+    id: &str,
+    page: context::Page,
 ) -> Result<OfficePageTemplate, AppError> {
-    let id = id_with_ext.trim_end_matches(".html");
-    let conn = state.get_conn()?;
-    let repo = RecordRepo::new(&conn);
+    let data = load(conn, id)?;
+
+    // metadata
+    let metadata = context::Metadata {
+        maintenance: context::Maintenance { incomplete: false },
+        commit_id: data.commit_id,
+    };
+
+    Ok(OfficePageTemplate {
+        office: data.office,
+        photo: data.photo,
+        contacts: data.contacts,
+        supervisors: data.supervisors,
+        establishment: data.establishment,
+        abolition: data.abolition,
+        jurisdiction: data.jurisdiction,
+        incumbent: data.incumbent,
+        preceded_by: data.preceded_by,
+        quondams: data.quondams,
+        succession: data.succession,
+        sources: None,
+        config: crate::theme::config(),
+        page,
+        metadata,
+    })
+}
+
+/// Gather what is known about the office from the index tables.
+pub fn load(conn: &Connection, id: &str) -> Result<OfficeData, AppError> {
+    let repo = RecordRepo::new(conn);
 
     let name = conn
         .get_entity_name(&dto::EntityType::Office, id, |row| row.get(0))
-        .with_context(|| format!("could not get name for office: {}", id))?;
+        .optional()
+        .with_context(|| format!("could not get name for office: {}", id))?
+        .ok_or_else(|| AppError::NotFound(format!("office/{}", id)))?;
 
     let photo = conn
         .get_entity_photo(&dto::EntityType::Office, id, |row| {
             Ok(data::Photo {
                 url: row.get(0)?,
                 attribution: row.get(1)?,
+                license: row.get(2)?,
+                source_url: row.get(3)?,
             })
         })
         .optional()
@@ -61,7 +149,8 @@ pub async fn page(
         Ok(())
     })?;
 
-    let mut supervisors: BTreeMap<data::SupervisingRelation, context::Office> = BTreeMap::new();
+    let mut supervisors: BTreeMap<data::SupervisingRelation, Vec<context::Office>> =
+        BTreeMap::new();
     conn.get_office_supervising_offices(id, |row| {
         let relation = row.get(0)?;
         let supervising_office_id: String = row.get(1)?;
@@ -69,28 +158,45 @@ pub async fn page(
             conn.get_entity_name(&dto::EntityType::Office, &supervising_office_id, |row| {
                 row.get(0)
             })?;
-        supervisors.insert(
-            relation,
-            context::Office {
-                id: supervising_office_id,
-                name,
-            },
-        );
+        supervisors.entry(relation).or_default().push(context::Office {
+            id: supervising_office_id,
+            name,
+        });
 
         Ok(())
     })
     .with_context(|| format!("could not get supervising offices for office: {}", id))?;
 
+    let (establishment, abolition) = conn
+        .get_office_lifecycle(id, |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+        .with_context(|| format!("could not get lifecycle for office: {}", id))?
+        .unwrap_or_default();
+
+    let jurisdiction = conn
+        .get_office_jurisdiction(id, |row| row.get(0))
+        .optional()
+        .with_context(|| format!("could not get jurisdiction for office: {}", id))?;
+
     let incumbent = conn
         .get_office_incumbent(id, |row| {
             Ok(context::Person {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 start: row.get(2)?,
+                acting: row.get(3)?,
             })
         })
         .optional()?;
 
+    let succession = load_succession(conn, id)?;
+    let preceded_by = incumbent.as_ref().and_then(|incumbent| {
+        succession
+            .iter()
+            .find(|s| s.end.is_none() && s.person.id == incumbent.id)
+            .and_then(|s| s.preceded_by.clone())
+    });
+
     let mut quondams = Vec::new();
     conn.get_office_quondams(id, |row| {
         quondams.push(context::Quondam {
@@ -98,26 +204,19 @@ pub async fn page(
                 id: row.get(0)?,
                 name: row.get(1)?,
                 start: None,
+                acting: false,
             },
             start: row.get(2)?,
             end: row.get(3)?,
+            acting: row.get(4)?,
         });
 
         Ok(())
     })?;
 
-    let commit_id = repo.working()?.commit_id()?;
-
-    // page
-    let page = state.page_context();
+    let commit_id = repo.root_hash(RepoRefType::Working)?;
 
-    // metadata
-    let metadata = context::Metadata {
-        maintenance: context::Maintenance { incomplete: false },
-        commit_id,
-    };
-
-    Ok(OfficePageTemplate {
+    Ok(OfficeData {
         office: context::Office {
             id: id.to_string(),
             name,
@@ -125,11 +224,44 @@ pub async fn page(
         photo,
         contacts: Some(contacts).filter(|v| !v.is_empty()),
         supervisors: Some(supervisors).filter(|v| !v.is_empty()),
+        establishment,
+        abolition,
+        jurisdiction,
         incumbent,
+        preceded_by,
         quondams: Some(quondams).filter(|v| !v.is_empty()),
-        sources: None,
-        config: &CONFIG,
-        page,
-        metadata,
+        succession: Some(succession).filter(|v| !v.is_empty()),
+        commit_id,
+    })
+}
+
+/// The tenures of the office in order, each linked to the ones either side
+/// of it. Acting tenures take their place in the chain like any other.
+pub fn load_succession(conn: &Connection, id: &str) -> Result<Vec<context::Succession>, AppError> {
+    let mut tenures: Vec<(context::Person, Option<String>)> = Vec::new();
+    conn.get_office_succession(id, |row| {
+        tenures.push((
+            context::Person {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                start: row.get(2)?,
+                acting: row.get(4)?,
+            },
+            row.get(3)?,
+        ));
+
+        Ok(())
     })
+    .with_context(|| format!("could not get succession for office: {}", id))?;
+
+    let succession = (0..tenures.len())
+        .map(|i| context::Succession {
+            person: tenures[i].0.clone(),
+            end: tenures[i].1.clone(),
+            preceded_by: i.checked_sub(1).map(|j| tenures[j].0.clone()),
+            succeeded_by: tenures.get(i + 1).map(|(person, _)| person.clone()),
+        })
+        .collect();
+
+    Ok(succession)
 }
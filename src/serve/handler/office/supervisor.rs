@@ -1,7 +1,4 @@
-use std::{
-    collections::{BTreeMap, HashSet},
-    sync::Arc,
-};
+use std::sync::Arc;
 
 use askama::Template;
 use askama_web::WebTemplate;
@@ -18,7 +15,7 @@ use crate::{
     LibrarySql, context,
     data::{self, SupervisingRelation},
     dto,
-    record::{Key, OfficePath, RecordRepo},
+    record::{Key, OfficePath, RecordKey, RecordRepo, RecordValue},
     serve::{AppError, AppState},
 };
 
@@ -26,30 +23,14 @@ use crate::{
 #[template(path = "office/supervisor/add_partial.html")]
 pub struct AddSupervisorPartial {
     id: String,
-    missing_relations: Vec<data::SupervisingRelation>,
+    relations: &'static [data::SupervisingRelation],
 }
 
 #[axum::debug_handler]
-pub async fn add(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> Result<AddSupervisorPartial, AppError> {
-    let conn = state.get_conn()?;
-    let mut supervisors: HashSet<data::SupervisingRelation> = HashSet::new();
-    conn.get_office_supervising_offices(&id, |row| {
-        supervisors.insert(row.get(0)?);
-
-        Ok(())
-    })?;
-    let missing_relations = data::SupervisingRelation::VARIANTS
-        .iter()
-        .filter(|variant| !supervisors.contains(variant))
-        .cloned()
-        .collect();
-
+pub async fn add(Path(id): Path<String>) -> Result<AddSupervisorPartial, AppError> {
     Ok(AddSupervisorPartial {
         id,
-        missing_relations,
+        relations: data::SupervisingRelation::VARIANTS,
     })
 }
 
@@ -57,29 +38,32 @@ pub async fn add(
 #[template(path = "office/supervisor/view_partial.html")]
 pub struct ViewSupervisorPartial {
     id: String,
-    supervisors: BTreeMap<data::SupervisingRelation, context::Office>,
+    supervisors: Vec<(data::SupervisingRelation, context::Office)>,
 }
 
 impl ViewSupervisorPartial {
     pub fn new(conn: &Connection, id: String) -> Result<Self, AppError> {
-        let mut supervisors: BTreeMap<data::SupervisingRelation, context::Office> = BTreeMap::new();
+        let mut supervisors = Vec::new();
         conn.get_office_supervising_offices(&id, |row| {
-            let relation = row.get(0)?;
+            let relation: data::SupervisingRelation = row.get(0)?;
             let supervising_office_id: String = row.get(1)?;
             let name =
                 conn.get_entity_name(&dto::EntityType::Office, &supervising_office_id, |row| {
                     row.get(0)
                 })?;
-            supervisors.insert(
+            supervisors.push((
                 relation,
                 context::Office {
                     id: supervising_office_id,
                     name,
                 },
-            );
+            ));
 
             Ok(())
         })?;
+        supervisors.sort_by(|(a, a_office), (b, b_office)| {
+            a.cmp(b).then_with(|| a_office.id.cmp(&b_office.id))
+        });
         Ok(ViewSupervisorPartial { id, supervisors })
     }
 }
@@ -89,7 +73,7 @@ pub async fn view(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<ViewSupervisorPartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
 
     ViewSupervisorPartial::new(&conn, id)
 }
@@ -109,7 +93,7 @@ pub async fn save(
     let conn = state.get_conn()?;
     let repo = RecordRepo::new(&conn);
     repo.working()?.save(
-        Key::<OfficePath, ()>::new(&office_id).supervisor(form.relation),
+        Key::<OfficePath, ()>::new(&office_id).supervisor(form.relation, &form.office_id),
         &form.office_id,
     )?;
 
@@ -124,6 +108,7 @@ pub async fn save(
 #[derive(Deserialize)]
 pub struct DeleteSupervisorEntry {
     pub relation: SupervisingRelation,
+    pub office_id: String,
 }
 
 #[axum::debug_handler]
@@ -134,8 +119,21 @@ pub async fn delete(
 ) -> Result<Response, AppError> {
     let conn = state.get_conn()?;
     let repo = RecordRepo::new(&conn);
-    repo.working()?
-        .delete(Key::<OfficePath, ()>::new(&office_id).supervisor(form.relation))?;
+    let mut working = repo.working()?;
+    // Older records keep the supervisor only in the value, so match on that
+    // rather than building the key.
+    let records = working
+        .scan(Key::<OfficePath, ()>::new(&office_id))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (key, value) in records {
+        if let (RecordKey::Supervisor(key), RecordValue::Supervisor(supervisor)) = (key, value)
+            && key.entity_id == office_id
+            && key.state.relation == form.relation
+            && supervisor == form.office_id
+        {
+            working.delete(key)?;
+        }
+    }
 
     let partial = ViewSupervisorPartial::new(&conn, office_id)?;
     let mut response = partial.into_response();
@@ -1,27 +1,76 @@
 use crate::config::Config;
 use crate::record::RecordRepo;
+use crate::repo::{Hash, RepoRefType};
 use crate::serve::handler::filters;
-use crate::{CONFIG, LibrarySql};
+use crate::LibrarySql;
 use crate::{
     context, data, dto,
     serve::{AppError, AppState},
 };
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::extract::State;
-use rusqlite::OptionalExtension;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
 use std::{collections::BTreeMap, sync::Arc};
 
+use super::{Representation, negotiate, not_found, serialize_hex};
+
 pub mod tenure;
 
+/// How many past tenures the person page lists unless asked for all.
+const PAST_TENURES_SHOWN: u32 = 20;
+
+/// Which of a person's past tenures to list.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct TenuresParams {
+    /// Only tenures that ended on or after this date.
+    pub after: Option<chrono::NaiveDate>,
+    /// Only tenures that started on or before this date.
+    pub before: Option<chrono::NaiveDate>,
+    /// List all of them instead of the latest [`PAST_TENURES_SHOWN`].
+    #[serde(default)]
+    pub all: bool,
+}
+
+impl TenuresParams {
+    /// Every past tenure, as the static site has no way to ask for more.
+    pub fn all() -> Self {
+        Self {
+            all: true,
+            ..Self::default()
+        }
+    }
+
+    /// The query string listing all tenures within the same dates.
+    fn all_query(&self) -> String {
+        let mut query = "?all=true".to_string();
+        if let Some(after) = self.after {
+            query.push_str(&format!("&after={}", after));
+        }
+        if let Some(before) = self.before {
+            query.push_str(&format!("&before={}", before));
+        }
+        query
+    }
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "person.html")]
 pub struct PersonPageTemplate {
     pub person: context::Person,
+    /// Shown before the name, e.g. `Dr.`.
+    pub honorific: Option<String>,
+    pub current_role: Option<String>,
     pub photo: Option<data::Photo>,
     pub contacts: Option<BTreeMap<data::ContactType, String>>,
     pub offices: Option<Vec<context::OfficeDetails>>,
     pub past_tenures: Option<Vec<context::TenureDetails>>,
+    /// Where to list all the past tenures, if not all are shown.
+    pub all_past_tenures: Option<String>,
 
     pub sources: Option<Vec<String>>,
     pub config: &'static Config,
@@ -29,21 +78,91 @@ pub struct PersonPageTemplate {
     pub metadata: context::Metadata,
 }
 
+/// What the person page shows, and what is served for it as JSON.
+#[derive(Serialize)]
+pub struct PersonData {
+    pub person: context::Person,
+    /// Shown before the name, e.g. `Dr.`.
+    pub honorific: Option<String>,
+    pub current_role: Option<String>,
+    pub photo: Option<data::Photo>,
+    pub contacts: Option<BTreeMap<data::ContactType, String>>,
+    pub offices: Option<Vec<context::OfficeDetails>>,
+    pub past_tenures: Option<Vec<context::TenureDetails>>,
+    /// Whether there are past tenures besides the ones listed.
+    pub more_past_tenures: bool,
+    #[serde(serialize_with = "serialize_hex")]
+    pub commit_id: Hash,
+}
+
 #[axum::debug_handler]
 pub async fn page(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(id_with_ext): axum::extract::Path<String>,
+    Query(tenures): Query<TenuresParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (id, representation) = negotiate(&id_with_ext, &headers);
+    let conn = state.get_read_conn()?;
+
+    let response = match representation {
+        Representation::Json => Json(load(&conn, id, &tenures)?).into_response(),
+        Representation::Html => match load_page(&conn, id, &tenures, state.page_context()) {
+            Err(AppError::NotFound(_)) => {
+                not_found(dto::EntityType::Person, id, state.page_context())
+            }
+            page => page?.into_response(),
+        },
+    };
+    Ok(([(header::VARY, "Accept")], response).into_response())
+}
+
+/// Assemble the person page from the index tables, independent of the server.
+pub fn load_page(
+    conn: &Connection,
+    id: &str,
+    tenures: &TenuresParams,
+    page: context::Page,
 ) -> Result<PersonPageTemplate, AppError> {
-    let id = id_with_ext.trim_end_matches(".html");
-    let conn = state.get_conn()?;
-    let repo = RecordRepo::new(&conn);
+    let data = load(conn, id, tenures)?;
+
+    Ok(PersonPageTemplate {
+        person: data.person,
+        honorific: data.honorific,
+        current_role: data.current_role,
+        photo: data.photo,
+        contacts: data.contacts,
+        offices: data.offices,
+        past_tenures: data.past_tenures,
+        all_past_tenures: data.more_past_tenures.then(|| tenures.all_query()),
+        sources: None, // Initialize sources as None
+        config: crate::theme::config(),
+        page,
+        metadata: context::Metadata {
+            commit_id: data.commit_id,
+            maintenance: context::Maintenance { incomplete: false },
+        },
+    })
+}
+
+/// Gather what is known about the person from the index tables.
+pub fn load(conn: &Connection, id: &str, tenures: &TenuresParams) -> Result<PersonData, AppError> {
+    let repo = RecordRepo::new(conn);
 
-    let name = conn.get_entity_name(&dto::EntityType::Person, id, |row| row.get(0))?;
+    let name = conn
+        .get_entity_name(&dto::EntityType::Person, id, |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("person/{}", id)))?;
+    let honorific = conn
+        .get_person_honorific(id, |row| row.get(0))
+        .optional()?;
     let photo = conn
         .get_entity_photo(&dto::EntityType::Person, id, |row| {
             Ok(data::Photo {
                 url: row.get(0)?,
                 attribution: row.get(1)?,
+                license: row.get(2)?,
+                source_url: row.get(3)?,
             })
         })
         .optional()?;
@@ -69,6 +188,8 @@ pub async fn page(
                 Some(data::Photo {
                     url,
                     attribution: row.get(3)?,
+                    license: row.get(4)?,
+                    source_url: row.get(5)?,
                 })
             } else {
                 None
@@ -78,16 +199,18 @@ pub async fn page(
             } else {
                 Some(contacts)
             },
-            start: row.get(4)?,
+            start: row.get(6)?,
+            acting: row.get(7)?,
         });
 
         Ok(())
     })?;
+    let current_role = context::current_role(&offices_for_person);
     // office, official_contacts, supervisors, subordinates
     let mut offices = Vec::new();
     for office_dto in offices_for_person {
         // supervisors
-        let mut supervisors: BTreeMap<data::SupervisingRelation, context::Officer> =
+        let mut supervisors: BTreeMap<data::SupervisingRelation, Vec<context::Officer>> =
             BTreeMap::new();
         conn.get_office_supervisors(&office_dto.id, |row| {
             let person = if let (Some(id), Some(name)) = (row.get(3)?, row.get(4)?) {
@@ -95,18 +218,19 @@ pub async fn page(
                     id,
                     name,
                     start: None,
+                    acting: false,
                 })
             } else {
                 None
             };
-            supervisors.insert(
-                row.get(0)?,
-                context::Officer {
+            supervisors
+                .entry(row.get(0)?)
+                .or_default()
+                .push(context::Officer {
                     office_id: row.get(1)?,
                     office_name: row.get(2)?,
                     person,
-                },
-            );
+                });
 
             Ok(())
         })?;
@@ -124,6 +248,7 @@ pub async fn page(
                         id,
                         name,
                         start: None,
+                        acting: false,
                     })
                 } else {
                     None
@@ -134,6 +259,7 @@ pub async fn page(
             Ok(())
         })?;
 
+        let (preceded_by, _) = neighbours(conn, &office_dto.id, id, office_dto.start.as_deref())?;
         offices.push(context::OfficeDetails {
             office: context::Office {
                 id: office_dto.id,
@@ -152,11 +278,15 @@ pub async fn page(
                 Some(subordinates)
             },
             start: office_dto.start,
+            acting: office_dto.acting,
+            preceded_by,
         });
     }
 
     let mut past_tenures = Vec::new();
-    conn.get_past_tenures(id, |row| {
+    // One more than is shown, to tell whether there are more.
+    let limit = (!tenures.all).then_some(PAST_TENURES_SHOWN + 1);
+    conn.get_past_tenures(id, tenures.after.as_ref(), tenures.before.as_ref(), limit, |row| {
         past_tenures.push(context::TenureDetails {
             office: context::Office {
                 id: row.get(0)?,
@@ -164,17 +294,31 @@ pub async fn page(
             },
             start: row.get(2)?,
             end: row.get(3)?,
+            acting: row.get(4)?,
+            preceded_by: None,
+            succeeded_by: None,
         });
 
         Ok(())
     })?;
-    let commit_id = repo.working()?.commit_id()?;
-    Ok(PersonPageTemplate {
+    let more_past_tenures = limit.is_some_and(|limit| past_tenures.len() >= limit as usize);
+    if more_past_tenures {
+        past_tenures.truncate(PAST_TENURES_SHOWN as usize);
+    }
+    for tenure in &mut past_tenures {
+        (tenure.preceded_by, tenure.succeeded_by) =
+            neighbours(conn, &tenure.office.id, id, Some(&tenure.start))?;
+    }
+    let commit_id = repo.root_hash(RepoRefType::Working)?;
+    Ok(PersonData {
         person: context::Person {
             id: id.to_string(),
             name,
             start: None,
+            acting: false,
         },
+        honorific,
+        current_role,
         photo,
         contacts: if contacts.is_empty() {
             None
@@ -191,12 +335,289 @@ pub async fn page(
         } else {
             Some(past_tenures)
         },
-        sources: None, // Initialize sources as None
-        config: &CONFIG,
-        page: state.page_context(),
-        metadata: context::Metadata {
-            commit_id,
-            maintenance: context::Maintenance { incomplete: false },
-        },
+        more_past_tenures,
+        commit_id,
     })
 }
+
+/// Who held `office_id` just before and just after the person's tenure in it
+/// that started on `start`.
+fn neighbours(
+    conn: &Connection,
+    office_id: &str,
+    person_id: &str,
+    start: Option<&str>,
+) -> Result<(Option<context::Person>, Option<context::Person>), AppError> {
+    let succession = super::office::load_succession(conn, office_id)?;
+
+    Ok(succession
+        .into_iter()
+        .find(|s| s.person.id == person_id && s.person.start.as_deref() == start)
+        .map(|s| (s.preceded_by, s.succeeded_by))
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaSql;
+    use crate::record::{Key, OfficePath, PersonPath, TenureValue};
+    use chrono::NaiveDate;
+
+    fn date(y: i32, m: u32, d: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(y, m, d)
+    }
+
+    fn seed(conn: &Connection) {
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let repo = RecordRepo::new(conn);
+        repo.init().unwrap();
+        let mut working = repo.working().unwrap();
+
+        for (id, name) in [
+            ("minister", "Minister"),
+            ("secretary", "Secretary"),
+            ("cabinet", "Cabinet"),
+            ("pmo", "Prime Minister's Office"),
+            ("adviser", "Adviser"),
+            ("old", "Old Office"),
+        ] {
+            let office = Key::<OfficePath, ()>::new(id);
+            working.save(office.name(), &name.to_string()).unwrap();
+        }
+        for (id, name) in [("alice", "Alice"), ("bob", "Bob"), ("carol", "Carol")] {
+            let person = Key::<PersonPath, ()>::new(id);
+            working.save(person.name(), &name.to_string()).unwrap();
+        }
+
+        let secretary = Key::<OfficePath, ()>::new("secretary");
+        working
+            .save(
+                secretary.supervisor(data::SupervisingRelation::ResponsibleTo, "minister"),
+                &"minister".to_string(),
+            )
+            .unwrap();
+        let minister = Key::<OfficePath, ()>::new("minister");
+        working
+            .save(
+                minister.supervisor(data::SupervisingRelation::MemberOf, "cabinet"),
+                &"cabinet".to_string(),
+            )
+            .unwrap();
+        let adviser = Key::<OfficePath, ()>::new("adviser");
+        working
+            .save(
+                adviser.supervisor(data::SupervisingRelation::Adviser, "pmo"),
+                &"pmo".to_string(),
+            )
+            .unwrap();
+
+        let alice = Key::<PersonPath, ()>::new("alice");
+        let current = TenureValue::default();
+        let acting = TenureValue {
+            end: None,
+            acting: true,
+        };
+        working
+            .save(alice.tenure("minister", date(2020, 1, 1)), &current)
+            .unwrap();
+        working.save(alice.tenure("pmo", date(2021, 6, 1)), &acting).unwrap();
+        working
+            .save(
+                alice.tenure("old", date(2010, 1, 1)),
+                &date(2015, 1, 1).into(),
+            )
+            .unwrap();
+        let bob = Key::<PersonPath, ()>::new("bob");
+        working
+            .save(bob.tenure("secretary", date(2019, 1, 1)), &current)
+            .unwrap();
+        working
+            .save(
+                bob.tenure("minister", date(2015, 1, 1)),
+                &date(2020, 1, 1).into(),
+            )
+            .unwrap();
+        let carol = Key::<PersonPath, ()>::new("carol");
+        working
+            .save(carol.tenure("adviser", date(2022, 1, 1)), &current)
+            .unwrap();
+        let acting_until = TenureValue {
+            end: date(2016, 1, 1),
+            acting: true,
+        };
+        working
+            .save(carol.tenure("old", date(2015, 1, 1)), &acting_until)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+
+        let params = TenuresParams::default();
+        assert!(matches!(
+            load(&conn, "nobody", &params),
+            Err(AppError::NotFound(_))
+        ));
+        let page = context::Page {
+            base: "/".to_string(),
+            dynamic: false,
+        };
+        let response = not_found(dto::EntityType::Person, "nobody", page);
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_load_page() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+
+        let page = context::Page {
+            base: "/".to_string(),
+            dynamic: false,
+        };
+        let template = load_page(&conn, "alice", &TenuresParams::default(), page).unwrap();
+        let html = template.render().unwrap();
+
+        assert_eq!(template.person.name, "Alice");
+        assert_eq!(template.current_role.as_deref(), Some("Minister"));
+        assert!(html.contains("<title>Alice, Minister — "));
+        assert!(html.contains("<meta property=\"og:description\" content=\"Alice, Minister\">"));
+
+        let mut offices = template.offices.expect("offices");
+        offices.sort_by(|a, b| a.office.id.cmp(&b.office.id));
+        assert_eq!(offices.len(), 2);
+
+        let minister = &offices[0];
+        assert_eq!(minister.office.id, "minister");
+        assert_eq!(minister.start.as_deref(), Some("2020-01-01"));
+        assert!(!minister.acting);
+        assert_eq!(minister.preceded_by.as_ref().unwrap().id, "bob");
+        let supervisors = minister.supervisors.as_ref().expect("supervisors");
+        let cabinet = &supervisors[&data::SupervisingRelation::MemberOf][0];
+        assert_eq!(cabinet.office_id, "cabinet");
+        assert!(cabinet.person.is_none());
+        let subordinates = minister.subordinates.as_ref().expect("subordinates");
+        let secretaries = &subordinates[&data::SupervisingRelation::ResponsibleTo];
+        assert_eq!(secretaries.len(), 1);
+        assert_eq!(secretaries[0].office_id, "secretary");
+        assert_eq!(secretaries[0].person.as_ref().unwrap().name, "Bob");
+
+        let pmo = &offices[1];
+        assert_eq!(pmo.office.id, "pmo");
+        assert!(pmo.acting);
+        assert!(pmo.preceded_by.is_none());
+        assert!(pmo.supervisors.is_none());
+        let advisers = &pmo.subordinates.as_ref().expect("subordinates")
+            [&data::SupervisingRelation::Adviser];
+        assert_eq!(advisers[0].office_id, "adviser");
+        assert_eq!(advisers[0].person.as_ref().unwrap().id, "carol");
+
+        let past_tenures = template.past_tenures.expect("past tenures");
+        assert_eq!(past_tenures.len(), 1);
+        assert_eq!(past_tenures[0].office.name, "Old Office");
+        assert_eq!(past_tenures[0].start, "2010-01-01");
+        assert_eq!(past_tenures[0].end, "2015-01-01");
+        assert!(!past_tenures[0].acting);
+        assert!(past_tenures[0].preceded_by.is_none());
+        let successor = past_tenures[0].succeeded_by.as_ref().unwrap();
+        assert_eq!(successor.id, "carol");
+        assert!(successor.acting);
+    }
+
+    #[test]
+    fn test_past_tenures_bounds() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+        let repo = RecordRepo::new(&conn);
+        let mut working = repo.working().unwrap();
+        let dave = Key::<PersonPath, ()>::new("dave");
+        working.save(dave.name(), &"Dave".to_string()).unwrap();
+        for year in 1980..2005 {
+            working
+                .save(
+                    dave.tenure("old", date(year, 1, 1)),
+                    &date(year + 1, 1, 1).into(),
+                )
+                .unwrap();
+        }
+
+        let ends = |params: &TenuresParams| -> (Vec<String>, bool) {
+            let data = load(&conn, "dave", params).unwrap();
+            let ends = data
+                .past_tenures
+                .unwrap_or_default()
+                .into_iter()
+                .map(|t| t.end)
+                .collect();
+            (ends, data.more_past_tenures)
+        };
+
+        let (latest, more) = ends(&TenuresParams::default());
+        assert_eq!(latest.len(), PAST_TENURES_SHOWN as usize);
+        assert_eq!(latest[0], "2005-01-01");
+        assert!(more);
+        let (all, more) = ends(&TenuresParams::all());
+        assert_eq!(all.len(), 25);
+        assert!(!more);
+
+        let bounded = TenuresParams {
+            after: date(1990, 6, 1),
+            before: date(1992, 1, 1),
+            all: false,
+        };
+        assert_eq!(
+            ends(&bounded),
+            (
+                vec![
+                    "1993-01-01".to_string(),
+                    "1992-01-01".to_string(),
+                    "1991-01-01".to_string(),
+                ],
+                false
+            )
+        );
+
+        let page = || context::Page {
+            base: "/".to_string(),
+            dynamic: false,
+        };
+        let template = load_page(&conn, "dave", &bounded, page()).unwrap();
+        assert!(template.all_past_tenures.is_none());
+        let template = load_page(&conn, "dave", &TenuresParams::default(), page()).unwrap();
+        assert_eq!(template.all_past_tenures.as_deref(), Some("?all=true"));
+        assert!(template.render().unwrap().contains("All past tenures"));
+    }
+
+    #[test]
+    fn test_office_succession() {
+        let conn = Connection::open_in_memory().unwrap();
+        seed(&conn);
+
+        let page = context::Page {
+            base: "/".to_string(),
+            dynamic: false,
+        };
+        let template = super::super::office::load_page(&conn, "minister", page).unwrap();
+        template.render().unwrap();
+
+        assert_eq!(template.incumbent.as_ref().unwrap().id, "alice");
+        assert_eq!(template.preceded_by.as_ref().unwrap().id, "bob");
+        let succession = template.succession.expect("succession");
+        let ids: Vec<&str> = succession.iter().map(|s| s.person.id.as_str()).collect();
+        assert_eq!(ids, ["bob", "alice"]);
+        assert!(succession[0].preceded_by.is_none());
+        assert_eq!(succession[0].end.as_deref(), Some("2020-01-01"));
+        assert_eq!(succession[0].succeeded_by.as_ref().unwrap().id, "alice");
+        assert_eq!(succession[1].preceded_by.as_ref().unwrap().id, "bob");
+        assert!(succession[1].succeeded_by.is_none());
+
+        let old = super::super::office::load_succession(&conn, "old").unwrap();
+        let ids: Vec<&str> = old.iter().map(|s| s.person.id.as_str()).collect();
+        assert_eq!(ids, ["alice", "carol"]);
+        assert!(old[1].person.acting);
+    }
+}
@@ -12,8 +12,8 @@ use rusqlite::Connection;
 use serde::Deserialize;
 
 use crate::{
-    LibrarySql, data,
-    record::{Key, PersonPath, RecordRepo},
+    data,
+    record::{Key, PersonPath, RecordRepo, TenureValue},
     serve::{AppState, handler::AppError},
 };
 
@@ -24,6 +24,7 @@ pub struct AddTenurePartial {
     office_id: Option<String>,
     start: Option<String>,
     end: Option<String>,
+    acting: bool,
     error: Option<String>,
 }
 
@@ -34,6 +35,7 @@ pub async fn add(Path(id): Path<String>) -> Result<AddTenurePartial, AppError> {
         office_id: None,
         start: None,
         end: None,
+        acting: false,
         error: None,
     })
 }
@@ -58,25 +60,13 @@ pub async fn edit(
     Path(id): Path<String>,
     Query(params): Query<EditTenureParams>,
 ) -> Result<EditTenurePartial, AppError> {
-    let conn = state.get_conn()?;
-    let mut tenures = Vec::new();
-    conn.get_tenures(&id, |row| {
-        let office_id: String = row.get(0)?;
-        let start: Option<String> = row.get(1)?;
-        let end: Option<String> = row.get(2)?;
-        
-        if office_id == params.office_id && start == params.start {
-             tenures.push(data::Tenure {
-                office_id,
-                start,
-                end,
-            });
-        }
-       
-        Ok(())
-    })?;
-
-    let tenure = tenures.into_iter().next().ok_or_else(|| AppError::from("Tenure not found".to_string()))?;
+    let conn = state.get_read_conn()?;
+    let repo = RecordRepo::new(&conn);
+    let start = parse_date(params.start)?;
+    let tenure = repo
+        .working()?
+        .get_tenure(&id, &params.office_id, start)?
+        .ok_or_else(|| AppError::from("Tenure not found".to_string()))?;
 
     Ok(EditTenurePartial { id, tenure, error: None })
 }
@@ -90,16 +80,10 @@ pub struct ViewTenurePartial {
 
 impl ViewTenurePartial {
     pub fn new(conn: &Connection, id: String) -> Result<Self, AppError> {
-        let mut tenures = Vec::new();
-        conn.get_tenures(&id, |row| {
-            tenures.push(data::Tenure {
-                office_id: row.get(0)?,
-                start: row.get(1)?,
-                end: row.get(2)?,
-            });
-
-            Ok(())
-        })?;
+        let tenures = RecordRepo::new(conn)
+            .working()?
+            .list_tenures(&id)?
+            .collect::<Result<Vec<_>, _>>()?;
 
         Ok(ViewTenurePartial { id, tenures })
     }
@@ -110,7 +94,7 @@ pub async fn view(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<ViewTenurePartial, AppError> {
-    let conn = state.get_conn()?;
+    let conn = state.get_read_conn()?;
 
     ViewTenurePartial::new(&conn, id)
 }
@@ -120,6 +104,8 @@ pub struct TenureEntry {
     pub office_id: String,
     pub start: Option<String>,
     pub end: Option<String>,
+    /// A checkbox, so only sent when ticked.
+    pub acting: Option<String>,
 }
 
 fn parse_date(s: Option<String>) -> Result<Option<NaiveDate>, String> {
@@ -149,7 +135,10 @@ pub async fn save_add(
         (Ok(start), Ok(end)) => {
             repo.working()?.save(
                 Key::<PersonPath, ()>::new(&person_id).tenure(&form.office_id, start),
-                &end,
+                &TenureValue {
+                    end,
+                    acting: form.acting.is_some(),
+                },
             ).map_err(AppError::from)
         }
         (Err(e), _) => Err(AppError::from(e)),
@@ -171,6 +160,7 @@ pub async fn save_add(
                 office_id: Some(form.office_id),
                 start: form.start,
                 end: form.end,
+                acting: form.acting.is_some(),
                 error: Some(e.to_string()),
             }.into_response())
         }
@@ -193,7 +183,10 @@ pub async fn save_edit(
         (Ok(start), Ok(end)) => {
             repo.working()?.save(
                 Key::<PersonPath, ()>::new(&person_id).tenure(&form.office_id, start),
-                &end,
+                &TenureValue {
+                    end,
+                    acting: form.acting.is_some(),
+                },
             ).map_err(AppError::from)
         }
         (Err(e), _) => Err(AppError::from(e)),
@@ -216,6 +209,7 @@ pub async fn save_edit(
                     office_id: form.office_id,
                     start: form.start,
                     end: form.end,
+                    acting: form.acting.is_some(),
                 },
                 error: Some(e.to_string()),
             }.into_response())
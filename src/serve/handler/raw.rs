@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Path, State};
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::record::{RecordRepo, RecordValue};
+use crate::repo::Hash;
+use crate::serve::{AppError, AppState};
+
+use super::serialize_hex;
+
+/// The record at a path of the working tree, and the commits that touched it.
+#[derive(Serialize)]
+pub struct RawRecord {
+    pub path: String,
+    pub value: Option<RecordValue>,
+    /// Newest first.
+    pub history: Vec<RawCommit>,
+}
+
+#[derive(Serialize)]
+pub struct RawCommit {
+    #[serde(serialize_with = "serialize_hex")]
+    pub commit: Hash,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    pub author: Option<String>,
+    pub message: Option<String>,
+}
+
+#[axum::debug_handler]
+pub async fn view(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+) -> Result<Json<RawRecord>, AppError> {
+    let conn = state.get_read_conn()?;
+
+    Ok(Json(load(&conn, &path)?))
+}
+
+pub fn load(conn: &Connection, path: &str) -> Result<RawRecord, AppError> {
+    let repo = RecordRepo::new(conn);
+    let value = repo.working()?.get(path)?;
+    let history = repo
+        .history(path)?
+        .into_iter()
+        .map(|(commit, c)| RawCommit {
+            commit,
+            timestamp: c.timestamp,
+            author: c.author,
+            message: c.message,
+        })
+        .collect();
+
+    Ok(RawRecord {
+        path: path.to_string(),
+        value,
+        history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaSql;
+    use crate::record::{Key, PersonPath};
+
+    #[test]
+    fn test_load() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+        let alice = Key::<PersonPath, ()>::new("alice");
+
+        repo.working()
+            .unwrap()
+            .save(alice.name(), &"Alice".to_string())
+            .unwrap();
+        repo.commit(None, Some("Add Alice")).unwrap();
+        repo.working()
+            .unwrap()
+            .save(
+                alice.contact(crate::data::ContactType::Email),
+                &"a@b.org".to_string(),
+            )
+            .unwrap();
+        repo.commit(None, Some("Add email")).unwrap();
+        repo.working()
+            .unwrap()
+            .save(alice.name(), &"Alice B".to_string())
+            .unwrap();
+        repo.commit(None, Some("Rename")).unwrap();
+
+        let raw = load(&conn, "person/alice/name").unwrap();
+        assert_eq!(raw.value, Some(RecordValue::Name("Alice B".to_string())));
+        let messages: Vec<_> = raw.history.iter().map(|c| c.message.as_deref()).collect();
+        assert_eq!(messages, [Some("Rename"), Some("Add Alice")]);
+
+        let missing = load(&conn, "person/bob/name").unwrap();
+        assert_eq!(missing.value, None);
+        assert!(missing.history.is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::LibrarySql;
+use crate::record::RecordRepo;
+use crate::serve::{AppError, AppState};
+
+/// Counters collected while serving, exposed in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    commits: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert(0) += 1;
+    }
+
+    pub fn record_commit(&self) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, uncommitted: usize, persons: u32, offices: u32) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP tudgoi_http_requests_total Number of HTTP requests handled.\n");
+        out.push_str("# TYPE tudgoi_http_requests_total counter\n");
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        for ((method, route, status), count) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "tudgoi_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, count
+            );
+        }
+
+        out.push_str("# HELP tudgoi_commits_total Number of commits made through the server.\n");
+        out.push_str("# TYPE tudgoi_commits_total counter\n");
+        let _ = writeln!(
+            out,
+            "tudgoi_commits_total {}",
+            self.commits.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP tudgoi_uncommitted_changes Number of uncommitted record changes.\n");
+        out.push_str("# TYPE tudgoi_uncommitted_changes gauge\n");
+        let _ = writeln!(out, "tudgoi_uncommitted_changes {}", uncommitted);
+
+        out.push_str("# HELP tudgoi_entities Number of entities in the index.\n");
+        out.push_str("# TYPE tudgoi_entities gauge\n");
+        let _ = writeln!(out, "tudgoi_entities{{type=\"person\"}} {}", persons);
+        let _ = writeln!(out, "tudgoi_entities{{type=\"office\"}} {}", offices);
+
+        out
+    }
+}
+
+/// Middleware counting requests by method, matched route and status.
+pub async fn track(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(req).await;
+    state
+        .metrics
+        .record_request(&method, &route, response.status().as_u16());
+
+    response
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let conn = state.get_read_conn()?;
+    let repo = RecordRepo::new(&conn);
+
+    let uncommitted = repo.iterate_diff()?.count();
+    let (persons, offices) = conn.get_entity_counts(true, |row| {
+        let persons: u32 = row.get(0)?;
+        let offices: u32 = row.get(1)?;
+
+        Ok((persons, offices))
+    })?;
+
+    let body = state.metrics.render(uncommitted, persons, offices);
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        body,
+    )
+        .into_response())
+}
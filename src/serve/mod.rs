@@ -1,26 +1,36 @@
+pub mod csrf;
 pub mod handler;
+pub mod metrics;
 
 use anyhow::{Context, Result};
 use axum::{
-    Router,
+    Json, Router,
+    extract::{Request, State},
     http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header::InvalidHeaderValue},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post, put},
 };
 use axum_embed::ServeEmbed;
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
 use rust_embed::Embed;
+use serde::Serialize;
 use std::{path::PathBuf, sync::Arc};
 
 use r2d2::Error as R2D2Error;
 use thiserror::Error;
 
 use crate::{
-    CONFIG,
     context::Page,
-    record::{RecordRepoError, sqlitebe::SqlitePoolBackend},
-    repo::sync::server::RepoServer,
+    record::{RecordRepo, RecordRepoError, sqlitebe::SqlitePoolBackend},
+    repo::{
+        RepoError, RepoRefType,
+        sync::{allowlist::Allowlist, server::RepoServer},
+    },
+    serve::metrics::Metrics,
+    theme,
 };
 
 use tower_livereload::LiveReloadLayer;
@@ -33,6 +43,11 @@ pub struct StaticDir;
 pub enum AppError {
     #[error("Unexpected: {0}")]
     Unexpected(String),
+    /// The entity or record asked for does not exist.
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
     #[error(transparent)]
     Anyhow(#[from] anyhow::Error),
     #[error(transparent)]
@@ -55,36 +70,138 @@ impl From<String> for AppError {
     }
 }
 
+impl AppError {
+    /// The repository error this one was caused by, if any.
+    fn repo_error(&self) -> Option<&RepoError> {
+        let mut source = std::error::Error::source(self);
+        while let Some(e) = source {
+            if let Some(e) = e.downcast_ref::<RepoError>() {
+                return Some(e);
+            }
+            source = e.source();
+        }
+        None
+    }
+}
+
+impl AppError {
+    /// The status to answer with, and a short name for the kind of error
+    /// that clients can match on.
+    fn status(&self) -> (StatusCode, &'static str) {
+        match self {
+            AppError::NotFound(_) | AppError::RecordRepo(RecordRepoError::EntityNotFound(_)) => {
+                (StatusCode::NOT_FOUND, "not_found")
+            }
+            AppError::RecordRepo(
+                RecordRepoError::InvalidPath(_) | RecordRepoError::UnknownRecordType(_),
+            ) => (StatusCode::BAD_REQUEST, "bad_request"),
+            AppError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden"),
+            AppError::RecordRepo(RecordRepoError::RevertConflict(_)) => {
+                (StatusCode::CONFLICT, "conflict")
+            }
+            _ => match self.repo_error() {
+                // Another writer got there first; the client can reload and retry.
+                Some(RepoError::Conflict { .. } | RepoError::NotFastForward) => {
+                    (StatusCode::CONFLICT, "conflict")
+                }
+                Some(RepoError::KeyTooLong { .. } | RepoError::ValueTooLarge { .. }) => {
+                    (StatusCode::PAYLOAD_TOO_LARGE, "too_large")
+                }
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+            },
+        }
+    }
+}
+
+/// The body of an error response.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: &'static str,
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        #[cfg(debug_assertions)]
-        let message = format!("Error: {:?}", self);
-
-        #[cfg(not(debug_assertions))]
-        let message = "Internal Server Error".to_string();
+        let (status, code) = self.status();
+        let error = if status != StatusCode::INTERNAL_SERVER_ERROR {
+            match self.repo_error() {
+                Some(e) => e.to_string(),
+                None => self.to_string(),
+            }
+        } else if cfg!(debug_assertions) {
+            format!("Error: {:?}", self)
+        } else {
+            "Internal Server Error".to_string()
+        };
 
-        (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        (status, Json(ErrorBody { error, code })).into_response()
     }
 }
 
-pub async fn run(db: PathBuf, port: Option<&str>) -> Result<()> {
+pub async fn run(
+    db: PathBuf,
+    port: Option<&str>,
+    metrics: bool,
+    base_path: Option<&str>,
+    webhook: Option<String>,
+    read_connections: Option<u32>,
+    peer_allowlist: Option<Allowlist>,
+) -> Result<()> {
     let addr = format!("0.0.0.0:{}", port.unwrap_or("8080"));
-    let server_url = format!("http://{}/", addr);
-    let state = AppState::new(db.clone(), true, server_url.clone())?;
+    let base_path = normalize_base_path(base_path.unwrap_or_default());
+    let server_url = format!("http://{}{}/", addr, base_path);
+    // Links are root-relative so that they keep working when a reverse proxy
+    // sits in front under another host name.
+    let mut state = AppState::new(db.clone(), true, format!("{}/", base_path))?;
+    state.webhook = webhook;
+    if let Some(size) = read_connections {
+        state.open_read_pool(size)?;
+    }
+    let state = Arc::new(state);
+    let conn = state.get_conn()?;
+    let repo = RecordRepo::new(&conn);
+    if repo.migrate()? {
+        println!("Migrated the index of an older database.");
+    }
+    if repo.recover()? {
+        println!("Repaired the index after an interrupted write.");
+    }
+    drop(conn);
 
     let backend = SqlitePoolBackend::new(state.db_pool.clone());
-    let repo_server = RepoServer::new(backend);
+    let mut repo_server = RepoServer::new(backend);
+    if let Some(allowlist) = peer_allowlist {
+        if allowlist.is_empty() {
+            eprintln!("The peer allowlist is empty, so no peer can sync.");
+        } else {
+            println!("Syncing only with {} allowed peer(s).", allowlist.len());
+        }
+        repo_server = repo_server.with_allowlist(allowlist);
+    }
+    let pushed = state.clone();
+    repo_server = repo_server.on_push(Arc::new(move || {
+        let recovered = pushed
+            .get_conn()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| Ok(RecordRepo::new(&conn).recover()?));
+        if let Err(e) = recovered {
+            eprintln!("could not index the pushed tree: {:#}", e);
+        }
+    }));
     let (endpoint_id, _repo_router) = repo_server
         .start()
         .await
         .context("failed to start repo server")?;
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(handler::index))
         .route("/person/{id}", get(handler::person::page))
         .route("/office/{id}", get(handler::office::page))
+        .route("/offices", get(handler::office::list::page))
         .route("/search.db", get(handler::search_db))
+        .route("/search", get(handler::search))
         .route("/uncommitted", get(handler::uncommitted))
+        .route("/raw/{*path}", get(handler::raw::view))
         .route("/commit", post(handler::commit))
         .route("/abandon", post(handler::abandon))
         .route("/new/{typ}", get(handler::entity::new_form))
@@ -155,14 +272,40 @@ pub async fn run(db: PathBuf, port: Option<&str>) -> Result<()> {
         .route(
             "/office/{id}/supervisor/delete",
             post(handler::office::supervisor::delete),
-        )
+        );
+
+    if metrics {
+        app = app.route("/metrics", get(metrics::handler));
+    }
+
+    if !base_path.is_empty() {
+        // `nest` leaves out the prefix with a trailing slash, which is where
+        // the links to the home page point.
+        app = Router::new()
+            .route(&format!("{}/", base_path), get(handler::index))
+            .nest(&base_path, app);
+    }
+
+    app = app
+        .layer(middleware::from_fn_with_state(state.clone(), repo_commit))
+        .layer(middleware::from_fn_with_state(state.clone(), csrf::protect));
+
+    if metrics {
+        app = app.route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ));
+    }
+
+    let app = app
         .layer(LiveReloadLayer::new())
-        .with_state(Arc::new(state))
-        .nest_service("/static", ServeEmbed::<StaticDir>::new());
+        .with_state(state)
+        .nest_service(
+            &format!("{}/static", base_path),
+            ServeEmbed::<StaticDir>::new(),
+        );
 
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .context("could not listen")?;
+    let listener = bind(&addr).with_context(|| format!("could not listen on {}", addr))?;
 
     println!("Iroh ID: {}", endpoint_id);
     println!("Serving at {}", server_url);
@@ -173,11 +316,90 @@ pub async fn run(db: PathBuf, port: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+/// Middleware adding the working root to every response as `X-Repo-Commit`,
+/// so that clients can cheaply tell whether anything changed.
+async fn repo_commit(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    // Read after the handler so that its own writes show.
+    if let Some(root) = working_root(&state) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-repo-commit"), root);
+    }
+
+    response
+}
+
+/// The working root as a header value, or `None` if there is no repository
+/// to read it from.
+fn working_root(state: &AppState) -> Option<HeaderValue> {
+    let conn = state.get_read_conn().ok()?;
+    let root = RecordRepo::new(&conn)
+        .root_hash(RepoRefType::Working)
+        .ok()?;
+
+    HeaderValue::from_str(&root.to_hex()).ok()
+}
+
+/// Turn a user supplied prefix like `officers/` into `/officers`. The root
+/// prefix becomes the empty string.
+fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Bind the listener with `SO_REUSEADDR` so that a quick restart does not
+/// fail on sockets lingering in `TIME_WAIT`.
+fn bind(addr: &str) -> Result<tokio::net::TcpListener> {
+    let socket_addr: std::net::SocketAddr = addr.parse().context("invalid address")?;
+    let socket = if socket_addr.is_ipv4() {
+        tokio::net::TcpSocket::new_v4()?
+    } else {
+        tokio::net::TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+
+    if let Err(e) = socket.bind(socket_addr) {
+        if e.kind() == std::io::ErrorKind::AddrInUse {
+            anyhow::bail!(
+                "port {} is already in use. Stop the other process using it, \
+                or pick another port with `--port <PORT>`",
+                socket_addr.port()
+            );
+        }
+        return Err(e.into());
+    }
+
+    Ok(socket.listen(1024)?)
+}
+
+/// The format of the index used by the client-side search on the home page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchIndex {
+    /// `search.db`, a copy of the entity table queried with SQLite compiled to wasm.
+    #[default]
+    Sqlite,
+    /// `search-index.json`, a list of entities matched in plain JavaScript.
+    Json,
+}
+
 pub struct AppState {
     pub dynamic: bool,
     pub db: PathBuf,
     pub db_pool: Pool<SqliteConnectionManager>,
+    /// Read-only connections for handlers that only read, if opened with
+    /// [`AppState::open_read_pool`].
+    pub read_pool: Option<Pool<SqliteConnectionManager>>,
     pub server_url: String,
+    pub metrics: Metrics,
+    pub search_index: SearchIndex,
+    /// Where to POST a summary of each commit, if anywhere.
+    pub webhook: Option<String>,
+    pub csrf: csrf::CsrfKey,
 }
 
 impl AppState {
@@ -191,19 +413,52 @@ impl AppState {
             dynamic,
             db,
             db_pool,
+            read_pool: None,
             server_url,
+            metrics: Metrics::default(),
+            search_index: SearchIndex::default(),
+            webhook: None,
+            csrf: csrf::CsrfKey::generate(),
         })
     }
 
+    /// Open a separate pool of `size` read-only connections for
+    /// [`get_read_conn`](Self::get_read_conn). The database is switched to
+    /// WAL mode so that these readers do not wait on the writer.
+    pub fn open_read_pool(&mut self, size: u32) -> Result<()> {
+        self.get_conn()?
+            .pragma_update(None, "journal_mode", "WAL")
+            .context("could not switch the database to WAL mode")?;
+
+        let manager = SqliteConnectionManager::file(&self.db).with_flags(
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        );
+        self.read_pool = Some(r2d2::Pool::builder().max_size(size).build(manager)?);
+
+        Ok(())
+    }
+
+    /// A connection for handlers that write.
     pub fn get_conn(&self) -> Result<PooledConnection<SqliteConnectionManager>, R2D2Error> {
         self.db_pool.get()
     }
 
+    /// A connection for handlers that only read: from the read-only pool if
+    /// there is one, otherwise the writable one.
+    pub fn get_read_conn(&self) -> Result<PooledConnection<SqliteConnectionManager>, R2D2Error> {
+        match &self.read_pool {
+            Some(pool) => pool.get(),
+            None => self.db_pool.get(),
+        }
+    }
+
     pub fn page_context(&self) -> Page {
         let base = if self.dynamic {
             self.server_url.to_string()
         } else {
-            CONFIG.base_url.to_string()
+            theme::config().base_url.to_string()
         };
         Page {
             base,
@@ -221,3 +476,93 @@ pub fn hx_redirect(url: &str) -> Result<Response, AppError> {
 
     Ok((StatusCode::OK, headers).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaSql;
+
+    #[test]
+    fn test_normalize_base_path() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("/officers"), "/officers");
+        assert_eq!(normalize_base_path("officers/"), "/officers");
+        assert_eq!(normalize_base_path("/a/b/"), "/a/b");
+    }
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let respond = |e: AppError| async {
+            let response = e.into_response();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, body)
+        };
+
+        let (status, body) = respond(AppError::NotFound("person/nobody".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "not found: person/nobody", "code": "not_found"})
+        );
+
+        let invalid = RecordRepoError::InvalidPath("person".to_string());
+        let (status, body) = respond(invalid.into()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "bad_request");
+
+        let conflict = RecordRepoError::Repo(RepoError::NotFastForward);
+        let (status, body) = respond(conflict.into()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], RepoError::NotFastForward.to_string());
+
+        let (status, body) = respond(AppError::Unexpected("boom".to_string())).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal");
+    }
+
+    #[test]
+    fn test_working_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(dir.path().join("db.sqlite"), true, "/".to_string()).unwrap();
+        assert!(working_root(&state).is_none());
+
+        let conn = state.get_conn().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        RecordRepo::new(&conn).init().unwrap();
+        let root = RecordRepo::new(&conn)
+            .root_hash(RepoRefType::Working)
+            .unwrap();
+        assert_eq!(working_root(&state).unwrap(), root.to_hex().as_str());
+    }
+
+    #[test]
+    fn test_read_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state =
+            AppState::new(dir.path().join("db.sqlite"), true, "/".to_string()).unwrap();
+        state
+            .get_conn()
+            .unwrap()
+            .execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")
+            .unwrap();
+        state.open_read_pool(2).unwrap();
+
+        let conn = state.get_read_conn().unwrap();
+        let x: i64 = conn.query_row("SELECT x FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(x, 1);
+        assert!(conn.execute("INSERT INTO t VALUES (2)", []).is_err());
+
+        let mode: String = state
+            .get_conn()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{CONFIG, SchemaSql, config, data, ingest, repo::Limits};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path, sync::OnceLock};
+use strum::VariantArray;
+
+static LOADED: OnceLock<config::Config> = OnceLock::new();
+
+/// The config in effect: the one loaded with [`load`], or else the embedded `CONFIG`.
+pub fn config() -> &'static config::Config {
+    LOADED.get().unwrap_or(&CONFIG)
+}
+
+/// A config file read at runtime. Anything left out keeps its embedded value,
+/// except `icons`, which must cover every contact type when given. `links`
+/// need only hold the types whose link differs.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    title: Option<String>,
+    base_url: Option<String>,
+    source_url: Option<String>,
+    contact_order: Option<String>,
+    search_tokenizer: Option<String>,
+    lang: Option<String>,
+    #[serde(default)]
+    limits: LimitsOverrides,
+    #[serde(default)]
+    links: BTreeMap<data::ContactType, String>,
+    icons: Option<BTreeMap<data::ContactType, String>>,
+    #[serde(default)]
+    defaults: DefaultsOverrides,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LimitsOverrides {
+    max_key_len: Option<u32>,
+    max_value_size: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct DefaultsOverrides {
+    #[serde(default)]
+    photo: PhotoOverrides,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PhotoOverrides {
+    url: Option<String>,
+}
+
+/// Read the config at `path` over the embedded one. Must be called before
+/// [`config`] is first used for the override to take effect everywhere.
+pub fn load(path: &Path) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("could not read config {}", path.display()))?;
+    let merged = parse(&text).with_context(|| format!("invalid config {}", path.display()))?;
+    if LOADED.set(merged).is_err() {
+        anyhow::bail!("config was already loaded");
+    }
+
+    Ok(())
+}
+
+fn parse(text: &str) -> Result<config::Config> {
+    let overrides: Overrides = toml::from_str(text)?;
+    merge(overrides, &CONFIG)
+}
+
+fn merge(overrides: Overrides, base: &config::Config) -> Result<config::Config> {
+    // The generated config holds `&'static str`s, so values read at runtime are
+    // leaked. It is loaded once per process.
+    fn leak(s: String) -> &'static str {
+        Box::leak(s.into_boxed_str())
+    }
+
+    let icons = match overrides.icons {
+        Some(mut icons) => {
+            let missing: Vec<String> = data::ContactType::VARIANTS
+                .iter()
+                .filter(|typ| !icons.contains_key(typ))
+                .map(|typ| typ.to_string())
+                .collect();
+            if !missing.is_empty() {
+                anyhow::bail!("no icon for contact types: {}", missing.join(", "));
+            }
+            let mut take = |typ: data::ContactType| leak(icons.remove(&typ).unwrap_or_default());
+            config::icons::Icons {
+                address: take(data::ContactType::Address),
+                phone: take(data::ContactType::Phone),
+                email: take(data::ContactType::Email),
+                website: take(data::ContactType::Website),
+                wikipedia: take(data::ContactType::Wikipedia),
+                x: take(data::ContactType::X),
+                facebook: take(data::ContactType::Facebook),
+                instagram: take(data::ContactType::Instagram),
+                youtube: take(data::ContactType::Youtube),
+                wikidata: take(data::ContactType::Wikidata),
+                telegram: take(data::ContactType::Telegram),
+                linkedin: take(data::ContactType::LinkedIn),
+            }
+        }
+        None => config::icons::Icons { ..base.icons },
+    };
+
+    let mut links = overrides.links;
+    let mut link =
+        |typ: data::ContactType, base: &'static str| links.remove(&typ).map_or(base, leak);
+    let links = config::links::Links {
+        address: link(data::ContactType::Address, base.links.address),
+        phone: link(data::ContactType::Phone, base.links.phone),
+        email: link(data::ContactType::Email, base.links.email),
+        website: link(data::ContactType::Website, base.links.website),
+        wikipedia: link(data::ContactType::Wikipedia, base.links.wikipedia),
+        x: link(data::ContactType::X, base.links.x),
+        youtube: link(data::ContactType::Youtube, base.links.youtube),
+        facebook: link(data::ContactType::Facebook, base.links.facebook),
+        instagram: link(data::ContactType::Instagram, base.links.instagram),
+        wikidata: link(data::ContactType::Wikidata, base.links.wikidata),
+        telegram: link(data::ContactType::Telegram, base.links.telegram),
+        linkedin: link(data::ContactType::LinkedIn, base.links.linkedin),
+    };
+
+    if let Some(order) = &overrides.contact_order {
+        parse_contact_order(order)?;
+    }
+
+    if let Some(tokenizer) = &overrides.search_tokenizer {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        conn.create_entity_tables()?;
+        ingest::create_entity_index(&conn, tokenizer)
+            .with_context(|| format!("invalid search_tokenizer: {}", tokenizer))?;
+    }
+
+    Ok(config::Config {
+        title: overrides.title.map_or(base.title, leak),
+        base_url: overrides.base_url.map_or(base.base_url, leak),
+        source_url: overrides.source_url.map_or(base.source_url, leak),
+        contact_order: overrides.contact_order.map_or(base.contact_order, leak),
+        search_tokenizer: overrides
+            .search_tokenizer
+            .map_or(base.search_tokenizer, leak),
+        lang: overrides.lang.map_or(base.lang, leak),
+        limits: config::limits::Limits {
+            max_key_len: overrides
+                .limits
+                .max_key_len
+                .map_or(base.limits.max_key_len, i64::from),
+            max_value_size: overrides
+                .limits
+                .max_value_size
+                .map_or(base.limits.max_value_size, i64::from),
+        },
+        links,
+        icons,
+        defaults: config::defaults::Defaults {
+            photo: config::defaults::photo::Photo {
+                url: overrides
+                    .defaults
+                    .photo
+                    .url
+                    .map_or(base.defaults.photo.url, leak),
+            },
+        },
+    })
+}
+
+/// The bounds the config puts on the keys and values written to the tree.
+pub fn limits() -> Limits {
+    // Both are checked to fit a `u32` by `merge`, and the embedded ones by the
+    // tests.
+    let limits = &config().limits;
+    Limits {
+        max_key_len: limits.max_key_len as usize,
+        max_value_size: limits.max_value_size as usize,
+    }
+}
+
+/// The contact types the config wants shown first, in that order.
+pub fn contact_order() -> Vec<data::ContactType> {
+    // The embedded order is checked by the tests and a loaded one by `merge`.
+    parse_contact_order(config().contact_order).unwrap_or_default()
+}
+
+fn parse_contact_order(order: &str) -> Result<Vec<data::ContactType>> {
+    order
+        .split(',')
+        .map(str::trim)
+        .filter(|typ| !typ.is_empty())
+        .map(|typ| {
+            typ.parse()
+                .with_context(|| format!("unknown contact type in contact_order: {}", typ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = parse("title = \"Directory\"\n[defaults.photo]\nurl = \"x.svg\"").unwrap();
+        assert_eq!(config.title, "Directory");
+        assert_eq!(config.base_url, CONFIG.base_url);
+        assert_eq!(config.defaults.photo.url, "x.svg");
+        assert_eq!(config.icons.phone, CONFIG.icons.phone);
+
+        let icons: String = data::ContactType::VARIANTS
+            .iter()
+            .map(|typ| format!("{} = \"<{}>\"\n", typ, typ))
+            .collect();
+        let config = parse(&format!("[icons]\n{}", icons)).unwrap();
+        assert_eq!(config.title, CONFIG.title);
+        assert_eq!(config.icons.wikidata, "<wikidata>");
+        assert_eq!(config.icons.linkedin, "<linkedin>");
+
+        let config = parse("contact_order = \"x, phone\"").unwrap();
+        assert_eq!(config.contact_order, "x, phone");
+        assert_eq!(
+            parse_contact_order(CONFIG.contact_order).unwrap()[0],
+            data::ContactType::Website
+        );
+        assert!(parse("contact_order = \"phone, fax\"").is_err());
+
+        let config = parse("[limits]\nmax_value_size = 4096").unwrap();
+        assert_eq!(config.limits.max_value_size, 4096);
+        assert_eq!(config.limits.max_key_len, CONFIG.limits.max_key_len);
+        assert!(u32::try_from(CONFIG.limits.max_value_size).is_ok());
+        assert!(parse("[limits]\nmax_key_len = -1").is_err());
+
+        let config = parse("search_tokenizer = \"trigram\"").unwrap();
+        assert_eq!(config.search_tokenizer, "trigram");
+        assert!(parse("search_tokenizer = \"nonesuch\"").is_err());
+
+        let config = parse("lang = \"hi\"\n[links]\nx = \"https://twitter.com/{value}\"").unwrap();
+        assert_eq!(config.links.x, "https://twitter.com/{value}");
+        assert_eq!(config.links.phone, CONFIG.links.phone);
+        assert_eq!(
+            data::ContactType::Wikipedia.link(&config, "भारत"),
+            "https://hi.wikipedia.org/wiki/भारत"
+        );
+        assert_eq!(
+            data::ContactType::X.link(&config, "PMOIndia"),
+            "https://twitter.com/PMOIndia"
+        );
+        assert!(parse("[links]\nfax = \"fax:{value}\"").is_err());
+
+        let err = parse("[icons]\nphone = \"p\"").err().unwrap();
+        assert!(err.to_string().contains("address"));
+        assert!(parse("titel = \"typo\"").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::dto;
+use crate::record::RecordRepo;
+use crate::repo::Hash;
+use crate::serve::handler::{group_changes, serialize_hex};
+
+/// How long to wait for the webhook to answer; an unresponsive one must not
+/// hold up the commit or pile up background tasks.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a commit changed, as posted to the webhook.
+#[derive(Serialize, Debug)]
+pub struct Payload {
+    #[serde(serialize_with = "serialize_hex")]
+    pub commit_id: Hash,
+    pub added: Vec<dto::Entity>,
+    pub changed: Vec<dto::Entity>,
+    pub removed: Vec<dto::Entity>,
+}
+
+/// Summarize the entities that differ between the `previous` committed root
+/// and the current one.
+pub fn summarize(conn: &Connection, previous: &Hash) -> Result<Payload> {
+    let repo = RecordRepo::new(conn);
+    let committed = repo.committed()?;
+    let before = repo.get_at(previous)?;
+    let diffs = before
+        .iterate_diff(&committed)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut payload = Payload {
+        commit_id: committed.commit_id()?,
+        added: Vec::new(),
+        changed: Vec::new(),
+        removed: Vec::new(),
+    };
+    for change in group_changes(conn, diffs.into_iter()) {
+        if change.added {
+            payload.added.push(change.entity);
+        } else if change.removed {
+            payload.removed.push(change.entity);
+        } else {
+            payload.changed.push(change.entity);
+        }
+    }
+
+    Ok(payload)
+}
+
+/// POST the payload as JSON to `url`, giving up after [`TIMEOUT`].
+pub async fn send(url: &str, payload: &Payload) -> Result<()> {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("could not notify webhook {}", url))?;
+
+    Ok(())
+}
+
+/// Send the payload in the background, logging rather than returning a failure.
+pub fn spawn(url: String, payload: Payload) {
+    tokio::spawn(async move {
+        if let Err(e) = send(&url, &payload).await {
+            eprintln!("{:#}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemaSql;
+    use crate::record::{Key, OfficePath, PersonPath};
+
+    #[test]
+    fn test_summarize() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.create_entity_tables().unwrap();
+        conn.create_property_tables().unwrap();
+        let mut repo = RecordRepo::new(&conn);
+        repo.init().unwrap();
+
+        let alice = Key::<PersonPath, ()>::new("alice");
+        let bob = Key::<PersonPath, ()>::new("bob");
+        let pmo = Key::<OfficePath, ()>::new("pmo");
+        let mut working = repo.working().unwrap();
+        working.save(alice.name(), &"Alice".to_string()).unwrap();
+        working.save(bob.name(), &"Bob".to_string()).unwrap();
+        repo.commit(None, None).unwrap();
+        let previous = repo.committed().unwrap().commit_id().unwrap();
+
+        let mut working = repo.working().unwrap();
+        working.save(alice.name(), &"Alice A.".to_string()).unwrap();
+        working.delete(bob.name()).unwrap();
+        working.save(pmo.name(), &"PMO".to_string()).unwrap();
+        repo.commit(None, None).unwrap();
+
+        let payload = summarize(&conn, &previous).unwrap();
+        let ids = |entities: &[dto::Entity]| -> Vec<String> {
+            entities.iter().map(|e| e.id.clone()).collect()
+        };
+        assert_eq!(ids(&payload.added), ["pmo"]);
+        assert_eq!(ids(&payload.changed), ["alice"]);
+        assert_eq!(ids(&payload.removed), ["bob"]);
+        assert_eq!(payload.commit_id, repo.committed().unwrap().commit_id().unwrap());
+    }
+}